@@ -1,7 +1,9 @@
-use criterion::{Criterion, criterion_group, criterion_main};
-use logium_core::engine::{TimeRange, analyze};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use logium_core::engine::{RulePrefilter, TimeRange, analyze, compile_rules, evaluate_rule};
+use logium_core::grok::GrokLibrary;
 use logium_core::model::*;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 fn fixtures_dir() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
@@ -34,6 +36,7 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let src_a = Source {
         id: 1,
@@ -54,6 +57,7 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r#"HTTP/1\.\d"\s+\d+"#.into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -62,7 +66,13 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
             pattern: Some(r#"HTTP/1\.\d"\s+(?P<status>\d+)"#.into()),
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
     let method_rule = LogRule {
         id: 2,
@@ -71,6 +81,7 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
         match_rules: vec![MatchRule {
             id: 2,
             pattern: r"GET|POST|PUT".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 2,
@@ -79,13 +90,22 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
     let ruleset = Ruleset {
         id: 1,
         name: "nginx_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1, 2],
+        stop_rule_ids: vec![],
     };
     let pattern = Pattern {
         id: 1,
@@ -96,14 +116,19 @@ fn bench_nginx_pipeline(c: &mut Criterion) {
                 state_key: "status".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Integer(404)),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "status".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Integer(404)),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     c.bench_function("nginx_cross_source_1k_each", |b| {
@@ -140,6 +165,7 @@ fn bench_nginx_large(c: &mut Criterion) {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let src = Source {
         id: 1,
@@ -154,6 +180,7 @@ fn bench_nginx_large(c: &mut Criterion) {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r#"HTTP/1\.\d"\s+\d+"#.into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -162,13 +189,22 @@ fn bench_nginx_large(c: &mut Criterion) {
             pattern: Some(r#"HTTP/1\.\d"\s+(?P<status>\d+)"#.into()),
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
     let ruleset = Ruleset {
         id: 1,
         name: "nginx_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1],
+        stop_rule_ids: vec![],
     };
     let pattern = Pattern {
         id: 1,
@@ -178,7 +214,11 @@ fn bench_nginx_large(c: &mut Criterion) {
             state_key: "status".into(),
             operator: Operator::Eq,
             operand: Operand::Literal(StateValue::Integer(404)),
+            threshold: None,
         }],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     c.bench_function("nginx_large_51k_lines", |b| {
@@ -197,5 +237,89 @@ fn bench_nginx_large(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_nginx_pipeline, bench_nginx_large);
+/// Matching 2k lines against 10/100/1000 rules, one rule at a time versus
+/// through the template's prefilter.
+fn bench_rule_matching(c: &mut Criterion) {
+    let lines: Vec<LogLine> = (0..2_000u64)
+        .map(|i| {
+            let content: Arc<str> = if i % 4 == 0 {
+                format!("heartbeat ok seq={i}").into()
+            } else {
+                format!("service_{} request took {}ms", i * 7 % 1_000, i % 250).into()
+            };
+            LogLine {
+                timestamp: chrono::NaiveDateTime::default(),
+                source_id: 1,
+                line_number: i + 1,
+                raw: content.clone(),
+                content,
+                cached_json: None,
+            }
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("rule_matching");
+    for count in [10u64, 100, 1_000] {
+        let rules: Vec<LogRule> = (0..count)
+            .map(|i| LogRule {
+                id: i + 1,
+                name: format!("service_{i}"),
+                match_mode: MatchMode::Any,
+                match_rules: vec![MatchRule {
+                    id: i + 1,
+                    pattern: format!(r"service_{i} request"),
+                    negate: false,
+                }],
+                extraction_rules: vec![ExtractionRule {
+                    id: i + 1,
+                    extraction_type: ExtractionType::Parsed,
+                    state_key: "latency".into(),
+                    pattern: Some(r"took (?P<latency>\d+)ms".into()),
+                    static_value: None,
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
+                }],
+                guard: None,
+                script: None,
+                plugin_id: None,
+            })
+            .collect();
+        let compiled = compile_rules(&rules, &GrokLibrary::new(&[])).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("per_rule", count), &lines, |b, lines| {
+            b.iter(|| {
+                lines
+                    .iter()
+                    .map(|line| {
+                        rules
+                            .iter()
+                            .zip(&compiled)
+                            .filter(|(rule, c)| evaluate_rule(rule, line, c).is_some())
+                            .count()
+                    })
+                    .sum::<usize>()
+            });
+        });
+
+        let prefilter = RulePrefilter::new(rules.iter().zip(&compiled).collect()).unwrap();
+        group.bench_with_input(BenchmarkId::new("prefilter", count), &lines, |b, lines| {
+            b.iter(|| {
+                lines
+                    .iter()
+                    .map(|line| prefilter.evaluate(line).len())
+                    .sum::<usize>()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_nginx_pipeline,
+    bench_nginx_large,
+    bench_rule_matching
+);
 criterion_main!(benches);
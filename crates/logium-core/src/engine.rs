@@ -1,15 +1,25 @@
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
-use std::fs::File;
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
 use std::io::{BufRead, BufReader};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::Instant;
 
+use aho_corasick::AhoCorasick;
 use chrono::NaiveDateTime;
 use rayon::prelude::*;
 use regex::{Regex, RegexSet};
+use regex_syntax::hir::literal;
 use serde::{Deserialize, Serialize};
 
+use crate::expr::Expr;
+use crate::grok::GrokLibrary;
+use crate::log_source::{FileSource, LogReader, LogSource};
 use crate::model::*;
+use crate::plugin::{CompiledPlugin, PluginInstance};
+use crate::script::{CompiledScript, ScriptInput, ScriptOutput, ScriptRunner};
+use crate::transform::Pipeline;
 
 // ---------------------------------------------------------------------------
 // Time-range filtering
@@ -24,6 +34,19 @@ pub struct TimeRange {
     pub end: Option<NaiveDateTime>,
 }
 
+/// Optional analysis outputs, all off by default.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+    /// Report how far each pattern that never fired got.
+    pub near_misses: bool,
+    /// Time each rule and source. Rules are then run one by one rather than
+    /// through the template prefilter, so their costs can be told apart.
+    pub profile: bool,
+    /// Report unmatched lines per source, and rules and patterns that never
+    /// did anything.
+    pub coverage: bool,
+}
+
 // ---------------------------------------------------------------------------
 // Error types
 // ---------------------------------------------------------------------------
@@ -62,6 +85,12 @@ pub enum AnalysisEvent {
     RuleMatch(RuleMatch),
     PatternMatch(PatternMatch),
     StateChange(StateChange),
+    NearMiss(NearMiss),
+    /// A child pattern refined an earlier match, given by its position
+    /// among the `PatternMatch` events sent so far.
+    Superseded {
+        match_index: u64,
+    },
     Progress {
         lines_processed: u64,
     },
@@ -70,6 +99,10 @@ pub enum AnalysisEvent {
         total_rule_matches: u64,
         total_pattern_matches: u64,
         total_state_changes: u64,
+        #[serde(default)]
+        profile: Option<AnalysisProfile>,
+        #[serde(default)]
+        coverage: Option<CoverageReport>,
     },
     Error {
         message: String,
@@ -80,9 +113,9 @@ pub enum AnalysisEvent {
 // Log line parser (streaming iterator)
 // ---------------------------------------------------------------------------
 
-/// Lazily yields `LogLine` items from a source file.
+/// Lazily yields `LogLine` items from a source's log.
 pub struct LogLineIterator {
-    reader: BufReader<File>,
+    reader: BufReader<LogReader>,
     source_id: u64,
     timestamp_format: String,
     extraction_regex: Option<Regex>,
@@ -90,18 +123,32 @@ pub struct LogLineIterator {
     content_regex: Option<Regex>,
     continuation_regex: Option<Regex>,
     json_timestamp_field: Option<String>,
+    /// The template's parser plugin, which replaces timestamp and content
+    /// parsing.
+    parser: Option<PluginInstance>,
     pending_line: Option<String>,
+    /// Physical lines read so far; the pending line, if any, is the last one.
+    lines_read: u64,
     buf: String,
 }
 
 impl LogLineIterator {
+    /// Read `source`'s file.
     pub fn new(
         source: &Source,
         template: &SourceTemplate,
         ts_template: &TimestampTemplate,
     ) -> Result<Self, AnalysisError> {
-        let file = File::open(&source.file_path)
-            .map_err(|_| AnalysisError::FileNotFound(source.file_path.clone()))?;
+        Self::from_reader(source, FileSource.open(source)?, template, ts_template)
+    }
+
+    /// Read `source`'s log from `reader`.
+    pub fn from_reader(
+        source: &Source,
+        reader: LogReader,
+        template: &SourceTemplate,
+        ts_template: &TimestampTemplate,
+    ) -> Result<Self, AnalysisError> {
         let content_regex = match &template.content_regex {
             Some(pat) => {
                 let re = Regex::new(pat).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
@@ -124,7 +171,7 @@ impl LogLineIterator {
             None => None,
         };
         Ok(Self {
-            reader: BufReader::with_capacity(64 * 1024, file),
+            reader: BufReader::with_capacity(64 * 1024, reader),
             source_id: source.id,
             timestamp_format: ts_template.format.clone(),
             extraction_regex,
@@ -132,16 +179,24 @@ impl LogLineIterator {
             content_regex,
             continuation_regex,
             json_timestamp_field: template.json_timestamp_field.clone(),
+            parser: None,
             pending_line: None,
+            lines_read: 0,
             buf: String::new(),
         })
     }
-}
 
-impl Iterator for LogLineIterator {
-    type Item = Result<LogLine, AnalysisError>;
+    /// Parse lines with a parser plugin instead of the timestamp template.
+    pub fn with_parser(mut self, parser: PluginInstance) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
+impl LogLineIterator {
+    /// Read the next entry: a head line with its continuation lines merged,
+    /// and the head's line number.
+    fn read_entry(&mut self) -> Option<Result<(u64, String), AnalysisError>> {
         // Get the head line: either from pending_line or by reading from the reader.
         let head_line = if let Some(pending) = self.pending_line.take() {
             pending
@@ -149,14 +204,17 @@ impl Iterator for LogLineIterator {
             self.buf.clear();
             match self.reader.read_line(&mut self.buf) {
                 Ok(0) => return None,
-                Ok(_) => self
-                    .buf
-                    .trim_end_matches('\n')
-                    .trim_end_matches('\r')
-                    .to_string(),
+                Ok(_) => {
+                    self.lines_read += 1;
+                    self.buf
+                        .trim_end_matches('\n')
+                        .trim_end_matches('\r')
+                        .to_string()
+                }
                 Err(e) => return Some(Err(AnalysisError::ParseError(e.to_string()))),
             }
         };
+        let line_number = self.lines_read;
 
         // If continuation_regex is set, merge continuation lines.
         let merged_raw = if let Some(cont_re) = &self.continuation_regex {
@@ -166,6 +224,7 @@ impl Iterator for LogLineIterator {
                 match self.reader.read_line(&mut self.buf) {
                     Ok(0) => break, // EOF
                     Ok(_) => {
+                        self.lines_read += 1;
                         let line = self
                             .buf
                             .trim_end_matches('\n')
@@ -186,25 +245,29 @@ impl Iterator for LogLineIterator {
         } else {
             head_line
         };
+        Some(Ok((line_number, merged_raw)))
+    }
 
+    /// Parse an entry's timestamp and content per the templates.
+    fn parse_entry(&self, line_number: u64, merged_raw: String) -> Result<LogLine, AnalysisError> {
         // JSON mode: parse line as JSON, extract timestamp from configured field
         if let Some(ref field_name) = self.json_timestamp_field {
             let json_val: serde_json::Value = match serde_json::from_str(&merged_raw) {
                 Ok(v) => v,
                 Err(e) => {
-                    return Some(Err(AnalysisError::ParseError(format!(
+                    return Err(AnalysisError::ParseError(format!(
                         "failed to parse JSON: {e}"
-                    ))));
+                    )));
                 }
             };
 
             let ts_str = match json_val.get(field_name).and_then(|v| v.as_str()) {
                 Some(s) => s.to_string(),
                 None => {
-                    return Some(Err(AnalysisError::ParseError(format!(
+                    return Err(AnalysisError::ParseError(format!(
                         "JSON field '{}' not found or not a string",
                         field_name
-                    ))));
+                    )));
                 }
             };
 
@@ -224,18 +287,19 @@ impl Iterator for LogLineIterator {
             return match timestamp {
                 Ok(ts) => {
                     let raw: Arc<str> = Arc::from(merged_raw);
-                    Some(Ok(LogLine {
+                    Ok(LogLine {
                         timestamp: ts,
                         source_id: self.source_id,
                         content: Arc::clone(&raw),
                         raw,
                         cached_json: Some(json_val),
-                    }))
+                        line_number,
+                    })
                 }
-                Err(e) => Some(Err(AnalysisError::InvalidTimestampFormat(format!(
+                Err(e) => Err(AnalysisError::InvalidTimestampFormat(format!(
                     "failed to parse timestamp from '{}' with format '{}': {}",
                     ts_str, self.timestamp_format, e
-                )))),
+                ))),
             };
         }
 
@@ -295,18 +359,64 @@ impl Iterator for LogLineIterator {
                     Some(s) => Arc::from(s),
                     None => Arc::clone(&raw),
                 };
-                Some(Ok(LogLine {
+                Ok(LogLine {
                     timestamp: ts,
                     source_id: self.source_id,
                     raw,
                     content,
                     cached_json: None,
-                }))
+                    line_number,
+                })
             }
-            Err(e) => Some(Err(AnalysisError::InvalidTimestampFormat(format!(
+            Err(e) => Err(AnalysisError::InvalidTimestampFormat(format!(
                 "failed to parse timestamp from '{}' with format '{}': {}",
                 first_line, self.timestamp_format, e
-            )))),
+            ))),
+        }
+    }
+
+    /// Parse an entry with the template's parser plugin; `None` skips it.
+    fn parse_with_plugin(
+        &mut self,
+        line_number: u64,
+        merged_raw: String,
+    ) -> Option<Result<LogLine, AnalysisError>> {
+        let parser = self.parser.as_mut()?;
+        let parsed = match parser.parse_line(&merged_raw) {
+            Ok(parsed) => parsed?,
+            Err(e) => {
+                return Some(Err(AnalysisError::ParseError(format!(
+                    "parser plugin failed on line {line_number}: {e}"
+                ))));
+            }
+        };
+        let raw: Arc<str> = Arc::from(merged_raw);
+        Some(Ok(LogLine {
+            timestamp: parsed.timestamp,
+            source_id: self.source_id,
+            content: parsed.content.map_or_else(|| Arc::clone(&raw), Arc::from),
+            raw,
+            cached_json: parsed.fields,
+            line_number,
+        }))
+    }
+}
+
+impl Iterator for LogLineIterator {
+    type Item = Result<LogLine, AnalysisError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, merged_raw) = match self.read_entry()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.parser.is_none() {
+                return Some(self.parse_entry(line_number, merged_raw));
+            }
+            if let Some(line) = self.parse_with_plugin(line_number, merged_raw) {
+                return Some(line);
+            }
         }
     }
 }
@@ -618,30 +728,153 @@ impl Iterator for ProcessedLineMerger {
 /// Pre-compiled regex data for a single LogRule.
 pub struct CompiledRule {
     pub rule_id: u64,
+    /// Positive match rules.
     pub match_set: RegexSet,
     pub match_count: usize,
+    /// Negated match rules; any hit rejects the line.
+    pub negated_set: RegexSet,
     pub match_mode: MatchMode,
     pub extraction_regexes: Vec<(usize, Regex)>, // (extraction_rule_index, compiled regex)
+    /// Transform pipeline of each extraction rule, by index.
+    pub pipelines: Vec<Pipeline>,
+    guard: Option<CompiledGuard>,
+}
+
+/// A rule guard in predicate form, with its operand compiled.
+struct CompiledGuard {
+    /// `source_name` is empty when the guard tests the line's own source.
+    predicate: PatternPredicate,
+    regexes: HashMap<String, Regex>,
+    exprs: HashMap<String, Expr>,
+}
+
+/// The predicate a guard is evaluated as.
+fn guard_predicate(guard: &RuleGuard) -> PatternPredicate {
+    PatternPredicate {
+        source_name: guard.source_name.clone().unwrap_or_default(),
+        state_key: guard.state_key.clone(),
+        operator: guard.operator.clone(),
+        operand: guard.operand.clone(),
+        threshold: None,
+    }
+}
+
+/// Check a rule guard the way `validate_predicate` checks pattern steps.
+/// Edge operators are rejected since a guard only sees the current state.
+pub fn validate_guard(guard: &RuleGuard) -> Result<(), AnalysisError> {
+    if guard.operator.is_edge() {
+        return Err(AnalysisError::ParseError(
+            "rule guards cannot use edge operators".into(),
+        ));
+    }
+    validate_predicate(&guard_predicate(guard))
+}
+
+impl CompiledGuard {
+    fn new(guard: &RuleGuard) -> Result<Self, AnalysisError> {
+        validate_guard(guard)?;
+        let predicate = guard_predicate(guard);
+        let mut regexes = HashMap::new();
+        let mut exprs = HashMap::new();
+        compile_operand(&predicate, &mut regexes, &mut exprs)?;
+        Ok(Self {
+            predicate,
+            regexes,
+            exprs,
+        })
+    }
+
+    /// Whether the guard holds for a line from `source_name` at `now`.
+    fn holds(&self, state: &StateManager, source_name: &str, now: NaiveDateTime) -> bool {
+        let source_name = if self.predicate.source_name.is_empty() {
+            source_name
+        } else {
+            &self.predicate.source_name
+        };
+        let tracked = state.get_tracked_by_name(source_name, &self.predicate.state_key);
+        let env = PredicateEnv {
+            regexes: &self.regexes,
+            exprs: &self.exprs,
+            now: Some(now),
+        };
+        compare_operand(
+            &self.predicate,
+            tracked.map(|t| &t.value),
+            tracked.map(|t| t.set_at),
+            state,
+            &env,
+        )
+    }
+}
+
+/// Check an extraction rule's mode and compile its transform pipeline.
+fn compile_extraction(ext: &ExtractionRule) -> Result<Pipeline, AnalysisError> {
+    if let ExtractionMode::List { cap: 0 } = ext.mode {
+        return Err(AnalysisError::ParseError(format!(
+            "extraction of '{}': list cap must be at least 1",
+            ext.state_key
+        )));
+    }
+    if ext.ttl_ms == Some(0) {
+        return Err(AnalysisError::ParseError(format!(
+            "extraction of '{}': ttl must be at least 1 ms",
+            ext.state_key
+        )));
+    }
+    if ext.ttl_ms.is_some_and(|ttl| ttl > i64::MAX as u64) {
+        return Err(AnalysisError::ParseError(format!(
+            "extraction of '{}': ttl must be at most {} ms",
+            ext.state_key,
+            i64::MAX
+        )));
+    }
+    Pipeline::new(ext)
+}
+
+/// Check an extraction rule the way `compile_rules` does.
+pub fn validate_extraction_rule(ext: &ExtractionRule) -> Result<(), AnalysisError> {
+    compile_extraction(ext).map(drop)
 }
 
-fn compile_rules(rules: &[LogRule]) -> Result<Vec<CompiledRule>, AnalysisError> {
+/// Compile rules, expanding `%{NAME}` references against `grok` first.
+/// Expansion errors name the rule they occur in.
+pub fn compile_rules(
+    rules: &[LogRule],
+    grok: &GrokLibrary,
+) -> Result<Vec<CompiledRule>, AnalysisError> {
     let mut compiled = Vec::with_capacity(rules.len());
     for rule in rules {
-        let patterns: Vec<&str> = rule
-            .match_rules
+        let expand = |pattern: &str| -> Result<String, AnalysisError> {
+            grok.expand(pattern)
+                .map(Cow::into_owned)
+                .map_err(|e| AnalysisError::ParseError(format!("rule '{}': {e}", rule.name)))
+        };
+        let (negated, positive): (Vec<&MatchRule>, Vec<&MatchRule>) =
+            rule.match_rules.iter().partition(|m| m.negate);
+        let positive = positive
             .iter()
-            .map(|m| m.pattern.as_str())
-            .collect();
+            .map(|m| expand(&m.pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        let negated = negated
+            .iter()
+            .map(|m| expand(&m.pattern))
+            .collect::<Result<Vec<_>, _>>()?;
         let match_set =
-            RegexSet::new(&patterns).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
-        let match_count = rule.match_rules.len();
+            RegexSet::new(&positive).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+        let match_count = positive.len();
+        let negated_set =
+            RegexSet::new(&negated).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+        let guard = rule.guard.as_ref().map(CompiledGuard::new).transpose()?;
 
         let mut extraction_regexes = Vec::new();
+        let mut pipelines = Vec::with_capacity(rule.extraction_rules.len());
         for (idx, ext) in rule.extraction_rules.iter().enumerate() {
+            pipelines.push(compile_extraction(ext)?);
             if let ExtractionType::Parsed = ext.extraction_type
                 && let Some(pat) = &ext.pattern
             {
-                let re = Regex::new(pat).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+                let re = Regex::new(&expand(pat)?)
+                    .map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
                 extraction_regexes.push((idx, re));
             }
         }
@@ -650,8 +883,11 @@ fn compile_rules(rules: &[LogRule]) -> Result<Vec<CompiledRule>, AnalysisError>
             rule_id: rule.id,
             match_set,
             match_count,
+            negated_set,
             match_mode: rule.match_mode.clone(),
             extraction_regexes,
+            pipelines,
+            guard,
         });
     }
     Ok(compiled)
@@ -667,28 +903,39 @@ pub fn evaluate_rule(
     line: &LogLine,
     compiled: &CompiledRule,
 ) -> Option<HashMap<String, StateValue>> {
-    let matches: Vec<usize> = compiled
-        .match_set
-        .matches(&line.content)
-        .into_iter()
-        .collect();
+    let hits = compiled.match_set.matches(&line.content).iter().count();
+    if !rule_matched(compiled, hits, &line.content) {
+        return None;
+    }
+    Some(extract_state(rule, line, compiled))
+}
 
+/// Whether a rule whose positive match rules hit `hits` times matches `text`.
+fn rule_matched(compiled: &CompiledRule, hits: usize, text: &str) -> bool {
+    // A rule made only of negated match rules matches every line they reject.
     let matched = match compiled.match_mode {
-        MatchMode::Any => !matches.is_empty(),
-        MatchMode::All => matches.len() == compiled.match_count,
+        MatchMode::Any => {
+            hits > 0 || (compiled.match_count == 0 && !compiled.negated_set.is_empty())
+        }
+        MatchMode::All => hits == compiled.match_count,
     };
+    matched && !compiled.negated_set.is_match(text)
+}
 
-    if !matched {
-        return None;
-    }
-
+/// Run a matched rule's extraction rules against the line.
+fn extract_state(
+    rule: &LogRule,
+    line: &LogLine,
+    compiled: &CompiledRule,
+) -> HashMap<String, StateValue> {
     let mut extracted = HashMap::new();
 
-    for ext in &rule.extraction_rules {
+    for (idx, ext) in rule.extraction_rules.iter().enumerate() {
+        let pipeline = &compiled.pipelines[idx];
         match ext.extraction_type {
             ExtractionType::Static => {
-                if let Some(val) = &ext.static_value {
-                    extracted.insert(ext.state_key.clone(), StateValue::String(val.clone()));
+                if let Some(value) = ext.static_value.as_deref().and_then(|v| pipeline.apply(v)) {
+                    extracted.insert(ext.state_key.clone(), value);
                 }
             }
             ExtractionType::Clear => {
@@ -703,56 +950,313 @@ pub fn evaluate_rule(
                     .find(|(idx, _)| rule.extraction_rules[*idx].id == ext.id)
                     && let Some(caps) = re.captures(&line.content)
                     && let Some(m) = caps.name(&ext.state_key)
+                    && let Some(value) = pipeline.apply(m.as_str())
                 {
-                    let val_str = m.as_str();
-                    // Try to parse as integer, then float, otherwise string
-                    let value = if let Ok(i) = val_str.parse::<i64>() {
-                        StateValue::Integer(i)
-                    } else if let Ok(f) = val_str.parse::<f64>() {
-                        StateValue::Float(f)
-                    } else if val_str == "true" || val_str == "false" {
-                        StateValue::Bool(val_str == "true")
-                    } else {
-                        StateValue::String(val_str.to_string())
-                    };
                     extracted.insert(ext.state_key.clone(), value);
                 }
             }
         }
     }
 
-    Some(extracted)
+    extracted
+}
+
+/// Literal prefixes shorter than this match too many lines to be worth
+/// looking for; patterns with one fall back to the shared `RegexSet`.
+const MIN_PREFILTER_LITERAL: usize = 2;
+
+/// Finds the rules of one template a line matches in a single pass, instead
+/// of running every rule's own `RegexSet` over it.
+///
+/// Positive match patterns with selective literal prefixes (`ERROR`,
+/// `GET|POST`, `conn(ected|lost)`) are found through one Aho-Corasick
+/// automaton and only the patterns it points at are confirmed with their
+/// regex. The rest share one `RegexSet`. Negated patterns and extraction
+/// regexes then run only for rules whose positive patterns matched.
+pub struct RulePrefilter<'a> {
+    rules: Vec<(&'a LogRule, &'a CompiledRule)>,
+    literals: Option<AhoCorasick>,
+    /// Indexes into `filtered` of the patterns each literal starts.
+    literal_targets: Vec<Vec<usize>>,
+    /// Literal-prefixed patterns as (rule slot, regex).
+    filtered: Vec<(usize, Regex)>,
+    unfiltered: RegexSet,
+    /// Rule slot of each `unfiltered` pattern.
+    unfiltered_slots: Vec<usize>,
+    /// Slots of stop rules without a guard: when one matches it always
+    /// applies, so the rules after it are not evaluated.
+    stops: Vec<bool>,
+}
+
+impl<'a> RulePrefilter<'a> {
+    /// Build the prefilter for `rules`, which are evaluated in this order.
+    pub fn new(rules: Vec<(&'a LogRule, &'a CompiledRule)>) -> Result<Self, AnalysisError> {
+        let mut literal_index: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut literal_targets: Vec<Vec<usize>> = Vec::new();
+        let mut filtered = Vec::new();
+        let mut unfiltered = Vec::new();
+        let mut unfiltered_slots = Vec::new();
+        for (slot, (_, compiled)) in rules.iter().enumerate() {
+            for pattern in compiled.match_set.patterns() {
+                let Some(prefixes) = required_prefixes(pattern) else {
+                    unfiltered.push(pattern.as_str());
+                    unfiltered_slots.push(slot);
+                    continue;
+                };
+                let re =
+                    Regex::new(pattern).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+                let target = filtered.len();
+                filtered.push((slot, re));
+                for prefix in prefixes {
+                    let next = literal_targets.len();
+                    let i = *literal_index.entry(prefix).or_insert(next);
+                    if i == next {
+                        literal_targets.push(Vec::new());
+                    }
+                    literal_targets[i].push(target);
+                }
+            }
+        }
+
+        let literals = if literal_index.is_empty() {
+            None
+        } else {
+            let mut by_index: Vec<(Vec<u8>, usize)> = literal_index.into_iter().collect();
+            by_index.sort_by_key(|(_, i)| *i);
+            Some(
+                AhoCorasick::new(by_index.into_iter().map(|(lit, _)| lit))
+                    .map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?,
+            )
+        };
+        let unfiltered =
+            RegexSet::new(unfiltered).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+
+        Ok(Self {
+            stops: vec![false; rules.len()],
+            rules,
+            literals,
+            literal_targets,
+            filtered,
+            unfiltered,
+            unfiltered_slots,
+        })
+    }
+
+    /// Stop evaluating a line after the first of `stop_rule_ids` that
+    /// matches it, unless that rule has a guard, which may yet not hold.
+    pub fn with_stop_rules(mut self, stop_rule_ids: &HashSet<u64>) -> Self {
+        for (stop, (rule, _)) in self.stops.iter_mut().zip(&self.rules) {
+            *stop = rule.guard.is_none() && stop_rule_ids.contains(&rule.id);
+        }
+        self
+    }
+
+    /// Hits of each rule's positive match patterns on `text`.
+    fn hits(&self, text: &str) -> Vec<usize> {
+        let mut hits = vec![0; self.rules.len()];
+        if let Some(literals) = &self.literals {
+            let mut checked = vec![false; self.filtered.len()];
+            for m in literals.find_overlapping_iter(text) {
+                for &target in &self.literal_targets[m.pattern().as_usize()] {
+                    if std::mem::replace(&mut checked[target], true) {
+                        continue;
+                    }
+                    let (slot, re) = &self.filtered[target];
+                    if re.is_match(text) {
+                        hits[*slot] += 1;
+                    }
+                }
+            }
+        }
+        if !self.unfiltered_slots.is_empty() {
+            for i in self.unfiltered.matches(text).iter() {
+                hits[self.unfiltered_slots[i]] += 1;
+            }
+        }
+        hits
+    }
+
+    /// The rules matching `line`, in order, with their extracted state, up
+    /// to the first unguarded stop rule. The same as calling `evaluate_rule`
+    /// for each.
+    pub fn evaluate(&self, line: &LogLine) -> Vec<(u64, HashMap<String, StateValue>)> {
+        let hits = self.hits(&line.content);
+        let mut out = Vec::new();
+        for (slot, ((rule, compiled), hits)) in self.rules.iter().zip(hits).enumerate() {
+            if !rule_matched(compiled, hits, &line.content) {
+                continue;
+            }
+            out.push((rule.id, extract_state(rule, line, compiled)));
+            if self.stops[slot] {
+                break;
+            }
+        }
+        out
+    }
+}
+
+/// Per-rule totals shared by the parallel workers while profiling.
+#[derive(Default)]
+struct RuleCounters {
+    evaluations: AtomicU64,
+    matches: AtomicU64,
+    match_ns: AtomicU64,
+    extraction_ns: AtomicU64,
+}
+
+impl RuleCounters {
+    fn to_profile(&self, rule_id: u64) -> RuleProfile {
+        RuleProfile {
+            rule_id,
+            evaluations: self.evaluations.load(AtomicOrdering::Relaxed),
+            matches: self.matches.load(AtomicOrdering::Relaxed),
+            match_ns: self.match_ns.load(AtomicOrdering::Relaxed),
+            extraction_ns: self.extraction_ns.load(AtomicOrdering::Relaxed),
+        }
+    }
+}
+
+impl RulePrefilter<'_> {
+    /// `evaluate`, one rule at a time, timing each into `counters`.
+    fn evaluate_profiled(
+        &self,
+        line: &LogLine,
+        counters: &HashMap<u64, RuleCounters>,
+    ) -> Vec<(u64, HashMap<String, StateValue>)> {
+        let mut out = Vec::new();
+        for (slot, (rule, compiled)) in self.rules.iter().enumerate() {
+            let counter = &counters[&rule.id];
+            let started = Instant::now();
+            let hits = compiled.match_set.matches(&line.content).iter().count();
+            let matched = rule_matched(compiled, hits, &line.content);
+            let matched_at = Instant::now();
+            counter.evaluations.fetch_add(1, AtomicOrdering::Relaxed);
+            counter.match_ns.fetch_add(
+                (matched_at - started).as_nanos() as u64,
+                AtomicOrdering::Relaxed,
+            );
+            if !matched {
+                continue;
+            }
+            out.push((rule.id, extract_state(rule, line, compiled)));
+            counter.matches.fetch_add(1, AtomicOrdering::Relaxed);
+            counter.extraction_ns.fetch_add(
+                matched_at.elapsed().as_nanos() as u64,
+                AtomicOrdering::Relaxed,
+            );
+            if self.stops[slot] {
+                break;
+            }
+        }
+        out
+    }
+}
+
+/// Literals one of which every match of `pattern` starts with, when they are
+/// all long enough to be selective.
+fn required_prefixes(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    let hir = regex_syntax::Parser::new().parse(pattern).ok()?;
+    let seq = literal::Extractor::new().extract(&hir);
+    let literals = seq.literals()?;
+    if literals.is_empty()
+        || literals
+            .iter()
+            .any(|lit| lit.as_bytes().len() < MIN_PREFILTER_LITERAL)
+    {
+        return None;
+    }
+    Some(literals.iter().map(|lit| lit.as_bytes().to_vec()).collect())
+}
+
+/// Compile the plugin `id` names, checking it is of the kind `owner` runs.
+fn compile_plugin(
+    plugins: &[Plugin],
+    id: u64,
+    kind: PluginKind,
+    owner: &str,
+) -> Result<CompiledPlugin, AnalysisError> {
+    let plugin = plugins
+        .iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| AnalysisError::ParseError(format!("{owner} uses unknown plugin {id}")))?;
+    if plugin.kind != kind {
+        return Err(AnalysisError::ParseError(format!(
+            "{owner} cannot use plugin '{}', a {:?} plugin",
+            plugin.name, plugin.kind
+        )));
+    }
+    CompiledPlugin::new(plugin)
+        .map_err(|e| AnalysisError::ParseError(format!("invalid plugin '{}': {e}", plugin.name)))
+}
+
+/// Compile the parser plugins of the templates that name one, by template id.
+fn compile_parsers(
+    templates: &[SourceTemplate],
+    plugins: &[Plugin],
+) -> Result<HashMap<u64, CompiledPlugin>, AnalysisError> {
+    templates
+        .iter()
+        .filter_map(|t| Some((t, t.parser_plugin_id?)))
+        .map(|(t, id)| {
+            let owner = format!("template '{}'", t.name);
+            Ok((
+                t.id,
+                compile_plugin(plugins, id, PluginKind::LineParser, &owner)?,
+            ))
+        })
+        .collect()
+}
+
+/// Open a source's lines, parsed by its template's parser plugin if any.
+fn open_source(
+    source: &Source,
+    logs: &dyn LogSource,
+    template: &SourceTemplate,
+    ts_template: &TimestampTemplate,
+    parser: Option<&CompiledPlugin>,
+) -> Result<LogLineIterator, AnalysisError> {
+    let lines = LogLineIterator::from_reader(source, logs.open(source)?, template, ts_template)?;
+    match parser {
+        Some(parser) => parser
+            .instantiate()
+            .map(|parser| lines.with_parser(parser))
+            .map_err(AnalysisError::ParseError),
+        None => Ok(lines),
+    }
 }
 
 /// Read all lines from a source (sequential I/O), then evaluate rules in parallel.
 /// Returns a Vec of ProcessedLine in chronological order.
 fn process_source(
     source: &Source,
+    logs: &dyn LogSource,
     template: &SourceTemplate,
     ts_template: &TimestampTemplate,
-    rule_ids: &[u64],
-    rule_map: &HashMap<u64, &LogRule>,
-    compiled_map: &HashMap<u64, &CompiledRule>,
-) -> Result<Vec<ProcessedLine>, AnalysisError> {
+    parser: Option<&CompiledPlugin>,
+    prefilter: &RulePrefilter,
+    counters: Option<&HashMap<u64, RuleCounters>>,
+) -> Result<(Vec<ProcessedLine>, SourceProfile), AnalysisError> {
     // Step 1: sequential I/O — read all lines
+    let started = Instant::now();
     let lines: Vec<LogLine> =
-        LogLineIterator::new(source, template, ts_template)?.collect::<Result<Vec<_>, _>>()?;
+        open_source(source, logs, template, ts_template, parser)?.collect::<Result<Vec<_>, _>>()?;
+    let source_profile = SourceProfile {
+        source_id: source.id,
+        lines: lines.len() as u64,
+        parse_ns: started.elapsed().as_nanos() as u64,
+    };
 
-    let is_json = template.json_timestamp_field.is_some();
+    // Parser plugin fields are applied as JSON fields are
+    let is_json = template.json_timestamp_field.is_some() || parser.is_some();
 
     // Step 2: parallel rule evaluation (rayon)
     let processed: Vec<ProcessedLine> = lines
         .into_par_iter()
         .map(|mut line| {
-            let mut rule_matches = Vec::new();
-            for rule_id in rule_ids {
-                if let (Some(rule), Some(compiled)) =
-                    (rule_map.get(rule_id), compiled_map.get(rule_id))
-                    && let Some(extracted) = evaluate_rule(rule, &line, compiled)
-                {
-                    rule_matches.push((*rule_id, extracted));
-                }
-            }
+            let rule_matches = match counters {
+                Some(counters) => prefilter.evaluate_profiled(&line, counters),
+                None => prefilter.evaluate(&line),
+            };
             let json_fields = if is_json {
                 if let Some(serde_json::Value::Object(map)) = line.cached_json.take() {
                     let mut fields = HashMap::new();
@@ -776,18 +1280,129 @@ fn process_source(
         })
         .collect();
 
-    Ok(processed)
+    Ok((processed, source_profile))
 }
 
 // ---------------------------------------------------------------------------
 // State manager
 // ---------------------------------------------------------------------------
 
+/// A derived key with its expression parsed and its inputs resolved to
+/// source ids.
+struct CompiledDerived {
+    source_id: u64,
+    state_key: String,
+    expr: Expr,
+    inputs: Vec<(u64, String)>,
+}
+
+/// Parse derived keys and order them so every key comes after the derived
+/// keys it reads. Rejects unparsable expressions, two definitions of one key
+/// and cycles.
+fn order_derived_keys(keys: &[DerivedKey]) -> Result<Vec<(&DerivedKey, Expr)>, AnalysisError> {
+    let mut parsed = Vec::with_capacity(keys.len());
+    let mut index: HashMap<(&str, &str), usize> = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        let expr = Expr::parse_in(&key.expression, &key.source_name).map_err(|e| {
+            AnalysisError::ParseError(format!(
+                "derived key '{}.{}': {e}",
+                key.source_name, key.state_key
+            ))
+        })?;
+        if expr.refs().is_empty() {
+            return Err(AnalysisError::ParseError(format!(
+                "derived key '{}.{}' does not read any state",
+                key.source_name, key.state_key
+            )));
+        }
+        if index
+            .insert((&key.source_name, &key.state_key), i)
+            .is_some()
+        {
+            return Err(AnalysisError::ParseError(format!(
+                "derived key '{}.{}' is defined twice",
+                key.source_name, key.state_key
+            )));
+        }
+        parsed.push(expr);
+    }
+
+    // Depth-first topological sort; `visiting` marks the current path.
+    fn visit(
+        i: usize,
+        keys: &[DerivedKey],
+        parsed: &[Expr],
+        index: &HashMap<(&str, &str), usize>,
+        visiting: &mut Vec<bool>,
+        done: &mut Vec<bool>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), AnalysisError> {
+        if done[i] {
+            return Ok(());
+        }
+        if visiting[i] {
+            return Err(AnalysisError::ParseError(format!(
+                "derived key '{}.{}' depends on itself",
+                keys[i].source_name, keys[i].state_key
+            )));
+        }
+        visiting[i] = true;
+        for input in parsed[i].refs() {
+            if let Some(&j) = index.get(&input) {
+                visit(j, keys, parsed, index, visiting, done, order)?;
+            }
+        }
+        visiting[i] = false;
+        done[i] = true;
+        order.push(i);
+        Ok(())
+    }
+
+    let mut visiting = vec![false; keys.len()];
+    let mut done = vec![false; keys.len()];
+    let mut order = Vec::with_capacity(keys.len());
+    for i in 0..keys.len() {
+        visit(
+            i,
+            keys,
+            &parsed,
+            &index,
+            &mut visiting,
+            &mut done,
+            &mut order,
+        )?;
+    }
+    let mut parsed: Vec<Option<Expr>> = parsed.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|i| {
+            (
+                &keys[i],
+                parsed[i].take().expect("each key is ordered once"),
+            )
+        })
+        .collect())
+}
+
+/// Check a project's derived keys the way an analysis does.
+pub fn validate_derived_keys(keys: &[DerivedKey]) -> Result<(), AnalysisError> {
+    order_derived_keys(keys).map(drop)
+}
+
 /// Manages per-source state.
 pub struct StateManager {
     pub per_source_state: HashMap<u64, Arc<HashMap<String, TrackedValue>>>,
     pub source_names: HashMap<u64, String>,
     name_to_id: HashMap<String, u64>,
+    /// Running (count, sum) of `Average` keys, by source id and key.
+    means: HashMap<(u64, String), (u64, f64)>,
+    /// When each key set by an extraction with a TTL expires.
+    deadlines: HashMap<(u64, String), NaiveDateTime>,
+    /// Deadlines in expiry order. Entries superseded by a refresh or a
+    /// removal stay queued and are skipped when they come due.
+    expiry_queue: BinaryHeap<Reverse<(NaiveDateTime, u64, String)>>,
+    /// Derived keys in dependency order.
+    derived: Vec<CompiledDerived>,
 }
 
 impl StateManager {
@@ -802,75 +1417,173 @@ impl StateManager {
             per_source_state: HashMap::new(),
             source_names,
             name_to_id,
+            means: HashMap::new(),
+            deadlines: HashMap::new(),
+            expiry_queue: BinaryHeap::new(),
+            derived: Vec::new(),
+        }
+    }
+
+    /// Install the derived keys `recompute_derived` maintains. Keys on
+    /// sources outside this analysis are ignored.
+    pub fn set_derived_keys(&mut self, keys: &[DerivedKey]) -> Result<(), AnalysisError> {
+        self.derived = order_derived_keys(keys)?
+            .into_iter()
+            .filter_map(|(key, expr)| {
+                let source_id = self.source_id(&key.source_name)?;
+                let inputs = expr
+                    .refs()
+                    .into_iter()
+                    .filter_map(|(source, k)| Some((self.source_id(source)?, k.to_string())))
+                    .collect();
+                Some(CompiledDerived {
+                    source_id,
+                    state_key: key.state_key.clone(),
+                    expr,
+                    inputs,
+                })
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Recompute the derived keys that read any key in `changes`, stamping
+    /// them with `line`. Returns the resulting changes, which may feed later
+    /// derived keys in turn.
+    pub fn recompute_derived(
+        &mut self,
+        line: &LogLine,
+        changes: &[StateChange],
+    ) -> Vec<StateChange> {
+        let mut out = Vec::new();
+        if self.derived.is_empty() || changes.is_empty() {
+            return out;
+        }
+        let mut changed: HashSet<(u64, String)> = changes
+            .iter()
+            .map(|c| (c.source_id, c.state_key.clone()))
+            .collect();
+        for i in 0..self.derived.len() {
+            let d = &self.derived[i];
+            if !d.inputs.iter().any(|input| changed.contains(input)) {
+                continue;
+            }
+            let new = d.expr.eval(self);
+            let state = Arc::make_mut(self.per_source_state.entry(d.source_id).or_default());
+            let old = state.get(&d.state_key).map(|t| t.value.clone());
+            if old == new {
+                continue;
+            }
+            match &new {
+                Some(value) => {
+                    state.insert(
+                        d.state_key.clone(),
+                        TrackedValue {
+                            value: value.clone(),
+                            set_at: line.timestamp,
+                            rule_id: 0,
+                            line_number: Some(line.line_number),
+                        },
+                    );
+                }
+                None => {
+                    state.remove(&d.state_key);
+                }
+            }
+            changed.insert((d.source_id, d.state_key.clone()));
+            out.push(StateChange {
+                timestamp: line.timestamp,
+                source_id: d.source_id,
+                source_name: self
+                    .source_names
+                    .get(&d.source_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                state_key: d.state_key.clone(),
+                old_value: old,
+                new_value: new,
+                rule_id: 0,
+            });
         }
+        out
     }
 
-    /// Apply extractions to a source's state, respecting extraction rules for mode/type.
+    /// Apply extractions from `rule_id` matching `line` to the line's source state,
+    /// respecting extraction rules for mode/type. Values are stamped with the
+    /// line and rule that set them.
     /// Returns a list of (key, old_value, new_value) for each actual change.
     pub fn apply_mutations(
         &mut self,
-        source_id: u64,
+        line: &LogLine,
+        rule_id: u64,
         extractions: &HashMap<String, StateValue>,
         rules: &[ExtractionRule],
-        timestamp: NaiveDateTime,
     ) -> Vec<(String, Option<StateValue>, Option<StateValue>)> {
-        let state = Arc::make_mut(self.per_source_state.entry(source_id).or_default());
+        let state = Arc::make_mut(self.per_source_state.entry(line.source_id).or_default());
+        let track = |value: StateValue| TrackedValue {
+            value,
+            set_at: line.timestamp,
+            rule_id,
+            line_number: Some(line.line_number),
+        };
         let mut changes = Vec::new();
 
         for rule in rules {
-            match rule.extraction_type {
+            let new_val = match rule.extraction_type {
                 ExtractionType::Clear => {
+                    let slot = (line.source_id, rule.state_key.clone());
+                    self.deadlines.remove(&slot);
+                    self.means.remove(&slot);
                     let old = state.remove(&rule.state_key).map(|t| t.value);
                     if old.is_some() {
                         changes.push((rule.state_key.clone(), old, None));
                     }
+                    continue;
                 }
-                ExtractionType::Static => {
-                    if let Some(val) = &rule.static_value {
-                        let new_val = StateValue::String(val.clone());
-                        let old = state.get(&rule.state_key).map(|t| t.value.clone());
-                        match rule.mode {
-                            ExtractionMode::Replace => {
-                                state.insert(
-                                    rule.state_key.clone(),
-                                    TrackedValue {
-                                        value: new_val,
-                                        set_at: timestamp,
-                                    },
-                                );
-                            }
-                            ExtractionMode::Accumulate => {
-                                accumulate(state, &rule.state_key, new_val, timestamp);
-                            }
-                        }
-                        let new = state.get(&rule.state_key).map(|t| t.value.clone());
-                        if old != new {
-                            changes.push((rule.state_key.clone(), old, new));
-                        }
-                    }
+                // `evaluate_rule` supplies the typed, transformed value.
+                ExtractionType::Static => extractions
+                    .get(&rule.state_key)
+                    .cloned()
+                    .or_else(|| rule.static_value.clone().map(StateValue::String)),
+                ExtractionType::Parsed => extractions.get(&rule.state_key).cloned(),
+            };
+            let Some(new_val) = new_val else {
+                continue;
+            };
+            let old = state.get(&rule.state_key).map(|t| t.value.clone());
+            // Any other mode overwrites the key, so a later average restarts.
+            let mean = if matches!(rule.mode, ExtractionMode::Average) {
+                Some(
+                    self.means
+                        .entry((line.source_id, rule.state_key.clone()))
+                        .or_default(),
+                )
+            } else {
+                self.means.remove(&(line.source_id, rule.state_key.clone()));
+                None
+            };
+            if let Some(merged) = merge(&rule.mode, old.as_ref(), new_val, mean) {
+                state.insert(rule.state_key.clone(), track(merged));
+            }
+            let new = state.get(&rule.state_key).map(|t| t.value.clone());
+            if old != new {
+                changes.push((rule.state_key.clone(), old, new));
+            }
+            // Every firing restarts the TTL; a rule without one, or with one
+            // running past the last representable time, makes the value
+            // permanent again.
+            let slot = (line.source_id, rule.state_key.clone());
+            let deadline = rule.ttl_ms.and_then(|ttl| {
+                let ttl = chrono::TimeDelta::try_milliseconds(i64::try_from(ttl).ok()?)?;
+                line.timestamp.checked_add_signed(ttl)
+            });
+            match deadline {
+                Some(deadline) => {
+                    self.deadlines.insert(slot.clone(), deadline);
+                    self.expiry_queue.push(Reverse((deadline, slot.0, slot.1)));
                 }
-                ExtractionType::Parsed => {
-                    if let Some(val) = extractions.get(&rule.state_key) {
-                        let old = state.get(&rule.state_key).map(|t| t.value.clone());
-                        match rule.mode {
-                            ExtractionMode::Replace => {
-                                state.insert(
-                                    rule.state_key.clone(),
-                                    TrackedValue {
-                                        value: val.clone(),
-                                        set_at: timestamp,
-                                    },
-                                );
-                            }
-                            ExtractionMode::Accumulate => {
-                                accumulate(state, &rule.state_key, val.clone(), timestamp);
-                            }
-                        }
-                        let new = state.get(&rule.state_key).map(|t| t.value.clone());
-                        if old != new {
-                            changes.push((rule.state_key.clone(), old, new));
-                        }
-                    }
+                None => {
+                    self.deadlines.remove(&slot);
                 }
             }
         }
@@ -878,74 +1591,457 @@ impl StateManager {
         changes
     }
 
-    /// Resolve the value of a source's state key by source name.
-    pub fn get_state_by_name(&self, source_name: &str, key: &str) -> Option<&StateValue> {
-        let id = self.name_to_id.get(source_name)?;
-        self.per_source_state.get(id)?.get(key).map(|t| &t.value)
+    /// Run `rule_id`'s script on `line` and apply the state it returns as
+    /// replacements and clears, recording the values it set in `extracted`.
+    /// A script that fails or runs out of time changes nothing.
+    /// Returns changes as [`StateManager::apply_mutations`] does.
+    pub fn apply_script(
+        &mut self,
+        runner: &ScriptRunner,
+        script: &CompiledScript,
+        line: &LogLine,
+        rule_id: u64,
+        fields: Option<&HashMap<String, StateValue>>,
+        extracted: &mut HashMap<String, StateValue>,
+    ) -> Vec<(String, Option<StateValue>, Option<StateValue>)> {
+        match runner.run(script, self.script_input(line, fields)) {
+            Ok(output) => self.apply_computed(line, rule_id, output, extracted),
+            Err(_) => Vec::new(),
+        }
     }
 
-    /// Snapshot all state, keyed by source name.
-    pub fn snapshot(&self) -> HashMap<String, Arc<HashMap<String, TrackedValue>>> {
-        let mut snap = HashMap::new();
-        for (id, state) in &self.per_source_state {
-            if let Some(name) = self.source_names.get(id) {
-                snap.insert(name.clone(), Arc::clone(state));
-            }
+    /// Run `rule_id`'s extractor plugin on `line`, as
+    /// [`StateManager::apply_script`] runs a script. A call that fails or
+    /// runs out of fuel changes nothing.
+    pub fn apply_plugin(
+        &mut self,
+        plugin: &mut PluginInstance,
+        line: &LogLine,
+        rule_id: u64,
+        fields: Option<&HashMap<String, StateValue>>,
+        extracted: &mut HashMap<String, StateValue>,
+    ) -> Vec<(String, Option<StateValue>, Option<StateValue>)> {
+        match plugin.extract(self.script_input(line, fields)) {
+            Ok(output) => self.apply_computed(line, rule_id, output, extracted),
+            Err(_) => Vec::new(),
         }
-        snap
     }
-}
 
-/// Accumulate a value into existing state.
-fn accumulate(
-    state: &mut HashMap<String, TrackedValue>,
-    key: &str,
-    new_val: StateValue,
-    timestamp: NaiveDateTime,
-) {
-    if let Some(existing) = state.get(key) {
-        let merged = match (&existing.value, &new_val) {
-            (StateValue::String(a), StateValue::String(b)) => {
-                StateValue::String(format!("{a},{b}"))
-            }
-            (StateValue::Integer(a), StateValue::Integer(b)) => StateValue::Integer(a + b),
-            (StateValue::Float(a), StateValue::Float(b)) => StateValue::Float(a + b),
-            (StateValue::Integer(a), StateValue::Float(b)) => StateValue::Float(*a as f64 + b),
-            (StateValue::Float(a), StateValue::Integer(b)) => StateValue::Float(a + *b as f64),
-            _ => new_val,
-        };
-        state.insert(
-            key.to_string(),
-            TrackedValue {
-                value: merged,
-                set_at: timestamp,
-            },
-        );
-    } else {
-        state.insert(
-            key.to_string(),
-            TrackedValue {
-                value: new_val,
-                set_at: timestamp,
-            },
-        );
+    fn script_input<'a>(
+        &'a self,
+        line: &'a LogLine,
+        fields: Option<&'a HashMap<String, StateValue>>,
+    ) -> ScriptInput<'a> {
+        let state = self.per_source_state.get(&line.source_id);
+        ScriptInput {
+            line,
+            source_name: self
+                .source_names
+                .get(&line.source_id)
+                .map_or("", String::as_str),
+            fields,
+            state: state
+                .into_iter()
+                .flat_map(|state| state.iter())
+                .map(|(key, tracked)| (key.as_str(), &tracked.value))
+                .collect(),
+        }
     }
-}
 
-// ---------------------------------------------------------------------------
-// Pattern evaluator
+    /// Apply state computed by a script or plugin as replacements and clears.
+    fn apply_computed(
+        &mut self,
+        line: &LogLine,
+        rule_id: u64,
+        output: ScriptOutput,
+        extracted: &mut HashMap<String, StateValue>,
+    ) -> Vec<(String, Option<StateValue>, Option<StateValue>)> {
+        let mut values = HashMap::new();
+        let rules: Vec<ExtractionRule> = output
+            .into_iter()
+            .map(|(state_key, value)| {
+                let extraction_type = match value {
+                    Some(value) => {
+                        values.insert(state_key.clone(), value);
+                        ExtractionType::Parsed
+                    }
+                    None => ExtractionType::Clear,
+                };
+                ExtractionRule {
+                    id: 0,
+                    extraction_type,
+                    state_key,
+                    pattern: None,
+                    static_value: None,
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
+                }
+            })
+            .collect();
+        let changes = self.apply_mutations(line, rule_id, &values, &rules);
+        extracted.extend(values);
+        changes
+    }
+
+    /// Unset every value whose TTL ran out at or before `now`, in expiry
+    /// order. Each expiry is reported as a change to `None` at its deadline,
+    /// attributed to the rule that last set the value.
+    pub fn expire(&mut self, now: NaiveDateTime) -> Vec<StateChange> {
+        let mut expired = Vec::new();
+        while let Some(Reverse((deadline, _, _))) = self.expiry_queue.peek()
+            && *deadline <= now
+        {
+            let Some(Reverse((deadline, source_id, key))) = self.expiry_queue.pop() else {
+                break;
+            };
+            let slot = (source_id, key);
+            if self.deadlines.get(&slot) != Some(&deadline) {
+                continue;
+            }
+            self.deadlines.remove(&slot);
+            self.means.remove(&slot);
+            let (source_id, key) = slot;
+            let Some(old) = self
+                .per_source_state
+                .get_mut(&source_id)
+                .and_then(|state| Arc::make_mut(state).remove(&key))
+            else {
+                continue;
+            };
+            expired.push(StateChange {
+                timestamp: deadline,
+                source_id,
+                source_name: self
+                    .source_names
+                    .get(&source_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                state_key: key,
+                old_value: Some(old.value),
+                new_value: None,
+                rule_id: old.rule_id,
+            });
+        }
+        expired
+    }
+
+    /// Resolve the value of a source's state key by source name.
+    pub fn get_state_by_name(&self, source_name: &str, key: &str) -> Option<&StateValue> {
+        self.get_tracked_by_name(source_name, key).map(|t| &t.value)
+    }
+
+    /// Resolve a source's state key by source name, including where it was set.
+    pub fn get_tracked_by_name(&self, source_name: &str, key: &str) -> Option<&TrackedValue> {
+        let id = self.name_to_id.get(source_name)?;
+        self.per_source_state.get(id)?.get(key)
+    }
+
+    /// Look up a source's id by name.
+    pub fn source_id(&self, source_name: &str) -> Option<u64> {
+        self.name_to_id.get(source_name).copied()
+    }
+
+    /// Snapshot all state, keyed by source name.
+    pub fn snapshot(&self) -> HashMap<String, Arc<HashMap<String, TrackedValue>>> {
+        let mut snap = HashMap::new();
+        for (id, state) in &self.per_source_state {
+            if let Some(name) = self.source_names.get(id) {
+                snap.insert(name.clone(), Arc::clone(state));
+            }
+        }
+        snap
+    }
+}
+
+/// Combine a newly extracted value with a key's current value according to
+/// the extraction mode. `None` leaves the current value, and when it was
+/// set, untouched. `mean` is the key's running (count, sum) for `Average`.
+fn merge(
+    mode: &ExtractionMode,
+    existing: Option<&StateValue>,
+    new_val: StateValue,
+    mean: Option<&mut (u64, f64)>,
+) -> Option<StateValue> {
+    match mode {
+        ExtractionMode::Replace => Some(new_val),
+        ExtractionMode::Accumulate => Some(match existing {
+            Some(existing) => accumulate(existing, new_val),
+            None => new_val,
+        }),
+        ExtractionMode::Count => Some(StateValue::Integer(match existing {
+            Some(StateValue::Integer(n)) => n + 1,
+            _ => 1,
+        })),
+        ExtractionMode::Min | ExtractionMode::Max => {
+            let keep = if matches!(mode, ExtractionMode::Min) {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+            match existing {
+                Some(existing) => (new_val.partial_cmp(existing) == Some(keep)).then_some(new_val),
+                None => Some(new_val),
+            }
+        }
+        ExtractionMode::Average => {
+            let (count, sum) = mean?;
+            let x = match new_val {
+                StateValue::Integer(i) => i as f64,
+                StateValue::Float(f) => f,
+                _ => return None,
+            };
+            *count += 1;
+            *sum += x;
+            Some(StateValue::Float(*sum / *count as f64))
+        }
+        ExtractionMode::DistinctSet => match existing {
+            Some(StateValue::Set(items)) if items.contains(&new_val) => None,
+            Some(StateValue::Set(items)) => {
+                let mut items = items.clone();
+                items.push(new_val);
+                Some(StateValue::Set(items))
+            }
+            _ => Some(StateValue::Set(vec![new_val])),
+        },
+        ExtractionMode::List { cap } => {
+            let mut items = match existing {
+                Some(StateValue::List(items)) => items.clone(),
+                _ => Vec::new(),
+            };
+            items.push(new_val);
+            if items.len() > *cap {
+                items.drain(..items.len() - cap);
+            }
+            Some(StateValue::List(items))
+        }
+        ExtractionMode::FirstSeen => existing.is_none().then_some(new_val),
+    }
+}
+
+/// Add numbers, saturating at the integer bounds, or comma-join strings;
+/// other combinations take the new value.
+fn accumulate(existing: &StateValue, new_val: StateValue) -> StateValue {
+    match (existing, &new_val) {
+        (StateValue::String(a), StateValue::String(b)) => StateValue::String(format!("{a},{b}")),
+        (StateValue::Integer(a), StateValue::Integer(b)) => {
+            StateValue::Integer(a.saturating_add(*b))
+        }
+        (StateValue::Float(a), StateValue::Float(b)) => StateValue::Float(a + b),
+        (StateValue::Integer(a), StateValue::Float(b)) => StateValue::Float(*a as f64 + b),
+        (StateValue::Float(a), StateValue::Integer(b)) => StateValue::Float(a + *b as f64),
+        (StateValue::Duration(a), StateValue::Duration(b)) => {
+            StateValue::Duration(a.saturating_add(*b))
+        }
+        _ => new_val,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Pattern evaluator
 // ---------------------------------------------------------------------------
 
+/// Events counted towards one threshold step, bucketed by correlation group.
+/// Ordered so that when several groups cross the bound at once, the same one
+/// is picked on every run.
+type ThresholdWindow = BTreeMap<Option<String>, VecDeque<CountedEvent>>;
+
 /// Evaluates ordered-predicate patterns against the current state.
 pub struct PatternEvaluator {
     /// Current progress index per pattern (index into predicates).
     progress: Vec<usize>,
+    /// Sliding event windows for threshold steps, keyed by (pattern index, step index).
+    windows: HashMap<(usize, usize), ThresholdWindow>,
+    /// Events consumed by threshold steps during each pattern's current attempt.
+    counted: Vec<Vec<CountedEvent>>,
+    /// Provenance of the steps satisfied so far in each pattern's current attempt.
+    steps: Vec<Vec<StepProvenance>>,
+    /// Whether to record each pattern's furthest attempt for near-miss reports.
+    track_near_misses: bool,
+    /// Furthest attempt per pattern, recorded when `track_near_misses` is set.
+    furthest: Vec<Option<NearMiss>>,
+    /// Whether each pattern has fired at least once.
+    fired: Vec<bool>,
+    /// Log time each pattern last fired, for cooldowns.
+    last_fired: Vec<Option<NaiveDateTime>>,
+    /// Whether any pattern uses an edge operator (keeps `line_changes` empty otherwise).
+    has_edges: bool,
+    /// State changes produced by the most recently observed line.
+    line_changes: Vec<StateChange>,
+    /// Line number of the most recently observed line.
+    line_number: u64,
+    /// Timestamp of the most recently observed line.
+    now: Option<NaiveDateTime>,
+    /// `Matches` operands compiled once, keyed by their source text.
+    regexes: HashMap<String, Regex>,
+    /// `Expr` operands parsed once, keyed by their source text.
+    exprs: HashMap<String, Expr>,
+    /// Index of each pattern's parent, when the parent is being evaluated too.
+    parents: Vec<Option<usize>>,
+    /// Evaluation order with parents ahead of their children, so a child
+    /// completing on the same line as its parent sees the parent's match.
+    order: Vec<usize>,
 }
 
 impl PatternEvaluator {
-    pub fn new(patterns: &[Pattern]) -> Self {
-        Self {
+    pub fn new(patterns: &[Pattern]) -> Result<Self, AnalysisError> {
+        let has_edges = patterns
+            .iter()
+            .flat_map(|p| &p.predicates)
+            .any(|pred| pred.operator.is_edge());
+        let mut windows = HashMap::new();
+        let mut regexes = HashMap::new();
+        let mut exprs = HashMap::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            if pattern
+                .parent
+                .as_ref()
+                .and_then(|link| link.window_ms)
+                .is_some_and(|ms| ms > MAX_SPAN_MS)
+            {
+                return Err(AnalysisError::ParseError(format!(
+                    "pattern '{}': parent window must be at most {MAX_SPAN_MS} ms",
+                    pattern.name
+                )));
+            }
+            for (j, pred) in pattern.predicates.iter().enumerate() {
+                validate_predicate(pred)?;
+                if pred.threshold.is_some() {
+                    windows.insert((i, j), ThresholdWindow::new());
+                }
+                compile_operand(pred, &mut regexes, &mut exprs)?;
+            }
+        }
+        let index_by_id: HashMap<u64, usize> = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.id, i))
+            .collect();
+        let parents: Vec<Option<usize>> = patterns
+            .iter()
+            .map(|p| {
+                p.parent
+                    .as_ref()
+                    .and_then(|parent| index_by_id.get(&parent.pattern_id).copied())
+            })
+            .collect();
+        let order = parents_first_order(&parents);
+        Ok(Self {
             progress: vec![0; patterns.len()],
+            windows,
+            counted: vec![Vec::new(); patterns.len()],
+            steps: vec![Vec::new(); patterns.len()],
+            track_near_misses: false,
+            furthest: vec![None; patterns.len()],
+            fired: vec![false; patterns.len()],
+            last_fired: vec![None; patterns.len()],
+            has_edges,
+            line_changes: Vec::new(),
+            line_number: 0,
+            now: None,
+            regexes,
+            exprs,
+            parents,
+            order,
+        })
+    }
+
+    fn env(&self) -> PredicateEnv<'_> {
+        PredicateEnv {
+            regexes: &self.regexes,
+            exprs: &self.exprs,
+            now: self.now,
+        }
+    }
+
+    /// Record the rule matches and state changes produced by one log line.
+    /// Must be called before `evaluate_patterns` for that line so threshold
+    /// and edge steps see the line's events.
+    pub fn observe_line(
+        &mut self,
+        patterns: &[Pattern],
+        state: &StateManager,
+        line: &LogLine,
+        rule_ids: &[u64],
+        changes: &[StateChange],
+    ) {
+        self.now = Some(line.timestamp);
+        self.line_number = line.line_number;
+        if self.has_edges {
+            self.line_changes.clear();
+            self.line_changes.extend_from_slice(changes);
+        }
+        if self.windows.is_empty() {
+            return;
+        }
+        let Some(source_name) = state.source_names.get(&line.source_id) else {
+            return;
+        };
+
+        let env = PredicateEnv {
+            regexes: &self.regexes,
+            exprs: &self.exprs,
+            now: self.now,
+        };
+        for (&(i, j), window) in self.windows.iter_mut() {
+            let pred = &patterns[i].predicates[j];
+            let Some(threshold) = &pred.threshold else {
+                continue;
+            };
+            if &pred.source_name != source_name {
+                continue;
+            }
+
+            let mut events = Vec::new();
+            match threshold.rule_id {
+                Some(rule_id) => {
+                    for _ in rule_ids.iter().filter(|&&id| id == rule_id) {
+                        events.push((rule_id, None));
+                    }
+                }
+                None => {
+                    for change in changes.iter().filter(|c| c.state_key == pred.state_key) {
+                        let qualifies = if pred.operator.is_edge() {
+                            change_satisfies(pred, change, state, &env)
+                        } else {
+                            compare_operand(
+                                pred,
+                                change.new_value.as_ref(),
+                                Some(change.timestamp),
+                                state,
+                                &env,
+                            )
+                        };
+                        if qualifies {
+                            events.push((change.rule_id, change.new_value.clone()));
+                        }
+                    }
+                }
+            }
+            if events.is_empty() {
+                continue;
+            }
+
+            let group = threshold
+                .group_by
+                .as_ref()
+                .and_then(|key| state.get_state_by_name(&pred.source_name, key))
+                .cloned();
+            let bucket = window
+                .entry(group.as_ref().map(|g| g.to_string()))
+                .or_default();
+            for (rule_id, value) in events {
+                bucket.push_back(CountedEvent {
+                    timestamp: line.timestamp,
+                    source_id: line.source_id,
+                    rule_id,
+                    line_number: line.line_number,
+                    value,
+                    group: group.clone(),
+                });
+            }
+            prune_window(bucket, line.timestamp, threshold.window_ms);
         }
     }
 
@@ -957,7 +2053,9 @@ impl PatternEvaluator {
     ) -> Vec<PatternMatch> {
         let mut matches = Vec::new();
 
-        for (i, pattern) in patterns.iter().enumerate() {
+        for k in 0..self.order.len() {
+            let i = self.order[k];
+            let pattern = &patterns[i];
             if pattern.predicates.is_empty() {
                 continue;
             }
@@ -965,57 +2063,507 @@ impl PatternEvaluator {
             let progress = self.progress[i];
 
             // Check if the current predicate (at progress index) is satisfied
-            let current_pred = &pattern.predicates[progress];
-            if evaluate_predicate(current_pred, state) {
-                // Verify all previous predicates still hold
-                let mut all_previous_hold = true;
-                for prev_idx in 0..progress {
-                    if !evaluate_predicate(&pattern.predicates[prev_idx], state) {
-                        all_previous_hold = false;
-                        break;
-                    }
-                }
+            if self.step_satisfied(i, progress, &pattern.predicates[progress], state) {
+                // Verify all previous predicates still hold. Threshold and
+                // edge steps describe past events, so once crossed they stay
+                // satisfied.
+                let all_previous_hold = pattern.predicates[..progress]
+                    .iter()
+                    .all(|pred| is_latched(pred) || evaluate_predicate(pred, state, &self.env()));
 
                 if !all_previous_hold {
                     // Previous predicate no longer holds, reset progress
                     self.progress[i] = 0;
+                    self.counted[i].clear();
+                    self.steps[i].clear();
                 } else {
                     // Advance progress
+                    let step = self.provenance(i, progress, &pattern.predicates[progress], state);
+                    self.steps[i].push(step);
                     self.progress[i] = progress + 1;
 
                     // Check if all predicates are satisfied
                     if self.progress[i] == pattern.predicates.len() {
-                        matches.push(PatternMatch {
-                            pattern_id: pattern.id,
-                            timestamp: chrono::Utc::now().naive_utc(),
-                            state_snapshot: state.snapshot(),
-                        });
+                        let counted_events = std::mem::take(&mut self.counted[i]);
+                        let steps = std::mem::take(&mut self.steps[i]);
+                        // Completions inside the cooldown, or outside the
+                        // parent's window, are dropped
+                        if !self.in_cooldown(i, pattern) && self.parent_window_open(i, pattern) {
+                            matches.push(PatternMatch {
+                                pattern_id: pattern.id,
+                                timestamp: chrono::Utc::now().naive_utc(),
+                                state_snapshot: state.snapshot(),
+                                counted_events,
+                                steps,
+                                outcome: render_outcome(&pattern.outcome, state),
+                                refines: self.parents[i].map(|p| patterns[p].id),
+                                superseded: false,
+                            });
+                            self.last_fired[i] = self.now;
+                            self.fired[i] = true;
+                        }
                         // Reset for potential re-firing
                         self.progress[i] = 0;
                     }
                 }
             }
+
+            if self.track_near_misses && !self.fired[i] {
+                let reached = self.furthest[i].as_ref().map_or(0, |nm| nm.steps_reached);
+                if self.progress[i] > reached {
+                    self.furthest[i] = Some(self.near_miss(i, self.progress[i], pattern, state));
+                }
+            }
         }
 
         matches
     }
+
+    /// Near-miss reports for every pattern that never fired. Patterns that
+    /// never satisfied a step are reported against the final state.
+    pub fn near_misses(&mut self, patterns: &[Pattern], state: &StateManager) -> Vec<NearMiss> {
+        let mut out = Vec::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            if self.fired[i] || pattern.predicates.is_empty() {
+                continue;
+            }
+            match self.furthest[i].take() {
+                Some(nm) => out.push(nm),
+                None => {
+                    // Describe the first step as it stands at the end of the run.
+                    let mut nm = self.near_miss(i, 0, pattern, state);
+                    nm.reached_at = None;
+                    out.push(nm);
+                }
+            }
+        }
+        out
+    }
+
+    /// Describe pattern `i`'s current attempt up to `failed_step`: the steps
+    /// satisfied so far and the actual vs expected values of the step it is
+    /// waiting on.
+    fn near_miss(
+        &self,
+        i: usize,
+        failed_step: usize,
+        pattern: &Pattern,
+        state: &StateManager,
+    ) -> NearMiss {
+        let pred = &pattern.predicates[failed_step];
+        let (actual, expected) = match &pred.threshold {
+            Some(threshold) => {
+                let largest = self
+                    .windows
+                    .get(&(i, failed_step))
+                    .and_then(|w| w.values().map(|b| b.len()).max())
+                    .unwrap_or(0);
+                (
+                    Some(StateValue::Integer(largest as i64)),
+                    Some(StateValue::Integer(threshold.count as i64)),
+                )
+            }
+            None => (
+                state
+                    .get_state_by_name(&pred.source_name, &pred.state_key)
+                    .cloned(),
+                match pred.operator {
+                    Operator::Exists | Operator::NotExists => None,
+                    _ => resolve_operand(&pred.operand, state, &self.env()),
+                },
+            ),
+        };
+        NearMiss {
+            pattern_id: pattern.id,
+            steps_reached: failed_step,
+            total_steps: pattern.predicates.len(),
+            reached_at: self.now,
+            state_snapshot: state.snapshot(),
+            steps: self.steps[i][..failed_step].to_vec(),
+            failed_step,
+            failed_predicate: pred.clone(),
+            actual,
+            expected,
+        }
+    }
+
+    /// Whether pattern `i` fired less than its cooldown ago. A cooldown
+    /// ending past the latest representable time never ends.
+    fn in_cooldown(&self, i: usize, pattern: &Pattern) -> bool {
+        match (pattern.cooldown_ms, self.last_fired[i], self.now) {
+            (Some(cooldown_ms), Some(last), Some(now)) => i64::try_from(cooldown_ms)
+                .ok()
+                .and_then(chrono::TimeDelta::try_milliseconds)
+                .and_then(|cooldown| last.checked_add_signed(cooldown))
+                .is_none_or(|end| now < end),
+            _ => false,
+        }
+    }
+
+    /// Whether pattern `i` may report a match now: always for patterns
+    /// without a parent, otherwise only once the parent has matched and, if
+    /// the link has a window, no longer than that ago.
+    fn parent_window_open(&self, i: usize, pattern: &Pattern) -> bool {
+        let Some(p) = self.parents[i] else {
+            return true;
+        };
+        let window_ms = pattern.parent.as_ref().and_then(|link| link.window_ms);
+        match (self.last_fired[p], self.now, window_ms) {
+            (None, _, _) => false,
+            (Some(_), _, None) => true,
+            (Some(last), Some(now), Some(window_ms)) => i64::try_from(window_ms)
+                .ok()
+                .and_then(chrono::TimeDelta::try_milliseconds)
+                .and_then(|window| last.checked_add_signed(window))
+                .is_none_or(|end| now <= end),
+            (Some(_), None, Some(_)) => true,
+        }
+    }
+
+    /// Check a single step. Threshold steps consume the burst that crossed
+    /// the bound so the same events cannot satisfy the step twice. Edge steps
+    /// hold only on the line that produced a matching transition.
+    fn step_satisfied(
+        &mut self,
+        pattern_idx: usize,
+        step_idx: usize,
+        pred: &PatternPredicate,
+        state: &StateManager,
+    ) -> bool {
+        let Some(threshold) = &pred.threshold else {
+            if pred.operator.is_edge() {
+                return self
+                    .line_changes
+                    .iter()
+                    .any(|change| change_satisfies(pred, change, state, &self.env()));
+            }
+            return evaluate_predicate(pred, state, &self.env());
+        };
+        let (Some(now), Some(window)) = (self.now, self.windows.get_mut(&(pattern_idx, step_idx)))
+        else {
+            return false;
+        };
+        for bucket in window.values_mut() {
+            prune_window(bucket, now, threshold.window_ms);
+            if bucket.len() as u64 >= threshold.count.max(1) {
+                self.counted[pattern_idx].extend(bucket.drain(..));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Record what satisfied a step: the last counted event for threshold
+    /// steps, otherwise the line and rule that last set the step's state key.
+    fn provenance(
+        &self,
+        pattern_idx: usize,
+        step_idx: usize,
+        pred: &PatternPredicate,
+        state: &StateManager,
+    ) -> StepProvenance {
+        let source_id = state.source_id(&pred.source_name).unwrap_or_default();
+        let tracked = state.get_tracked_by_name(&pred.source_name, &pred.state_key);
+        let mut step = StepProvenance {
+            step_index: step_idx,
+            source_id,
+            source_name: pred.source_name.clone(),
+            state_key: pred.state_key.clone(),
+            timestamp: tracked.map(|t| t.set_at).or(self.now).unwrap_or_default(),
+            rule_id: tracked.map_or(0, |t| t.rule_id),
+            line_number: tracked.and_then(|t| t.line_number),
+            value: tracked.map(|t| t.value.clone()),
+        };
+        if pred.threshold.is_none()
+            && pred.operator.is_edge()
+            && let Some(change) = self
+                .line_changes
+                .iter()
+                .find(|change| change_satisfies(pred, change, state, &self.env()))
+        {
+            step.timestamp = change.timestamp;
+            step.rule_id = change.rule_id;
+            step.line_number = Some(self.line_number);
+            step.value = change.new_value.clone();
+        }
+        if pred.threshold.is_some()
+            && let Some(last) = self.counted[pattern_idx].last()
+        {
+            step.source_id = last.source_id;
+            step.timestamp = last.timestamp;
+            step.rule_id = last.rule_id;
+            step.line_number = Some(last.line_number);
+            step.value = last.value.clone();
+        }
+        step
+    }
+}
+
+/// Order pattern indices so every parent comes before its children. Patterns
+/// caught in a parent cycle keep their list order at the end.
+fn parents_first_order(parents: &[Option<usize>]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(parents.len());
+    let mut placed = vec![false; parents.len()];
+    // Repeatedly place patterns whose parent is already placed
+    loop {
+        let before = order.len();
+        for i in 0..parents.len() {
+            if !placed[i] && parents[i].is_none_or(|p| placed[p]) {
+                placed[i] = true;
+                order.push(i);
+            }
+        }
+        if order.len() == before {
+            break;
+        }
+    }
+    order.extend((0..parents.len()).filter(|&i| !placed[i]));
+    order
+}
+
+/// Drop events that fell out of the sliding window ending at `now`. A
+/// window reaching back before the earliest representable time keeps
+/// everything.
+fn prune_window(bucket: &mut VecDeque<CountedEvent>, now: NaiveDateTime, window_ms: u64) {
+    let cutoff = i64::try_from(window_ms)
+        .ok()
+        .and_then(chrono::TimeDelta::try_milliseconds)
+        .and_then(|window| now.checked_sub_signed(window))
+        .unwrap_or(NaiveDateTime::MIN);
+    while bucket.front().is_some_and(|e| e.timestamp < cutoff) {
+        bucket.pop_front();
+    }
+}
+
+/// Whether a step, once satisfied, stays satisfied for the rest of the attempt.
+fn is_latched(pred: &PatternPredicate) -> bool {
+    pred.threshold.is_some() || pred.operator.is_edge()
+}
+
+/// Test a state change against an edge predicate.
+fn change_satisfies(
+    pred: &PatternPredicate,
+    change: &StateChange,
+    state: &StateManager,
+    env: &PredicateEnv,
+) -> bool {
+    if change.source_name != pred.source_name || change.state_key != pred.state_key {
+        return false;
+    }
+    match pred.operator {
+        Operator::BecameSet => change.old_value.is_none() && change.new_value.is_some(),
+        Operator::BecameUnset => change.old_value.is_some() && change.new_value.is_none(),
+        Operator::Changed => match &pred.operand {
+            Operand::Transition { from, to } => {
+                (from.is_none() || *from == change.old_value)
+                    && (to.is_none() || *to == change.new_value)
+            }
+            operand => {
+                let target = resolve_operand(operand, state, env);
+                target.is_some() && change.new_value == target
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Longest threshold window, cooldown, parent window or clock skew a
+/// pattern may use: one year.
+pub const MAX_SPAN_MS: u64 = 365 * 24 * 60 * 60 * 1000;
+
+/// Check that a predicate's operand has the shape its operator needs, that
+/// `Matches` patterns compile and that expressions parse and type-check.
+pub fn validate_predicate(pred: &PatternPredicate) -> Result<(), AnalysisError> {
+    if let Operand::Expr(src) = &pred.operand {
+        Expr::parse(src).map_err(|e| {
+            AnalysisError::ParseError(format!("{}.{}: {e}", pred.source_name, pred.state_key))
+        })?;
+    }
+    let invalid = |msg: &str| {
+        Err(AnalysisError::ParseError(format!(
+            "{}.{}: {msg}",
+            pred.source_name, pred.state_key
+        )))
+    };
+    if pred
+        .threshold
+        .as_ref()
+        .is_some_and(|t| t.window_ms > MAX_SPAN_MS)
+    {
+        return invalid(&format!(
+            "threshold window must be at most {MAX_SPAN_MS} ms"
+        ));
+    }
+    match (&pred.operator, &pred.operand) {
+        (Operator::Matches, Operand::Literal(StateValue::String(pat))) => Regex::new(pat)
+            .map(|_| ())
+            .map_err(|e| AnalysisError::InvalidRegex(e.to_string())),
+        (Operator::Matches, _) => invalid("matches needs a literal string regex"),
+        (Operator::In | Operator::NotIn, Operand::List(_)) => Ok(()),
+        (Operator::In | Operator::NotIn, _) => invalid("in/not_in need a list operand"),
+        (Operator::Between, Operand::Range { min, max }) => match min.partial_cmp(max) {
+            Some(Ordering::Less | Ordering::Equal) => Ok(()),
+            _ => invalid("between needs comparable bounds with min <= max"),
+        },
+        (Operator::Between, _) => invalid("between needs a range operand"),
+        (Operator::OlderThan | Operator::NewerThan, Operand::Literal(StateValue::Integer(ms)))
+            if *ms >= 0 =>
+        {
+            Ok(())
+        }
+        (Operator::OlderThan | Operator::NewerThan, _) => {
+            invalid("older_than/newer_than need a non-negative literal integer of milliseconds")
+        }
+        (
+            Operator::SizeEq | Operator::SizeGt | Operator::SizeLt,
+            Operand::Literal(StateValue::Integer(n)),
+        ) if *n >= 0 => Ok(()),
+        (
+            Operator::SizeEq | Operator::SizeGt | Operator::SizeLt,
+            Operand::StateRef { .. } | Operand::Expr(_),
+        ) => Ok(()),
+        (Operator::SizeEq | Operator::SizeGt | Operator::SizeLt, _) => {
+            invalid("size_eq/size_gt/size_lt need a non-negative integer operand")
+        }
+        (
+            Operator::SetWithin,
+            Operand::TimeRef {
+                within_ms: None, ..
+            },
+        ) => invalid("set_within needs within_ms"),
+        (
+            Operator::SetBefore | Operator::SetAfter | Operator::SetWithin,
+            Operand::TimeRef {
+                within_ms, skew_ms, ..
+            },
+        ) => {
+            if within_ms.is_some_and(|ms| ms > MAX_SPAN_MS) {
+                invalid(&format!("within_ms must be at most {MAX_SPAN_MS} ms"))
+            } else if skew_ms.unsigned_abs() > MAX_SPAN_MS {
+                invalid(&format!("skew_ms must be within ±{MAX_SPAN_MS} ms"))
+            } else {
+                Ok(())
+            }
+        }
+        (Operator::SetBefore | Operator::SetAfter | Operator::SetWithin, _) => {
+            invalid("set_before/set_after/set_within need a time_ref operand")
+        }
+        (_, Operand::TimeRef { .. }) => {
+            invalid("time_ref operands are only valid with set_before/set_after/set_within")
+        }
+        (Operator::Changed, _) => Ok(()),
+        (_, Operand::Transition { .. }) => {
+            invalid("transition operands are only valid with changed")
+        }
+        (_, Operand::List(_) | Operand::Range { .. }) => {
+            invalid("list and range operands are only valid with in/not_in/between")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Compile a predicate's `Matches` regex or `Expr` operand into the caches,
+/// unless an identical one is already there.
+fn compile_operand(
+    pred: &PatternPredicate,
+    regexes: &mut HashMap<String, Regex>,
+    exprs: &mut HashMap<String, Expr>,
+) -> Result<(), AnalysisError> {
+    if let (Operator::Matches, Operand::Literal(StateValue::String(pat))) =
+        (&pred.operator, &pred.operand)
+        && !regexes.contains_key(pat)
+    {
+        let re = Regex::new(pat).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+        regexes.insert(pat.clone(), re);
+    }
+    if let Operand::Expr(src) = &pred.operand
+        && !exprs.contains_key(src)
+    {
+        exprs.insert(src.clone(), Expr::parse(src)?);
+    }
+    Ok(())
+}
+
+/// What a predicate is checked against besides state: regexes and
+/// expressions compiled from its operand and the current line time for age
+/// operators.
+struct PredicateEnv<'a> {
+    regexes: &'a HashMap<String, Regex>,
+    exprs: &'a HashMap<String, Expr>,
+    now: Option<NaiveDateTime>,
 }
 
 /// Evaluate a single predicate against the current state.
-fn evaluate_predicate(pred: &PatternPredicate, state: &StateManager) -> bool {
-    let current_val = state.get_state_by_name(&pred.source_name, &pred.state_key);
+fn evaluate_predicate(pred: &PatternPredicate, state: &StateManager, env: &PredicateEnv) -> bool {
+    let tracked = state.get_tracked_by_name(&pred.source_name, &pred.state_key);
+    compare_operand(
+        pred,
+        tracked.map(|t| &t.value),
+        tracked.map(|t| t.set_at),
+        state,
+        env,
+    )
+}
 
-    // Resolve the operand
-    let operand_val: Option<StateValue> = match &pred.operand {
+/// Resolve an operand to a concrete value against the current state.
+fn resolve_operand(
+    operand: &Operand,
+    state: &StateManager,
+    env: &PredicateEnv,
+) -> Option<StateValue> {
+    match operand {
         Operand::Literal(v) => Some(v.clone()),
         Operand::StateRef {
             source_name,
             state_key,
         } => state.get_state_by_name(source_name, state_key).cloned(),
-    };
+        Operand::Transition { to, .. } => to.clone(),
+        Operand::Expr(src) => env.exprs.get(src)?.eval(state),
+        Operand::List(_) | Operand::Range { .. } | Operand::TimeRef { .. } => None,
+    }
+}
+
+/// A value as text, for the string operators.
+fn value_text(v: &StateValue) -> Cow<'_, str> {
+    match v {
+        StateValue::String(s) => Cow::Borrowed(s),
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+/// Read a text operand compared against a timestamp or duration as one, so
+/// patterns can write `"5s"` or `"2024-01-15 10:00:00"`. `None` when the
+/// operand is used as it is.
+fn coerce_text(current: &StateValue, operand: &StateValue) -> Option<StateValue> {
+    match (current, operand) {
+        (StateValue::Timestamp(_), StateValue::String(text)) => {
+            crate::transform::convert(text, ValueType::Timestamp)
+        }
+        (StateValue::Duration(_), StateValue::String(text)) => {
+            crate::transform::convert(text, ValueType::Duration)
+        }
+        _ => None,
+    }
+}
+
+/// Test a value, last set at `set_at`, against a predicate's operator and
+/// operand.
+fn compare_operand(
+    pred: &PatternPredicate,
+    current_val: Option<&StateValue>,
+    set_at: Option<NaiveDateTime>,
+    state: &StateManager,
+    env: &PredicateEnv,
+) -> bool {
+    let operand_val = resolve_operand(&pred.operand, state, env).map(|operand| {
+        current_val
+            .and_then(|a| coerce_text(a, &operand))
+            .unwrap_or(operand)
+    });
 
     match pred.operator {
         Operator::Exists => current_val.is_some(),
+        Operator::NotExists => current_val.is_none(),
         Operator::Eq => match (current_val, &operand_val) {
             (Some(a), Some(b)) => a == b,
             _ => false,
@@ -1045,24 +2593,250 @@ fn evaluate_predicate(pred: &PatternPredicate, state: &StateManager) -> bool {
             _ => false,
         },
         Operator::Contains => match (current_val, &operand_val) {
-            (Some(StateValue::String(a)), Some(StateValue::String(b))) => a.contains(b.as_str()),
+            (Some(a), Some(b)) => match a.items() {
+                Some(items) => items.contains(b),
+                None => value_text(a).contains(value_text(b).as_ref()),
+            },
             _ => false,
         },
-    }
-}
-
-// ---------------------------------------------------------------------------
-// JSON field extraction helper
-// ---------------------------------------------------------------------------
-
-fn json_value_to_state_value(v: &serde_json::Value) -> Option<StateValue> {
-    match v {
-        serde_json::Value::String(s) => Some(StateValue::String(s.clone())),
-        serde_json::Value::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                Some(StateValue::Integer(i))
-            } else {
-                n.as_f64().map(StateValue::Float)
+        Operator::StartsWith => match (current_val, &operand_val) {
+            (Some(a), Some(b)) => value_text(a).starts_with(value_text(b).as_ref()),
+            _ => false,
+        },
+        Operator::EndsWith => match (current_val, &operand_val) {
+            (Some(a), Some(b)) => value_text(a).ends_with(value_text(b).as_ref()),
+            _ => false,
+        },
+        Operator::Matches => match (current_val, &pred.operand) {
+            (Some(a), Operand::Literal(StateValue::String(pat))) => env
+                .regexes
+                .get(pat)
+                .is_some_and(|re| re.is_match(&value_text(a))),
+            _ => false,
+        },
+        Operator::In => match (current_val, &pred.operand) {
+            (Some(a), Operand::List(values)) => values.contains(a),
+            _ => false,
+        },
+        Operator::NotIn => match (current_val, &pred.operand) {
+            (Some(a), Operand::List(values)) => !values.contains(a),
+            _ => false,
+        },
+        Operator::Between => match (current_val, &pred.operand) {
+            (Some(a), Operand::Range { min, max }) => {
+                let (min_val, max_val) = (coerce_text(a, min), coerce_text(a, max));
+                let (min, max) = (
+                    min_val.as_ref().unwrap_or(min),
+                    max_val.as_ref().unwrap_or(max),
+                );
+                matches!(
+                    a.partial_cmp(min),
+                    Some(Ordering::Greater | Ordering::Equal)
+                ) && matches!(a.partial_cmp(max), Some(Ordering::Less | Ordering::Equal))
+            }
+            _ => false,
+        },
+        Operator::SetBefore | Operator::SetAfter | Operator::SetWithin => {
+            let Operand::TimeRef {
+                source_name,
+                state_key,
+                within_ms,
+                skew_ms,
+            } = &pred.operand
+            else {
+                return false;
+            };
+            let (Some(set_at), Some(other)) =
+                (set_at, state.get_tracked_by_name(source_name, state_key))
+            else {
+                return false;
+            };
+            // Positive when this key was set after the referenced one
+            let Some(other_at) = chrono::TimeDelta::try_milliseconds(*skew_ms)
+                .and_then(|skew| other.set_at.checked_add_signed(skew))
+            else {
+                return false;
+            };
+            let gap = set_at - other_at;
+            let in_window = within_ms.is_none_or(|ms| {
+                i64::try_from(ms)
+                    .ok()
+                    .and_then(chrono::TimeDelta::try_milliseconds)
+                    .is_none_or(|within| gap.abs() <= within)
+            });
+            match pred.operator {
+                Operator::SetBefore => gap < chrono::Duration::zero() && in_window,
+                Operator::SetAfter => gap > chrono::Duration::zero() && in_window,
+                _ => in_window,
+            }
+        }
+        Operator::OlderThan | Operator::NewerThan => match (set_at, env.now, &operand_val) {
+            (Some(set_at), Some(now), Some(StateValue::Integer(ms))) => {
+                let Some(limit) = chrono::TimeDelta::try_milliseconds(*ms) else {
+                    return false;
+                };
+                let age = now - set_at;
+                match pred.operator {
+                    Operator::OlderThan => age > limit,
+                    _ => age <= limit,
+                }
+            }
+            _ => false,
+        },
+        Operator::SizeEq | Operator::SizeGt | Operator::SizeLt => {
+            match (current_val.and_then(StateValue::items), &operand_val) {
+                (Some(items), Some(StateValue::Integer(n))) => {
+                    let ord = (items.len() as i64).cmp(n);
+                    match pred.operator {
+                        Operator::SizeEq => ord == Ordering::Equal,
+                        Operator::SizeGt => ord == Ordering::Greater,
+                        _ => ord == Ordering::Less,
+                    }
+                }
+                _ => false,
+            }
+        }
+        // Edge operators never hold against level state alone
+        Operator::Changed | Operator::BecameSet | Operator::BecameUnset => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Outcome templates
+// ---------------------------------------------------------------------------
+
+/// A piece of a diagnosis or remediation template.
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Ref {
+        /// The placeholder as written, without its braces.
+        placeholder: &'a str,
+        source_name: &'a str,
+        state_key: &'a str,
+    },
+}
+
+/// Split a `source.key` placeholder. As in expressions, either name may be
+/// quoted with backticks, so `` {`app.log`.status} `` refers to a source
+/// named `app.log`.
+fn split_placeholder(placeholder: &str) -> Option<(&str, &str)> {
+    let (source_name, rest) = match placeholder.strip_prefix('`') {
+        Some(quoted) => {
+            let (source_name, rest) = quoted.split_once('`')?;
+            (source_name, rest.strip_prefix('.')?)
+        }
+        None => placeholder.split_once('.')?,
+    };
+    let state_key = match rest.strip_prefix('`') {
+        Some(quoted) => quoted.strip_suffix('`').filter(|key| !key.contains('`'))?,
+        None => rest,
+    };
+    (!source_name.is_empty() && !state_key.is_empty()).then_some((source_name, state_key))
+}
+
+/// Split a template into text and `{source.key}` placeholders. `{{` and `}}`
+/// stand for literal braces.
+fn parse_template(template: &str) -> Result<Vec<TemplatePart<'_>>, AnalysisError> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        if pos > 0 {
+            parts.push(TemplatePart::Text(&rest[..pos]));
+        }
+        let brace = &rest[pos..pos + 1];
+        if rest[pos + 1..].starts_with(brace) {
+            parts.push(TemplatePart::Text(brace));
+            rest = &rest[pos + 2..];
+            continue;
+        }
+        if brace == "}" {
+            return Err(AnalysisError::ParseError(format!(
+                "unmatched '}}' in template: {template}"
+            )));
+        }
+        let Some(end) = rest[pos..].find('}') else {
+            return Err(AnalysisError::ParseError(format!(
+                "unclosed '{{' in template: {template}"
+            )));
+        };
+        let placeholder = &rest[pos + 1..pos + end];
+        let Some((source_name, state_key)) = split_placeholder(placeholder) else {
+            return Err(AnalysisError::ParseError(format!(
+                "placeholder {{{placeholder}}} must be {{source.key}}"
+            )));
+        };
+        parts.push(TemplatePart::Ref {
+            placeholder,
+            source_name,
+            state_key,
+        });
+        rest = &rest[pos + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Check that a diagnosis or remediation template is well formed.
+pub fn validate_template(template: &str) -> Result<(), AnalysisError> {
+    parse_template(template).map(|_| ())
+}
+
+/// Fill a template's placeholders from the current state. Unset keys are
+/// left as the placeholder text so gaps stay visible.
+pub fn render_template(template: &str, state: &StateManager) -> String {
+    let Ok(parts) = parse_template(template) else {
+        return template.to_string();
+    };
+    let mut out = String::with_capacity(template.len());
+    for part in parts {
+        match part {
+            TemplatePart::Text(text) => out.push_str(text),
+            TemplatePart::Ref {
+                placeholder,
+                source_name,
+                state_key,
+            } => match state.get_state_by_name(source_name, state_key) {
+                Some(value) => out.push_str(&value.to_string()),
+                None => {
+                    out.push('{');
+                    out.push_str(placeholder);
+                    out.push('}');
+                }
+            },
+        }
+    }
+    out
+}
+
+fn render_outcome(outcome: &PatternOutcome, state: &StateManager) -> PatternOutcome {
+    PatternOutcome {
+        severity: outcome.severity,
+        category: outcome.category.clone(),
+        diagnosis: outcome
+            .diagnosis
+            .as_deref()
+            .map(|t| render_template(t, state)),
+        remediation: outcome
+            .remediation
+            .as_deref()
+            .map(|t| render_template(t, state)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// JSON field extraction helper
+// ---------------------------------------------------------------------------
+
+fn json_value_to_state_value(v: &serde_json::Value) -> Option<StateValue> {
+    match v {
+        serde_json::Value::String(s) => Some(StateValue::String(s.clone())),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Some(StateValue::Integer(i))
+            } else {
+                n.as_f64().map(StateValue::Float)
             }
         }
         serde_json::Value::Bool(b) => Some(StateValue::Bool(*b)),
@@ -1075,7 +2849,7 @@ fn json_value_to_state_value(v: &serde_json::Value) -> Option<StateValue> {
 // Main analysis function
 // ---------------------------------------------------------------------------
 
-/// Run the full analysis pipeline.
+/// Run the full analysis pipeline on the sources' files.
 pub fn analyze(
     sources: &[Source],
     templates: &[SourceTemplate],
@@ -1085,6 +2859,361 @@ pub fn analyze(
     patterns: &[Pattern],
     time_range: &TimeRange,
 ) -> Result<AnalysisResult, AnalysisError> {
+    analyze_with_options(
+        sources,
+        &FileSource,
+        templates,
+        timestamp_templates,
+        rules,
+        rulesets,
+        patterns,
+        &[],
+        &[],
+        &[],
+        time_range,
+        &AnalysisOptions::default(),
+    )
+}
+
+/// Run the full analysis pipeline with optional outputs enabled.
+#[allow(clippy::too_many_arguments)]
+pub fn analyze_with_options(
+    sources: &[Source],
+    logs: &dyn LogSource,
+    templates: &[SourceTemplate],
+    timestamp_templates: &[TimestampTemplate],
+    rules: &[LogRule],
+    rulesets: &[Ruleset],
+    patterns: &[Pattern],
+    derived_keys: &[DerivedKey],
+    grok_patterns: &[GrokPattern],
+    plugins: &[Plugin],
+    time_range: &TimeRange,
+    options: &AnalysisOptions,
+) -> Result<AnalysisResult, AnalysisError> {
+    let mut result = AnalysisResult {
+        rule_matches: Vec::new(),
+        pattern_matches: Vec::new(),
+        state_changes: Vec::new(),
+        near_misses: Vec::new(),
+        profile: None,
+        coverage: None,
+    };
+
+    let summary = run_pipeline(
+        sources,
+        logs,
+        templates,
+        timestamp_templates,
+        rules,
+        rulesets,
+        patterns,
+        derived_keys,
+        grok_patterns,
+        plugins,
+        time_range,
+        options,
+        |event| {
+            match event {
+                AnalysisEvent::RuleMatch(rm) => result.rule_matches.push(rm),
+                AnalysisEvent::PatternMatch(pm) => result.pattern_matches.push(pm),
+                AnalysisEvent::StateChange(sc) => result.state_changes.push(sc),
+                AnalysisEvent::NearMiss(nm) => result.near_misses.push(nm),
+                AnalysisEvent::Superseded { match_index } => {
+                    result.pattern_matches[match_index as usize].superseded = true
+                }
+                _ => {}
+            }
+            true
+        },
+    )?;
+
+    result.profile = summary.profile;
+    result.coverage = summary.coverage;
+    Ok(result)
+}
+
+/// Run the analysis pipeline, streaming events through a channel.
+///
+/// Mirrors `analyze()` but sends each match as it occurs rather than collecting.
+/// Returns early if the receiver is dropped (client disconnected).
+#[allow(clippy::too_many_arguments)]
+pub fn analyze_streaming(
+    sources: &[Source],
+    logs: &dyn LogSource,
+    templates: &[SourceTemplate],
+    timestamp_templates: &[TimestampTemplate],
+    rules: &[LogRule],
+    rulesets: &[Ruleset],
+    patterns: &[Pattern],
+    derived_keys: &[DerivedKey],
+    grok_patterns: &[GrokPattern],
+    plugins: &[Plugin],
+    tx: std::sync::mpsc::Sender<AnalysisEvent>,
+    time_range: &TimeRange,
+    options: &AnalysisOptions,
+) -> Result<(), AnalysisError> {
+    let mut total_rule_matches: u64 = 0;
+    let mut total_pattern_matches: u64 = 0;
+    let mut total_state_changes: u64 = 0;
+
+    let summary = run_pipeline(
+        sources,
+        logs,
+        templates,
+        timestamp_templates,
+        rules,
+        rulesets,
+        patterns,
+        derived_keys,
+        grok_patterns,
+        plugins,
+        time_range,
+        options,
+        |event| {
+            match &event {
+                AnalysisEvent::RuleMatch(_) => total_rule_matches += 1,
+                AnalysisEvent::PatternMatch(_) => total_pattern_matches += 1,
+                AnalysisEvent::StateChange(_) => total_state_changes += 1,
+                _ => {}
+            }
+            tx.send(event).is_ok()
+        },
+    )?;
+
+    let _ = tx.send(AnalysisEvent::Complete {
+        total_lines: summary.lines_processed,
+        total_rule_matches,
+        total_pattern_matches,
+        total_state_changes,
+        profile: summary.profile,
+        coverage: summary.coverage,
+    });
+
+    Ok(())
+}
+
+/// Run the pipeline for a single pattern and explain the outcome: how often
+/// it fired and, if it never did, how far it got. The pattern's ancestors are
+/// looked up in `patterns` and evaluated alongside it, so a child is held to
+/// its parent window as in a full analysis.
+#[allow(clippy::too_many_arguments)]
+pub fn explain_pattern(
+    sources: &[Source],
+    logs: &dyn LogSource,
+    templates: &[SourceTemplate],
+    timestamp_templates: &[TimestampTemplate],
+    rules: &[LogRule],
+    rulesets: &[Ruleset],
+    pattern: &Pattern,
+    patterns: &[Pattern],
+    derived_keys: &[DerivedKey],
+    grok_patterns: &[GrokPattern],
+    plugins: &[Plugin],
+    time_range: &TimeRange,
+) -> Result<PatternExplanation, AnalysisError> {
+    let mut explanation = PatternExplanation {
+        pattern_id: pattern.id,
+        match_count: 0,
+        near_miss: None,
+    };
+
+    let mut chain = vec![pattern.clone()];
+    let mut next = pattern.parent.as_ref().map(|link| link.pattern_id);
+    while let Some(id) = next {
+        if chain.iter().any(|p| p.id == id) {
+            break;
+        }
+        let Some(parent) = patterns.iter().find(|p| p.id == id) else {
+            break;
+        };
+        next = parent.parent.as_ref().map(|link| link.pattern_id);
+        chain.push(parent.clone());
+    }
+
+    run_pipeline(
+        sources,
+        logs,
+        templates,
+        timestamp_templates,
+        rules,
+        rulesets,
+        &chain,
+        derived_keys,
+        grok_patterns,
+        plugins,
+        time_range,
+        &AnalysisOptions {
+            near_misses: true,
+            ..Default::default()
+        },
+        |event| {
+            match event {
+                AnalysisEvent::PatternMatch(pm) if pm.pattern_id == pattern.id => {
+                    explanation.match_count += 1
+                }
+                AnalysisEvent::NearMiss(nm) if nm.pattern_id == pattern.id => {
+                    explanation.near_miss = Some(nm)
+                }
+                _ => {}
+            }
+            true
+        },
+    )?;
+
+    Ok(explanation)
+}
+
+/// The rules that run on one source's lines.
+pub struct SourceRules {
+    /// In evaluation order: rulesets in the order given, then each
+    /// ruleset's own order.
+    pub rule_ids: Vec<u64>,
+    /// Rules that, once applied to a line, stop the rest.
+    pub stop_rule_ids: HashSet<u64>,
+}
+
+impl SourceRules {
+    /// Sources with equal keys can share one prefilter.
+    fn prefilter_key(&self) -> (&[u64], Vec<u64>) {
+        let mut stops: Vec<u64> = self.stop_rule_ids.iter().copied().collect();
+        stops.sort_unstable();
+        (&self.rule_ids, stops)
+    }
+}
+
+/// Resolve the rules `source` runs from the rulesets that apply to it.
+pub fn resolve_source_rules(source: &Source, rulesets: &[Ruleset]) -> SourceRules {
+    let mut resolved = SourceRules {
+        rule_ids: Vec::new(),
+        stop_rule_ids: HashSet::new(),
+    };
+    for rs in rulesets.iter().filter(|rs| rs.applies_to(source)) {
+        resolved.rule_ids.extend(&rs.rule_ids);
+        resolved.stop_rule_ids.extend(&rs.stop_rule_ids);
+    }
+    resolved
+}
+
+/// What `run_pipeline` reports besides its events.
+struct PipelineSummary {
+    lines_processed: u64,
+    profile: Option<AnalysisProfile>,
+    /// Only built when the run was not stopped early.
+    coverage: Option<CoverageReport>,
+}
+
+/// Unmatched templates kept per source in a coverage report.
+const COVERAGE_TEMPLATE_LIMIT: usize = 20;
+
+/// Lines of one source seen for a coverage report.
+#[derive(Default)]
+struct SourceTally {
+    lines: u64,
+    unmatched: u64,
+    /// Unmatched line template -> (count, first line)
+    templates: HashMap<String, (u64, String)>,
+}
+
+/// Tallies for a coverage report, fed from the sequential phase.
+#[derive(Default)]
+struct CoverageTracker {
+    sources: HashMap<u64, SourceTally>,
+    matched_rules: HashSet<u64>,
+    changed_rules: HashSet<u64>,
+    fired_patterns: HashSet<u64>,
+}
+
+impl CoverageTracker {
+    fn observe_line(&mut self, line: &LogLine, matches: &[RuleMatch], changes: &[StateChange]) {
+        let tally = self.sources.entry(line.source_id).or_default();
+        tally.lines += 1;
+        if matches.is_empty() {
+            tally.unmatched += 1;
+            tally
+                .templates
+                .entry(tokenize(&line.content))
+                .or_insert_with(|| (0, line.raw.to_string()))
+                .0 += 1;
+        }
+        self.matched_rules
+            .extend(matches.iter().map(|rm| rm.rule_id));
+        // Rule id 0 marks JSON fields, expiries and derived keys
+        self.changed_rules
+            .extend(changes.iter().map(|sc| sc.rule_id).filter(|id| *id != 0));
+    }
+
+    fn report(
+        mut self,
+        sources: &[Source],
+        rules: &[LogRule],
+        patterns: &[Pattern],
+    ) -> CoverageReport {
+        let sources = sources
+            .iter()
+            .map(|source| {
+                let tally = self.sources.remove(&source.id).unwrap_or_default();
+                let mut unmatched_templates: Vec<UnmatchedTemplate> = tally
+                    .templates
+                    .into_iter()
+                    .map(|(template, (count, sample_line))| UnmatchedTemplate {
+                        template,
+                        count,
+                        sample_line,
+                    })
+                    .collect();
+                unmatched_templates.sort_by(|a, b| {
+                    b.count
+                        .cmp(&a.count)
+                        .then_with(|| a.template.cmp(&b.template))
+                });
+                unmatched_templates.truncate(COVERAGE_TEMPLATE_LIMIT);
+                SourceCoverage {
+                    source_id: source.id,
+                    lines: tally.lines,
+                    unmatched_lines: tally.unmatched,
+                    unmatched_templates,
+                }
+            })
+            .collect();
+        let missing = |ids: &mut dyn Iterator<Item = u64>, seen: &HashSet<u64>| {
+            let mut ids: Vec<u64> = ids.filter(|id| !seen.contains(id)).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            ids
+        };
+        CoverageReport {
+            sources,
+            unmatched_rules: missing(&mut rules.iter().map(|r| r.id), &self.matched_rules),
+            rules_without_changes: missing(
+                &mut self.matched_rules.iter().copied(),
+                &self.changed_rules,
+            ),
+            unfired_patterns: missing(&mut patterns.iter().map(|p| p.id), &self.fired_patterns),
+        }
+    }
+}
+
+/// Shared pipeline behind `analyze()` and `analyze_streaming()`.
+///
+/// Every event is handed to `emit` as it occurs; returning `false` stops the
+/// run early.
+#[allow(clippy::too_many_arguments)]
+fn run_pipeline(
+    sources: &[Source],
+    logs: &dyn LogSource,
+    templates: &[SourceTemplate],
+    timestamp_templates: &[TimestampTemplate],
+    rules: &[LogRule],
+    rulesets: &[Ruleset],
+    patterns: &[Pattern],
+    derived_keys: &[DerivedKey],
+    grok_patterns: &[GrokPattern],
+    plugins: &[Plugin],
+    time_range: &TimeRange,
+    options: &AnalysisOptions,
+    mut emit: impl FnMut(AnalysisEvent) -> bool,
+) -> Result<PipelineSummary, AnalysisError> {
     // Build template lookup
     let template_map: HashMap<u64, &SourceTemplate> = templates.iter().map(|t| (t.id, t)).collect();
 
@@ -1096,21 +3225,71 @@ pub fn analyze(
     let rule_map: HashMap<u64, &LogRule> = rules.iter().map(|r| (r.id, r)).collect();
 
     // Compile all rules
-    let compiled_rules = compile_rules(rules)?;
+    let compiled_rules = compile_rules(rules, &GrokLibrary::new(grok_patterns))?;
     let compiled_map: HashMap<u64, &CompiledRule> =
         compiled_rules.iter().map(|c| (c.rule_id, c)).collect();
+    // Scripts only run in the sequential phase, so they are compiled apart
+    // from the rules the parallel phase shares
+    let script_runner = ScriptRunner::new();
+    let scripts: HashMap<u64, CompiledScript> = rules
+        .iter()
+        .filter_map(|rule| Some((rule.id, rule.script.as_deref()?)))
+        .map(|(id, script)| {
+            script_runner
+                .compile(script)
+                .map(|s| (id, s))
+                .map_err(|e| AnalysisError::ParseError(format!("invalid script in rule {id}: {e}")))
+        })
+        .collect::<Result<_, _>>()?;
+    // Likewise extractor plugins, which get one instance each per analysis
+    let mut extractors: HashMap<u64, PluginInstance> = HashMap::new();
+    for rule in rules {
+        let Some(plugin_id) = rule.plugin_id else {
+            continue;
+        };
+        if extractors.contains_key(&plugin_id) {
+            continue;
+        }
+        let owner = format!("rule {}", rule.id);
+        let instance = compile_plugin(plugins, plugin_id, PluginKind::Extractor, &owner)?
+            .instantiate()
+            .map_err(AnalysisError::ParseError)?;
+        extractors.insert(plugin_id, instance);
+    }
+    let parsers = compile_parsers(templates, plugins)?;
 
-    // Build ruleset-by-template lookup: template_id -> list of rule_ids
-    let mut template_rule_ids: HashMap<u64, Vec<u64>> = HashMap::new();
-    for rs in rulesets {
-        template_rule_ids
-            .entry(rs.template_id)
-            .or_default()
-            .extend(rs.rule_ids.iter());
+    // The rules each source runs; sources resolving to the same list (as
+    // those sharing a template usually do) share a prefilter
+    let source_rules: HashMap<u64, SourceRules> = sources
+        .iter()
+        .map(|s| (s.id, resolve_source_rules(s, rulesets)))
+        .collect();
+    let mut prefilters: HashMap<(&[u64], Vec<u64>), RulePrefilter> = HashMap::new();
+    for resolved in source_rules.values() {
+        let key = resolved.prefilter_key();
+        if prefilters.contains_key(&key) {
+            continue;
+        }
+        let bound = resolved
+            .rule_ids
+            .iter()
+            .filter_map(|id| Some((*rule_map.get(id)?, *compiled_map.get(id)?)))
+            .collect();
+        let prefilter = RulePrefilter::new(bound)?.with_stop_rules(&resolved.stop_rule_ids);
+        prefilters.insert(key, prefilter);
     }
+    let no_rules = RulePrefilter::new(Vec::new())?;
+    let counters: Option<HashMap<u64, RuleCounters>> = options.profile.then(|| {
+        source_rules
+            .values()
+            .flat_map(|resolved| &resolved.rule_ids)
+            .filter(|id| compiled_map.contains_key(id))
+            .map(|id| (*id, RuleCounters::default()))
+            .collect()
+    });
 
     // --- Phase 1: parallel per-source processing (rayon) ---
-    let processed_sources: Vec<Vec<ProcessedLine>> = sources
+    let processed_sources: Vec<(Vec<ProcessedLine>, SourceProfile)> = sources
         .par_iter()
         .map(|source| {
             let template = template_map.get(&source.template_id).ok_or_else(|| {
@@ -1127,32 +3306,50 @@ pub fn analyze(
                         template.timestamp_template_id
                     ))
                 })?;
-            let rule_ids = template_rule_ids
-                .get(&source.template_id)
-                .map(|v| v.as_slice())
-                .unwrap_or(&[]);
+            let prefilter = source_rules
+                .get(&source.id)
+                .and_then(|resolved| prefilters.get(&resolved.prefilter_key()))
+                .unwrap_or(&no_rules);
             process_source(
                 source,
+                logs,
                 template,
                 ts_template,
-                rule_ids,
-                &rule_map,
-                &compiled_map,
+                parsers.get(&template.id),
+                prefilter,
+                counters.as_ref(),
             )
         })
         .collect::<Result<_, _>>()?;
+    let (processed_sources, source_profiles): (Vec<_>, Vec<_>) =
+        processed_sources.into_iter().unzip();
+
+    let profile = counters.map(|counters| {
+        let mut rules: Vec<RuleProfile> =
+            counters.iter().map(|(id, c)| c.to_profile(*id)).collect();
+        rules.sort_by_key(|r| (Reverse(r.match_ns + r.extraction_ns), r.rule_id));
+        AnalysisProfile {
+            rules,
+            sources: source_profiles,
+        }
+    });
 
     // --- Phase 2: sequential merge + state mutations + pattern evaluation ---
     let merger = ProcessedLineMerger::new(processed_sources);
 
     let mut state_manager = StateManager::new(sources);
-    let mut pattern_eval = PatternEvaluator::new(patterns);
+    state_manager.set_derived_keys(derived_keys)?;
+    let mut pattern_eval = PatternEvaluator::new(patterns)?;
+    pattern_eval.track_near_misses = options.near_misses;
 
-    let mut all_rule_matches = Vec::new();
-    let mut all_pattern_matches = Vec::new();
-    let mut all_state_changes = Vec::new();
+    let mut lines_processed: u64 = 0;
+    let mut coverage = options.coverage.then(CoverageTracker::default);
+    // Position of each pattern's latest match among those sent
+    let mut latest_match: HashMap<u64, u64> = HashMap::new();
+    let mut pattern_matches_sent: u64 = 0;
+    let mut stopped = false;
 
-    for processed in merger {
+    'lines: for processed in merger {
         let line = &processed.line;
 
         // Time-range filtering (stream is chronological)
@@ -1167,13 +3364,19 @@ pub fn analyze(
             break;
         }
 
+        lines_processed += 1;
+
+        let source_name = state_manager
+            .source_names
+            .get(&line.source_id)
+            .cloned()
+            .unwrap_or_default();
+        // Values whose TTL ran out by this line are gone before it applies
+        let mut line_changes: Vec<StateChange> = state_manager.expire(line.timestamp);
+        let mut line_matches: Vec<RuleMatch> = Vec::new();
+
         // Apply pre-computed JSON fields as state
         if let Some(json_fields) = &processed.json_fields {
-            let source_name = state_manager
-                .source_names
-                .get(&line.source_id)
-                .cloned()
-                .unwrap_or_default();
             let state = Arc::make_mut(
                 state_manager
                     .per_source_state
@@ -1181,6 +3384,10 @@ pub fn analyze(
                     .or_default(),
             );
             for (key, sv) in json_fields {
+                // JSON fields never expire
+                state_manager
+                    .deadlines
+                    .remove(&(line.source_id, key.clone()));
                 let old = state.get(key).map(|t| t.value.clone());
                 let new = Some(sv.clone());
                 state.insert(
@@ -1188,10 +3395,12 @@ pub fn analyze(
                     TrackedValue {
                         value: sv.clone(),
                         set_at: line.timestamp,
+                        rule_id: 0,
+                        line_number: Some(line.line_number),
                     },
                 );
                 if old != new {
-                    all_state_changes.push(StateChange {
+                    line_changes.push(StateChange {
                         timestamp: line.timestamp,
                         source_id: line.source_id,
                         source_name: source_name.clone(),
@@ -1205,23 +3414,44 @@ pub fn analyze(
         }
 
         // Apply pre-computed rule matches
-        let source_name = state_manager
-            .source_names
-            .get(&line.source_id)
-            .cloned()
-            .unwrap_or_default();
-
         for (rule_id, extracted) in &processed.rule_matches {
             if let Some(rule) = rule_map.get(rule_id) {
-                let changes = state_manager.apply_mutations(
-                    line.source_id,
+                // Guards depend on state as of this line, so they are checked
+                // here rather than in the parallel matching phase.
+                if let Some(guard) = compiled_map.get(rule_id).and_then(|c| c.guard.as_ref())
+                    && !guard.holds(&state_manager, &source_name, line.timestamp)
+                {
+                    continue;
+                }
+                let mut changes = state_manager.apply_mutations(
+                    line,
+                    *rule_id,
                     extracted,
                     &rule.extraction_rules,
-                    line.timestamp,
                 );
+                let mut extracted = extracted.clone();
+                if let Some(plugin) = rule.plugin_id.and_then(|id| extractors.get_mut(&id)) {
+                    changes.extend(state_manager.apply_plugin(
+                        plugin,
+                        line,
+                        *rule_id,
+                        processed.json_fields.as_ref(),
+                        &mut extracted,
+                    ));
+                }
+                if let Some(script) = scripts.get(rule_id) {
+                    changes.extend(state_manager.apply_script(
+                        &script_runner,
+                        script,
+                        line,
+                        *rule_id,
+                        processed.json_fields.as_ref(),
+                        &mut extracted,
+                    ));
+                }
 
                 for (key, old, new) in changes {
-                    all_state_changes.push(StateChange {
+                    line_changes.push(StateChange {
                         timestamp: line.timestamp,
                         source_id: line.source_id,
                         source_name: source_name.clone(),
@@ -1232,242 +3462,103 @@ pub fn analyze(
                     });
                 }
 
-                all_rule_matches.push(RuleMatch {
+                line_matches.push(RuleMatch {
                     rule_id: *rule_id,
                     source_id: line.source_id,
                     log_line: line.clone(),
-                    extracted_state: extracted.clone(),
+                    extracted_state: extracted,
                 });
+
+                // Matches arrive in evaluation order; a stop rule that
+                // applied shadows everything after it
+                if source_rules
+                    .get(&line.source_id)
+                    .is_some_and(|resolved| resolved.stop_rule_ids.contains(rule_id))
+                {
+                    break;
+                }
             }
         }
 
+        // Derived keys see every change this line made, including expiries
+        let derived = state_manager.recompute_derived(line, &line_changes);
+        line_changes.extend(derived);
+
         // Evaluate patterns after each line
+        let line_rule_ids: Vec<u64> = line_matches.iter().map(|rm| rm.rule_id).collect();
+        pattern_eval.observe_line(
+            patterns,
+            &state_manager,
+            line,
+            &line_rule_ids,
+            &line_changes,
+        );
         let pmatches = pattern_eval.evaluate_patterns(patterns, &state_manager);
+        if let Some(coverage) = &mut coverage {
+            coverage.observe_line(line, &line_matches, &line_changes);
+            coverage
+                .fired_patterns
+                .extend(pmatches.iter().map(|pm| pm.pattern_id));
+        }
+
+        for sc in line_changes {
+            if !emit(AnalysisEvent::StateChange(sc)) {
+                stopped = true;
+                break 'lines;
+            }
+        }
+        for rm in line_matches {
+            if !emit(AnalysisEvent::RuleMatch(rm)) {
+                stopped = true;
+                break 'lines;
+            }
+        }
         for mut pm in pmatches {
             pm.timestamp = line.timestamp;
-            all_pattern_matches.push(pm);
+            // A child match supersedes its parent's most recent match,
+            // leaving the child as the most specific diagnosis
+            let refined = pm
+                .refines
+                .and_then(|parent_id| latest_match.get(&parent_id).copied());
+            latest_match.insert(pm.pattern_id, pattern_matches_sent);
+            pattern_matches_sent += 1;
+            if !emit(AnalysisEvent::PatternMatch(pm)) {
+                stopped = true;
+                break 'lines;
+            }
+            if let Some(match_index) = refined
+                && !emit(AnalysisEvent::Superseded { match_index })
+            {
+                stopped = true;
+                break 'lines;
+            }
+        }
+
+        if lines_processed.is_multiple_of(500) && !emit(AnalysisEvent::Progress { lines_processed })
+        {
+            stopped = true;
+            break;
+        }
+    }
+
+    if !stopped && options.near_misses {
+        for nm in pattern_eval.near_misses(patterns, &state_manager) {
+            if !emit(AnalysisEvent::NearMiss(nm)) {
+                stopped = true;
+                break;
+            }
         }
     }
 
-    Ok(AnalysisResult {
-        rule_matches: all_rule_matches,
-        pattern_matches: all_pattern_matches,
-        state_changes: all_state_changes,
+    Ok(PipelineSummary {
+        lines_processed,
+        profile,
+        coverage: coverage
+            .filter(|_| !stopped)
+            .map(|c| c.report(sources, rules, patterns)),
     })
 }
 
-/// Run the analysis pipeline, streaming events through a channel.
-///
-/// Mirrors `analyze()` but sends each match as it occurs rather than collecting.
-/// Returns early if the receiver is dropped (client disconnected).
-#[allow(clippy::too_many_arguments)]
-pub fn analyze_streaming(
-    sources: &[Source],
-    templates: &[SourceTemplate],
-    timestamp_templates: &[TimestampTemplate],
-    rules: &[LogRule],
-    rulesets: &[Ruleset],
-    patterns: &[Pattern],
-    tx: std::sync::mpsc::Sender<AnalysisEvent>,
-    time_range: &TimeRange,
-) -> Result<(), AnalysisError> {
-    // Build template lookup
-    let template_map: HashMap<u64, &SourceTemplate> = templates.iter().map(|t| (t.id, t)).collect();
-    let ts_template_map: HashMap<u64, &TimestampTemplate> =
-        timestamp_templates.iter().map(|t| (t.id, t)).collect();
-    let rule_map: HashMap<u64, &LogRule> = rules.iter().map(|r| (r.id, r)).collect();
-
-    let compiled_rules = compile_rules(rules)?;
-    let compiled_map: HashMap<u64, &CompiledRule> =
-        compiled_rules.iter().map(|c| (c.rule_id, c)).collect();
-
-    let mut template_rule_ids: HashMap<u64, Vec<u64>> = HashMap::new();
-    for rs in rulesets {
-        template_rule_ids
-            .entry(rs.template_id)
-            .or_default()
-            .extend(rs.rule_ids.iter());
-    }
-
-    // --- Phase 1: parallel per-source processing (rayon) ---
-    let processed_sources: Vec<Vec<ProcessedLine>> = sources
-        .par_iter()
-        .map(|source| {
-            let template = template_map.get(&source.template_id).ok_or_else(|| {
-                AnalysisError::ParseError(format!(
-                    "no template found for template_id {}",
-                    source.template_id
-                ))
-            })?;
-            let ts_template = ts_template_map
-                .get(&template.timestamp_template_id)
-                .ok_or_else(|| {
-                    AnalysisError::ParseError(format!(
-                        "no timestamp template found for timestamp_template_id {}",
-                        template.timestamp_template_id
-                    ))
-                })?;
-            let rule_ids = template_rule_ids
-                .get(&source.template_id)
-                .map(|v| v.as_slice())
-                .unwrap_or(&[]);
-            process_source(
-                source,
-                template,
-                ts_template,
-                rule_ids,
-                &rule_map,
-                &compiled_map,
-            )
-        })
-        .collect::<Result<_, _>>()?;
-
-    // --- Phase 2: sequential merge + state mutations + pattern evaluation ---
-    let merger = ProcessedLineMerger::new(processed_sources);
-
-    let mut state_manager = StateManager::new(sources);
-    let mut pattern_eval = PatternEvaluator::new(patterns);
-
-    let mut lines_processed: u64 = 0;
-    let mut total_rule_matches: u64 = 0;
-    let mut total_pattern_matches: u64 = 0;
-    let mut total_state_changes: u64 = 0;
-
-    for processed in merger {
-        let line = &processed.line;
-
-        // Time-range filtering (stream is chronological)
-        if let Some(start) = time_range.start
-            && line.timestamp < start
-        {
-            continue;
-        }
-        if let Some(end) = time_range.end
-            && line.timestamp > end
-        {
-            break;
-        }
-
-        lines_processed += 1;
-
-        // Apply pre-computed JSON fields as state
-        if let Some(json_fields) = &processed.json_fields {
-            let source_name = state_manager
-                .source_names
-                .get(&line.source_id)
-                .cloned()
-                .unwrap_or_default();
-            let state = Arc::make_mut(
-                state_manager
-                    .per_source_state
-                    .entry(line.source_id)
-                    .or_default(),
-            );
-            for (key, sv) in json_fields {
-                let old = state.get(key).map(|t| t.value.clone());
-                let new = Some(sv.clone());
-                state.insert(
-                    key.clone(),
-                    TrackedValue {
-                        value: sv.clone(),
-                        set_at: line.timestamp,
-                    },
-                );
-                if old != new {
-                    total_state_changes += 1;
-                    if tx
-                        .send(AnalysisEvent::StateChange(StateChange {
-                            timestamp: line.timestamp,
-                            source_id: line.source_id,
-                            source_name: source_name.clone(),
-                            state_key: key.clone(),
-                            old_value: old,
-                            new_value: new,
-                            rule_id: 0,
-                        }))
-                        .is_err()
-                    {
-                        return Ok(());
-                    }
-                }
-            }
-        }
-
-        // Apply pre-computed rule matches
-        let source_name = state_manager
-            .source_names
-            .get(&line.source_id)
-            .cloned()
-            .unwrap_or_default();
-
-        for (rule_id, extracted) in &processed.rule_matches {
-            if let Some(rule) = rule_map.get(rule_id) {
-                let changes = state_manager.apply_mutations(
-                    line.source_id,
-                    extracted,
-                    &rule.extraction_rules,
-                    line.timestamp,
-                );
-
-                for (key, old, new) in changes {
-                    total_state_changes += 1;
-                    if tx
-                        .send(AnalysisEvent::StateChange(StateChange {
-                            timestamp: line.timestamp,
-                            source_id: line.source_id,
-                            source_name: source_name.clone(),
-                            state_key: key,
-                            old_value: old,
-                            new_value: new,
-                            rule_id: *rule_id,
-                        }))
-                        .is_err()
-                    {
-                        return Ok(());
-                    }
-                }
-
-                let rm = RuleMatch {
-                    rule_id: *rule_id,
-                    source_id: line.source_id,
-                    log_line: line.clone(),
-                    extracted_state: extracted.clone(),
-                };
-                total_rule_matches += 1;
-                if tx.send(AnalysisEvent::RuleMatch(rm)).is_err() {
-                    return Ok(()); // receiver dropped
-                }
-            }
-        }
-
-        let pmatches = pattern_eval.evaluate_patterns(patterns, &state_manager);
-        for mut pm in pmatches {
-            pm.timestamp = line.timestamp;
-            total_pattern_matches += 1;
-            if tx.send(AnalysisEvent::PatternMatch(pm)).is_err() {
-                return Ok(());
-            }
-        }
-
-        if lines_processed.is_multiple_of(500)
-            && tx
-                .send(AnalysisEvent::Progress { lines_processed })
-                .is_err()
-        {
-            return Ok(());
-        }
-    }
-
-    let _ = tx.send(AnalysisEvent::Complete {
-        total_lines: lines_processed,
-        total_rule_matches,
-        total_pattern_matches,
-        total_state_changes,
-    });
-
-    Ok(())
-}
-
 // ---------------------------------------------------------------------------
 // Log clustering (Drain-inspired tokenization)
 // ---------------------------------------------------------------------------
@@ -1519,10 +3610,13 @@ fn tokenize(line: &str) -> String {
 /// Cluster log lines by structural template.
 pub fn cluster_logs(
     sources: &[Source],
+    logs: &dyn LogSource,
     templates: &[SourceTemplate],
     timestamp_templates: &[TimestampTemplate],
+    plugins: &[Plugin],
     time_range: &TimeRange,
 ) -> Result<ClusterResult, AnalysisError> {
+    let parsers = compile_parsers(templates, plugins)?;
     let template_map: HashMap<u64, &SourceTemplate> = templates.iter().map(|t| (t.id, t)).collect();
     let ts_template_map: HashMap<u64, &TimestampTemplate> =
         timestamp_templates.iter().map(|t| (t.id, t)).collect();
@@ -1543,7 +3637,13 @@ pub fn cluster_logs(
                     template.timestamp_template_id
                 ))
             })?;
-        iterators.push(LogLineIterator::new(source, template, ts_template)?);
+        iterators.push(open_source(
+            source,
+            logs,
+            template,
+            ts_template,
+            parsers.get(&template.id),
+        )?);
     }
 
     let stream = MergedLogStream::new(iterators)?;
@@ -1599,7 +3699,7 @@ pub fn cluster_logs(
             }
         })
         .collect();
-    result_clusters.sort_by(|a, b| b.count.cmp(&a.count));
+    result_clusters.sort_by_key(|c| std::cmp::Reverse(c.count));
 
     Ok(ClusterResult {
         clusters: result_clusters,
@@ -1614,12 +3714,15 @@ pub fn cluster_logs(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::log_source::MemorySource;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
     // Helper to build a simple compiled rule from a LogRule
     fn compile_one(rule: &LogRule) -> CompiledRule {
-        compile_rules(std::slice::from_ref(rule)).unwrap().remove(0)
+        compile_rules(std::slice::from_ref(rule), &GrokLibrary::new(&[]))
+            .unwrap()
+            .remove(0)
     }
 
     fn test_ts() -> NaiveDateTime {
@@ -1631,6 +3734,7 @@ mod tests {
             timestamp: NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
                 .unwrap(),
             source_id: 1,
+            line_number: 1,
             raw: Arc::from(content),
             content: Arc::from(content),
             cached_json: None,
@@ -1650,8 +3754,12 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 1,
                 pattern: r"ERROR".into(),
+                negate: false,
             }],
             extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
         let line = make_log_line("2024-01-01 ERROR something broke");
@@ -1667,8 +3775,12 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 1,
                 pattern: r"ERROR".into(),
+                negate: false,
             }],
             extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
         let line = make_log_line("2024-01-01 INFO all good");
@@ -1685,13 +3797,18 @@ mod tests {
                 MatchRule {
                     id: 1,
                     pattern: r"ERROR".into(),
+                    negate: false,
                 },
                 MatchRule {
                     id: 2,
                     pattern: r"WARN".into(),
+                    negate: false,
                 },
             ],
             extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
 
@@ -1718,13 +3835,18 @@ mod tests {
                 MatchRule {
                     id: 1,
                     pattern: r"server".into(),
+                    negate: false,
                 },
                 MatchRule {
                     id: 2,
                     pattern: r"error".into(),
+                    negate: false,
                 },
             ],
             extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
 
@@ -1737,81 +3859,505 @@ mod tests {
         assert!(evaluate_rule(&rule, &line2, &compiled).is_none());
     }
 
-    // -----------------------------------------------------------------------
-    // State mutation tests
-    // -----------------------------------------------------------------------
-
     #[test]
-    fn test_replace_mode() {
-        let sources = vec![Source {
+    fn test_negated_match_rule_rejects_line() {
+        let rule = LogRule {
             id: 1,
-            name: "src1".into(),
-            template_id: 1,
-            file_path: "".into(),
-        }];
-        let mut sm = StateManager::new(&sources);
-        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
-            "key".into(),
-            TrackedValue {
-                value: StateValue::String("old".into()),
-                set_at: test_ts(),
-            },
-        );
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![
+                MatchRule {
+                    id: 1,
+                    pattern: r"ERROR".into(),
+                    negate: false,
+                },
+                MatchRule {
+                    id: 2,
+                    pattern: r"expected".into(),
+                    negate: true,
+                },
+            ],
+            extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
 
-        let extractions: HashMap<String, StateValue> = HashMap::new();
-        let rules = vec![ExtractionRule {
-            id: 1,
-            extraction_type: ExtractionType::Static,
-            state_key: "key".into(),
-            pattern: None,
-            static_value: Some("new".into()),
-            mode: ExtractionMode::Replace,
-        }];
-        sm.apply_mutations(1, &extractions, &rules, test_ts());
+        let line = make_log_line("ERROR disk full");
+        assert!(evaluate_rule(&rule, &line, &compiled).is_some());
 
-        assert_eq!(
-            sm.per_source_state[&1]["key"].value,
-            StateValue::String("new".into())
-        );
+        let line2 = make_log_line("ERROR expected shutdown");
+        assert!(evaluate_rule(&rule, &line2, &compiled).is_none());
+
+        let line3 = make_log_line("INFO ok");
+        assert!(evaluate_rule(&rule, &line3, &compiled).is_none());
     }
 
     #[test]
-    fn test_accumulate_mode_strings() {
-        let sources = vec![Source {
+    fn test_negated_match_rules_all_mode() {
+        let rule = LogRule {
             id: 1,
-            name: "src1".into(),
-            template_id: 1,
-            file_path: "".into(),
-        }];
-        let mut sm = StateManager::new(&sources);
-        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
-            "tags".into(),
-            TrackedValue {
-                value: StateValue::String("a".into()),
-                set_at: test_ts(),
-            },
-        );
+            name: "test".into(),
+            match_mode: MatchMode::All,
+            match_rules: vec![
+                MatchRule {
+                    id: 1,
+                    pattern: r"server".into(),
+                    negate: false,
+                },
+                MatchRule {
+                    id: 2,
+                    pattern: r"error".into(),
+                    negate: false,
+                },
+                MatchRule {
+                    id: 3,
+                    pattern: r"retrying".into(),
+                    negate: true,
+                },
+            ],
+            extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
 
-        let extractions: HashMap<String, StateValue> = HashMap::new();
-        let rules = vec![ExtractionRule {
+        let line = make_log_line("server error occurred");
+        assert!(evaluate_rule(&rule, &line, &compiled).is_some());
+
+        let line2 = make_log_line("server error, retrying");
+        assert!(evaluate_rule(&rule, &line2, &compiled).is_none());
+    }
+
+    #[test]
+    fn test_only_negated_match_rules_match_other_lines() {
+        let rule = LogRule {
             id: 1,
-            extraction_type: ExtractionType::Static,
-            state_key: "tags".into(),
-            pattern: None,
-            static_value: Some("b".into()),
-            mode: ExtractionMode::Accumulate,
-        }];
-        sm.apply_mutations(1, &extractions, &rules, test_ts());
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"DEBUG".into(),
+                negate: true,
+            }],
+            extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
 
-        assert_eq!(
-            sm.per_source_state[&1]["tags"].value,
-            StateValue::String("a,b".into())
-        );
+        assert!(evaluate_rule(&rule, &make_log_line("INFO up"), &compiled).is_some());
+        assert!(evaluate_rule(&rule, &make_log_line("DEBUG noise"), &compiled).is_none());
     }
 
     #[test]
-    fn test_accumulate_mode_integers() {
-        let sources = vec![Source {
+    fn test_rule_without_match_rules_matches_nothing() {
+        let rule = LogRule {
+            id: 1,
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![],
+            extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
+        assert!(evaluate_rule(&rule, &make_log_line("anything"), &compiled).is_none());
+    }
+
+    #[test]
+    fn test_extraction_target_type_and_transforms() {
+        let rule = LogRule {
+            id: 1,
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"order".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![
+                ExtractionRule {
+                    id: 1,
+                    extraction_type: ExtractionType::Parsed,
+                    state_key: "order_id".into(),
+                    pattern: Some(r"order (?P<order_id>\d+)".into()),
+                    static_value: None,
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::String,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
+                },
+                ExtractionRule {
+                    id: 2,
+                    extraction_type: ExtractionType::Parsed,
+                    state_key: "code".into(),
+                    pattern: Some(r"code=(?P<code>0x[0-9A-Fa-f]+)".into()),
+                    static_value: None,
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Integer,
+                    transforms: vec![Transform::ParseInt { radix: 16 }],
+                    ttl_ms: None,
+                },
+                ExtractionRule {
+                    id: 3,
+                    extraction_type: ExtractionType::Parsed,
+                    state_key: "took_ms".into(),
+                    pattern: Some(r"took (?P<took_ms>\S+)".into()),
+                    static_value: None,
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: vec![Transform::Duration { to: "ms".into() }],
+                    ttl_ms: None,
+                },
+                ExtractionRule {
+                    id: 4,
+                    extraction_type: ExtractionType::Static,
+                    state_key: "retries".into(),
+                    pattern: None,
+                    static_value: Some("3".into()),
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Integer,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
+                },
+            ],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
+        let extracted = evaluate_rule(
+            &rule,
+            &make_log_line("order 007 failed code=0x1F took 1m2s"),
+            &compiled,
+        )
+        .unwrap();
+
+        assert!(matches!(&extracted["order_id"], StateValue::String(s) if s == "007"));
+        assert!(matches!(extracted["code"], StateValue::Integer(31)));
+        assert!(matches!(extracted["took_ms"], StateValue::Integer(62_000)));
+        assert!(matches!(extracted["retries"], StateValue::Integer(3)));
+
+        // The typed static value is what lands in state.
+        let mut sm = StateManager::new(&make_sources());
+        let line = make_log_line("order 007");
+        sm.apply_mutations(&line, 1, &extracted, &rule.extraction_rules);
+        assert!(matches!(
+            sm.get_state_by_name("server", "retries"),
+            Some(StateValue::Integer(3))
+        ));
+    }
+
+    #[test]
+    fn test_extraction_skipped_when_conversion_fails() {
+        let rule = LogRule {
+            id: 1,
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"count".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "count".into(),
+                pattern: Some(r"count=(?P<count>\w+)".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Integer,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let compiled = compile_one(&rule);
+        let extracted = evaluate_rule(&rule, &make_log_line("count=many"), &compiled).unwrap();
+        assert!(extracted.is_empty());
+    }
+
+    #[test]
+    fn test_compile_rules_rejects_bad_transform() {
+        let rule = LogRule {
+            id: 1,
+            name: "test".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "size".into(),
+                pattern: Some(r"(?P<size>\S+)".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: vec![Transform::Unit {
+                    to: "furlongs".into(),
+                }],
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        assert!(compile_rules(&[rule], &GrokLibrary::new(&[])).is_err());
+    }
+
+    #[test]
+    fn test_prefilter_agrees_with_per_rule_evaluation() {
+        let rule = |id: u64, mode: MatchMode, patterns: &[(&str, bool)]| LogRule {
+            id,
+            name: format!("r{id}"),
+            match_mode: mode,
+            match_rules: patterns
+                .iter()
+                .map(|&(pattern, negate)| MatchRule {
+                    id,
+                    pattern: pattern.into(),
+                    negate,
+                })
+                .collect(),
+            extraction_rules: vec![ExtractionRule {
+                id,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "n".into(),
+                pattern: Some(r"(?P<n>\d+)".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let rules = vec![
+            rule(1, MatchMode::Any, &[("ERROR", false), ("FATAL", false)]),
+            rule(
+                2,
+                MatchMode::All,
+                &[("conn(ected|lost)", false), (r"\d+", false)],
+            ),
+            rule(
+                3,
+                MatchMode::Any,
+                &[("(?i)timeout", false), ("debug", true)],
+            ),
+            rule(4, MatchMode::Any, &[("^GET ", false), ("x", false)]),
+            rule(5, MatchMode::Any, &[("heartbeat", true)]),
+            rule(6, MatchMode::All, &[]),
+            rule(7, MatchMode::Any, &[(r".*ms$", false), ("", false)]),
+            // The same rule bound twice matches twice
+            rule(1, MatchMode::Any, &[("ERROR", false), ("FATAL", false)]),
+        ];
+        let compiled = compile_rules(&rules, &GrokLibrary::new(&[])).unwrap();
+        let prefilter = RulePrefilter::new(rules.iter().zip(&compiled).collect()).unwrap();
+
+        for text in [
+            "ERROR disk full 3",
+            "connected to 10.0.0.1",
+            "connection lost",
+            "Request TIMEOUT after 30",
+            "debug: timeout 5",
+            "GET /x 200 15ms",
+            "heartbeat",
+            "",
+            "FATAL ERROR connlost 1",
+        ] {
+            let line = make_log_line(text);
+            let expected: Vec<_> = rules
+                .iter()
+                .zip(&compiled)
+                .filter_map(|(r, c)| Some((r.id, evaluate_rule(r, &line, c)?)))
+                .collect();
+            assert_eq!(prefilter.evaluate(&line), expected, "{text:?}");
+        }
+
+        // Nothing after a matching stop rule is extracted, unless the stop
+        // rule has a guard that may not hold
+        let line = make_log_line("ERROR connected 7");
+        let ids = |prefilter: &RulePrefilter| -> Vec<u64> {
+            prefilter
+                .evaluate(&line)
+                .iter()
+                .map(|(id, _)| *id)
+                .collect()
+        };
+        let stopped = RulePrefilter::new(rules.iter().zip(&compiled).collect())
+            .unwrap()
+            .with_stop_rules(&HashSet::from([1]));
+        assert_eq!(ids(&stopped), vec![1]);
+        let stopped = RulePrefilter::new(rules.iter().zip(&compiled).collect())
+            .unwrap()
+            .with_stop_rules(&HashSet::from([2]));
+        assert_eq!(ids(&stopped), vec![1, 2]);
+
+        let mut guarded = rules.clone();
+        guarded[0].guard = Some(RuleGuard {
+            source_name: None,
+            state_key: "phase".into(),
+            operator: Operator::Exists,
+            operand: Operand::Literal(StateValue::Bool(true)),
+        });
+        let guarded_stop = RulePrefilter::new(guarded.iter().zip(&compiled).collect())
+            .unwrap()
+            .with_stop_rules(&HashSet::from([1]));
+        assert!(ids(&guarded_stop).len() > 2);
+        assert_eq!(ids(&guarded_stop), ids(&prefilter));
+    }
+
+    #[test]
+    fn test_compile_rules_expands_grok_references() {
+        let mut rule = LogRule {
+            id: 1,
+            name: "request".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"%{HTTPMETHOD} %{PATH}".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "client".into(),
+                pattern: Some(r"from %{IP:client}".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let library = [GrokPattern {
+            id: 1,
+            name: "PATH".into(),
+            pattern: r"/\S*".into(),
+        }];
+        let compiled = compile_rules(std::slice::from_ref(&rule), &GrokLibrary::new(&library))
+            .unwrap()
+            .remove(0);
+        let extracted = evaluate_rule(
+            &rule,
+            &make_log_line("GET /health from 10.0.0.7"),
+            &compiled,
+        )
+        .unwrap();
+        assert_eq!(
+            extracted.get("client"),
+            Some(&StateValue::String("10.0.0.7".into()))
+        );
+        assert!(evaluate_rule(&rule, &make_log_line("GET health"), &compiled).is_none());
+
+        // Without the project's PATH the rule cannot compile
+        let err = compile_rules(std::slice::from_ref(&rule), &GrokLibrary::new(&[]))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "parse error: rule 'request': unknown pattern %{PATH}"
+        );
+        rule.match_rules[0].pattern = r"%{HTTPMETHOD} /".into();
+        assert!(compile_rules(&[rule], &GrokLibrary::new(&[])).is_ok());
+    }
+
+    // -----------------------------------------------------------------------
+    // State mutation tests
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn test_replace_mode() {
+        let sources = vec![Source {
+            id: 1,
+            name: "src1".into(),
+            template_id: 1,
+            file_path: "".into(),
+        }];
+        let mut sm = StateManager::new(&sources);
+        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
+            "key".into(),
+            TrackedValue {
+                value: StateValue::String("old".into()),
+                set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
+            },
+        );
+
+        let extractions: HashMap<String, StateValue> = HashMap::new();
+        let rules = vec![ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Static,
+            state_key: "key".into(),
+            pattern: None,
+            static_value: Some("new".into()),
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        }];
+        sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
+
+        assert_eq!(
+            sm.per_source_state[&1]["key"].value,
+            StateValue::String("new".into())
+        );
+    }
+
+    #[test]
+    fn test_accumulate_mode_strings() {
+        let sources = vec![Source {
+            id: 1,
+            name: "src1".into(),
+            template_id: 1,
+            file_path: "".into(),
+        }];
+        let mut sm = StateManager::new(&sources);
+        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
+            "tags".into(),
+            TrackedValue {
+                value: StateValue::String("a".into()),
+                set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
+            },
+        );
+
+        let extractions: HashMap<String, StateValue> = HashMap::new();
+        let rules = vec![ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Static,
+            state_key: "tags".into(),
+            pattern: None,
+            static_value: Some("b".into()),
+            mode: ExtractionMode::Accumulate,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        }];
+        sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
+
+        assert_eq!(
+            sm.per_source_state[&1]["tags"].value,
+            StateValue::String("a,b".into())
+        );
+    }
+
+    #[test]
+    fn test_accumulate_mode_integers() {
+        let sources = vec![Source {
             id: 1,
             name: "src1".into(),
             template_id: 1,
@@ -1823,6 +4369,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::Integer(10),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
@@ -1836,8 +4384,11 @@ mod tests {
             pattern: Some(r"(?P<count>\d+)".into()),
             static_value: None,
             mode: ExtractionMode::Accumulate,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        sm.apply_mutations(1, &extractions, &rules, test_ts());
+        sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert_eq!(
             sm.per_source_state[&1]["count"].value,
@@ -1845,6 +4396,180 @@ mod tests {
         );
     }
 
+    /// Feed `values` for `key` through one extraction rule in `mode`,
+    /// returning the state after each line.
+    fn run_mode(mode: ExtractionMode, values: &[StateValue]) -> Vec<Option<StateValue>> {
+        let mut sm = StateManager::new(&make_sources());
+        let rules = vec![ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Parsed,
+            state_key: "v".into(),
+            pattern: Some(r"(?P<v>\S+)".into()),
+            static_value: None,
+            mode,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        }];
+        values
+            .iter()
+            .map(|v| {
+                let extractions = HashMap::from([("v".to_string(), v.clone())]);
+                sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
+                sm.get_state_by_name("server", "v").cloned()
+            })
+            .collect()
+    }
+
+    fn ints(values: &[i64]) -> Vec<StateValue> {
+        values.iter().map(|&i| StateValue::Integer(i)).collect()
+    }
+
+    #[test]
+    fn test_count_min_max_modes() {
+        let values = ints(&[5, 2, 9, 2]);
+        let last = |mode| run_mode(mode, &values).pop().flatten();
+        assert_eq!(last(ExtractionMode::Count), Some(StateValue::Integer(4)));
+        assert_eq!(last(ExtractionMode::Min), Some(StateValue::Integer(2)));
+        assert_eq!(last(ExtractionMode::Max), Some(StateValue::Integer(9)));
+        assert_eq!(
+            last(ExtractionMode::FirstSeen),
+            Some(StateValue::Integer(5))
+        );
+    }
+
+    #[test]
+    fn test_average_mode() {
+        let history = run_mode(ExtractionMode::Average, &ints(&[10, 20, 60]));
+        assert_eq!(
+            history,
+            vec![
+                Some(StateValue::Float(10.0)),
+                Some(StateValue::Float(15.0)),
+                Some(StateValue::Float(30.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_average_restarts_after_clear() {
+        let mut sm = StateManager::new(&make_sources());
+        let average = ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Parsed,
+            state_key: "v".into(),
+            pattern: Some(r"(?P<v>\d+)".into()),
+            static_value: None,
+            mode: ExtractionMode::Average,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        };
+        let clear = ExtractionRule {
+            extraction_type: ExtractionType::Clear,
+            ..average.clone()
+        };
+        let line = make_log_line("");
+        let value = |v: i64| HashMap::from([("v".to_string(), StateValue::Integer(v))]);
+        sm.apply_mutations(&line, 1, &value(10), std::slice::from_ref(&average));
+        sm.apply_mutations(&line, 1, &value(20), std::slice::from_ref(&average));
+        sm.apply_mutations(&line, 2, &HashMap::new(), &[clear]);
+        sm.apply_mutations(&line, 1, &value(40), std::slice::from_ref(&average));
+        assert_eq!(
+            sm.get_state_by_name("server", "v"),
+            Some(&StateValue::Float(40.0))
+        );
+
+        // An overwrite restarts the average even when it writes the current mean
+        let replace = ExtractionRule {
+            mode: ExtractionMode::Replace,
+            ..average.clone()
+        };
+        let mean = HashMap::from([("v".to_string(), StateValue::Float(40.0))]);
+        sm.apply_mutations(&line, 3, &mean, &[replace]);
+        sm.apply_mutations(&line, 1, &value(10), std::slice::from_ref(&average));
+        assert_eq!(
+            sm.get_state_by_name("server", "v"),
+            Some(&StateValue::Float(10.0))
+        );
+
+        // So does an expiry
+        let expiring = ExtractionRule {
+            ttl_ms: Some(1000),
+            ..average
+        };
+        sm.apply_mutations(&line, 1, &value(20), std::slice::from_ref(&expiring));
+        assert_eq!(
+            sm.expire(line.timestamp + chrono::TimeDelta::seconds(1))
+                .len(),
+            1
+        );
+        sm.apply_mutations(&line, 1, &value(30), &[expiring]);
+        assert_eq!(
+            sm.get_state_by_name("server", "v"),
+            Some(&StateValue::Float(30.0))
+        );
+    }
+
+    #[test]
+    fn test_distinct_set_and_bounded_list_modes() {
+        let codes: Vec<StateValue> = ["E1", "E2", "E1", "E3"]
+            .iter()
+            .map(|c| StateValue::String(c.to_string()))
+            .collect();
+        let set = run_mode(ExtractionMode::DistinctSet, &codes);
+        assert!(matches!(set.last(), Some(Some(StateValue::Set(items))) if items.len() == 3));
+        // Re-adding a member is not a change
+        assert_eq!(set[1], set[2]);
+
+        let list = run_mode(ExtractionMode::List { cap: 2 }, &ints(&[1, 2, 3]));
+        assert_eq!(list[2], Some(StateValue::List(ints(&[2, 3]))));
+    }
+
+    #[test]
+    fn test_first_seen_keeps_set_at() {
+        let mut sm = StateManager::new(&make_sources());
+        let rules = vec![ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Parsed,
+            state_key: "v".into(),
+            pattern: Some(r"(?P<v>\d+)".into()),
+            static_value: None,
+            mode: ExtractionMode::FirstSeen,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        }];
+        let mut first = make_log_line("");
+        first.line_number = 1;
+        let mut second = make_log_line("");
+        second.line_number = 2;
+        let value = HashMap::from([("v".to_string(), StateValue::Integer(1))]);
+        sm.apply_mutations(&first, 1, &value, &rules);
+        let changes = sm.apply_mutations(&second, 1, &value, &rules);
+        assert!(changes.is_empty());
+        assert_eq!(
+            sm.get_tracked_by_name("server", "v").unwrap().line_number,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_list_cap_must_be_positive() {
+        let rule = ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Parsed,
+            state_key: "v".into(),
+            pattern: Some(r"(?P<v>\d+)".into()),
+            static_value: None,
+            mode: ExtractionMode::List { cap: 0 },
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        };
+        assert!(validate_extraction_rule(&rule).is_err());
+    }
+
     #[test]
     fn test_clear_type() {
         let sources = vec![Source {
@@ -1859,6 +4584,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("val".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
@@ -1870,8 +4597,11 @@ mod tests {
             pattern: None,
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        sm.apply_mutations(1, &extractions, &rules, test_ts());
+        sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert!(!sm.per_source_state[&1].contains_key("key"));
     }
@@ -1885,6 +4615,7 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 1,
                 pattern: r"players: \d+".into(),
+                negate: false,
             }],
             extraction_rules: vec![ExtractionRule {
                 id: 1,
@@ -1893,7 +4624,13 @@ mod tests {
                 pattern: Some(r"players: (?P<player_count>\d+)".into()),
                 static_value: None,
                 mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
             }],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
         let line = make_log_line("server players: 42 online");
@@ -1910,6 +4647,7 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 1,
                 pattern: r"ERROR".into(),
+                negate: false,
             }],
             extraction_rules: vec![ExtractionRule {
                 id: 1,
@@ -1918,7 +4656,13 @@ mod tests {
                 pattern: None,
                 static_value: Some("error_detected".into()),
                 mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
             }],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
         let compiled = compile_one(&rule);
         let line = make_log_line("ERROR something");
@@ -1965,17 +4709,22 @@ mod tests {
                     state_key: "status".into(),
                     operator: Operator::Eq,
                     operand: Operand::Literal(StateValue::String("running".into())),
+                    threshold: None,
                 },
                 PatternPredicate {
                     source_name: "server".into(),
                     state_key: "players".into(),
                     operator: Operator::Gt,
                     operand: Operand::Literal(StateValue::Integer(0)),
+                    threshold: None,
                 },
             ],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
         let patterns = vec![pattern];
-        let mut eval = PatternEvaluator::new(&patterns);
+        let mut eval = PatternEvaluator::new(&patterns).unwrap();
 
         // Pred 1 not yet satisfied
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -1987,6 +4736,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("running".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -1998,6 +4749,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::Integer(5),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -2019,17 +4772,22 @@ mod tests {
                     state_key: "status".into(),
                     operator: Operator::Eq,
                     operand: Operand::Literal(StateValue::String("running".into())),
+                    threshold: None,
                 },
                 PatternPredicate {
                     source_name: "server".into(),
                     state_key: "count".into(),
                     operator: Operator::Gt,
                     operand: Operand::Literal(StateValue::Integer(10)),
+                    threshold: None,
                 },
             ],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
         let patterns = vec![pattern];
-        let mut eval = PatternEvaluator::new(&patterns);
+        let mut eval = PatternEvaluator::new(&patterns).unwrap();
 
         // Satisfy pred 1
         Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
@@ -2037,6 +4795,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("running".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         eval.evaluate_patterns(&patterns, &sm);
@@ -2048,6 +4808,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("stopped".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         Arc::make_mut(sm.per_source_state.get_mut(&1).unwrap()).insert(
@@ -2055,6 +4817,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::Integer(20),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -2076,10 +4840,14 @@ mod tests {
                 state_key: "flag".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Bool(true)),
+                threshold: None,
             }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
         let patterns = vec![pattern];
-        let mut eval = PatternEvaluator::new(&patterns);
+        let mut eval = PatternEvaluator::new(&patterns).unwrap();
 
         // Set flag=true -> should match
         Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
@@ -2087,6 +4855,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::Bool(true),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -2116,10 +4886,14 @@ mod tests {
                     source_name: "client".into(),
                     state_key: "region".into(),
                 },
+                threshold: None,
             }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
         let patterns = vec![pattern];
-        let mut eval = PatternEvaluator::new(&patterns);
+        let mut eval = PatternEvaluator::new(&patterns).unwrap();
 
         // Different regions -> no match
         Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
@@ -2127,6 +4901,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("us-east".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         Arc::make_mut(sm.per_source_state.entry(2).or_default()).insert(
@@ -2134,6 +4910,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("eu-west".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -2145,6 +4923,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("us-east".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         let matches = eval.evaluate_patterns(&patterns, &sm);
@@ -2160,6 +4940,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::Integer(10),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
@@ -2167,9 +4949,19 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("hello world".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
+        let regexes = HashMap::new();
+        let exprs = HashMap::new();
+        let env = PredicateEnv {
+            regexes: &regexes,
+            exprs: &exprs,
+            now: None,
+        };
+
         // Eq
         assert!(evaluate_predicate(
             &PatternPredicate {
@@ -2177,8 +4969,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Integer(10)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Neq
@@ -2188,8 +4982,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Neq,
                 operand: Operand::Literal(StateValue::Integer(5)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Gt
@@ -2199,8 +4995,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Gt,
                 operand: Operand::Literal(StateValue::Integer(5)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Lt
@@ -2210,8 +5008,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Lt,
                 operand: Operand::Literal(StateValue::Integer(20)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Gte (equal case)
@@ -2221,8 +5021,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Gte,
                 operand: Operand::Literal(StateValue::Integer(10)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Lte (equal case)
@@ -2232,8 +5034,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Lte,
                 operand: Operand::Literal(StateValue::Integer(10)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Contains
@@ -2243,8 +5047,10 @@ mod tests {
                 state_key: "name".into(),
                 operator: Operator::Contains,
                 operand: Operand::Literal(StateValue::String("world".into())),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Exists
@@ -2254,8 +5060,10 @@ mod tests {
                 state_key: "val".into(),
                 operator: Operator::Exists,
                 operand: Operand::Literal(StateValue::Bool(false)),
+                threshold: None,
             },
             &sm,
+            &env,
         ));
 
         // Exists - false case
@@ -2265,9 +5073,489 @@ mod tests {
                 state_key: "nonexistent".into(),
                 operator: Operator::Exists,
                 operand: Operand::Literal(StateValue::Bool(false)),
+                threshold: None,
             },
             &sm,
+            &env,
+        ));
+    }
+
+    #[test]
+    fn test_extended_operators() {
+        let sources = make_sources();
+        let mut sm = StateManager::new(&sources);
+        for (key, value) in [
+            ("code", StateValue::Integer(503)),
+            ("path", StateValue::String("/api/v1/users".into())),
+        ] {
+            Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
+                key.into(),
+                TrackedValue {
+                    value,
+                    set_at: test_ts(),
+                    rule_id: 0,
+                    line_number: None,
+                },
+            );
+        }
+        let pred = |key: &str, operator: Operator, operand: Operand| PatternPredicate {
+            source_name: "server".into(),
+            state_key: key.into(),
+            operator,
+            operand,
+            threshold: None,
+        };
+        let regex_pred = pred(
+            "code",
+            Operator::Matches,
+            Operand::Literal(StateValue::String(r"^5\d\d$".into())),
+        );
+        let eval = PatternEvaluator::new(&[Pattern {
+            id: 1,
+            name: "p".into(),
+            predicates: vec![regex_pred.clone()],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        }])
+        .unwrap();
+        let env = PredicateEnv {
+            now: Some(test_ts() + chrono::Duration::seconds(5)),
+            ..eval.env()
+        };
+        let holds = |p: &PatternPredicate| evaluate_predicate(p, &sm, &env);
+        let str_lit = |s: &str| Operand::Literal(StateValue::String(s.into()));
+        let int_lit = |i: i64| Operand::Literal(StateValue::Integer(i));
+
+        assert!(holds(&regex_pred));
+        assert!(holds(&pred("path", Operator::StartsWith, str_lit("/api/"))));
+        assert!(!holds(&pred("path", Operator::EndsWith, str_lit("/api"))));
+        assert!(holds(&pred("path", Operator::EndsWith, str_lit("users"))));
+
+        let codes = Operand::List(vec![StateValue::Integer(502), StateValue::Integer(503)]);
+        assert!(holds(&pred("code", Operator::In, codes.clone())));
+        assert!(!holds(&pred("code", Operator::NotIn, codes.clone())));
+        // NotIn still requires the key to be set
+        assert!(!holds(&pred("missing", Operator::NotIn, codes)));
+
+        let range = |min: i64, max: i64| Operand::Range {
+            min: StateValue::Integer(min),
+            max: StateValue::Integer(max),
+        };
+        assert!(holds(&pred("code", Operator::Between, range(500, 503))));
+        assert!(!holds(&pred("code", Operator::Between, range(400, 499))));
+
+        assert!(holds(&pred("missing", Operator::NotExists, int_lit(0))));
+        assert!(!holds(&pred("code", Operator::NotExists, int_lit(0))));
+
+        // Values were set 5s before the current line
+        assert!(holds(&pred("code", Operator::OlderThan, int_lit(1_000))));
+        assert!(!holds(&pred("code", Operator::OlderThan, int_lit(10_000))));
+        assert!(holds(&pred("code", Operator::NewerThan, int_lit(10_000))));
+        assert!(holds(&pred("code", Operator::NewerThan, int_lit(i64::MAX))));
+        // An age limit past what a duration can hold is never satisfied
+        assert!(!holds(&pred(
+            "code",
+            Operator::OlderThan,
+            int_lit(i64::MIN)
+        )));
+        assert!(!holds(&pred(
+            "code",
+            Operator::NewerThan,
+            int_lit(i64::MIN)
+        )));
+        assert!(!holds(&pred(
+            "missing",
+            Operator::NewerThan,
+            int_lit(10_000)
+        )));
+    }
+
+    #[test]
+    fn test_expression_operand_across_sources() {
+        let sources = make_sources();
+        let mut sm = StateManager::new(&sources);
+        for (source_id, key, value) in [
+            (1, "max_retries", StateValue::Integer(3)),
+            (2, "retry_count", StateValue::Integer(5)),
+        ] {
+            Arc::make_mut(sm.per_source_state.entry(source_id).or_default()).insert(
+                key.into(),
+                TrackedValue {
+                    value,
+                    set_at: test_ts(),
+                    rule_id: 0,
+                    line_number: None,
+                },
+            );
+        }
+        let pattern = |expr: &str| Pattern {
+            id: 1,
+            name: "retry_storm".into(),
+            predicates: vec![PatternPredicate {
+                source_name: "client".into(),
+                state_key: "retry_count".into(),
+                operator: Operator::Gt,
+                operand: Operand::Expr(expr.into()),
+                threshold: None,
+            }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        };
+
+        let fires = |expr: &str| {
+            let patterns = vec![pattern(expr)];
+            let mut eval = PatternEvaluator::new(&patterns).unwrap();
+            !eval.evaluate_patterns(&patterns, &sm).is_empty()
+        };
+        assert!(fires("server.max_retries + 1"));
+        assert!(!fires("server.max_retries + 2"));
+        assert!(fires("server.max_retries * 1.5"));
+        assert!(!fires("abs(-server.max_retries) * 2"));
+        // Unset references evaluate to nothing, so the step does not hold
+        assert!(!fires("server.missing + 1"));
+
+        // Type errors surface when the evaluator is built
+        assert!(PatternEvaluator::new(&[pattern("server.max_retries * 'x'")]).is_err());
+        assert!(validate_predicate(&pattern("len(3)").predicates[0]).is_err());
+    }
+
+    #[test]
+    fn test_collection_predicates() {
+        let sources = make_sources();
+        let mut sm = StateManager::new(&sources);
+        let strings = |items: &[&str]| {
+            items
+                .iter()
+                .map(|s| StateValue::String(s.to_string()))
+                .collect::<Vec<_>>()
+        };
+        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
+            "codes".into(),
+            TrackedValue {
+                value: StateValue::Set(strings(&["E1", "E20"])),
+                set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
+            },
+        );
+
+        let regexes = HashMap::new();
+        let exprs = HashMap::new();
+        let env = PredicateEnv {
+            regexes: &regexes,
+            exprs: &exprs,
+            now: None,
+        };
+        let holds = |operator: Operator, operand: StateValue| {
+            evaluate_predicate(
+                &PatternPredicate {
+                    source_name: "server".into(),
+                    state_key: "codes".into(),
+                    operator,
+                    operand: Operand::Literal(operand),
+                    threshold: None,
+                },
+                &sm,
+                &env,
+            )
+        };
+
+        // Contains tests membership, not substrings of the rendered set
+        assert!(holds(Operator::Contains, StateValue::String("E1".into())));
+        assert!(!holds(Operator::Contains, StateValue::String("E2".into())));
+        assert!(holds(Operator::SizeEq, StateValue::Integer(2)));
+        assert!(holds(Operator::SizeGt, StateValue::Integer(1)));
+        assert!(!holds(Operator::SizeLt, StateValue::Integer(2)));
+        // Set equality ignores order
+        assert!(holds(
+            Operator::Eq,
+            StateValue::Set(strings(&["E20", "E1"]))
+        ));
+        assert!(!holds(
+            Operator::Eq,
+            StateValue::List(strings(&["E1", "E20"]))
+        ));
+
+        let size_pred = |operand: Operand| PatternPredicate {
+            source_name: "server".into(),
+            state_key: "codes".into(),
+            operator: Operator::SizeGt,
+            operand,
+            threshold: None,
+        };
+        assert!(validate_predicate(&size_pred(Operand::Literal(StateValue::Integer(2)))).is_ok());
+        assert!(validate_predicate(&size_pred(Operand::Literal(StateValue::Integer(-1)))).is_err());
+        assert!(
+            validate_predicate(&size_pred(Operand::Literal(StateValue::String("2".into()))))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_time_value_predicates() {
+        let mut sm = StateManager::new(&make_sources());
+        let state = Arc::make_mut(sm.per_source_state.entry(1).or_default());
+        for (key, value) in [
+            ("took", StateValue::Duration(7_500)),
+            ("deployed_at", StateValue::Timestamp(test_ts())),
+        ] {
+            state.insert(
+                key.into(),
+                TrackedValue {
+                    value,
+                    set_at: test_ts(),
+                    rule_id: 0,
+                    line_number: None,
+                },
+            );
+        }
+
+        let regexes = HashMap::new();
+        let exprs = HashMap::new();
+        let env = PredicateEnv {
+            regexes: &regexes,
+            exprs: &exprs,
+            now: None,
+        };
+        let holds = |key: &str, operator: Operator, operand: Operand| {
+            evaluate_predicate(
+                &PatternPredicate {
+                    source_name: "server".into(),
+                    state_key: key.into(),
+                    operator,
+                    operand,
+                    threshold: None,
+                },
+                &sm,
+                &env,
+            )
+        };
+        let text = |s: &str| Operand::Literal(StateValue::String(s.into()));
+
+        assert!(holds("took", Operator::Gt, text("5s")));
+        assert!(!holds("took", Operator::Gt, text("1m")));
+        assert!(holds("took", Operator::Eq, text("7.5s")));
+        assert!(holds(
+            "took",
+            Operator::Lt,
+            Operand::Literal(StateValue::Duration(8_000))
+        ));
+        assert!(holds(
+            "deployed_at",
+            Operator::Between,
+            Operand::Range {
+                min: StateValue::String("2023-12-31".into()),
+                max: StateValue::String("2024-01-01T01:00:00+01:00".into()),
+            }
+        ));
+        assert!(!holds(
+            "deployed_at",
+            Operator::Gt,
+            text("2024-01-01 00:00:00")
+        ));
+    }
+
+    #[test]
+    fn test_duration_accumulate_and_display() {
+        let history = run_mode(
+            ExtractionMode::Accumulate,
+            &[StateValue::Duration(1_500), StateValue::Duration(3_601_000)],
+        );
+        assert_eq!(history[1], Some(StateValue::Duration(3_602_500)));
+        // Totals saturate instead of overflowing
+        let history = run_mode(
+            ExtractionMode::Accumulate,
+            &[StateValue::Duration(i64::MAX), StateValue::Duration(1)],
+        );
+        assert_eq!(history[1], Some(StateValue::Duration(i64::MAX)));
+        let history = run_mode(
+            ExtractionMode::Accumulate,
+            &[StateValue::Integer(i64::MIN), StateValue::Integer(-1)],
+        );
+        assert_eq!(history[1], Some(StateValue::Integer(i64::MIN)));
+        assert_eq!(StateValue::Duration(3_602_500).to_string(), "1h2.5s");
+        assert_eq!(StateValue::Duration(250).to_string(), "250ms");
+        assert_eq!(StateValue::Duration(-90_000).to_string(), "-1m30s");
+        assert_eq!(
+            StateValue::Timestamp(test_ts()).to_string(),
+            test_ts().to_string()
+        );
+    }
+
+    #[test]
+    fn test_collection_display() {
+        let list = StateValue::List(vec![StateValue::Integer(1), StateValue::Integer(2)]);
+        let set = StateValue::Set(vec![StateValue::String("a".into())]);
+        assert_eq!(list.to_string(), "[1, 2]");
+        assert_eq!(set.to_string(), "{a}");
+    }
+
+    #[test]
+    fn test_temporal_predicates_across_sources() {
+        let sources = make_sources();
+        let mut sm = StateManager::new(&sources);
+        for (source_id, offset_ms) in [(1, 1_500), (2, 0)] {
+            Arc::make_mut(sm.per_source_state.entry(source_id).or_default()).insert(
+                "request".into(),
+                TrackedValue {
+                    value: StateValue::String("r1".into()),
+                    set_at: test_ts() + chrono::Duration::milliseconds(offset_ms),
+                    rule_id: 0,
+                    line_number: None,
+                },
+            );
+        }
+        let regexes = HashMap::new();
+        let exprs = HashMap::new();
+        let env = PredicateEnv {
+            regexes: &regexes,
+            exprs: &exprs,
+            now: None,
+        };
+        // server.request against client.request, which was set 1.5s earlier
+        let holds = |operator: Operator, key: &str, within_ms: Option<u64>, skew_ms: i64| {
+            let pred = PatternPredicate {
+                source_name: "server".into(),
+                state_key: "request".into(),
+                operator,
+                operand: Operand::TimeRef {
+                    source_name: "client".into(),
+                    state_key: key.into(),
+                    within_ms,
+                    skew_ms,
+                },
+                threshold: None,
+            };
+            assert!(validate_predicate(&pred).is_ok());
+            evaluate_predicate(&pred, &sm, &env)
+        };
+
+        assert!(holds(Operator::SetAfter, "request", None, 0));
+        assert!(!holds(Operator::SetBefore, "request", None, 0));
+        assert!(holds(Operator::SetWithin, "request", Some(2_000), 0));
+        assert!(!holds(Operator::SetWithin, "request", Some(1_000), 0));
+        assert!(!holds(Operator::SetAfter, "request", Some(1_000), 0));
+        // The client clock runs 2s behind, so the server actually saw it first
+        assert!(holds(Operator::SetBefore, "request", None, 2_000));
+        assert!(!holds(Operator::SetAfter, "request", None, 2_000));
+        assert!(!holds(Operator::SetAfter, "missing", None, 0));
+
+        let no_window = PatternPredicate {
+            source_name: "server".into(),
+            state_key: "request".into(),
+            operator: Operator::SetWithin,
+            operand: Operand::TimeRef {
+                source_name: "client".into(),
+                state_key: "request".into(),
+                within_ms: None,
+                skew_ms: 0,
+            },
+            threshold: None,
+        };
+        assert!(validate_predicate(&no_window).is_err());
+
+        // Out-of-range spans are rejected up front and never panic if they
+        // reach the evaluator anyway
+        for (within_ms, skew_ms) in [
+            (Some(u64::MAX), 0),
+            (Some(MAX_SPAN_MS + 1), 0),
+            (None, i64::MIN),
+            (None, i64::MAX),
+            (None, -(MAX_SPAN_MS as i64) - 1),
+        ] {
+            let pred = PatternPredicate {
+                source_name: "server".into(),
+                state_key: "request".into(),
+                operator: Operator::SetAfter,
+                operand: Operand::TimeRef {
+                    source_name: "client".into(),
+                    state_key: "request".into(),
+                    within_ms,
+                    skew_ms,
+                },
+                threshold: None,
+            };
+            assert!(validate_predicate(&pred).is_err());
+            evaluate_predicate(&pred, &sm, &env);
+        }
+        assert!(holds(Operator::SetWithin, "request", Some(MAX_SPAN_MS), 0));
+        assert!(holds(
+            Operator::SetBefore,
+            "request",
+            None,
+            MAX_SPAN_MS as i64
+        ));
+    }
+
+    #[test]
+    fn test_validate_predicate_operand_shapes() {
+        let pred = |operator: Operator, operand: Operand| PatternPredicate {
+            source_name: "server".into(),
+            state_key: "code".into(),
+            operator,
+            operand,
+            threshold: None,
+        };
+        let bad_regex = pred(
+            Operator::Matches,
+            Operand::Literal(StateValue::String("(unclosed".into())),
+        );
+        assert!(matches!(
+            validate_predicate(&bad_regex),
+            Err(AnalysisError::InvalidRegex(_))
         ));
+        assert!(
+            PatternEvaluator::new(&[Pattern {
+                id: 1,
+                name: "p".into(),
+                predicates: vec![bad_regex],
+                cooldown_ms: None,
+                outcome: PatternOutcome::default(),
+                parent: None,
+            }])
+            .is_err()
+        );
+        let in_literal = pred(Operator::In, Operand::Literal(StateValue::Integer(1)));
+        assert!(validate_predicate(&in_literal).is_err());
+        // The evaluator checks operand shapes itself rather than trusting callers
+        assert!(
+            PatternEvaluator::new(&[Pattern {
+                id: 1,
+                name: "p".into(),
+                predicates: vec![in_literal],
+                cooldown_ms: None,
+                outcome: PatternOutcome::default(),
+                parent: None,
+            }])
+            .is_err()
+        );
+        assert!(
+            validate_predicate(&pred(
+                Operator::Between,
+                Operand::Range {
+                    min: StateValue::Integer(10),
+                    max: StateValue::Integer(1),
+                }
+            ))
+            .is_err()
+        );
+        assert!(
+            validate_predicate(&pred(
+                Operator::OlderThan,
+                Operand::Literal(StateValue::Integer(-1))
+            ))
+            .is_err()
+        );
+        assert!(validate_predicate(&pred(Operator::Eq, Operand::List(vec![]))).is_err());
+        assert!(
+            validate_predicate(&pred(
+                Operator::NotIn,
+                Operand::List(vec![StateValue::Integer(1)])
+            ))
+            .is_ok()
+        );
     }
 
     // -----------------------------------------------------------------------
@@ -2295,6 +5583,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         }
     }
 
@@ -2400,6 +5689,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
 
         let sources = vec![
@@ -2425,6 +5715,7 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 1,
                 pattern: r"region \w+".into(),
+                negate: false,
             }],
             extraction_rules: vec![ExtractionRule {
                 id: 1,
@@ -2433,7 +5724,13 @@ mod tests {
                 pattern: Some(r"region (?P<region>\S+)".into()),
                 static_value: None,
                 mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
             }],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
 
         // Rule: extract player count
@@ -2444,6 +5741,7 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 2,
                 pattern: r"Players online: \d+".into(),
+                negate: false,
             }],
             extraction_rules: vec![ExtractionRule {
                 id: 2,
@@ -2452,7 +5750,13 @@ mod tests {
                 pattern: Some(r"Players online: (?P<player_count>\d+)".into()),
                 static_value: None,
                 mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
             }],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
 
         // Rule: extract client region
@@ -2463,6 +5767,7 @@ mod tests {
             match_rules: vec![MatchRule {
                 id: 3,
                 pattern: r"connecting to region".into(),
+                negate: false,
             }],
             extraction_rules: vec![ExtractionRule {
                 id: 3,
@@ -2471,7 +5776,13 @@ mod tests {
                 pattern: Some(r"region (?P<region>\S+)".into()),
                 static_value: None,
                 mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
             }],
+            guard: None,
+            script: None,
+            plugin_id: None,
         };
 
         let rules = vec![server_region_rule, player_count_rule, client_region_rule];
@@ -2480,7 +5791,10 @@ mod tests {
             id: 1,
             name: "server_rules".into(),
             template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
             rule_ids: vec![1, 2, 3],
+            stop_rule_ids: vec![],
         }];
 
         // Pattern: detect when server and client are in same region AND player count > 50
@@ -2496,14 +5810,19 @@ mod tests {
                         source_name: "client".into(),
                         state_key: "region".into(),
                     },
+                    threshold: None,
                 },
                 PatternPredicate {
                     source_name: "server".into(),
                     state_key: "player_count".into(),
                     operator: Operator::Gt,
                     operand: Operand::Literal(StateValue::Integer(50)),
+                    threshold: None,
                 },
             ],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
 
         let result = analyze(
@@ -2583,6 +5902,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
 
         let sources = vec![
@@ -2608,6 +5928,7 @@ mod tests {
                 match_rules: vec![MatchRule {
                     id: 1,
                     pattern: r"region \w+".into(),
+                    negate: false,
                 }],
                 extraction_rules: vec![ExtractionRule {
                     id: 1,
@@ -2616,7 +5937,13 @@ mod tests {
                     pattern: Some(r"region (?P<region>\S+)".into()),
                     static_value: None,
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                guard: None,
+                script: None,
+                plugin_id: None,
             },
             LogRule {
                 id: 2,
@@ -2625,6 +5952,7 @@ mod tests {
                 match_rules: vec![MatchRule {
                     id: 2,
                     pattern: r"Players online: \d+".into(),
+                    negate: false,
                 }],
                 extraction_rules: vec![ExtractionRule {
                     id: 2,
@@ -2633,7 +5961,13 @@ mod tests {
                     pattern: Some(r"Players online: (?P<player_count>\d+)".into()),
                     static_value: None,
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                guard: None,
+                script: None,
+                plugin_id: None,
             },
             LogRule {
                 id: 3,
@@ -2642,6 +5976,7 @@ mod tests {
                 match_rules: vec![MatchRule {
                     id: 3,
                     pattern: r"connecting to region".into(),
+                    negate: false,
                 }],
                 extraction_rules: vec![ExtractionRule {
                     id: 3,
@@ -2650,7 +5985,13 @@ mod tests {
                     pattern: Some(r"region (?P<region>\S+)".into()),
                     static_value: None,
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                guard: None,
+                script: None,
+                plugin_id: None,
             },
         ];
 
@@ -2658,7 +5999,10 @@ mod tests {
             id: 1,
             name: "server_rules".into(),
             template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
             rule_ids: vec![1, 2, 3],
+            stop_rule_ids: vec![],
         }];
 
         let pattern = Pattern {
@@ -2673,27 +6017,37 @@ mod tests {
                         source_name: "client".into(),
                         state_key: "region".into(),
                     },
+                    threshold: None,
                 },
                 PatternPredicate {
                     source_name: "server".into(),
                     state_key: "player_count".into(),
                     operator: Operator::Gt,
                     operand: Operand::Literal(StateValue::Integer(50)),
+                    threshold: None,
                 },
             ],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         };
 
         // Run streaming analysis
         let (tx, rx) = std::sync::mpsc::channel();
         analyze_streaming(
             &sources,
+            &FileSource,
             std::slice::from_ref(&template),
             std::slice::from_ref(&ts_template),
             &rules,
             &rulesets,
             std::slice::from_ref(&pattern),
+            &[],
+            &[],
+            &[],
             tx,
             &TimeRange::default(),
+            &AnalysisOptions::default(),
         )
         .unwrap();
 
@@ -2739,6 +6093,8 @@ mod tests {
             total_rule_matches,
             total_pattern_matches,
             total_state_changes,
+            profile,
+            coverage,
         } = &complete_events[0]
         {
             assert_eq!(*total_lines, 5);
@@ -2748,6 +6104,7 @@ mod tests {
                 sync_result.pattern_matches.len() as u64
             );
             assert_eq!(*total_state_changes, sync_result.state_changes.len() as u64);
+            assert!(profile.is_none() && coverage.is_none());
         } else {
             panic!("expected Complete event");
         }
@@ -2771,6 +6128,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("old".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
@@ -2782,8 +6141,11 @@ mod tests {
             pattern: None,
             static_value: Some("new".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        let changes = sm.apply_mutations(1, &extractions, &rules, test_ts());
+        let changes = sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].0, "key");
@@ -2805,6 +6167,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("val".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
@@ -2816,8 +6180,11 @@ mod tests {
             pattern: None,
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        let changes = sm.apply_mutations(1, &extractions, &rules, test_ts());
+        let changes = sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].0, "key");
@@ -2843,8 +6210,11 @@ mod tests {
             pattern: None,
             static_value: Some("val".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        let changes = sm.apply_mutations(1, &extractions, &rules, test_ts());
+        let changes = sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert_eq!(changes.len(), 1);
         assert_eq!(changes[0].0, "key");
@@ -2866,6 +6236,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("same".into()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
 
@@ -2877,8 +6249,11 @@ mod tests {
             pattern: None,
             static_value: Some("same".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }];
-        let changes = sm.apply_mutations(1, &extractions, &rules, test_ts());
+        let changes = sm.apply_mutations(&make_log_line(""), 1, &extractions, &rules);
 
         assert!(changes.is_empty());
     }
@@ -2904,6 +6279,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
 
         let sources = vec![Source {
@@ -2921,6 +6297,7 @@ mod tests {
                 match_rules: vec![MatchRule {
                     id: 1,
                     pattern: r"region \w+".into(),
+                    negate: false,
                 }],
                 extraction_rules: vec![ExtractionRule {
                     id: 1,
@@ -2929,7 +6306,13 @@ mod tests {
                     pattern: Some(r"region (?P<region>\S+)".into()),
                     static_value: None,
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                guard: None,
+                script: None,
+                plugin_id: None,
             },
             LogRule {
                 id: 2,
@@ -2938,6 +6321,7 @@ mod tests {
                 match_rules: vec![MatchRule {
                     id: 2,
                     pattern: r"Players online: \d+".into(),
+                    negate: false,
                 }],
                 extraction_rules: vec![ExtractionRule {
                     id: 2,
@@ -2946,7 +6330,13 @@ mod tests {
                     pattern: Some(r"Players online: (?P<player_count>\d+)".into()),
                     static_value: None,
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                guard: None,
+                script: None,
+                plugin_id: None,
             },
         ];
 
@@ -2954,19 +6344,27 @@ mod tests {
             id: 1,
             name: "server_rules".into(),
             template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
             rule_ids: vec![1, 2],
+            stop_rule_ids: vec![],
         }];
 
         let (tx, rx) = std::sync::mpsc::channel();
         analyze_streaming(
             &sources,
+            &FileSource,
             std::slice::from_ref(&template),
             std::slice::from_ref(&ts_template),
             &rules,
             &rulesets,
             &[],
+            &[],
+            &[],
+            &[],
             tx,
             &TimeRange::default(),
+            &AnalysisOptions::default(),
         )
         .unwrap();
 
@@ -2989,6 +6387,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_streaming_marks_superseded_matches() {
+        let log: String = CLIENT_LINES
+            .iter()
+            .map(|l| format!("2024-01-01 {l}\n"))
+            .collect();
+        let logs: MemorySource = [(1, log.into_bytes())].into_iter().collect();
+        let (_f, _, template, ts_template, _, rulesets) = make_time_range_test_data();
+        let source = Source {
+            id: 1,
+            name: "server".into(),
+            template_id: 1,
+            file_path: String::new(),
+        };
+        let rules = [client_error_rule()];
+        let patterns = [
+            client_pattern(1, &["a"]),
+            child_of(client_pattern(2, &["b"]), 1, Some(5_000)),
+        ];
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        analyze_streaming(
+            std::slice::from_ref(&source),
+            &logs,
+            std::slice::from_ref(&template),
+            std::slice::from_ref(&ts_template),
+            &rules,
+            &rulesets,
+            &patterns,
+            &[],
+            &[],
+            &[],
+            tx,
+            &TimeRange::default(),
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        // Replay the events the way a streaming client would
+        let mut streamed: Vec<PatternMatch> = Vec::new();
+        for event in rx.iter() {
+            match event {
+                AnalysisEvent::PatternMatch(pm) => streamed.push(pm),
+                AnalysisEvent::Superseded { match_index } => {
+                    assert!(streamed[match_index as usize].refines.is_none());
+                    streamed[match_index as usize].superseded = true;
+                }
+                _ => {}
+            }
+        }
+        let summary = |matches: &[PatternMatch]| -> Vec<(u64, bool)> {
+            matches
+                .iter()
+                .map(|pm| (pm.pattern_id, pm.superseded))
+                .collect()
+        };
+        assert_eq!(
+            summary(&streamed),
+            vec![(1, false), (1, true), (2, false), (1, false)]
+        );
+
+        let collected = analyze_server_lines(
+            &CLIENT_LINES,
+            &rules,
+            &patterns,
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(summary(&collected.pattern_matches), summary(&streamed));
+    }
+
     // -----------------------------------------------------------------------
     // Multi-line continuation tests
     // -----------------------------------------------------------------------
@@ -3013,6 +6483,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
@@ -3064,6 +6535,10 @@ mod tests {
             lines[2].timestamp,
             NaiveDateTime::parse_from_str("2024-01-15 10:00:06", "%Y-%m-%d %H:%M:%S").unwrap()
         );
+
+        // Line numbers refer to the head physical line of each entry
+        let numbers: Vec<u64> = lines.iter().map(|l| l.line_number).collect();
+        assert_eq!(numbers, vec![1, 2, 5]);
     }
 
     #[test]
@@ -3120,6 +6595,7 @@ mod tests {
             json_timestamp_field: Some("timestamp".into()),
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
@@ -3161,6 +6637,7 @@ mod tests {
             json_timestamp_field: Some("timestamp".into()),
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
@@ -3194,225 +6671,1942 @@ mod tests {
             json_timestamp_field: Some("timestamp".into()),
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
-            name: "json_src".into(),
+            name: "json_src".into(),
+            template_id: 1,
+            file_path: f.path().to_str().unwrap().into(),
+        };
+
+        let result = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &[],
+            &[],
+            &[],
+            &TimeRange::default(),
+        )
+        .unwrap();
+
+        // Should have state changes for all JSON fields across all lines
+        assert!(
+            !result.state_changes.is_empty(),
+            "expected state changes from JSON auto-extraction"
+        );
+
+        // Check specific field types by looking at state changes
+        let port_changes: Vec<_> = result
+            .state_changes
+            .iter()
+            .filter(|sc| sc.state_key == "port")
+            .collect();
+        assert!(!port_changes.is_empty(), "expected port state change");
+        assert_eq!(port_changes[0].new_value, Some(StateValue::Integer(8080)));
+
+        let usage_changes: Vec<_> = result
+            .state_changes
+            .iter()
+            .filter(|sc| sc.state_key == "usage_pct")
+            .collect();
+        assert!(!usage_changes.is_empty(), "expected usage_pct state change");
+        assert_eq!(usage_changes[0].new_value, Some(StateValue::Float(85.5)));
+
+        let success_changes: Vec<_> = result
+            .state_changes
+            .iter()
+            .filter(|sc| sc.state_key == "success")
+            .collect();
+        assert!(!success_changes.is_empty(), "expected success state change");
+        assert_eq!(success_changes[0].new_value, Some(StateValue::Bool(true)));
+
+        // rule_id should be 0 for JSON auto-extracted state changes
+        for sc in &result.state_changes {
+            assert_eq!(
+                sc.rule_id, 0,
+                "JSON auto-extracted state changes should have rule_id=0"
+            );
+        }
+    }
+
+    // -------------------------------------------------------------------
+    // Time-range filtering tests
+    // -------------------------------------------------------------------
+
+    fn make_time_range_test_data() -> (
+        NamedTempFile,
+        Source,
+        SourceTemplate,
+        TimestampTemplate,
+        Vec<LogRule>,
+        Vec<Ruleset>,
+    ) {
+        let mut f = NamedTempFile::new().unwrap();
+        // Lines at 00:01 through 00:05
+        for min in 1..=5 {
+            writeln!(f, "2024-01-01 00:{min:02}:00 event_{min}").unwrap();
+        }
+        f.flush().unwrap();
+
+        let ts_template = TimestampTemplate {
+            id: 1,
+            name: "ts".into(),
+            format: "%Y-%m-%d %H:%M:%S".into(),
+            extraction_regex: None,
+            default_year: None,
+        };
+        let template = SourceTemplate {
+            id: 1,
+            name: "tmpl".into(),
+            timestamp_template_id: 1,
+            line_delimiter: "\n".into(),
+            content_regex: Some(r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2} (.+)$".into()),
+            continuation_regex: None,
+            json_timestamp_field: None,
+            file_name_regex: None,
+            log_content_regex: None,
+            parser_plugin_id: None,
+        };
+        let source = Source {
+            id: 1,
+            name: "src".into(),
+            template_id: 1,
+            file_path: f.path().to_str().unwrap().into(),
+        };
+        let rules = vec![LogRule {
+            id: 1,
+            name: "match_event".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"event_\d+".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        }];
+        let rulesets = vec![Ruleset {
+            id: 1,
+            name: "rs".into(),
+            template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
+            rule_ids: vec![1],
+            stop_rule_ids: vec![],
+        }];
+        (f, source, template, ts_template, rules, rulesets)
+    }
+
+    #[test]
+    fn test_time_range_filtering() {
+        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
+        let time_range = TimeRange {
+            start: Some(
+                NaiveDateTime::parse_from_str("2024-01-01 00:02:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            ),
+            end: Some(
+                NaiveDateTime::parse_from_str("2024-01-01 00:04:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            ),
+        };
+        let result = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &rules,
+            &rulesets,
+            &[],
+            &time_range,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rule_matches.len(),
+            3,
+            "expected matches for events 2, 3, 4"
+        );
+    }
+
+    #[test]
+    fn test_time_range_start_only() {
+        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
+        let time_range = TimeRange {
+            start: Some(
+                NaiveDateTime::parse_from_str("2024-01-01 00:04:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            ),
+            end: None,
+        };
+        let result = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &rules,
+            &rulesets,
+            &[],
+            &time_range,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rule_matches.len(),
+            2,
+            "expected matches for events 4, 5"
+        );
+    }
+
+    #[test]
+    fn test_time_range_end_only() {
+        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
+        let time_range = TimeRange {
+            start: None,
+            end: Some(
+                NaiveDateTime::parse_from_str("2024-01-01 00:02:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            ),
+        };
+        let result = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &rules,
+            &rulesets,
+            &[],
+            &time_range,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rule_matches.len(),
+            2,
+            "expected matches for events 1, 2"
+        );
+    }
+
+    #[test]
+    fn test_time_range_default() {
+        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
+        let result = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &rules,
+            &rulesets,
+            &[],
+            &TimeRange::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.rule_matches.len(),
+            5,
+            "default TimeRange should return all matches"
+        );
+    }
+
+    // -----------------------------------------------------------------------
+    // Threshold step tests
+    // -----------------------------------------------------------------------
+
+    /// An ERROR rule (id 1) that extracts `client` from "from <client>".
+    fn client_error_rule() -> LogRule {
+        LogRule {
+            id: 1,
+            name: "error".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"ERROR".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "client".into(),
+                pattern: Some(r"from (?P<client>\w+)".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        }
+    }
+
+    /// Analyze `lines` (each "HH:MM:SS message", on 2024-01-01) from one
+    /// `server` source with every rule in one ruleset.
+    fn analyze_server_lines(
+        lines: &[&str],
+        rules: &[LogRule],
+        patterns: &[Pattern],
+        derived_keys: &[DerivedKey],
+        options: &AnalysisOptions,
+    ) -> Result<AnalysisResult, AnalysisError> {
+        let log: String = lines.iter().map(|l| format!("2024-01-01 {l}\n")).collect();
+        let logs: MemorySource = [(1, log.into_bytes())].into_iter().collect();
+
+        let (_f, _, template, ts_template, _, _) = make_time_range_test_data();
+        let source = Source {
+            id: 1,
+            name: "server".into(),
+            template_id: 1,
+            file_path: String::new(),
+        };
+        let rulesets = vec![Ruleset {
+            id: 1,
+            name: "rs".into(),
+            template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
+            rule_ids: rules.iter().map(|r| r.id).collect(),
+            stop_rule_ids: vec![],
+        }];
+        analyze_with_options(
+            &[source],
+            &logs,
+            &[template],
+            &[ts_template],
+            rules,
+            &rulesets,
+            patterns,
+            derived_keys,
+            &[],
+            &[],
+            &TimeRange::default(),
+            options,
+        )
+    }
+
+    fn error_burst_pattern(count: u64, window_ms: u64, group_by: Option<&str>) -> Pattern {
+        Pattern {
+            id: 1,
+            name: "error_burst".into(),
+            predicates: vec![PatternPredicate {
+                source_name: "server".into(),
+                state_key: "".into(),
+                operator: Operator::Exists,
+                operand: Operand::Literal(StateValue::Bool(true)),
+                threshold: Some(Threshold {
+                    count,
+                    window_ms,
+                    rule_id: Some(1),
+                    group_by: group_by.map(String::from),
+                }),
+            }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn test_threshold_fires_on_burst_within_window() {
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 ERROR from a",
+                "00:00:03 ERROR from a",
+                "00:00:05 ERROR from a",
+                "00:00:06 ERROR from a",
+            ],
+            &[client_error_rule()],
+            &[error_burst_pattern(3, 10_000, None)],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let pm = &result.pattern_matches[0];
+        assert_eq!(
+            pm.timestamp,
+            NaiveDateTime::parse_from_str("2024-01-01 00:00:05", "%Y-%m-%d %H:%M:%S").unwrap()
+        );
+        assert_eq!(pm.counted_events.len(), 3);
+        assert!(pm.counted_events.iter().all(|e| e.rule_id == 1));
+    }
+
+    #[test]
+    fn test_threshold_window_bounds() {
+        let pattern = error_burst_pattern(2, MAX_SPAN_MS + 1, None);
+        assert!(validate_predicate(&pattern.predicates[0]).is_err());
+        assert!(
+            validate_predicate(&error_burst_pattern(2, MAX_SPAN_MS, None).predicates[0]).is_ok()
+        );
+
+        // Unvalidated windows reaching past the earliest time keep every event
+        let at = |s: &str| {
+            NaiveDateTime::parse_from_str(&format!("2024-01-01 {s}"), "%Y-%m-%d %H:%M:%S").unwrap()
+        };
+        let event = |s: &str| CountedEvent {
+            timestamp: at(s),
+            source_id: 1,
+            rule_id: 1,
+            line_number: 1,
+            value: None,
+            group: None,
+        };
+        let mut bucket = VecDeque::from([event("00:00:01"), event("00:00:02")]);
+        prune_window(&mut bucket, at("00:00:03"), u64::MAX);
+        prune_window(&mut bucket, at("00:00:03"), i64::MAX as u64);
+        assert_eq!(bucket.len(), 2);
+        prune_window(&mut bucket, at("00:00:03"), 1_500);
+        assert_eq!(bucket.len(), 1);
+    }
+
+    #[test]
+    fn test_threshold_ignores_events_outside_window() {
+        let result = analyze_server_lines(
+            &[
+                "00:00:00 ERROR from a",
+                "00:00:20 ERROR from a",
+                "00:00:40 ERROR from a",
+                "00:01:00 ERROR from a",
+            ],
+            &[client_error_rule()],
+            &[error_burst_pattern(3, 30_000, None)],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert!(result.pattern_matches.is_empty());
+    }
+
+    #[test]
+    fn test_threshold_group_by_counts_per_key() {
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 ERROR from a",
+                "00:00:02 ERROR from b",
+                "00:00:03 ERROR from c",
+                "00:00:04 ERROR from b",
+            ],
+            &[client_error_rule()],
+            &[error_burst_pattern(2, 60_000, Some("client"))],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let pm = &result.pattern_matches[0];
+        assert_eq!(pm.counted_events.len(), 2);
+        assert!(
+            pm.counted_events
+                .iter()
+                .all(|e| e.group == Some(StateValue::String("b".into())))
+        );
+    }
+
+    #[test]
+    fn test_threshold_group_by_picks_groups_in_order() {
+        // Both `a` and `b` have crossed the bound by the time the step is
+        // reached; the lowest group wins every time
+        let mut pattern = error_burst_pattern(2, 60_000, Some("client"));
+        let first = client_pattern(1, &["z"]).predicates.remove(0);
+        pattern.predicates.insert(0, first);
+        for _ in 0..8 {
+            let result = analyze_server_lines(
+                &[
+                    "00:00:01 ERROR from b",
+                    "00:00:02 ERROR from b",
+                    "00:00:03 ERROR from a",
+                    "00:00:04 ERROR from a",
+                    "00:00:05 ERROR from z",
+                    "00:00:06 INFO tick",
+                ],
+                &[client_error_rule()],
+                std::slice::from_ref(&pattern),
+                &[],
+                &AnalysisOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(result.pattern_matches.len(), 1);
+            let groups: Vec<_> = result.pattern_matches[0]
+                .counted_events
+                .iter()
+                .map(|e| e.group.clone())
+                .collect();
+            assert_eq!(groups, vec![Some(StateValue::String("a".into())); 2]);
+        }
+    }
+
+    #[test]
+    fn test_threshold_counts_matching_state_changes() {
+        let mut pattern = error_burst_pattern(2, 60_000, None);
+        pattern.predicates[0].state_key = "client".into();
+        pattern.predicates[0].operator = Operator::Eq;
+        pattern.predicates[0].operand = Operand::Literal(StateValue::String("x".into()));
+        pattern.predicates[0].threshold.as_mut().unwrap().rule_id = None;
+
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 ERROR from x",
+                "00:00:02 ERROR from y",
+                "00:00:03 ERROR from x",
+            ],
+            &[client_error_rule()],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let values: Vec<_> = result.pattern_matches[0]
+            .counted_events
+            .iter()
+            .map(|e| e.value.clone())
+            .collect();
+        assert_eq!(values, vec![Some(StateValue::String("x".into())); 2]);
+    }
+
+    #[test]
+    fn test_pattern_match_records_step_provenance() {
+        let mut pattern = error_burst_pattern(2, 60_000, None);
+        pattern.predicates.insert(
+            0,
+            PatternPredicate {
+                source_name: "server".into(),
+                state_key: "client".into(),
+                operator: Operator::Eq,
+                operand: Operand::Literal(StateValue::String("a".into())),
+                threshold: None,
+            },
+        );
+
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 INFO boot",
+                "00:00:02 ERROR from a",
+                "00:00:03 INFO noise",
+                "00:00:04 ERROR from a",
+            ],
+            &[client_error_rule()],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let steps = &result.pattern_matches[0].steps;
+        assert_eq!(steps.len(), 2);
+
+        assert_eq!(steps[0].step_index, 0);
+        assert_eq!(steps[0].source_id, 1);
+        assert_eq!(steps[0].state_key, "client");
+        assert_eq!(steps[0].rule_id, 1);
+        assert_eq!(steps[0].line_number, Some(2));
+        assert_eq!(
+            steps[0].timestamp,
+            NaiveDateTime::parse_from_str("2024-01-01 00:00:02", "%Y-%m-%d %H:%M:%S").unwrap()
+        );
+
+        // Threshold step points at the event that crossed the bound
+        assert_eq!(steps[1].step_index, 1);
+        assert_eq!(steps[1].line_number, Some(4));
+        assert_eq!(steps[1].rule_id, 1);
+    }
+
+    // -----------------------------------------------------------------------
+    // Edge operator and cooldown tests
+    // -----------------------------------------------------------------------
+
+    /// A `phase` rule and an ERROR rule extracting `client` that only
+    /// applies while the guard holds.
+    fn guarded_rules(guard: RuleGuard) -> Vec<LogRule> {
+        let parsed = |id: u64, key: &str, pattern: &str| ExtractionRule {
+            id,
+            extraction_type: ExtractionType::Parsed,
+            state_key: key.into(),
+            pattern: Some(pattern.into()),
+            static_value: None,
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        };
+        vec![
+            LogRule {
+                id: 1,
+                name: "phase".into(),
+                match_mode: MatchMode::Any,
+                match_rules: vec![MatchRule {
+                    id: 1,
+                    pattern: r"PHASE".into(),
+                    negate: false,
+                }],
+                extraction_rules: vec![parsed(1, "phase", r"PHASE (?P<phase>\w+)")],
+                guard: None,
+                script: None,
+                plugin_id: None,
+            },
+            LogRule {
+                id: 2,
+                name: "error".into(),
+                match_mode: MatchMode::Any,
+                match_rules: vec![MatchRule {
+                    id: 2,
+                    pattern: r"ERROR".into(),
+                    negate: false,
+                }],
+                extraction_rules: vec![parsed(2, "client", r"from (?P<client>\w+)")],
+                guard: Some(guard),
+                script: None,
+                plugin_id: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_profile_counts_rule_evaluations() {
+        let rule = |id: u64, pattern: &str| LogRule {
+            id,
+            name: pattern.into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id,
+                pattern: pattern.into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "n".into(),
+                pattern: Some(r"(?P<n>\d+)$".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let rules = [rule(1, "ERROR"), rule(2, "WARN")];
+        let lines = [
+            "00:00:01 ERROR 1",
+            "00:00:02 WARN 2",
+            "00:00:03 ERROR 3",
+            "00:00:04 INFO 4",
+        ];
+        let profiled = analyze_server_lines(
+            &lines,
+            &rules,
+            &[],
+            &[],
+            &AnalysisOptions {
+                profile: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let plain =
+            analyze_server_lines(&lines, &rules, &[], &[], &AnalysisOptions::default()).unwrap();
+        assert!(plain.profile.is_none());
+        assert_eq!(profiled.rule_matches.len(), plain.rule_matches.len());
+        assert_eq!(profiled.state_changes.len(), plain.state_changes.len());
+
+        let profile = profiled.profile.unwrap();
+        let counts = |id: u64| {
+            let r = profile.rules.iter().find(|r| r.rule_id == id).unwrap();
+            (r.evaluations, r.matches)
+        };
+        assert_eq!(counts(1), (4, 2));
+        assert_eq!(counts(2), (4, 1));
+        assert_eq!(profile.rules.len(), 2);
+        assert_eq!(profile.sources.len(), 1);
+        assert_eq!(profile.sources[0].source_id, 1);
+        assert_eq!(profile.sources[0].lines, 4);
+    }
+
+    #[test]
+    fn test_resolve_source_rules() {
+        let ruleset = |id: u64, template_id: u64, extra: Vec<u64>, names: Vec<&str>| Ruleset {
+            id,
+            name: format!("rs{id}"),
+            template_id,
+            extra_template_ids: extra,
+            source_names: names.into_iter().map(String::from).collect(),
+            rule_ids: vec![id * 10, id * 10 + 1],
+            stop_rule_ids: vec![id * 10],
+        };
+        let rulesets = [
+            ruleset(1, 1, vec![], vec![]),
+            ruleset(2, 2, vec![1], vec![]),
+            ruleset(3, 1, vec![], vec!["lobby-3"]),
+            ruleset(4, 3, vec![], vec![]),
+        ];
+        let source = |name: &str, template_id: u64| Source {
+            id: 0,
+            name: name.into(),
+            template_id,
+            file_path: String::new(),
+        };
+
+        let game = resolve_source_rules(&source("game-1", 1), &rulesets);
+        assert_eq!(game.rule_ids, vec![10, 11, 20, 21]);
+        assert_eq!(game.stop_rule_ids, HashSet::from([10, 20]));
+
+        let lobby = resolve_source_rules(&source("lobby-3", 1), &rulesets);
+        assert_eq!(lobby.rule_ids, vec![10, 11, 20, 21, 30, 31]);
+
+        let other = resolve_source_rules(&source("lobby-3", 2), &rulesets);
+        assert_eq!(other.rule_ids, vec![20, 21]);
+        assert!(
+            resolve_source_rules(&source("x", 9), &rulesets)
+                .rule_ids
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_stop_rule_shadows_later_rules() {
+        let rule = |id: u64, pattern: &str, kind: &str| LogRule {
+            id,
+            name: kind.into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id,
+                pattern: pattern.into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id,
+                extraction_type: ExtractionType::Static,
+                state_key: "kind".into(),
+                pattern: None,
+                static_value: Some(kind.into()),
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let rules = [
+            rule(1, "ERROR", "generic"),
+            rule(2, "ERROR queue full", "queue_full"),
+        ];
+        let mut f = NamedTempFile::new().unwrap();
+        for l in ["00:00:01 ERROR queue full", "00:00:02 ERROR disk"] {
+            writeln!(f, "2024-01-01 {l}").unwrap();
+        }
+        f.flush().unwrap();
+        let (_f, _, template, ts_template, _, _) = make_time_range_test_data();
+        let source = Source {
+            id: 1,
+            name: "server".into(),
+            template_id: 1,
+            file_path: f.path().to_str().unwrap().into(),
+        };
+        let run = |stop_rule_ids: Vec<u64>| {
+            let ruleset = Ruleset {
+                id: 1,
+                name: "rs".into(),
+                template_id: 1,
+                extra_template_ids: vec![],
+                source_names: vec![],
+                rule_ids: vec![2, 1],
+                stop_rule_ids,
+            };
+            let result = analyze(
+                std::slice::from_ref(&source),
+                std::slice::from_ref(&template),
+                std::slice::from_ref(&ts_template),
+                &rules,
+                &[ruleset],
+                &[],
+                &TimeRange::default(),
+            )
+            .unwrap();
+            result
+                .rule_matches
+                .iter()
+                .map(|rm| (rm.log_line.line_number, rm.rule_id))
+                .collect::<Vec<_>>()
+        };
+
+        // Both apply, in ruleset order, so the generic rule wins the key
+        assert_eq!(run(vec![]), vec![(1, 2), (1, 1), (2, 1)]);
+        // The specific rule stops the generic one on its line only
+        assert_eq!(run(vec![2]), vec![(1, 2), (2, 1)]);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_rule_script_sets_and_clears_state() {
+        let rule = |script: &str| LogRule {
+            id: 1,
+            name: "login".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: "login".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Static,
+                state_key: "event".into(),
+                pattern: None,
+                static_value: Some("login".into()),
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: Some(script.into()),
+            plugin_id: None,
+        };
+        let script = r#"
+            let player = base64_decode(line.split("payload=")[1]);
+            if player == "" { throw "no payload" }
+            let logins = if "logins" in state { state.logins } else { 0 };
+            #{ player: player, logins: logins + 1, event: if logins > 0 { () } else { "first" } }
+        "#;
+        let lines = [
+            "00:00:01 login payload=YWxpY2U=",
+            "00:00:02 login payload=Ym9i",
+            "00:00:03 login payload=",
+        ];
+        let result = analyze_server_lines(
+            &lines,
+            &[rule(script)],
+            &[],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        let changes: Vec<(u64, &str, Option<&StateValue>)> = result
+            .state_changes
+            .iter()
+            .map(|sc| {
+                (
+                    sc.timestamp.and_utc().timestamp() as u64 % 60,
+                    sc.state_key.as_str(),
+                    sc.new_value.as_ref(),
+                )
+            })
+            .collect();
+        let s = |v: &str| StateValue::String(v.into());
+        assert_eq!(
+            changes,
+            vec![
+                (1, "event", Some(&s("login"))),
+                (1, "event", Some(&s("first"))),
+                (1, "logins", Some(&StateValue::Integer(1))),
+                (1, "player", Some(&s("alice"))),
+                (2, "event", Some(&s("login"))),
+                (2, "event", None),
+                (2, "logins", Some(&StateValue::Integer(2))),
+                (2, "player", Some(&s("bob"))),
+                // The failing script leaves only the rule's own extraction
+                (3, "event", Some(&s("login"))),
+            ]
+        );
+        assert_eq!(
+            result.rule_matches[1].extracted_state.get("player"),
+            Some(&s("bob"))
+        );
+
+        let err = analyze_server_lines(
+            &lines,
+            &[rule("#{ player: ")],
+            &[],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid script in rule 1"));
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_parser_and_extractor_plugins() {
+        // Splits `YYYY-MM-DD HH:MM:SS content`, adds a `via` field and skips
+        // lines starting with `#`
+        let parser = r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{\"timestamp\":\"")
+              (data (i32.const 32) "\",\"content\":\"")
+              (data (i32.const 64) "\",\"fields\":{\"via\":\"plugin\"}}")
+              (data (i32.const 96) "null")
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "parse_line") (param $ptr i32) (param $len i32) (result i64)
+                (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 35))
+                  (then (return (i64.or (i64.shl (i64.const 96) (i64.const 32)) (i64.const 4)))))
+                (memory.copy (i32.const 4096) (i32.const 0) (i32.const 14))
+                (memory.copy (i32.const 4110) (local.get $ptr) (i32.const 19))
+                (memory.copy (i32.const 4129) (i32.const 32) (i32.const 13))
+                (memory.copy
+                  (i32.const 4142)
+                  (i32.add (local.get $ptr) (i32.const 20))
+                  (i32.sub (local.get $len) (i32.const 20)))
+                (memory.copy (i32.add (i32.const 4122) (local.get $len)) (i32.const 64) (i32.const 28))
+                (i64.or
+                  (i64.shl (i64.const 4096) (i64.const 32))
+                  (i64.extend_i32_u (i32.add (local.get $len) (i32.const 54))))))
+        "#;
+        let extractor = r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{\"seen\":true}")
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "extract") (param i32 i32) (result i64)
+                (i64.const 13)))
+        "#;
+        let plugins = vec![
+            Plugin {
+                id: 1,
+                name: "parser".into(),
+                kind: PluginKind::LineParser,
+                wasm: wat::parse_str(parser).unwrap(),
+            },
+            Plugin {
+                id: 2,
+                name: "extractor".into(),
+                kind: PluginKind::Extractor,
+                wasm: wat::parse_str(extractor).unwrap(),
+            },
+        ];
+
+        let logs: MemorySource = [(
+            1,
+            "2024-01-01 00:00:01 login alice\n# banner\n2024-01-01 00:00:02 login bob\n".as_bytes(),
+        )]
+        .into_iter()
+        .collect();
+        let (_f, source, template, ts_template, mut rules, rulesets) = make_time_range_test_data();
+        rules[0].match_rules[0].pattern = "login".into();
+        let run = |parser_id: u64, extractor_id: u64| {
+            let template = SourceTemplate {
+                parser_plugin_id: Some(parser_id),
+                ..template.clone()
+            };
+            let rule = LogRule {
+                plugin_id: Some(extractor_id),
+                ..rules[0].clone()
+            };
+            analyze_with_options(
+                std::slice::from_ref(&source),
+                &logs,
+                &[template],
+                std::slice::from_ref(&ts_template),
+                &[rule],
+                &rulesets,
+                &[],
+                &[],
+                &[],
+                &plugins,
+                &TimeRange::default(),
+                &AnalysisOptions::default(),
+            )
+        };
+
+        let result = run(1, 2).unwrap();
+        let matched: Vec<(u64, &str)> = result
+            .rule_matches
+            .iter()
+            .map(|rm| (rm.log_line.line_number, &*rm.log_line.content))
+            .collect();
+        assert_eq!(matched, vec![(1, "login alice"), (3, "login bob")]);
+        assert_eq!(
+            result.rule_matches[0].extracted_state.get("seen"),
+            Some(&StateValue::Bool(true))
+        );
+        let changes: Vec<(&str, Option<&StateValue>, u64)> = result
+            .state_changes
+            .iter()
+            .map(|sc| (sc.state_key.as_str(), sc.new_value.as_ref(), sc.rule_id))
+            .collect();
+        assert!(changes.contains(&("via", Some(&StateValue::String("plugin".into())), 0)));
+        assert!(changes.contains(&("seen", Some(&StateValue::Bool(true)), 1)));
+
+        let err = run(2, 1).unwrap_err();
+        assert!(err.to_string().contains("cannot use plugin"));
+    }
+
+    #[test]
+    fn test_coverage_reports_unmatched_lines_and_dead_rules() {
+        let rule = |id: u64, pattern: &str| LogRule {
+            id,
+            name: pattern.into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id,
+                pattern: pattern.into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "n".into(),
+                pattern: Some(r"(?P<n>\d+)$".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        // WARN only ever re-sets the value ERROR already set
+        let rules = [rule(1, "ERROR"), rule(2, "WARN"), rule(3, "TIMEOUT")];
+        let patterns = [client_pattern(7, &["alice"])];
+        let lines = [
+            "00:00:01 ERROR 1",
+            "00:00:02 INFO user 17 joined",
+            "00:00:03 WARN 1",
+            "00:00:04 DEBUG tick",
+            "00:00:05 INFO user 18 joined",
+        ];
+        let result = analyze_server_lines(
+            &lines,
+            &rules,
+            &patterns,
+            &[],
+            &AnalysisOptions {
+                coverage: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(
+            analyze_server_lines(&lines, &rules, &[], &[], &AnalysisOptions::default())
+                .unwrap()
+                .coverage
+                .is_none()
+        );
+
+        let coverage = result.coverage.unwrap();
+        assert_eq!(coverage.unmatched_rules, vec![3]);
+        assert_eq!(coverage.rules_without_changes, vec![2]);
+        assert_eq!(coverage.unfired_patterns, vec![7]);
+
+        assert_eq!(coverage.sources.len(), 1);
+        let source = &coverage.sources[0];
+        assert_eq!((source.source_id, source.lines), (1, 5));
+        assert_eq!(source.unmatched_lines, 3);
+        let templates: Vec<(&str, u64)> = source
+            .unmatched_templates
+            .iter()
+            .map(|t| (t.template.as_str(), t.count))
+            .collect();
+        assert_eq!(
+            templates,
+            vec![("INFO user <*> joined", 2), ("DEBUG tick", 1)]
+        );
+        assert_eq!(
+            source.unmatched_templates[0].sample_line,
+            "2024-01-01 00:00:02 INFO user 17 joined"
+        );
+    }
+
+    #[test]
+    fn test_derived_keys_recompute_on_input_change() {
+        let counter = |id: u64, pattern: &str, key: &str| LogRule {
+            id,
+            name: key.into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id,
+                pattern: pattern.into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id,
+                extraction_type: ExtractionType::Static,
+                state_key: key.into(),
+                pattern: None,
+                static_value: Some("1".into()),
+                mode: ExtractionMode::Count,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let rules = [counter(1, "REQ", "requests"), counter(2, "ERR", "errors")];
+        let derived = |id: u64, key: &str, expression: &str| DerivedKey {
+            id,
+            source_name: "server".into(),
+            state_key: key.into(),
+            expression: expression.into(),
+        };
+        // The chained key is listed before the key it reads
+        let keys = [
+            derived(1, "error_pct", "error_rate * 100"),
+            derived(2, "error_rate", "errors / requests"),
+        ];
+        let pattern = Pattern {
+            id: 1,
+            name: "high_error_rate".into(),
+            predicates: vec![PatternPredicate {
+                source_name: "server".into(),
+                state_key: "error_pct".into(),
+                operator: Operator::Gt,
+                operand: Operand::Literal(StateValue::Integer(40)),
+                threshold: None,
+            }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        };
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 REQ",
+                "00:00:02 REQ",
+                "00:00:03 ERR",
+                "00:00:04 REQ",
+            ],
+            &rules,
+            std::slice::from_ref(&pattern),
+            &keys,
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        let pct: Vec<_> = result
+            .state_changes
+            .iter()
+            .filter(|c| c.state_key == "error_pct")
+            .map(|c| {
+                (
+                    c.timestamp.format("%S").to_string(),
+                    c.new_value.clone(),
+                    c.rule_id,
+                )
+            })
+            .collect();
+        // Unset until both inputs exist, then follows every change
+        assert_eq!(
+            pct,
+            vec![
+                ("03".into(), Some(StateValue::Float(50.0)), 0),
+                ("04".into(), Some(StateValue::Float(1.0 / 3.0 * 100.0)), 0),
+            ]
+        );
+        assert_eq!(result.pattern_matches.len(), 1);
+        assert_eq!(
+            result.pattern_matches[0].timestamp.format("%S").to_string(),
+            "03"
+        );
+
+        let cyclic = [derived(1, "a", "b + 1"), derived(2, "b", "a + 1")];
+        let err = analyze_server_lines(
+            &["00:00:01 REQ"],
+            &rules,
+            &[],
+            &cyclic,
+            &AnalysisOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("depends on itself"), "{err}");
+    }
+
+    #[test]
+    fn test_state_ttl_expires_values() {
+        let rule = LogRule {
+            id: 1,
+            name: "connected".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"CONNECTED".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Static,
+                state_key: "connected".into(),
+                pattern: None,
+                static_value: Some("true".into()),
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Bool,
+                transforms: Vec::new(),
+                ttl_ms: Some(30_000),
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let pattern = Pattern {
+            id: 1,
+            name: "stale_error".into(),
+            predicates: vec![PatternPredicate {
+                source_name: "server".into(),
+                state_key: "connected".into(),
+                operator: Operator::BecameUnset,
+                operand: Operand::Literal(StateValue::Bool(true)),
+                threshold: None,
+            }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        };
+        let result = analyze_server_lines(
+            &[
+                "00:00:00 CONNECTED",
+                "00:00:20 CONNECTED",
+                "00:00:45 tick",
+                "00:01:00 tick",
+            ],
+            &[rule],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        // The refresh at 00:00:20 pushes the expiry to 00:00:50
+        let changes: Vec<_> = result
+            .state_changes
+            .iter()
+            .map(|c| {
+                (
+                    c.timestamp.format("%H:%M:%S").to_string(),
+                    c.new_value.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("00:00:00".to_string(), Some(StateValue::Bool(true))),
+                ("00:00:50".to_string(), None),
+            ]
+        );
+        assert_eq!(result.state_changes[1].rule_id, 1);
+        assert_eq!(result.pattern_matches.len(), 1);
+    }
+
+    #[test]
+    fn test_ttl_bounds() {
+        let rule = ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Static,
+            state_key: "connected".into(),
+            pattern: None,
+            static_value: Some("true".into()),
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: Some(0),
+        };
+        assert!(validate_extraction_rule(&rule).is_err());
+        let too_long = ExtractionRule {
+            ttl_ms: Some(u64::MAX),
+            ..rule.clone()
+        };
+        assert!(validate_extraction_rule(&too_long).is_err());
+
+        // A deadline past the last representable time never comes
+        for ttl in [10_u64.pow(16), i64::MAX as u64] {
+            let rule = LogRule {
+                id: 1,
+                name: "connected".into(),
+                match_mode: MatchMode::Any,
+                match_rules: vec![MatchRule {
+                    id: 1,
+                    pattern: "CONNECTED".into(),
+                    negate: false,
+                }],
+                extraction_rules: vec![ExtractionRule {
+                    ttl_ms: Some(ttl),
+                    ..rule.clone()
+                }],
+                guard: None,
+                script: None,
+                plugin_id: None,
+            };
+            let result = analyze_server_lines(
+                &["00:00:00 CONNECTED", "00:00:01 tick"],
+                &[rule],
+                &[],
+                &[],
+                &AnalysisOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(result.state_changes.len(), 1);
+            assert!(result.state_changes[0].new_value.is_some());
+        }
+    }
+
+    const PHASE_LINES: [&str; 4] = [
+        "00:00:01 ERROR from a",
+        "00:00:02 PHASE matchmaking",
+        "00:00:03 ERROR from b",
+        "00:00:04 PHASE ingame",
+    ];
+
+    #[test]
+    fn test_rule_guard_skips_match_while_false() {
+        let mut lines = PHASE_LINES.to_vec();
+        lines.push("00:00:05 ERROR from c");
+        let result = analyze_server_lines(
+            &lines,
+            &guarded_rules(RuleGuard {
+                source_name: None,
+                state_key: "phase".into(),
+                operator: Operator::Eq,
+                operand: Operand::Literal(StateValue::String("matchmaking".into())),
+            }),
+            &[],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        let errors: Vec<u64> = result
+            .rule_matches
+            .iter()
+            .filter(|m| m.rule_id == 2)
+            .map(|m| m.log_line.line_number)
+            .collect();
+        assert_eq!(errors, vec![3]);
+        let clients: Vec<_> = result
+            .state_changes
+            .iter()
+            .filter(|c| c.state_key == "client")
+            .map(|c| c.new_value.clone())
+            .collect();
+        assert_eq!(clients, vec![Some(StateValue::String("b".into()))]);
+    }
+
+    #[test]
+    fn test_rule_guard_on_other_source_never_holds_when_unset() {
+        let result = analyze_server_lines(
+            &PHASE_LINES,
+            &guarded_rules(RuleGuard {
+                source_name: Some("client".into()),
+                state_key: "phase".into(),
+                operator: Operator::Exists,
+                operand: Operand::Literal(StateValue::Bool(true)),
+            }),
+            &[],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert!(result.rule_matches.iter().all(|m| m.rule_id == 1));
+    }
+
+    #[test]
+    fn test_invalid_rule_guard_fails_analysis() {
+        let (_f, source, template, ts_template, mut rules, rulesets) = make_time_range_test_data();
+        rules[0].guard = Some(RuleGuard {
+            source_name: None,
+            state_key: "phase".into(),
+            operator: Operator::In,
+            operand: Operand::Literal(StateValue::String("x".into())),
+        });
+        let err = analyze(
+            &[source],
+            &[template],
+            &[ts_template],
+            &rules,
+            &rulesets,
+            &[],
+            &TimeRange::default(),
+        );
+        assert!(err.is_err());
+    }
+
+    const CLIENT_LINES: [&str; 4] = [
+        "00:00:01 ERROR from a",
+        "00:00:02 ERROR from a",
+        "00:00:03 ERROR from b",
+        "00:00:04 ERROR from a",
+    ];
+
+    #[test]
+    fn test_pattern_match_renders_outcome_templates() {
+        let mut pattern = client_pattern(1, &["b"]);
+        pattern.outcome = PatternOutcome {
+            severity: Some(Severity::Error),
+            category: Some("clients".into()),
+            diagnosis: Some("Client {server.client} failed ({server.region})".into()),
+            remediation: Some("Restart {{client}} {server.client}".into()),
+        };
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let outcome = &result.pattern_matches[0].outcome;
+        assert_eq!(outcome.severity, Some(Severity::Error));
+        assert_eq!(outcome.category.as_deref(), Some("clients"));
+        // Unset keys keep their placeholder
+        assert_eq!(
+            outcome.diagnosis.as_deref(),
+            Some("Client b failed ({server.region})")
+        );
+        assert_eq!(outcome.remediation.as_deref(), Some("Restart {client} b"));
+    }
+
+    fn child_of(mut pattern: Pattern, parent_id: u64, window_ms: Option<u64>) -> Pattern {
+        pattern.parent = Some(PatternParent {
+            pattern_id: parent_id,
+            window_ms,
+        });
+        pattern
+    }
+
+    #[test]
+    fn test_child_pattern_refines_parent_match() {
+        // Child listed first: parents are still evaluated ahead of children
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[
+                child_of(client_pattern(2, &["b"]), 1, Some(5_000)),
+                client_pattern(1, &["a"]),
+            ],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        let fired: Vec<(u64, Option<u64>, bool)> = result
+            .pattern_matches
+            .iter()
+            .map(|pm| (pm.pattern_id, pm.refines, pm.superseded))
+            .collect();
+        assert_eq!(
+            fired,
+            vec![
+                (1, None, false),
+                (1, None, true),
+                (2, Some(1), false),
+                (1, None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_child_pattern_dropped_outside_parent_window() {
+        // The parent last matched 1s before the child completes
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[
+                client_pattern(1, &["a"]),
+                child_of(client_pattern(2, &["b"]), 1, Some(500)),
+            ],
+            &[],
+            &AnalysisOptions {
+                near_misses: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(result.pattern_matches.iter().all(|pm| pm.pattern_id == 1));
+        // A dropped completion still leaves the child as a near-miss
+        assert!(result.near_misses.iter().any(|nm| nm.pattern_id == 2));
+
+        // A parent that never matches gates the child entirely
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[
+                client_pattern(1, &["z"]),
+                child_of(client_pattern(2, &["b"]), 1, None),
+            ],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert!(result.pattern_matches.is_empty());
+
+        // The window is bounded like other spans
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[
+                client_pattern(1, &["a"]),
+                child_of(client_pattern(2, &["b"]), 1, Some(MAX_SPAN_MS)),
+            ],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert!(result.pattern_matches.iter().any(|pm| pm.pattern_id == 2));
+        assert!(
+            PatternEvaluator::new(&[
+                client_pattern(1, &["a"]),
+                child_of(client_pattern(2, &["b"]), 1, Some(u64::MAX)),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_explain_child_pattern_applies_parent_window() {
+        let log: String = CLIENT_LINES
+            .iter()
+            .map(|l| format!("2024-01-01 {l}\n"))
+            .collect();
+        let logs: MemorySource = [(1, log.into_bytes())].into_iter().collect();
+        let (_f, _, template, ts_template, _, _) = make_time_range_test_data();
+        let source = Source {
+            id: 1,
+            name: "server".into(),
+            template_id: 1,
+            file_path: String::new(),
+        };
+        let rulesets = vec![Ruleset {
+            id: 1,
+            name: "rs".into(),
+            template_id: 1,
+            extra_template_ids: vec![],
+            source_names: vec![],
+            rule_ids: vec![1],
+            stop_rule_ids: vec![],
+        }];
+        let parent = client_pattern(1, &["a"]);
+        let explain = |window_ms| {
+            let child = child_of(client_pattern(2, &["b"]), 1, Some(window_ms));
+            explain_pattern(
+                std::slice::from_ref(&source),
+                &logs,
+                std::slice::from_ref(&template),
+                std::slice::from_ref(&ts_template),
+                &[client_error_rule()],
+                &rulesets,
+                &child,
+                &[parent.clone(), child.clone()],
+                &[],
+                &[],
+                &[],
+                &TimeRange::default(),
+            )
+            .unwrap()
+        };
+
+        // Only the child's own matches count, not its parent's
+        let explanation = explain(5_000);
+        assert_eq!(explanation.match_count, 1);
+        assert!(explanation.near_miss.is_none());
+
+        // The parent last matched 1s before the child completes
+        let explanation = explain(500);
+        assert_eq!(explanation.match_count, 0);
+        assert_eq!(explanation.near_miss.unwrap().pattern_id, 2);
+    }
+
+    #[test]
+    fn test_parents_first_order_tolerates_cycles() {
+        assert_eq!(
+            parents_first_order(&[Some(1), None, Some(0)]),
+            vec![1, 0, 2]
+        );
+        assert_eq!(
+            parents_first_order(&[Some(1), Some(0), None]),
+            vec![2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_validate_template() {
+        assert!(
+            validate_template("Server {server.name} full: {server.players}/{server.cap}").is_ok()
+        );
+        assert!(validate_template("literal {{braces}}").is_ok());
+        assert!(validate_template("unclosed {server.name").is_err());
+        assert!(validate_template("stray } brace").is_err());
+        assert!(validate_template("no source {name}").is_err());
+        assert!(validate_template("quoted {`app.log`.status} {`app.log`.`a.b`}").is_ok());
+        assert!(validate_template("unclosed quote {`app.log.status}").is_err());
+        assert!(validate_template("no dot {`app.log`status}").is_err());
+        assert!(validate_template("empty {``.status}").is_err());
+
+        // A source whose name has a dot in it
+        let sources = vec![Source {
+            id: 1,
+            name: "app.log".into(),
             template_id: 1,
-            file_path: f.path().to_str().unwrap().into(),
-        };
+            file_path: String::new(),
+        }];
+        let mut sm = StateManager::new(&sources);
+        Arc::make_mut(sm.per_source_state.entry(1).or_default()).insert(
+            "status".into(),
+            TrackedValue {
+                value: StateValue::String("degraded".into()),
+                set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
+            },
+        );
+        assert_eq!(
+            render_template("{`app.log`.status}, {`app.log`.`status`}", &sm),
+            "degraded, degraded"
+        );
+        // Unquoted, the first dot ends the source name; unset keys keep
+        // their placeholder as written
+        assert_eq!(
+            render_template("{app.log.status} {`app.log`.missing}", &sm),
+            "{app.log.status} {`app.log`.missing}"
+        );
+    }
 
-        let result = analyze(
-            &[source],
-            &[template],
-            &[ts_template],
+    fn client_edge_pattern(operator: Operator, operand: Operand) -> Pattern {
+        let mut pattern = client_pattern(1, &["a"]);
+        pattern.predicates[0].operator = operator;
+        pattern.predicates[0].operand = operand;
+        pattern
+    }
+
+    #[test]
+    fn test_changed_to_fires_once_per_transition() {
+        let level = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[client_pattern(1, &["a"])],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(level.pattern_matches.len(), 3);
+
+        let edge = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[client_edge_pattern(
+                Operator::Changed,
+                Operand::Literal(StateValue::String("a".into())),
+            )],
             &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        let lines: Vec<Option<u64>> = edge
+            .pattern_matches
+            .iter()
+            .map(|pm| pm.steps[0].line_number)
+            .collect();
+        assert_eq!(lines, vec![Some(1), Some(4)]);
+    }
+
+    #[test]
+    fn test_changed_from_to_transition() {
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[client_edge_pattern(
+                Operator::Changed,
+                Operand::Transition {
+                    from: Some(StateValue::String("b".into())),
+                    to: Some(StateValue::String("a".into())),
+                },
+            )],
             &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        assert_eq!(result.pattern_matches[0].steps[0].line_number, Some(4));
+
+        let any_change = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[client_edge_pattern(
+                Operator::Changed,
+                Operand::Transition {
+                    from: None,
+                    to: None,
+                },
+            )],
             &[],
-            &TimeRange::default(),
+            &AnalysisOptions::default(),
         )
         .unwrap();
+        assert_eq!(any_change.pattern_matches.len(), 3);
+    }
 
-        // Should have state changes for all JSON fields across all lines
-        assert!(
-            !result.state_changes.is_empty(),
-            "expected state changes from JSON auto-extraction"
+    #[test]
+    fn test_became_set_and_unset() {
+        let sources = make_sources();
+        let sm = StateManager::new(&sources);
+        let mut unset = client_edge_pattern(
+            Operator::BecameUnset,
+            Operand::Literal(StateValue::Bool(true)),
         );
+        unset.id = 2;
+        let patterns = vec![
+            client_edge_pattern(
+                Operator::BecameSet,
+                Operand::Literal(StateValue::Bool(true)),
+            ),
+            unset,
+        ];
+        let mut eval = PatternEvaluator::new(&patterns).unwrap();
+        let line = make_log_line("x");
+        let change = |old: Option<&str>, new: Option<&str>| StateChange {
+            timestamp: line.timestamp,
+            source_id: 1,
+            source_name: "server".into(),
+            state_key: "client".into(),
+            old_value: old.map(|v| StateValue::String(v.into())),
+            new_value: new.map(|v| StateValue::String(v.into())),
+            rule_id: 1,
+        };
 
-        // Check specific field types by looking at state changes
-        let port_changes: Vec<_> = result
-            .state_changes
-            .iter()
-            .filter(|sc| sc.state_key == "port")
-            .collect();
-        assert!(!port_changes.is_empty(), "expected port state change");
-        assert_eq!(port_changes[0].new_value, Some(StateValue::Integer(8080)));
+        let mut fire = |changes: &[StateChange]| -> Vec<u64> {
+            eval.observe_line(&patterns, &sm, &line, &[1], changes);
+            eval.evaluate_patterns(&patterns, &sm)
+                .iter()
+                .map(|pm| pm.pattern_id)
+                .collect()
+        };
+        assert_eq!(fire(&[change(None, Some("a"))]), vec![1]);
+        assert!(fire(&[change(Some("a"), Some("b"))]).is_empty());
+        assert!(fire(&[]).is_empty());
+        assert_eq!(fire(&[change(Some("b"), None)]), vec![2]);
+    }
 
-        let usage_changes: Vec<_> = result
-            .state_changes
+    #[test]
+    fn test_edge_step_latches_for_following_steps() {
+        let mut pattern = client_edge_pattern(
+            Operator::Changed,
+            Operand::Literal(StateValue::String("b".into())),
+        );
+        pattern.predicates.push(PatternPredicate {
+            source_name: "server".into(),
+            state_key: "client".into(),
+            operator: Operator::Eq,
+            operand: Operand::Literal(StateValue::String("a".into())),
+            threshold: None,
+        });
+        let result = analyze_server_lines(
+            &CLIENT_LINES,
+            &[client_error_rule()],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        let lines: Vec<Option<u64>> = result.pattern_matches[0]
+            .steps
             .iter()
-            .filter(|sc| sc.state_key == "usage_pct")
+            .map(|s| s.line_number)
             .collect();
-        assert!(!usage_changes.is_empty(), "expected usage_pct state change");
-        assert_eq!(usage_changes[0].new_value, Some(StateValue::Float(85.5)));
+        assert_eq!(lines, vec![Some(3), Some(4)]);
+    }
 
-        let success_changes: Vec<_> = result
-            .state_changes
+    #[test]
+    fn test_cooldown_suppresses_repeat_matches() {
+        let mut pattern = client_pattern(1, &["a"]);
+        pattern.cooldown_ms = Some(10_000);
+        let result = analyze_server_lines(
+            &[
+                "00:00:01 ERROR from a",
+                "00:00:02 ERROR from a",
+                "00:00:05 ERROR from a",
+                "00:00:11 ERROR from a",
+                "00:00:12 ERROR from a",
+            ],
+            &[client_error_rule()],
+            &[pattern],
+            &[],
+            &AnalysisOptions::default(),
+        )
+        .unwrap();
+        let times: Vec<String> = result
+            .pattern_matches
             .iter()
-            .filter(|sc| sc.state_key == "success")
+            .map(|pm| pm.timestamp.format("%H:%M:%S").to_string())
             .collect();
-        assert!(!success_changes.is_empty(), "expected success state change");
-        assert_eq!(success_changes[0].new_value, Some(StateValue::Bool(true)));
-
-        // rule_id should be 0 for JSON auto-extracted state changes
-        for sc in &result.state_changes {
-            assert_eq!(
-                sc.rule_id, 0,
-                "JSON auto-extracted state changes should have rule_id=0"
-            );
+        assert_eq!(times, vec!["00:00:01", "00:00:11"]);
+
+        for cooldown_ms in [u64::MAX, i64::MAX as u64] {
+            let mut pattern = client_pattern(1, &["a"]);
+            pattern.cooldown_ms = Some(cooldown_ms);
+            let result = analyze_server_lines(
+                &["00:00:01 ERROR from a", "00:00:11 ERROR from a"],
+                &[client_error_rule()],
+                &[pattern],
+                &[],
+                &AnalysisOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(result.pattern_matches.len(), 1);
         }
     }
 
-    // -------------------------------------------------------------------
-    // Time-range filtering tests
-    // -------------------------------------------------------------------
+    // -----------------------------------------------------------------------
+    // Near-miss tests
+    // -----------------------------------------------------------------------
 
-    fn make_time_range_test_data() -> (
-        NamedTempFile,
-        Source,
-        SourceTemplate,
-        TimestampTemplate,
-        Vec<LogRule>,
-        Vec<Ruleset>,
-    ) {
-        let mut f = NamedTempFile::new().unwrap();
-        // Lines at 00:01 through 00:05
-        for min in 1..=5 {
-            writeln!(f, "2024-01-01 00:{min:02}:00 event_{min}").unwrap();
+    fn client_pattern(id: u64, steps: &[&str]) -> Pattern {
+        Pattern {
+            id,
+            name: format!("pattern_{id}"),
+            predicates: steps
+                .iter()
+                .map(|v| PatternPredicate {
+                    source_name: "server".into(),
+                    state_key: "client".into(),
+                    operator: Operator::Eq,
+                    operand: Operand::Literal(StateValue::String((*v).into())),
+                    threshold: None,
+                })
+                .collect(),
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         }
-        f.flush().unwrap();
-
-        let ts_template = TimestampTemplate {
-            id: 1,
-            name: "ts".into(),
-            format: "%Y-%m-%d %H:%M:%S".into(),
-            extraction_regex: None,
-            default_year: None,
-        };
-        let template = SourceTemplate {
-            id: 1,
-            name: "tmpl".into(),
-            timestamp_template_id: 1,
-            line_delimiter: "\n".into(),
-            content_regex: Some(r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2} (.+)$".into()),
-            continuation_regex: None,
-            json_timestamp_field: None,
-            file_name_regex: None,
-            log_content_regex: None,
-        };
-        let source = Source {
-            id: 1,
-            name: "src".into(),
-            template_id: 1,
-            file_path: f.path().to_str().unwrap().into(),
-        };
-        let rules = vec![LogRule {
-            id: 1,
-            name: "match_event".into(),
-            match_mode: MatchMode::Any,
-            match_rules: vec![MatchRule {
-                id: 1,
-                pattern: r"event_\d+".into(),
-            }],
-            extraction_rules: vec![],
-        }];
-        let rulesets = vec![Ruleset {
-            id: 1,
-            name: "rs".into(),
-            template_id: 1,
-            rule_ids: vec![1],
-        }];
-        (f, source, template, ts_template, rules, rulesets)
     }
 
     #[test]
-    fn test_time_range_filtering() {
-        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
-        let time_range = TimeRange {
-            start: Some(
-                NaiveDateTime::parse_from_str("2024-01-01 00:02:00", "%Y-%m-%d %H:%M:%S").unwrap(),
-            ),
-            end: Some(
-                NaiveDateTime::parse_from_str("2024-01-01 00:04:00", "%Y-%m-%d %H:%M:%S").unwrap(),
-            ),
-        };
-        let result = analyze(
-            &[source],
-            &[template],
-            &[ts_template],
-            &rules,
-            &rulesets,
+    fn test_near_miss_reports_furthest_step_and_failure() {
+        let patterns = vec![
+            client_pattern(1, &["a", "z"]),
+            client_pattern(2, &["b"]),
+            client_pattern(3, &["q"]),
+        ];
+        let result = analyze_server_lines(
+            &["00:00:01 ERROR from a", "00:00:02 ERROR from b"],
+            &[client_error_rule()],
+            &patterns,
             &[],
-            &time_range,
+            &AnalysisOptions {
+                near_misses: true,
+                ..Default::default()
+            },
         )
         .unwrap();
+        assert_eq!(result.pattern_matches.len(), 1);
+        // Pattern 2 fired, so it is not a near miss
+        assert_eq!(result.near_misses.len(), 2);
+
+        let nm = &result.near_misses[0];
+        assert_eq!(nm.pattern_id, 1);
+        assert_eq!(nm.steps_reached, 1);
+        assert_eq!(nm.total_steps, 2);
+        assert_eq!(nm.failed_step, 1);
         assert_eq!(
-            result.rule_matches.len(),
-            3,
-            "expected matches for events 2, 3, 4"
+            nm.reached_at,
+            Some(
+                NaiveDateTime::parse_from_str("2024-01-01 00:00:01", "%Y-%m-%d %H:%M:%S").unwrap()
+            )
+        );
+        assert_eq!(nm.steps.len(), 1);
+        assert_eq!(nm.steps[0].line_number, Some(1));
+        assert_eq!(nm.actual, Some(StateValue::String("a".into())));
+        assert_eq!(nm.expected, Some(StateValue::String("z".into())));
+        assert_eq!(
+            nm.state_snapshot["server"]["client"].value,
+            StateValue::String("a".into())
         );
+
+        // Never satisfied a step: described against the final state
+        let nm = &result.near_misses[1];
+        assert_eq!(nm.pattern_id, 3);
+        assert_eq!(nm.steps_reached, 0);
+        assert_eq!(nm.reached_at, None);
+        assert_eq!(nm.actual, Some(StateValue::String("b".into())));
+        assert_eq!(nm.expected, Some(StateValue::String("q".into())));
     }
 
     #[test]
-    fn test_time_range_start_only() {
+    fn test_near_misses_off_by_default() {
         let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
-        let time_range = TimeRange {
-            start: Some(
-                NaiveDateTime::parse_from_str("2024-01-01 00:04:00", "%Y-%m-%d %H:%M:%S").unwrap(),
-            ),
-            end: None,
-        };
         let result = analyze(
             &[source],
             &[template],
             &[ts_template],
             &rules,
             &rulesets,
-            &[],
-            &time_range,
+            &[client_pattern(1, &["a"])],
+            &TimeRange::default(),
         )
         .unwrap();
-        assert_eq!(
-            result.rule_matches.len(),
-            2,
-            "expected matches for events 4, 5"
-        );
+        assert!(result.near_misses.is_empty());
     }
 
     #[test]
-    fn test_time_range_end_only() {
-        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
-        let time_range = TimeRange {
-            start: None,
-            end: Some(
-                NaiveDateTime::parse_from_str("2024-01-01 00:02:00", "%Y-%m-%d %H:%M:%S").unwrap(),
-            ),
-        };
-        let result = analyze(
-            &[source],
-            &[template],
-            &[ts_template],
+    fn test_explain_pattern() {
+        let (_f, source, template, ts_template, _, rulesets) = make_time_range_test_data();
+        let rules = vec![LogRule {
+            id: 1,
+            name: "event".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 1,
+                pattern: r"event_\d+".into(),
+                negate: false,
+            }],
+            extraction_rules: vec![ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "n".into(),
+                pattern: Some(r"event_(?P<n>\d+)".into()),
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: Vec::new(),
+                ttl_ms: None,
+            }],
+            guard: None,
+            script: None,
+            plugin_id: None,
+        }];
+        let mut pattern = client_pattern(7, &["x"]);
+        pattern.predicates[0].source_name = "src".into();
+        pattern.predicates[0].state_key = "n".into();
+        pattern.predicates[0].operator = Operator::Gt;
+        pattern.predicates[0].operand = Operand::Literal(StateValue::Integer(3));
+
+        let explanation = explain_pattern(
+            std::slice::from_ref(&source),
+            &FileSource,
+            std::slice::from_ref(&template),
+            std::slice::from_ref(&ts_template),
             &rules,
             &rulesets,
+            &pattern,
             &[],
-            &time_range,
+            &[],
+            &[],
+            &[],
+            &TimeRange::default(),
         )
         .unwrap();
-        assert_eq!(
-            result.rule_matches.len(),
-            2,
-            "expected matches for events 1, 2"
-        );
-    }
+        assert_eq!(explanation.pattern_id, 7);
+        assert_eq!(explanation.match_count, 2);
+        assert!(explanation.near_miss.is_none());
 
-    #[test]
-    fn test_time_range_default() {
-        let (_f, source, template, ts_template, rules, rulesets) = make_time_range_test_data();
-        let result = analyze(
+        pattern.predicates[0].operand = Operand::Literal(StateValue::Integer(9));
+        let explanation = explain_pattern(
             &[source],
+            &FileSource,
             &[template],
             &[ts_template],
             &rules,
             &rulesets,
+            &pattern,
+            &[],
+            &[],
+            &[],
             &[],
             &TimeRange::default(),
         )
         .unwrap();
-        assert_eq!(
-            result.rule_matches.len(),
-            5,
-            "default TimeRange should return all matches"
-        );
+        assert_eq!(explanation.match_count, 0);
+        let nm = explanation.near_miss.unwrap();
+        assert_eq!(nm.actual, Some(StateValue::Integer(5)));
+        assert_eq!(nm.expected, Some(StateValue::Integer(9)));
     }
 
     // -----------------------------------------------------------------------
@@ -3483,6 +8677,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
@@ -3493,8 +8688,10 @@ mod tests {
 
         let result = cluster_logs(
             &[source],
+            &FileSource,
             &[template],
             &[ts_template],
+            &[],
             &TimeRange::default(),
         )
         .unwrap();
@@ -3562,6 +8759,7 @@ mod tests {
             json_timestamp_field: None,
             file_name_regex: None,
             log_content_regex: None,
+            parser_plugin_id: None,
         };
         let source = Source {
             id: 1,
@@ -3572,8 +8770,10 @@ mod tests {
 
         let result = cluster_logs(
             &[source],
+            &FileSource,
             &[template],
             &[ts_template],
+            &[],
             &TimeRange::default(),
         )
         .unwrap();
@@ -1,12 +1,11 @@
 use std::collections::HashMap;
-use std::sync::Arc;
 
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 use crate::model::{
-    AnalysisResult, LogRule, Pattern, PatternMatch, RuleMatch, Source, StateChange, StateValue,
-    TrackedValue,
+    AnalysisResult, LogRule, Pattern, PatternMatch, RuleMatch, Severity, Source, StateChange,
+    StateValue, TrackedValue,
 };
 
 // ---- Export options ----
@@ -62,7 +61,52 @@ struct ExportRuleMatch {
 struct ExportPatternMatch {
     timestamp: NaiveDateTime,
     pattern_name: String,
-    state_snapshot: HashMap<String, Arc<HashMap<String, TrackedValue>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity: Option<Severity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diagnosis: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remediation: Option<String>,
+    /// Name of the parent pattern this match refines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refines: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    superseded: bool,
+    state_snapshot: HashMap<String, HashMap<String, ExportTrackedValue>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    counted_events: Vec<ExportCountedEvent>,
+    causal_chain: Vec<ExportStep>,
+}
+
+#[derive(Serialize)]
+struct ExportTrackedValue {
+    value: StateValue,
+    set_at: NaiveDateTime,
+    rule_name: String,
+    line_number: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ExportCountedEvent {
+    timestamp: NaiveDateTime,
+    source_name: String,
+    rule_name: String,
+    line_number: u64,
+    value: Option<StateValue>,
+    group: Option<StateValue>,
+}
+
+#[derive(Serialize)]
+struct ExportStep {
+    step_index: usize,
+    timestamp: NaiveDateTime,
+    source_name: String,
+    state_key: String,
+    rule_name: String,
+    line_number: Option<u64>,
+    value: Option<StateValue>,
 }
 
 #[derive(Serialize)]
@@ -118,10 +162,65 @@ fn enrich_pattern_match(pm: &PatternMatch, lookups: &Lookups) -> ExportPatternMa
     ExportPatternMatch {
         timestamp: pm.timestamp,
         pattern_name: lookup_name(&lookups.patterns, pm.pattern_id),
-        state_snapshot: pm.state_snapshot.clone(),
+        severity: pm.outcome.severity,
+        category: pm.outcome.category.clone(),
+        diagnosis: pm.outcome.diagnosis.clone(),
+        remediation: pm.outcome.remediation.clone(),
+        refines: pm.refines.map(|id| lookup_name(&lookups.patterns, id)),
+        superseded: pm.superseded,
+        state_snapshot: pm
+            .state_snapshot
+            .iter()
+            .map(|(source, state)| (source.clone(), enrich_state(state, lookups)))
+            .collect(),
+        counted_events: pm
+            .counted_events
+            .iter()
+            .map(|e| ExportCountedEvent {
+                timestamp: e.timestamp,
+                source_name: lookup_name(&lookups.sources, e.source_id),
+                rule_name: lookup_name(&lookups.rules, e.rule_id),
+                line_number: e.line_number,
+                value: e.value.clone(),
+                group: e.group.clone(),
+            })
+            .collect(),
+        causal_chain: pm
+            .steps
+            .iter()
+            .map(|step| ExportStep {
+                step_index: step.step_index,
+                timestamp: step.timestamp,
+                source_name: step.source_name.clone(),
+                state_key: step.state_key.clone(),
+                rule_name: lookup_name(&lookups.rules, step.rule_id),
+                line_number: step.line_number,
+                value: step.value.clone(),
+            })
+            .collect(),
     }
 }
 
+fn enrich_state(
+    state: &HashMap<String, TrackedValue>,
+    lookups: &Lookups,
+) -> HashMap<String, ExportTrackedValue> {
+    state
+        .iter()
+        .map(|(key, tv)| {
+            (
+                key.clone(),
+                ExportTrackedValue {
+                    value: tv.value.clone(),
+                    set_at: tv.set_at,
+                    rule_name: lookup_name(&lookups.rules, tv.rule_id),
+                    line_number: tv.line_number,
+                },
+            )
+        })
+        .collect()
+}
+
 fn enrich_state_change(sc: &StateChange, lookups: &Lookups) -> ExportStateChange {
     ExportStateChange {
         timestamp: sc.timestamp,
@@ -241,6 +340,29 @@ fn fmt_state_value(sv: &Option<StateValue>) -> String {
     }
 }
 
+/// One step per line: `<n>. <time> <source>.<key> = <value> (<rule>, line <n>)`.
+fn fmt_causal_chain(steps: &[ExportStep]) -> String {
+    steps
+        .iter()
+        .map(|step| {
+            let line = step
+                .line_number
+                .map_or(String::new(), |n| format!(", line {n}"));
+            format!(
+                "{}. {} {}.{} = {} ({}{})",
+                step.step_index + 1,
+                fmt_ts(step.timestamp),
+                step.source_name,
+                step.state_key,
+                fmt_state_value(&step.value),
+                step.rule_name,
+                line
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn write_rule_matches_csv(
     wtr: &mut csv::Writer<Vec<u8>>,
     matches: &[RuleMatch],
@@ -273,14 +395,34 @@ fn write_pattern_matches_csv(
     matches: &[PatternMatch],
     lookups: &Lookups,
 ) {
-    wtr.write_record(["timestamp", "pattern_name", "state_snapshot"])
-        .unwrap();
+    wtr.write_record([
+        "timestamp",
+        "pattern_name",
+        "severity",
+        "category",
+        "diagnosis",
+        "remediation",
+        "refines",
+        "superseded",
+        "causal_chain",
+        "state_snapshot",
+    ])
+    .unwrap();
     for pm in matches {
         let enriched = enrich_pattern_match(pm, lookups);
         let snapshot_json = serde_json::to_string(&enriched.state_snapshot).unwrap();
         wtr.write_record([
             &fmt_ts(enriched.timestamp),
             &enriched.pattern_name,
+            &enriched
+                .severity
+                .map_or(String::new(), |s| format!("{s:?}")),
+            enriched.category.as_deref().unwrap_or_default(),
+            enriched.diagnosis.as_deref().unwrap_or_default(),
+            enriched.remediation.as_deref().unwrap_or_default(),
+            enriched.refines.as_deref().unwrap_or_default(),
+            &enriched.superseded.to_string(),
+            &fmt_causal_chain(&enriched.causal_chain),
             &snapshot_json,
         ])
         .unwrap();
@@ -329,6 +471,9 @@ mod tests {
             match_mode: MatchMode::Any,
             match_rules: vec![],
             extraction_rules: vec![],
+            guard: None,
+            script: None,
+            plugin_id: None,
         }]
     }
 
@@ -346,6 +491,9 @@ mod tests {
             id: 100,
             name: "Service Down".to_string(),
             predicates: vec![],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
         }]
     }
 
@@ -367,6 +515,8 @@ mod tests {
             TrackedValue {
                 value: StateValue::String("val".to_string()),
                 set_at: test_ts(),
+                rule_id: 0,
+                line_number: None,
             },
         );
         snapshot.insert("app.log".to_string(), Arc::new(inner));
@@ -381,6 +531,7 @@ mod tests {
                     raw: Arc::from("ERROR broke"),
                     content: Arc::from("broke"),
                     cached_json: None,
+                    line_number: 1,
                 },
                 extracted_state: extracted,
             }],
@@ -388,6 +539,25 @@ mod tests {
                 pattern_id: 100,
                 timestamp: test_ts(),
                 state_snapshot: snapshot,
+                counted_events: vec![],
+                steps: vec![StepProvenance {
+                    step_index: 0,
+                    source_id: 10,
+                    source_name: "app.log".to_string(),
+                    state_key: "status".to_string(),
+                    timestamp: test_ts(),
+                    rule_id: 1,
+                    line_number: Some(7),
+                    value: Some(StateValue::String("error".to_string())),
+                }],
+                outcome: PatternOutcome {
+                    severity: Some(Severity::Critical),
+                    category: None,
+                    diagnosis: Some("app.log reported error".to_string()),
+                    remediation: None,
+                },
+                refines: None,
+                superseded: false,
             }],
             state_changes: vec![StateChange {
                 timestamp: test_ts(),
@@ -398,6 +568,9 @@ mod tests {
                 new_value: Some(StateValue::String("error".to_string())),
                 rule_id: 1,
             }],
+            near_misses: vec![],
+            profile: None,
+            coverage: None,
         }
     }
 
@@ -454,6 +627,12 @@ mod tests {
         assert!(json.contains("Timeout"), "should contain rule name");
         assert!(json.contains("app.log"), "should contain source name");
         assert!(json.contains("Service Down"), "should contain pattern name");
+        assert!(json.contains(r#""severity": "Critical""#));
+        assert!(json.contains(r#""diagnosis": "app.log reported error""#));
+        assert!(
+            !json.contains("remediation"),
+            "unset outcome fields are omitted"
+        );
         assert!(!json.contains("rule_id"));
         assert!(!json.contains("source_id"));
         assert!(!json.contains("pattern_id"));
@@ -493,7 +672,13 @@ mod tests {
         let lines: Vec<&str> = csv.lines().collect();
         assert_eq!(lines.len(), 2);
         assert!(lines[0].contains("pattern_name"));
+        assert!(lines[0].contains("causal_chain"));
+        assert!(lines[0].contains("diagnosis"));
         assert!(lines[1].contains("Service Down"));
+        assert!(lines[1].contains(r#""Critical","","app.log reported error","""#));
+        assert!(
+            lines[1].contains("1. 2024-01-15 10:30:00 app.log.status = error (Timeout, line 7)")
+        );
     }
 
     #[test]
@@ -518,12 +703,67 @@ mod tests {
         assert_eq!(&record[5], "error", "new_value should be 'error'");
     }
 
+    #[test]
+    fn test_collection_values_in_csv_and_json() {
+        let mut result = test_result();
+        result.state_changes[0].new_value = Some(StateValue::Set(vec![
+            StateValue::String("E1".to_string()),
+            StateValue::String("E2".to_string()),
+        ]));
+        let csv_out = to_csv(
+            &result,
+            &test_rules(),
+            &test_sources(),
+            &test_patterns(),
+            CsvSection::StateChanges,
+        );
+        let csv_out = strip_bom(&csv_out);
+        let mut rdr = csv::ReaderBuilder::new().from_reader(csv_out.as_bytes());
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(&record[5], "{E1, E2}");
+
+        let json = serde_json::to_value(&result.state_changes[0]).unwrap();
+        assert_eq!(
+            json["new_value"],
+            serde_json::json!({"Set": [{"String": "E1"}, {"String": "E2"}]})
+        );
+    }
+
+    #[test]
+    fn test_time_values_in_csv_and_json() {
+        let mut result = test_result();
+        result.state_changes[0].old_value = Some(StateValue::Timestamp(test_ts()));
+        result.state_changes[0].new_value = Some(StateValue::Duration(90_500));
+        let csv_out = to_csv(
+            &result,
+            &test_rules(),
+            &test_sources(),
+            &test_patterns(),
+            CsvSection::StateChanges,
+        );
+        let csv_out = strip_bom(&csv_out);
+        let mut rdr = csv::ReaderBuilder::new().from_reader(csv_out.as_bytes());
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(&record[4], "2024-01-15 10:30:00");
+        assert_eq!(&record[5], "1m30.5s");
+
+        let json = serde_json::to_value(&result.state_changes[0]).unwrap();
+        assert_eq!(
+            json["old_value"],
+            serde_json::json!({"Timestamp": "2024-01-15T10:30:00"})
+        );
+        assert_eq!(json["new_value"], serde_json::json!({"Duration": 90500}));
+    }
+
     #[test]
     fn test_csv_empty_section() {
         let result = AnalysisResult {
             rule_matches: vec![],
             pattern_matches: vec![],
             state_changes: vec![],
+            near_misses: vec![],
+            profile: None,
+            coverage: None,
         };
         let csv = to_csv(
             &result,
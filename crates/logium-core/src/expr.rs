@@ -0,0 +1,700 @@
+//! Expression operands for pattern predicates.
+//!
+//! An expression combines literals and references to other sources' state
+//! with arithmetic, string concatenation and a few functions:
+//!
+//! ```text
+//! server.max_retries + 2
+//! client.timeout_ms * 0.8
+//! lower(client.user) + "@" + `auth-server`.realm
+//! client.finished_at - server.started_at + duration("5s")
+//! ```
+//!
+//! Timestamps subtract to a duration, durations add to or subtract from
+//! timestamps and each other, and scale by numbers. `duration` and
+//! `timestamp` parse their text argument the way extraction does.
+//!
+//! References are written `source.key`; names that are not plain identifiers
+//! can be quoted with backticks. Expressions are parsed and type-checked
+//! once, when a pattern is saved or an analysis starts, and evaluate to
+//! `None` at run time when a referenced key is unset or the values do not
+//! fit the operation.
+
+use chrono::TimeDelta;
+
+use crate::engine::{AnalysisError, StateManager};
+use crate::model::{StateValue, ValueType};
+use crate::transform::convert;
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(StateValue),
+    Ref {
+        source_name: String,
+        state_key: String,
+    },
+    Neg(Box<Expr>),
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        func: Func,
+        arg: Box<Expr>,
+    },
+}
+
+/// Binary operators. `Add` also concatenates when either side is a string.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Built-in functions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func {
+    Abs,
+    Len,
+    Lower,
+    Duration,
+    Timestamp,
+}
+
+/// Statically known result type; state references are `Unknown` until
+/// evaluated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Int,
+    Float,
+    String,
+    Bool,
+    Timestamp,
+    Duration,
+    Unknown,
+}
+
+impl Ty {
+    fn is_numeric(self) -> bool {
+        matches!(self, Ty::Int | Ty::Float | Ty::Unknown)
+    }
+}
+
+impl Expr {
+    /// Parse and type-check an expression.
+    pub fn parse(src: &str) -> Result<Expr, AnalysisError> {
+        Self::parse_with(src, None)
+    }
+
+    /// Parse an expression in which bare names are keys of `source_name`.
+    pub fn parse_in(src: &str, source_name: &str) -> Result<Expr, AnalysisError> {
+        Self::parse_with(src, Some(source_name))
+    }
+
+    fn parse_with(src: &str, default_source: Option<&str>) -> Result<Expr, AnalysisError> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            default_source,
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(tok) = parser.tokens.get(parser.pos) {
+            return Err(parse_error(format!("unexpected {tok:?}")));
+        }
+        expr.check()?;
+        Ok(expr)
+    }
+
+    /// The `(source, key)` pairs the expression reads.
+    pub fn refs(&self) -> Vec<(&str, &str)> {
+        let mut out = Vec::new();
+        self.collect_refs(&mut out);
+        out
+    }
+
+    fn collect_refs<'a>(&'a self, out: &mut Vec<(&'a str, &'a str)>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Ref {
+                source_name,
+                state_key,
+            } => out.push((source_name, state_key)),
+            Expr::Neg(inner) | Expr::Call { arg: inner, .. } => inner.collect_refs(out),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.collect_refs(out);
+                rhs.collect_refs(out);
+            }
+        }
+    }
+
+    /// Evaluate against the current state.
+    pub fn eval(&self, state: &StateManager) -> Option<StateValue> {
+        match self {
+            Expr::Literal(v) => Some(v.clone()),
+            Expr::Ref {
+                source_name,
+                state_key,
+            } => state.get_state_by_name(source_name, state_key).cloned(),
+            Expr::Neg(inner) => match inner.eval(state)? {
+                StateValue::Integer(i) => i.checked_neg().map(StateValue::Integer),
+                StateValue::Float(f) => Some(StateValue::Float(-f)),
+                StateValue::Duration(ms) => ms.checked_neg().map(StateValue::Duration),
+                _ => None,
+            },
+            Expr::Binary { op, lhs, rhs } => binary(*op, lhs.eval(state)?, rhs.eval(state)?),
+            Expr::Call { func, arg } => {
+                let value = arg.eval(state)?;
+                match (func, value) {
+                    (Func::Abs, StateValue::Integer(i)) => i.checked_abs().map(StateValue::Integer),
+                    (Func::Abs, StateValue::Float(f)) => Some(StateValue::Float(f.abs())),
+                    (Func::Abs, StateValue::Duration(ms)) => {
+                        ms.checked_abs().map(StateValue::Duration)
+                    }
+                    (Func::Len, StateValue::String(s)) => {
+                        Some(StateValue::Integer(s.chars().count() as i64))
+                    }
+                    (Func::Len, StateValue::List(items) | StateValue::Set(items)) => {
+                        Some(StateValue::Integer(items.len() as i64))
+                    }
+                    (Func::Lower, StateValue::String(s)) => {
+                        Some(StateValue::String(s.to_lowercase()))
+                    }
+                    (Func::Duration, v @ StateValue::Duration(_)) => Some(v),
+                    (Func::Duration, StateValue::String(s)) => convert(&s, ValueType::Duration),
+                    (Func::Timestamp, v @ StateValue::Timestamp(_)) => Some(v),
+                    (Func::Timestamp, StateValue::String(s)) => convert(&s, ValueType::Timestamp),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn check(&self) -> Result<Ty, AnalysisError> {
+        match self {
+            Expr::Literal(StateValue::Integer(_)) => Ok(Ty::Int),
+            Expr::Literal(StateValue::Float(_)) => Ok(Ty::Float),
+            Expr::Literal(StateValue::String(_)) => Ok(Ty::String),
+            Expr::Literal(StateValue::Bool(_)) => Ok(Ty::Bool),
+            Expr::Literal(StateValue::Timestamp(_)) => Ok(Ty::Timestamp),
+            Expr::Literal(StateValue::Duration(_)) => Ok(Ty::Duration),
+            Expr::Literal(StateValue::List(_) | StateValue::Set(_)) => Ok(Ty::Unknown),
+            Expr::Ref { .. } => Ok(Ty::Unknown),
+            Expr::Neg(inner) => match inner.check()? {
+                ty if ty.is_numeric() || ty == Ty::Duration => Ok(ty),
+                ty => Err(type_error(format!("cannot negate {ty:?}"))),
+            },
+            Expr::Binary { op, lhs, rhs } => {
+                let (l, r) = (lhs.check()?, rhs.check()?);
+                if l == Ty::Bool || r == Ty::Bool {
+                    return Err(type_error(format!("{op:?} does not apply to Bool")));
+                }
+                if *op == BinOp::Add && (l == Ty::String || r == Ty::String) {
+                    return Ok(Ty::String);
+                }
+                use BinOp::{Add, Div, Mul, Sub};
+                use Ty::{Duration, Float, Int, Timestamp, Unknown};
+                Ok(match (op, l, r) {
+                    (_, Unknown, ty) | (_, ty, Unknown) if ty != Ty::String => Unknown,
+                    (Sub, Timestamp, Timestamp) => Duration,
+                    (Add | Sub, Timestamp, Duration) | (Add, Duration, Timestamp) => Timestamp,
+                    (Add | Sub, Duration, Duration) => Duration,
+                    (Mul, Duration, Int | Float) | (Mul, Int | Float, Duration) => Duration,
+                    (Div, Duration, Int | Float) => Duration,
+                    (Div, Duration, Duration) => Float,
+                    (Div, Int | Float, Int | Float) => Float,
+                    (_, Int, Int) => Int,
+                    (_, Int | Float, Int | Float) => Float,
+                    _ => {
+                        return Err(type_error(format!(
+                            "{op:?} does not apply to {l:?} and {r:?}"
+                        )));
+                    }
+                })
+            }
+            Expr::Call { func, arg } => {
+                let ty = arg.check()?;
+                let (ok, out) = match func {
+                    Func::Abs => (ty.is_numeric() || ty == Ty::Duration, ty),
+                    Func::Len => (matches!(ty, Ty::String | Ty::Unknown), Ty::Int),
+                    Func::Lower => (matches!(ty, Ty::String | Ty::Unknown), Ty::String),
+                    Func::Duration => (
+                        matches!(ty, Ty::String | Ty::Duration | Ty::Unknown),
+                        Ty::Duration,
+                    ),
+                    Func::Timestamp => (
+                        matches!(ty, Ty::String | Ty::Timestamp | Ty::Unknown),
+                        Ty::Timestamp,
+                    ),
+                };
+                if ok {
+                    Ok(out)
+                } else {
+                    Err(type_error(format!("{func:?} does not apply to {ty:?}")))
+                }
+            }
+        }
+    }
+}
+
+fn binary(op: BinOp, lhs: StateValue, rhs: StateValue) -> Option<StateValue> {
+    use StateValue::{Duration, Float, Integer, Timestamp};
+    match (op, lhs, rhs) {
+        (BinOp::Add, StateValue::String(a), b) => Some(StateValue::String(format!("{a}{b}"))),
+        (BinOp::Add, a, StateValue::String(b)) => Some(StateValue::String(format!("{a}{b}"))),
+        (BinOp::Sub, Timestamp(a), Timestamp(b)) => Some(Duration((a - b).num_milliseconds())),
+        (BinOp::Add, Timestamp(t), Duration(d)) | (BinOp::Add, Duration(d), Timestamp(t)) => t
+            .checked_add_signed(TimeDelta::milliseconds(d))
+            .map(Timestamp),
+        (BinOp::Sub, Timestamp(t), Duration(d)) => t
+            .checked_sub_signed(TimeDelta::milliseconds(d))
+            .map(Timestamp),
+        (BinOp::Add, Duration(a), Duration(b)) => a.checked_add(b).map(Duration),
+        (BinOp::Sub, Duration(a), Duration(b)) => a.checked_sub(b).map(Duration),
+        (BinOp::Div, Duration(a), Duration(b)) => (b != 0).then(|| Float(a as f64 / b as f64)),
+        (BinOp::Mul, Duration(d), n) | (BinOp::Mul, n, Duration(d)) => scale(d, as_f64(&n)?),
+        (BinOp::Div, Duration(d), n) => {
+            let n = as_f64(&n)?;
+            (n != 0.0).then(|| scale(d, 1.0 / n))?
+        }
+        (op, Integer(a), Integer(b)) => match op {
+            BinOp::Add => a.checked_add(b).map(Integer),
+            BinOp::Sub => a.checked_sub(b).map(Integer),
+            BinOp::Mul => a.checked_mul(b).map(Integer),
+            BinOp::Div => (b != 0).then(|| Float(a as f64 / b as f64)),
+            BinOp::Rem => a.checked_rem(b).map(Integer),
+        },
+        (op, a, b) => {
+            let (a, b) = (as_f64(&a)?, as_f64(&b)?);
+            let out = match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                BinOp::Div if b == 0.0 => return None,
+                BinOp::Div => a / b,
+                BinOp::Rem if b == 0.0 => return None,
+                BinOp::Rem => a % b,
+            };
+            Some(Float(out))
+        }
+    }
+}
+
+/// Scale a duration, rounding to whole milliseconds.
+fn scale(ms: i64, factor: f64) -> Option<StateValue> {
+    let out = (ms as f64 * factor).round();
+    (out.is_finite() && out.abs() < 9.0e15).then_some(StateValue::Duration(out as i64))
+}
+
+fn as_f64(v: &StateValue) -> Option<f64> {
+    match v {
+        StateValue::Integer(i) => Some(*i as f64),
+        StateValue::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn parse_error(msg: String) -> AnalysisError {
+    AnalysisError::ParseError(format!("expression: {msg}"))
+}
+
+fn type_error(msg: String) -> AnalysisError {
+    AnalysisError::ParseError(format!("expression type error: {msg}"))
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizer
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Op(char),
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, AnalysisError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' | '%' | '(' | ')' | '.' => {
+                chars.next();
+                tokens.push(Token::Op(c));
+            }
+            '0'..='9' => {
+                let mut text = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || d == '.' || d == '_' {
+                        text.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let text = text.replace('_', "");
+                let token = if text.contains('.') {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                tokens.push(token.ok_or_else(|| parse_error(format!("invalid number {text}")))?);
+            }
+            '"' | '\'' | '`' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => text.push(escaped),
+                            None => break,
+                        },
+                        Some(q) if q == c => break,
+                        Some(other) => text.push(other),
+                        None => return Err(parse_error(format!("unterminated {c} quote"))),
+                    }
+                }
+                tokens.push(if c == '`' {
+                    Token::Ident(text)
+                } else {
+                    Token::Str(text)
+                });
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut text = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_alphanumeric() || d == '_' {
+                        text.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(text));
+            }
+            other => return Err(parse_error(format!("unexpected character {other:?}"))),
+        }
+    }
+    Ok(tokens)
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+// ---------------------------------------------------------------------------
+
+/// Deepest an expression may nest, counting parentheses, calls, negations
+/// and chained operators. Parsing, checking and evaluating all recurse on the
+/// tree, so this keeps them off the end of the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'s> {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Source of bare-name references, when they are allowed.
+    default_source: Option<&'s str>,
+    /// Nesting of the node being parsed.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn descend(&mut self) -> Result<(), AnalysisError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(parse_error("expression nested too deeply".into()));
+        }
+        Ok(())
+    }
+
+    fn peek_op(&self) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<(), AnalysisError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(parse_error(format!("expected '{op}'")))
+        }
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, AnalysisError> {
+        let depth = self.depth;
+        let mut lhs = self.term()?;
+        while let Some(c @ ('+' | '-')) = self.peek_op() {
+            self.pos += 1;
+            // Each operator nests the chain so far one level deeper
+            self.descend()?;
+            let op = if c == '+' { BinOp::Add } else { BinOp::Sub };
+            let rhs = self.term()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    /// term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Expr, AnalysisError> {
+        let depth = self.depth;
+        let mut lhs = self.unary()?;
+        while let Some(c @ ('*' | '/' | '%')) = self.peek_op() {
+            self.pos += 1;
+            self.descend()?;
+            let op = match c {
+                '*' => BinOp::Mul,
+                '/' => BinOp::Div,
+                _ => BinOp::Rem,
+            };
+            let rhs = self.unary()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    /// unary := '-' unary | primary
+    fn unary(&mut self) -> Result<Expr, AnalysisError> {
+        if self.peek_op() == Some('-') {
+            self.pos += 1;
+            self.descend()?;
+            let inner = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr::Neg(Box::new(inner)));
+        }
+        self.primary()
+    }
+
+    /// primary := number | string | bool | '(' expr ')' | func '(' expr ')'
+    ///          | source '.' key | key (with a default source)
+    fn primary(&mut self) -> Result<Expr, AnalysisError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| parse_error("unexpected end of input".into()))?;
+        self.pos += 1;
+        match token {
+            Token::Int(i) => Ok(Expr::Literal(StateValue::Integer(i))),
+            Token::Float(f) => Ok(Expr::Literal(StateValue::Float(f))),
+            Token::Str(s) => Ok(Expr::Literal(StateValue::String(s))),
+            Token::Op('(') => {
+                self.descend()?;
+                let inner = self.expr()?;
+                self.expect_op(')')?;
+                self.depth -= 1;
+                Ok(inner)
+            }
+            Token::Ident(name) if self.peek_op() == Some('(') => {
+                let func = match name.as_str() {
+                    "abs" => Func::Abs,
+                    "len" => Func::Len,
+                    "lower" => Func::Lower,
+                    "duration" => Func::Duration,
+                    "timestamp" => Func::Timestamp,
+                    _ => return Err(parse_error(format!("unknown function {name}"))),
+                };
+                self.pos += 1;
+                self.descend()?;
+                let arg = self.expr()?;
+                self.expect_op(')')?;
+                self.depth -= 1;
+                Ok(Expr::Call {
+                    func,
+                    arg: Box::new(arg),
+                })
+            }
+            Token::Ident(name) if self.peek_op() == Some('.') => {
+                self.pos += 1;
+                match self.tokens.get(self.pos).cloned() {
+                    Some(Token::Ident(key)) => {
+                        self.pos += 1;
+                        Ok(Expr::Ref {
+                            source_name: name,
+                            state_key: key,
+                        })
+                    }
+                    _ => Err(parse_error(format!("expected a state key after {name}."))),
+                }
+            }
+            Token::Ident(name) if name == "true" || name == "false" => {
+                Ok(Expr::Literal(StateValue::Bool(name == "true")))
+            }
+            Token::Ident(name) => match self.default_source {
+                Some(source_name) => Ok(Expr::Ref {
+                    source_name: source_name.to_string(),
+                    state_key: name,
+                }),
+                None => Err(parse_error(format!(
+                    "bare name {name}; state references are written source.key"
+                ))),
+            },
+            other => Err(parse_error(format!("unexpected {other:?}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(src: &str) -> Option<StateValue> {
+        let state = StateManager::new(&[]);
+        Expr::parse(src).unwrap().eval(&state)
+    }
+
+    #[test]
+    fn test_parse_precedence_and_refs() {
+        assert_eq!(
+            Expr::parse("server.max_retries + 2 * `auth-db`.x").unwrap(),
+            Expr::Binary {
+                op: BinOp::Add,
+                lhs: Box::new(Expr::Ref {
+                    source_name: "server".into(),
+                    state_key: "max_retries".into(),
+                }),
+                rhs: Box::new(Expr::Binary {
+                    op: BinOp::Mul,
+                    lhs: Box::new(Expr::Literal(StateValue::Integer(2))),
+                    rhs: Box::new(Expr::Ref {
+                        source_name: "auth-db".into(),
+                        state_key: "x".into(),
+                    }),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_in_default_source() {
+        let expr = Expr::parse_in("errors / requests + db.load", "server").unwrap();
+        assert_eq!(
+            expr.refs(),
+            vec![("server", "errors"), ("server", "requests"), ("db", "load")]
+        );
+        assert!(Expr::parse("errors / requests").is_err());
+    }
+
+    #[test]
+    fn test_eval_literals() {
+        assert_eq!(lit("(1 + 2) * 3"), Some(StateValue::Integer(9)));
+        assert_eq!(lit("7 % 4 - 1"), Some(StateValue::Integer(2)));
+        assert_eq!(lit("1 / 4"), Some(StateValue::Float(0.25)));
+        assert_eq!(lit("1 / 0"), None);
+        assert_eq!(lit("abs(-2.5)"), Some(StateValue::Float(2.5)));
+        assert_eq!(lit("len('héllo')"), Some(StateValue::Integer(5)));
+        assert_eq!(
+            lit("lower('Bob') + '@' + 42"),
+            Some(StateValue::String("bob@42".into()))
+        );
+    }
+
+    #[test]
+    fn test_time_arithmetic() {
+        let started =
+            chrono::NaiveDateTime::parse_from_str("2024-01-15 10:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap();
+        let mut state = StateManager::new(&[crate::model::Source {
+            id: 1,
+            name: "server".into(),
+            template_id: 1,
+            file_path: String::new(),
+        }]);
+        std::sync::Arc::make_mut(state.per_source_state.entry(1).or_default()).insert(
+            "started".into(),
+            crate::model::TrackedValue {
+                value: StateValue::Timestamp(started),
+                set_at: started,
+                rule_id: 0,
+                line_number: None,
+            },
+        );
+        let eval = |src: &str| Expr::parse(src).unwrap().eval(&state);
+
+        assert_eq!(
+            eval("timestamp('2024-01-15 10:01:30') - server.started"),
+            Some(StateValue::Duration(90_000))
+        );
+        assert_eq!(
+            eval("server.started + duration('1m') * 2"),
+            Some(StateValue::Timestamp(started + TimeDelta::minutes(2)))
+        );
+        assert_eq!(
+            lit("duration('1s') / duration('250ms')"),
+            Some(StateValue::Float(4.0))
+        );
+        assert_eq!(
+            lit("-duration('1.5s') / 3"),
+            Some(StateValue::Duration(-500))
+        );
+        assert_eq!(lit("duration('soon')"), None);
+        for src in [
+            "duration('1s') + 1",
+            "timestamp('2024-01-15') + timestamp('2024-01-16')",
+            "duration(1)",
+        ] {
+            assert!(Expr::parse(src).is_err(), "{src:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for src in [
+            "",
+            "1 +",
+            "(1",
+            "server",
+            "server.",
+            "upper('x')",
+            "'unterminated",
+            "1 $ 2",
+            "'a' * 2",
+            "-'a'",
+            "true + 1",
+            "abs('x')",
+            "len(1)",
+        ] {
+            assert!(Expr::parse(src).is_err(), "{src:?} should not parse");
+        }
+
+        // Deep nesting is refused rather than overflowing the stack
+        let deep = |open: &str, close: &str| {
+            let n = 200_000;
+            format!("{}1{}", open.repeat(n), close.repeat(n))
+        };
+        for src in [
+            deep("(", ")"),
+            deep("-", ""),
+            deep("abs(", ")"),
+            deep("", "+1"),
+        ] {
+            let err = Expr::parse(&src).unwrap_err();
+            assert!(err.to_string().contains("nested too deeply"), "{err}");
+        }
+        let nested = format!("{}1{}", "(".repeat(60), ")".repeat(60));
+        assert_eq!(lit(&nested), Some(StateValue::Integer(1)));
+        assert!(Expr::parse(&format!("1{}", " + 1".repeat(60))).is_ok());
+    }
+}
@@ -0,0 +1,218 @@
+//! Grok-style named sub-patterns.
+//!
+//! Match and extraction patterns may reference a named regex fragment instead
+//! of spelling it out:
+//!
+//! ```text
+//! %{IP:client} %{HTTPMETHOD:method} (?P<path>\S+) %{HTTPSTATUS:status}
+//! ```
+//!
+//! `%{NAME}` expands to the fragment in a non-capturing group and
+//! `%{NAME:field}` to a `(?P<field>...)` capture. Names resolve against the
+//! project's library first and the built-in set second, and library entries
+//! may reference each other. Anything that is not a well-formed reference —
+//! `%{2}`, say — is left alone, so existing regexes keep their meaning.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+use crate::engine::AnalysisError;
+use crate::model::GrokPattern;
+
+/// The built-in fragments, available in every project.
+pub const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("INT", r"[+-]?\d+"),
+    ("NUMBER", r"[+-]?(?:\d+(?:\.\d*)?|\.\d+)"),
+    ("WORD", r"\w+"),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)",
+    ),
+    ("IPV6", r"(?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f]{0,4}"),
+    ("IP", r"%{IPV6}|%{IPV4}"),
+    (
+        "UUID",
+        r"[0-9A-Fa-f]{8}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{4}-[0-9A-Fa-f]{12}",
+    ),
+    (
+        "ISO8601",
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+    ),
+    (
+        "HTTPMETHOD",
+        r"GET|HEAD|POST|PUT|DELETE|CONNECT|OPTIONS|TRACE|PATCH",
+    ),
+    ("HTTPSTATUS", r"[1-5]\d{2}"),
+    ("DURATION", r"(?:\d+(?:\.\d+)?(?:ns|us|µs|ms|s|m|h|d))+"),
+    ("HEX", r"(?:0[xX])?[0-9A-Fa-f]+"),
+];
+
+static REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"%\{([A-Za-z_][A-Za-z0-9_]*)(?::([A-Za-z_][A-Za-z0-9_]*))?\}").unwrap()
+});
+
+/// Fragments by name: a project's library over the built-in set.
+pub struct GrokLibrary<'a> {
+    patterns: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> GrokLibrary<'a> {
+    pub fn new(project: &'a [GrokPattern]) -> Self {
+        let mut patterns: HashMap<&str, &str> = BUILTIN_PATTERNS.iter().copied().collect();
+        for p in project {
+            patterns.insert(&p.name, &p.pattern);
+        }
+        Self { patterns }
+    }
+
+    /// Expand every reference in `pattern`. Patterns without references are
+    /// returned unchanged.
+    pub fn expand<'p>(&self, pattern: &'p str) -> Result<Cow<'p, str>, String> {
+        if !REFERENCE.is_match(pattern) {
+            return Ok(Cow::Borrowed(pattern));
+        }
+        self.expand_in(pattern, &mut Vec::new()).map(Cow::Owned)
+    }
+
+    /// `stack` holds the names being expanded, to catch self-reference.
+    fn expand_in<'n>(&'n self, pattern: &str, stack: &mut Vec<&'n str>) -> Result<String, String> {
+        let mut out = String::with_capacity(pattern.len());
+        let mut last = 0;
+        for caps in REFERENCE.captures_iter(pattern) {
+            let whole = caps.get(0).expect("group 0 always matches");
+            out.push_str(&pattern[last..whole.start()]);
+            last = whole.end();
+            out.push_str(&self.expand_reference(&caps, stack)?);
+        }
+        out.push_str(&pattern[last..]);
+        Ok(out)
+    }
+
+    fn expand_reference<'n>(
+        &'n self,
+        caps: &Captures,
+        stack: &mut Vec<&'n str>,
+    ) -> Result<String, String> {
+        let name = &caps[1];
+        let (&name, &body) = self
+            .patterns
+            .get_key_value(name)
+            .ok_or_else(|| format!("unknown pattern %{{{name}}}"))?;
+        if stack.contains(&name) {
+            return Err(format!("pattern %{{{name}}} refers to itself"));
+        }
+        stack.push(name);
+        let body = self.expand_in(body, stack)?;
+        stack.pop();
+        Ok(match caps.get(2) {
+            Some(field) => format!("(?P<{}>{body})", field.as_str()),
+            None => format!("(?:{body})"),
+        })
+    }
+}
+
+/// Check a project's library: names are identifiers, defined once, and every
+/// entry expands to a valid regex.
+pub fn validate_grok_patterns(patterns: &[GrokPattern]) -> Result<(), AnalysisError> {
+    let name_re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let library = GrokLibrary::new(patterns);
+    for (i, p) in patterns.iter().enumerate() {
+        if !name_re.is_match(&p.name) {
+            return Err(AnalysisError::ParseError(format!(
+                "pattern name '{}' must be letters, digits and underscores",
+                p.name
+            )));
+        }
+        if patterns[..i].iter().any(|other| other.name == p.name) {
+            return Err(AnalysisError::ParseError(format!(
+                "pattern %{{{}}} is defined twice",
+                p.name
+            )));
+        }
+        let expanded = library
+            .expand(&p.pattern)
+            .map_err(|e| AnalysisError::ParseError(format!("pattern %{{{}}}: {e}", p.name)))?;
+        Regex::new(&expanded).map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grok(id: u64, name: &str, pattern: &str) -> GrokPattern {
+        GrokPattern {
+            id,
+            name: name.into(),
+            pattern: pattern.into(),
+        }
+    }
+
+    #[test]
+    fn test_expand_builtins() {
+        let library = GrokLibrary::new(&[]);
+        let re = Regex::new(
+            &library
+                .expand(r"^%{IP:client} %{HTTPMETHOD:method} \S+ %{HTTPSTATUS:status} %{DURATION}$")
+                .unwrap(),
+        )
+        .unwrap();
+        let caps = re.captures("10.0.0.7 GET /health 200 1m2.5s").unwrap();
+        assert_eq!(&caps["client"], "10.0.0.7");
+        assert_eq!(&caps["method"], "GET");
+        assert_eq!(&caps["status"], "200");
+        assert!(!re.is_match("10.0.0.7 FETCH /health 200 1s"));
+
+        let caps = re.captures("fe80::1 PUT / 503 10ms").unwrap();
+        assert_eq!(&caps["client"], "fe80::1");
+
+        // Not references: left for the regex engine
+        assert_eq!(library.expand(r"%{2}\d").unwrap(), r"%{2}\d");
+    }
+
+    #[test]
+    fn test_project_patterns_nest_and_shadow() {
+        let project = [
+            grok(1, "ENDPOINT", r"%{IP}:%{PORT}"),
+            grok(2, "PORT", r"\d{1,5}"),
+            grok(3, "HEX", r"0x[0-9a-f]+"),
+        ];
+        let library = GrokLibrary::new(&project);
+        let re = Regex::new(&library.expand("%{ENDPOINT:peer} %{HEX:id}").unwrap()).unwrap();
+        let caps = re.captures("10.1.2.3:8080 0xbeef").unwrap();
+        assert_eq!(&caps["peer"], "10.1.2.3:8080");
+        assert_eq!(&caps["id"], "0xbeef");
+        // The project's HEX wants the prefix the built-in makes optional
+        assert!(!re.is_match("10.1.2.3:8080 deadbeef"));
+        assert!(validate_grok_patterns(&project).is_ok());
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let project = [grok(1, "A", "x%{B}"), grok(2, "B", "%{A}")];
+        let library = GrokLibrary::new(&project);
+        assert_eq!(
+            library.expand("%{NOPE:x}").unwrap_err(),
+            "unknown pattern %{NOPE}"
+        );
+        assert!(
+            library
+                .expand("%{A}")
+                .unwrap_err()
+                .contains("refers to itself")
+        );
+        assert!(validate_grok_patterns(&project).is_err());
+
+        for bad in [
+            vec![grok(1, "has space", "x")],
+            vec![grok(1, "A", "x"), grok(2, "A", "y")],
+            vec![grok(1, "A", "(")],
+        ] {
+            assert!(validate_grok_patterns(&bad).is_err(), "{bad:?}");
+        }
+    }
+}
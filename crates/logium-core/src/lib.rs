@@ -1,3 +1,9 @@
 pub mod engine;
 pub mod export;
+pub mod expr;
+pub mod grok;
+pub mod log_source;
 pub mod model;
+pub mod plugin;
+pub mod script;
+pub mod transform;
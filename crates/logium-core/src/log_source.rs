@@ -0,0 +1,167 @@
+//! Where the engine reads each source's log from.
+//!
+//! The engine never opens files itself: it asks a [`LogSource`] for a byte
+//! stream per [`Source`]. [`FileSource`] reads `file_path` as before,
+//! [`CompressedFileSource`] also gunzips `.gz` files, [`MemorySource`] serves
+//! buffers already in memory, such as uploaded or pasted logs, and
+//! [`StdinSource`] reads standard input.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::engine::AnalysisError;
+use crate::model::Source;
+
+/// A byte stream of one source's log.
+pub type LogReader = Box<dyn Read + Send>;
+
+/// Provides the log of each source an analysis reads. Sources are opened
+/// from several threads at once, and each is opened once per analysis.
+pub trait LogSource: Sync {
+    fn open(&self, source: &Source) -> Result<LogReader, AnalysisError>;
+}
+
+/// Reads each source's `file_path`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSource;
+
+impl LogSource for FileSource {
+    fn open(&self, source: &Source) -> Result<LogReader, AnalysisError> {
+        let file = File::open(&source.file_path)
+            .map_err(|_| AnalysisError::FileNotFound(source.file_path.clone()))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Reads each source's `file_path`, gunzipping files that end in `.gz`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressedFileSource;
+
+impl LogSource for CompressedFileSource {
+    fn open(&self, source: &Source) -> Result<LogReader, AnalysisError> {
+        let file = FileSource.open(source)?;
+        if source.file_path.ends_with(".gz") {
+            Ok(Box::new(MultiGzDecoder::new(file)))
+        } else {
+            Ok(file)
+        }
+    }
+}
+
+/// Serves logs held in memory, by source id.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    logs: HashMap<u64, Arc<[u8]>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the log of the source with id `source_id`.
+    pub fn insert(&mut self, source_id: u64, log: impl Into<Arc<[u8]>>) {
+        self.logs.insert(source_id, log.into());
+    }
+}
+
+impl<T: Into<Arc<[u8]>>> FromIterator<(u64, T)> for MemorySource {
+    fn from_iter<I: IntoIterator<Item = (u64, T)>>(iter: I) -> Self {
+        Self {
+            logs: iter
+                .into_iter()
+                .map(|(source_id, log)| (source_id, log.into()))
+                .collect(),
+        }
+    }
+}
+
+impl LogSource for MemorySource {
+    fn open(&self, source: &Source) -> Result<LogReader, AnalysisError> {
+        let log = self.logs.get(&source.id).ok_or_else(|| {
+            AnalysisError::FileNotFound(format!("no log in memory for source '{}'", source.name))
+        })?;
+        Ok(Box::new(io::Cursor::new(Arc::clone(log))))
+    }
+}
+
+/// Reads standard input, for a single source piped in. A second source
+/// would only see what the first left unread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdinSource;
+
+impl LogSource for StdinSource {
+    fn open(&self, _source: &Source) -> Result<LogReader, AnalysisError> {
+        Ok(Box::new(io::stdin()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tempfile::Builder;
+
+    use super::*;
+
+    fn source(id: u64, file_path: &str) -> Source {
+        Source {
+            id,
+            name: format!("s{id}"),
+            template_id: 1,
+            file_path: file_path.into(),
+        }
+    }
+
+    fn read_all(logs: &dyn LogSource, source: &Source) -> String {
+        let mut text = String::new();
+        logs.open(source)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_memory_source() {
+        let logs: MemorySource = [(1, b"a\nb\n".as_slice())].into_iter().collect();
+        assert_eq!(read_all(&logs, &source(1, "")), "a\nb\n");
+        // Each open starts from the beginning
+        assert_eq!(read_all(&logs, &source(1, "")), "a\nb\n");
+        assert!(matches!(
+            logs.open(&source(2, "")),
+            Err(AnalysisError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_compressed_file_source() {
+        let mut gz = Builder::new().suffix(".log.gz").tempfile().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        gz.write_all(&encoder.finish().unwrap()).unwrap();
+        let gz_source = source(1, gz.path().to_str().unwrap());
+
+        assert_eq!(
+            read_all(&CompressedFileSource, &gz_source),
+            "line 1\nline 2\n"
+        );
+        // Plain files read as they are
+        let plain = Builder::new().suffix(".log").tempfile().unwrap();
+        std::fs::write(plain.path(), "line 1\n").unwrap();
+        let plain_source = source(2, plain.path().to_str().unwrap());
+        assert_eq!(read_all(&CompressedFileSource, &plain_source), "line 1\n");
+        assert_eq!(read_all(&FileSource, &plain_source), "line 1\n");
+        assert!(
+            FileSource
+                .open(&source(3, "/nonexistent/file.log"))
+                .is_err()
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::sync::Arc;
 
@@ -12,6 +12,24 @@ pub enum StateValue {
     Integer(i64),
     Float(f64),
     Bool(bool),
+    /// A point in time, in the logs' own (zone-less) time.
+    Timestamp(NaiveDateTime),
+    /// A length of time in milliseconds.
+    Duration(i64),
+    /// Values in the order they were added, duplicates kept.
+    List(Vec<StateValue>),
+    /// Distinct values in the order they were first added.
+    Set(Vec<StateValue>),
+}
+
+impl StateValue {
+    /// The elements of a `List` or `Set`.
+    pub fn items(&self) -> Option<&[StateValue]> {
+        match self {
+            StateValue::List(items) | StateValue::Set(items) => Some(items),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for StateValue {
@@ -21,6 +39,57 @@ impl fmt::Display for StateValue {
             StateValue::Integer(i) => write!(f, "{i}"),
             StateValue::Float(v) => write!(f, "{v}"),
             StateValue::Bool(b) => write!(f, "{b}"),
+            StateValue::Timestamp(t) => write!(f, "{t}"),
+            StateValue::Duration(ms) => fmt_duration(*ms, f),
+            StateValue::List(items) | StateValue::Set(items) => {
+                let (open, close) = if matches!(self, StateValue::List(_)) {
+                    ('[', ']')
+                } else {
+                    ('{', '}')
+                };
+                write!(f, "{open}")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "{close}")
+            }
+        }
+    }
+}
+
+/// Write milliseconds as `1h2m3.5s`, `250ms` or `0s`.
+fn fmt_duration(ms: i64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if ms < 0 {
+        write!(f, "-")?;
+    }
+    let ms = ms.unsigned_abs();
+    if ms == 0 {
+        return write!(f, "0s");
+    }
+    if ms < 1_000 {
+        return write!(f, "{ms}ms");
+    }
+    let (h, m, s, frac) = (
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1_000 % 60,
+        ms % 1_000,
+    );
+    if h > 0 {
+        write!(f, "{h}h")?;
+    }
+    if m > 0 {
+        write!(f, "{m}m")?;
+    }
+    match (s, frac) {
+        (0, 0) => Ok(()),
+        (s, 0) => write!(f, "{s}s"),
+        (s, frac) => {
+            let frac = format!("{frac:03}");
+            write!(f, "{s}.{}s", frac.trim_end_matches('0'))
         }
     }
 }
@@ -32,6 +101,13 @@ impl PartialEq for StateValue {
             (StateValue::Integer(a), StateValue::Integer(b)) => a == b,
             (StateValue::Float(a), StateValue::Float(b)) => a == b,
             (StateValue::Bool(a), StateValue::Bool(b)) => a == b,
+            (StateValue::Timestamp(a), StateValue::Timestamp(b)) => a == b,
+            (StateValue::Duration(a), StateValue::Duration(b)) => a == b,
+            (StateValue::List(a), StateValue::List(b)) => a == b,
+            // Sets are equal regardless of insertion order
+            (StateValue::Set(a), StateValue::Set(b)) => {
+                a.len() == b.len() && a.iter().all(|v| b.contains(v))
+            }
             // Cross-type: attempt numeric comparison
             (StateValue::Integer(a), StateValue::Float(b)) => (*a as f64) == *b,
             (StateValue::Float(a), StateValue::Integer(b)) => *a == (*b as f64),
@@ -47,6 +123,8 @@ impl PartialOrd for StateValue {
             (StateValue::Integer(a), StateValue::Integer(b)) => a.partial_cmp(b),
             (StateValue::Float(a), StateValue::Float(b)) => a.partial_cmp(b),
             (StateValue::Bool(a), StateValue::Bool(b)) => a.partial_cmp(b),
+            (StateValue::Timestamp(a), StateValue::Timestamp(b)) => a.partial_cmp(b),
+            (StateValue::Duration(a), StateValue::Duration(b)) => a.partial_cmp(b),
             (StateValue::Integer(a), StateValue::Float(b)) => (*a as f64).partial_cmp(b),
             (StateValue::Float(a), StateValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
             _ => None,
@@ -54,12 +132,18 @@ impl PartialOrd for StateValue {
     }
 }
 
-/// A state value paired with the timestamp of the log line that last set it.
-/// TODO: Add `line_number: Option<u64>` for source-line provenance.
+/// A state value paired with the log line and rule that last set it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrackedValue {
     pub value: StateValue,
     pub set_at: NaiveDateTime,
+    /// Rule that set the value; 0 for JSON auto-extracted fields and derived
+    /// keys.
+    #[serde(default)]
+    pub rule_id: u64,
+    /// 1-based line number in the source file of the line that set the value.
+    #[serde(default)]
+    pub line_number: Option<u64>,
 }
 
 /// Timestamp template - describes how to parse timestamps from log lines.
@@ -84,6 +168,9 @@ pub struct SourceTemplate {
     pub json_timestamp_field: Option<String>,
     pub file_name_regex: Option<String>,
     pub log_content_regex: Option<String>,
+    /// Line parser plugin that replaces the regexes and timestamp template.
+    #[serde(default)]
+    pub parser_plugin_id: Option<u64>,
 }
 
 /// Source - an actual log file.
@@ -100,6 +187,9 @@ pub struct Source {
 pub struct LogLine {
     pub timestamp: NaiveDateTime,
     pub source_id: u64,
+    /// 1-based number of the (first) physical line in the source file.
+    #[serde(default)]
+    pub line_number: u64,
     pub raw: Arc<str>,
     pub content: Arc<str>,
     #[serde(skip)]
@@ -118,6 +208,9 @@ pub enum MatchMode {
 pub struct MatchRule {
     pub id: u64,
     pub pattern: String,
+    /// The rule only matches lines this regex does not match.
+    #[serde(default)]
+    pub negate: bool,
 }
 
 /// Extraction types.
@@ -132,7 +225,24 @@ pub enum ExtractionType {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExtractionMode {
     Replace,
+    /// Add numbers, comma-join strings.
     Accumulate,
+    /// Count the extractions as an integer; the values themselves are ignored.
+    Count,
+    /// Keep the smallest value seen.
+    Min,
+    /// Keep the largest value seen.
+    Max,
+    /// Running mean of the numeric values seen, as a float.
+    Average,
+    /// Distinct values seen, as a `Set`.
+    DistinctSet,
+    /// The most recent `cap` values, oldest first, as a `List`.
+    List {
+        cap: usize,
+    },
+    /// Keep the first value; later extractions do not overwrite it.
+    FirstSeen,
 }
 
 /// Extraction rule.
@@ -144,6 +254,70 @@ pub struct ExtractionRule {
     pub pattern: Option<String>,
     pub static_value: Option<String>,
     pub mode: ExtractionMode,
+    /// Type the transformed text is converted to.
+    #[serde(default)]
+    pub target_type: ValueType,
+    /// Applied in order to the captured or static text before conversion.
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+    /// Log time after which the key is unset again, unless this rule fires
+    /// again first. `None` keeps the value until it is cleared.
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
+}
+
+/// Type an extracted value is converted to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueType {
+    /// Parsed values try integer, then float, then bool, then fall back to
+    /// string; static values stay strings.
+    #[default]
+    Auto,
+    String,
+    Integer,
+    Float,
+    Bool,
+    /// RFC 3339, `YYYY-MM-DD[ T]HH:MM:SS[.frac]`, a bare date, or Unix epoch
+    /// seconds (milliseconds when 13 digits long).
+    Timestamp,
+    /// `1h30m`, `1.5s`, `[hh:]mm:ss` or a bare number of seconds.
+    Duration,
+}
+
+/// One step of an extraction's transform pipeline, mapping text to text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Transform {
+    Lowercase,
+    Uppercase,
+    Trim,
+    /// Replace every match of a regex; `$1`/`$name` refer to its groups.
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Parse an integer in the given base (2-36), with an optional `0x`,
+    /// `0o` or `0b` prefix matching it, into decimal.
+    ParseInt {
+        radix: u32,
+    },
+    /// Convert a number with a time (`ns` … `d`) or size (`B` … `TB`, binary
+    /// multiples) suffix into the unit `to`. Bare numbers are taken as
+    /// already in `to`.
+    Unit {
+        to: String,
+    },
+    /// Parse a duration such as `1h30m`, `1.5s` or `01:02:03.5` into a
+    /// number of `to` time units.
+    Duration {
+        to: String,
+    },
+    /// Look the text up in a table; unmapped text becomes `default` when
+    /// given and passes through unchanged otherwise.
+    Map {
+        table: BTreeMap<String, String>,
+        #[serde(default)]
+        default: Option<String>,
+    },
 }
 
 /// A log rule combining match rules and extraction rules.
@@ -154,15 +328,97 @@ pub struct LogRule {
     pub match_mode: MatchMode,
     pub match_rules: Vec<MatchRule>,
     pub extraction_rules: Vec<ExtractionRule>,
+    /// State condition that must hold for a match to apply.
+    #[serde(default)]
+    pub guard: Option<RuleGuard>,
+    /// Rhai script run after the extraction rules; see [`crate::script`].
+    #[serde(default)]
+    pub script: Option<String>,
+    /// Extractor plugin run after the extraction rules, before the script.
+    #[serde(default)]
+    pub plugin_id: Option<u64>,
+}
+
+/// A condition on state checked before a rule's extractions are applied.
+/// A matching line is ignored by the rule while the guard does not hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleGuard {
+    /// Source whose state is tested; the matched line's own source if unset.
+    #[serde(default)]
+    pub source_name: Option<String>,
+    pub state_key: String,
+    pub operator: Operator,
+    pub operand: Operand,
 }
 
-/// A ruleset binding rules to a template.
+/// A ruleset binding rules to one or more templates, and optionally to
+/// particular sources using them.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ruleset {
     pub id: u64,
     pub name: String,
     pub template_id: u64,
+    /// Further templates whose sources the ruleset also applies to.
+    #[serde(default)]
+    pub extra_template_ids: Vec<u64>,
+    /// When non-empty, the ruleset applies only to sources with these names.
+    #[serde(default)]
+    pub source_names: Vec<String>,
+    /// In evaluation order.
     pub rule_ids: Vec<u64>,
+    /// Rules that, once applied to a line, stop every later rule for the
+    /// line's source from applying to it.
+    #[serde(default)]
+    pub stop_rule_ids: Vec<u64>,
+}
+
+impl Ruleset {
+    /// Whether the ruleset's rules run on `source`'s lines.
+    pub fn applies_to(&self, source: &Source) -> bool {
+        (self.template_id == source.template_id
+            || self.extra_template_ids.contains(&source.template_id))
+            && (self.source_names.is_empty() || self.source_names.contains(&source.name))
+    }
+}
+
+/// A state key computed from other state, e.g. `queue_wait` as
+/// `match_start_ts - join_ts`. It is recomputed whenever one of its inputs
+/// changes and unset while the expression has no value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DerivedKey {
+    pub id: u64,
+    /// Source whose state holds the value. Bare names in the expression are
+    /// this source's keys; other sources' keys are written `source.key`.
+    pub source_name: String,
+    pub state_key: String,
+    pub expression: String,
+}
+
+/// A named regex fragment referenced from match and extraction patterns as
+/// `%{NAME}` or `%{NAME:field}`. Project entries shadow the built-in set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrokPattern {
+    pub id: u64,
+    pub name: String,
+    pub pattern: String,
+}
+
+/// A WebAssembly module that parses a template's lines or computes a rule's
+/// state; see [`crate::plugin`] for its ABI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plugin {
+    pub id: u64,
+    pub name: String,
+    pub kind: PluginKind,
+    /// The module's bytes, base64 in JSON.
+    #[serde(with = "crate::plugin::wasm_base64")]
+    pub wasm: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PluginKind {
+    LineParser,
+    Extractor,
 }
 
 /// Predicate operators.
@@ -174,8 +430,58 @@ pub enum Operator {
     Lt,
     Gte,
     Lte,
+    /// Substring of the value's text, or an element of a `List` or `Set`.
     Contains,
     Exists,
+    /// The key is unset.
+    NotExists,
+    /// The string value matches a `Literal` regex operand.
+    Matches,
+    /// The value equals one of the values in a `List` operand.
+    In,
+    /// The value is set and equals none of the values in a `List` operand.
+    NotIn,
+    StartsWith,
+    EndsWith,
+    /// The value lies inside a `Range` operand, bounds inclusive.
+    Between,
+    /// The value was last set more than `Literal(Integer)` milliseconds
+    /// before the current line.
+    OlderThan,
+    /// The value was last set at most `Literal(Integer)` milliseconds
+    /// before the current line.
+    NewerThan,
+    /// The key was set before the key named by a `TimeRef` operand.
+    SetBefore,
+    /// The key was set after the key named by a `TimeRef` operand.
+    SetAfter,
+    /// The key was set within `within_ms` of the key named by a `TimeRef`
+    /// operand, in either order.
+    SetWithin,
+    /// A `List` or `Set` value has exactly `Literal(Integer)` elements.
+    SizeEq,
+    /// A `List` or `Set` value has more than `Literal(Integer)` elements.
+    SizeGt,
+    /// A `List` or `Set` value has fewer than `Literal(Integer)` elements.
+    SizeLt,
+    /// The key changed on the current line. A `Literal` operand means
+    /// "changed to"; a `Transition` operand restricts the old and new values.
+    Changed,
+    /// The key went from unset to set on the current line.
+    BecameSet,
+    /// The key went from set to unset on the current line.
+    BecameUnset,
+}
+
+impl Operator {
+    /// Edge operators test the transitions produced by the current line
+    /// rather than the current state.
+    pub fn is_edge(&self) -> bool {
+        matches!(
+            self,
+            Operator::Changed | Operator::BecameSet | Operator::BecameUnset
+        )
+    }
 }
 
 /// Operand - can be literal value or reference to another source's state.
@@ -186,6 +492,48 @@ pub enum Operand {
         source_name: String,
         state_key: String,
     },
+    /// Old/new value constraint for `Changed`; `None` matches any value.
+    Transition {
+        from: Option<StateValue>,
+        to: Option<StateValue>,
+    },
+    /// Candidate values for `In` and `NotIn`.
+    List(Vec<StateValue>),
+    /// Inclusive bounds for `Between`.
+    Range {
+        min: StateValue,
+        max: StateValue,
+    },
+    /// Another source's key whose set time the temporal operators compare
+    /// against. `skew_ms` is added to that source's timestamps to correct a
+    /// known clock offset; `within_ms` bounds the gap between the two.
+    TimeRef {
+        source_name: String,
+        state_key: String,
+        #[serde(default)]
+        within_ms: Option<u64>,
+        #[serde(default)]
+        skew_ms: i64,
+    },
+    /// Arithmetic over literals and state references, e.g.
+    /// `server.max_retries + 2`; see [`crate::expr`].
+    Expr(String),
+}
+
+/// Burst condition for a pattern step: fires when at least `count` qualifying
+/// events fall inside a sliding window of log time.
+///
+/// A qualifying event is a match of `rule_id` on the step's source when set,
+/// otherwise a change of the step's state key whose new value satisfies the
+/// step's operator and operand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Threshold {
+    pub count: u64,
+    pub window_ms: u64,
+    pub rule_id: Option<u64>,
+    /// Optional state key on the same source used as a correlation key;
+    /// events are counted separately for each of its values.
+    pub group_by: Option<String>,
 }
 
 /// A single predicate in a pattern.
@@ -195,6 +543,10 @@ pub struct PatternPredicate {
     pub state_key: String,
     pub operator: Operator,
     pub operand: Operand,
+    /// Turns the step into a threshold step counting events instead of
+    /// testing the current state.
+    #[serde(default)]
+    pub threshold: Option<Threshold>,
 }
 
 /// A pattern consisting of an ordered sequence of predicates.
@@ -203,6 +555,50 @@ pub struct Pattern {
     pub id: u64,
     pub name: String,
     pub predicates: Vec<PatternPredicate>,
+    /// Suppress further matches for this long after the pattern fires, so one
+    /// incident yields one match.
+    #[serde(default)]
+    pub cooldown_ms: Option<u64>,
+    #[serde(flatten)]
+    pub outcome: PatternOutcome,
+    /// A more general pattern this one refines. The pattern's matches are
+    /// only reported after a match of the parent.
+    #[serde(default)]
+    pub parent: Option<PatternParent>,
+}
+
+/// Link from a child pattern to the pattern it refines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternParent {
+    pub pattern_id: u64,
+    /// How long after a parent match the child may still match; `None`
+    /// allows any later match.
+    #[serde(default)]
+    pub window_ms: Option<u64>,
+}
+
+/// How serious a diagnosed failure is, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+/// What a pattern means when it fires. On a `Pattern`, `diagnosis` and
+/// `remediation` are templates with `{source.key}` placeholders; on a
+/// `PatternMatch` they are rendered against the state at match time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatternOutcome {
+    #[serde(default)]
+    pub severity: Option<Severity>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub diagnosis: Option<String>,
+    #[serde(default)]
+    pub remediation: Option<String>,
 }
 
 /// Result of a rule match on a specific log line.
@@ -214,12 +610,90 @@ pub struct RuleMatch {
     pub extracted_state: HashMap<String, StateValue>,
 }
 
+/// An event counted towards a threshold step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountedEvent {
+    pub timestamp: NaiveDateTime,
+    pub source_id: u64,
+    pub rule_id: u64,
+    pub line_number: u64,
+    /// New state value for state-change events; `None` for rule-match events.
+    pub value: Option<StateValue>,
+    /// Correlation key value the event was counted under.
+    pub group: Option<StateValue>,
+}
+
+/// The state change (or, for threshold steps, the last counted event) that
+/// satisfied one step of a pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepProvenance {
+    pub step_index: usize,
+    pub source_id: u64,
+    pub source_name: String,
+    pub state_key: String,
+    pub timestamp: NaiveDateTime,
+    /// Rule that produced the change; 0 for JSON auto-extracted fields.
+    pub rule_id: u64,
+    pub line_number: Option<u64>,
+    /// State value at the time the step was satisfied.
+    pub value: Option<StateValue>,
+}
+
 /// Result of a pattern matching.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PatternMatch {
     pub pattern_id: u64,
     pub timestamp: NaiveDateTime,
     pub state_snapshot: HashMap<String, Arc<HashMap<String, TrackedValue>>>,
+    /// Events that satisfied the pattern's threshold steps, if any.
+    #[serde(default)]
+    pub counted_events: Vec<CountedEvent>,
+    /// Per-step causal chain, in step order.
+    #[serde(default)]
+    pub steps: Vec<StepProvenance>,
+    /// The pattern's outcome with templates rendered.
+    #[serde(flatten)]
+    pub outcome: PatternOutcome,
+    /// Parent pattern whose most recent match this match refines.
+    #[serde(default)]
+    pub refines: Option<u64>,
+    /// Set when a child pattern's match refined this one, so the child's
+    /// diagnosis is the more specific one. Only filled in on collected
+    /// results; streaming sends a `superseded` event after the fact.
+    #[serde(default)]
+    pub superseded: bool,
+}
+
+/// How far a pattern that never fired got, and the step that held it back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NearMiss {
+    pub pattern_id: u64,
+    /// Steps satisfied by the furthest attempt.
+    pub steps_reached: usize,
+    pub total_steps: usize,
+    /// Log time the furthest step was reached; `None` if no step was ever satisfied.
+    pub reached_at: Option<NaiveDateTime>,
+    /// State when the furthest step was reached (end-of-run state if none was).
+    pub state_snapshot: HashMap<String, Arc<HashMap<String, TrackedValue>>>,
+    /// Provenance of the satisfied steps.
+    pub steps: Vec<StepProvenance>,
+    /// Index of the first unsatisfied step.
+    pub failed_step: usize,
+    pub failed_predicate: PatternPredicate,
+    /// Value of the failed step's state key; for threshold steps, the largest
+    /// event count inside the window.
+    pub actual: Option<StateValue>,
+    /// Resolved operand of the failed step; for threshold steps, the required count.
+    pub expected: Option<StateValue>,
+}
+
+/// Result of explaining a single pattern against the project's logs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternExplanation {
+    pub pattern_id: u64,
+    pub match_count: u64,
+    /// Present when the pattern never fired.
+    pub near_miss: Option<NearMiss>,
 }
 
 /// A state change event emitted when a mutation modifies per-source state.
@@ -240,6 +714,76 @@ pub struct AnalysisResult {
     pub rule_matches: Vec<RuleMatch>,
     pub pattern_matches: Vec<PatternMatch>,
     pub state_changes: Vec<StateChange>,
+    /// Only populated when near-miss reporting is requested.
+    #[serde(default)]
+    pub near_misses: Vec<NearMiss>,
+    /// Only populated when profiling is requested.
+    #[serde(default)]
+    pub profile: Option<AnalysisProfile>,
+    /// Only populated when a coverage report is requested.
+    #[serde(default)]
+    pub coverage: Option<CoverageReport>,
+}
+
+/// Where an analysis spent its time, for finding expensive rules.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnalysisProfile {
+    /// Most expensive first.
+    pub rules: Vec<RuleProfile>,
+    pub sources: Vec<SourceProfile>,
+}
+
+/// Cost of one rule, summed over every line and source it was run against.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleProfile {
+    pub rule_id: u64,
+    /// Lines the rule was evaluated against.
+    pub evaluations: u64,
+    pub matches: u64,
+    /// Time in the rule's match and negated patterns.
+    pub match_ns: u64,
+    /// Time in extraction regexes and transforms, on matching lines.
+    pub extraction_ns: u64,
+}
+
+/// Cost of reading one source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceProfile {
+    pub source_id: u64,
+    pub lines: u64,
+    /// Time reading the file and parsing lines and timestamps.
+    pub parse_ns: u64,
+}
+
+/// Where a rule set has blind spots: lines no rule matched, and rules and
+/// patterns that never did anything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoverageReport {
+    pub sources: Vec<SourceCoverage>,
+    /// Rules that matched no line, by id.
+    pub unmatched_rules: Vec<u64>,
+    /// Rules that matched but never changed state, by id.
+    pub rules_without_changes: Vec<u64>,
+    /// Patterns that never fired, by id.
+    pub unfired_patterns: Vec<u64>,
+}
+
+/// Lines of one source that no rule matched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceCoverage {
+    pub source_id: u64,
+    pub lines: u64,
+    pub unmatched_lines: u64,
+    /// The most common shapes of unmatched line, most frequent first.
+    pub unmatched_templates: Vec<UnmatchedTemplate>,
+}
+
+/// Unmatched lines sharing a structural template, as in clustering.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UnmatchedTemplate {
+    pub template: String,
+    pub count: u64,
+    pub sample_line: String,
 }
 
 /// A cluster of log lines sharing the same structural template.
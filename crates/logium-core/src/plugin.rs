@@ -0,0 +1,542 @@
+//! WebAssembly plugins, for log formats and extractions that templates and
+//! rules cannot describe.
+//!
+//! A plugin is a module a project stores with one of two kinds:
+//!
+//! - a **line parser**, named by a source template, turns each line of a
+//!   source into a timestamp, content and fields;
+//! - an **extractor**, named by a rule, computes state from each line the
+//!   rule applies to, after its extraction rules.
+//!
+//! The ABI passes UTF-8 JSON through the module's memory. A module exports
+//! `memory`, `alloc(len: i32) -> i32` returning a buffer the host writes the
+//! input into, and its entry point, `parse_line` or `extract`, taking
+//! `(ptr: i32, len: i32)` and returning the output buffer packed as
+//! `ptr << 32 | len` in an `i64`.
+//!
+//! `parse_line` gets the raw line (continuation lines already merged) and
+//! returns `{"timestamp": ..., "content": ..., "fields": {...}}` or `null` to
+//! skip the line. The timestamp is text in any form a `Timestamp` extraction
+//! accepts, or epoch seconds or milliseconds; content defaults to the raw
+//! line, and fields become state as a JSON log's do.
+//!
+//! `extract` gets `{"line", "raw", "source", "fields", "state"}` and returns
+//! an object from state key to new value, where `null` unsets the key.
+//!
+//! Modules may not import anything, so they cannot reach the host, and every
+//! call is limited to [`PLUGIN_FUEL`] instructions and [`PLUGIN_MEMORY_LIMIT`]
+//! bytes of memory. An extractor call that fails changes nothing on that
+//! line, as a failing script does; a parser that fails stops the analysis.
+//! Plugins need logium-core's `plugins` feature; without it an analysis
+//! using one fails.
+
+use serde_json::{Map, Value, json};
+
+use crate::model::{Plugin, PluginKind, StateValue};
+use crate::script::{ScriptInput, ScriptOutput};
+use crate::transform::parse_timestamp;
+
+/// Instructions a plugin may execute per call.
+pub const PLUGIN_FUEL: u64 = 100_000_000;
+
+/// Largest memory a plugin may grow to.
+pub const PLUGIN_MEMORY_LIMIT: usize = 64 << 20;
+
+/// A line as a parser plugin returns it.
+#[derive(Debug)]
+pub struct ParsedLine {
+    pub timestamp: chrono::NaiveDateTime,
+    pub content: Option<String>,
+    /// A JSON object, if the plugin returned fields.
+    pub fields: Option<Value>,
+}
+
+/// Compile a plugin and check its exports, to reject it before it is saved.
+pub fn validate_plugin(plugin: &Plugin) -> Result<(), String> {
+    CompiledPlugin::new(plugin).map(drop)
+}
+
+#[cfg(feature = "plugins")]
+mod imp {
+    use wasmi::{
+        Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+    };
+
+    use super::*;
+
+    /// A validated module, shared by every instance of one analysis.
+    pub struct CompiledPlugin {
+        engine: Engine,
+        module: Module,
+        kind: PluginKind,
+    }
+
+    /// A plugin's own memory and exports; one per thread that calls it.
+    pub struct PluginInstance {
+        store: Store<StoreLimits>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        entry: TypedFunc<(i32, i32), i64>,
+    }
+
+    fn entry_point(kind: PluginKind) -> &'static str {
+        match kind {
+            PluginKind::LineParser => "parse_line",
+            PluginKind::Extractor => "extract",
+        }
+    }
+
+    impl CompiledPlugin {
+        pub fn new(plugin: &Plugin) -> Result<Self, String> {
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, &plugin.wasm[..]).map_err(|e| e.to_string())?;
+            if let Some(import) = module.imports().next() {
+                return Err(format!(
+                    "plugins cannot import anything, found '{}::{}'",
+                    import.module(),
+                    import.name()
+                ));
+            }
+            let compiled = Self {
+                engine,
+                module,
+                kind: plugin.kind,
+            };
+            compiled.instantiate()?;
+            Ok(compiled)
+        }
+
+        pub fn kind(&self) -> PluginKind {
+            self.kind
+        }
+
+        pub fn instantiate(&self) -> Result<PluginInstance, String> {
+            let limits = StoreLimitsBuilder::new()
+                .memory_size(PLUGIN_MEMORY_LIMIT)
+                .instances(1)
+                .build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(PLUGIN_FUEL).map_err(|e| e.to_string())?;
+            let instance = Linker::new(&self.engine)
+                .instantiate(&mut store, &self.module)
+                .and_then(|pre| pre.start(&mut store))
+                .map_err(|e| e.to_string())?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or("plugin must export its memory as 'memory'")?;
+            let alloc = instance
+                .get_typed_func(&store, "alloc")
+                .map_err(|e| format!("plugin must export alloc(len: i32) -> i32: {e}"))?;
+            let name = entry_point(self.kind);
+            let entry = instance.get_typed_func(&store, name).map_err(|e| {
+                format!("plugin must export {name}(ptr: i32, len: i32) -> i64: {e}")
+            })?;
+            Ok(PluginInstance {
+                store,
+                memory,
+                alloc,
+                entry,
+            })
+        }
+    }
+
+    impl PluginInstance {
+        pub(super) fn call(&mut self, input: &[u8]) -> Result<Value, String> {
+            self.store
+                .set_fuel(PLUGIN_FUEL)
+                .map_err(|e| e.to_string())?;
+            let len = i32::try_from(input.len()).map_err(|_| "plugin input too large")?;
+            let ptr = self
+                .alloc
+                .call(&mut self.store, len)
+                .map_err(|e| e.to_string())?;
+            self.memory
+                .write(&mut self.store, ptr as u32 as usize, input)
+                .map_err(|e| e.to_string())?;
+            let packed = self
+                .entry
+                .call(&mut self.store, (ptr, len))
+                .map_err(|e| e.to_string())? as u64;
+            let start = (packed >> 32) as usize;
+            let end = start + (packed & 0xFFFF_FFFF) as usize;
+            let output = self
+                .memory
+                .data(&self.store)
+                .get(start..end)
+                .ok_or("plugin output lies outside its memory")?;
+            serde_json::from_slice(output).map_err(|e| format!("plugin output is not JSON: {e}"))
+        }
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+mod imp {
+    use super::*;
+
+    /// Stands in for a module when plugins are compiled out.
+    pub enum CompiledPlugin {}
+
+    pub enum PluginInstance {}
+
+    impl CompiledPlugin {
+        pub fn new(_plugin: &Plugin) -> Result<Self, String> {
+            Err("plugins need logium-core's `plugins` feature".into())
+        }
+
+        pub fn kind(&self) -> PluginKind {
+            match *self {}
+        }
+
+        pub fn instantiate(&self) -> Result<PluginInstance, String> {
+            match *self {}
+        }
+    }
+
+    impl PluginInstance {
+        pub(super) fn call(&mut self, _input: &[u8]) -> Result<Value, String> {
+            match *self {}
+        }
+    }
+}
+
+pub use imp::{CompiledPlugin, PluginInstance};
+
+impl PluginInstance {
+    /// Run a line parser on a raw line; `None` skips the line.
+    pub fn parse_line(&mut self, raw: &str) -> Result<Option<ParsedLine>, String> {
+        let mut output = match self.call(raw.as_bytes())? {
+            Value::Null => return Ok(None),
+            Value::Object(output) => output,
+            _ => return Err("parser output must be an object or null".into()),
+        };
+        let timestamp = match output.get("timestamp") {
+            Some(Value::String(text)) => parse_timestamp(text),
+            Some(Value::Number(n)) => parse_timestamp(&n.to_string()),
+            _ => None,
+        }
+        .ok_or("parser output needs a valid timestamp")?;
+        let content = match output.remove("content") {
+            Some(Value::String(content)) => Some(content),
+            None | Some(Value::Null) => None,
+            Some(_) => return Err("parser content must be a string".into()),
+        };
+        let fields = match output.remove("fields") {
+            Some(fields @ Value::Object(_)) => Some(fields),
+            None | Some(Value::Null) => None,
+            Some(_) => return Err("parser fields must be an object".into()),
+        };
+        Ok(Some(ParsedLine {
+            timestamp,
+            content,
+            fields,
+        }))
+    }
+
+    /// Run an extractor on a line; returns the state it asks for.
+    pub fn extract(&mut self, input: ScriptInput) -> Result<ScriptOutput, String> {
+        let fields: Map<String, Value> = input
+            .fields
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.clone(), state_to_json(v)))
+            .collect();
+        let state: Map<String, Value> = input
+            .state
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), state_to_json(v)))
+            .collect();
+        let request = json!({
+            "line": &*input.line.content,
+            "raw": &*input.line.raw,
+            "source": input.source_name,
+            "fields": fields,
+            "state": state,
+        });
+        let Value::Object(output) = self.call(request.to_string().as_bytes())? else {
+            return Err("extractor output must be an object of state keys".into());
+        };
+        Ok(output
+            .into_iter()
+            .map(|(key, value)| (key, json_to_state(&value)))
+            .collect())
+    }
+}
+
+fn state_to_json(value: &StateValue) -> Value {
+    match value {
+        StateValue::String(s) => Value::from(s.as_str()),
+        StateValue::Integer(i) => Value::from(*i),
+        StateValue::Float(f) => Value::from(*f),
+        StateValue::Bool(b) => Value::from(*b),
+        StateValue::Timestamp(_) => Value::from(value.to_string()),
+        StateValue::Duration(ms) => Value::from(*ms),
+        StateValue::List(items) | StateValue::Set(items) => {
+            items.iter().map(state_to_json).collect()
+        }
+    }
+}
+
+/// `None` for `null`; objects are kept as their JSON text.
+fn json_to_state(value: &Value) -> Option<StateValue> {
+    match value {
+        Value::Array(items) => Some(StateValue::List(
+            items.iter().filter_map(json_to_state).collect(),
+        )),
+        Value::Object(_) => Some(StateValue::String(value.to_string())),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Some(StateValue::Integer(i)),
+            None => n.as_f64().map(StateValue::Float),
+        },
+        Value::String(s) => Some(StateValue::String(s.clone())),
+        Value::Bool(b) => Some(StateValue::Bool(*b)),
+        Value::Null => None,
+    }
+}
+
+/// Serializes module bytes as standard, padded base64.
+pub mod wasm_base64 {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+        let text = text.trim_end_matches('=');
+        let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        for c in text.bytes() {
+            let v = ALPHABET
+                .iter()
+                .position(|a| *a == c)
+                .ok_or_else(|| format!("invalid base64 character '{}'", c as char))?;
+            acc = (acc << 6) | v as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((acc >> bits) as u8);
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        decode(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_base64() {
+        assert_eq!(wasm_base64::encode(b"hello"), "aGVsbG8=");
+        assert_eq!(wasm_base64::decode("aGVsbG8=").unwrap(), b"hello");
+        for len in 0..6 {
+            let bytes: Vec<u8> = (0..len).map(|i| 250 - i).collect();
+            let text = wasm_base64::encode(&bytes);
+            assert_eq!(text.len() % 4, 0);
+            assert_eq!(wasm_base64::decode(&text).unwrap(), bytes);
+        }
+        assert!(wasm_base64::decode("aGV*").is_err());
+    }
+
+    #[cfg(feature = "plugins")]
+    fn plugin(kind: PluginKind, wat: &str) -> Plugin {
+        Plugin {
+            id: 1,
+            name: "test".into(),
+            kind,
+            wasm: wat::parse_str(wat).unwrap(),
+        }
+    }
+
+    /// Splits `YYYY-MM-DD HH:MM:SS content` and skips lines starting with `#`.
+    #[cfg(feature = "plugins")]
+    const PARSER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"timestamp\":\"")
+          (data (i32.const 32) "\",\"content\":\"")
+          (data (i32.const 64) "\"}")
+          (data (i32.const 96) "null")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "parse_line") (param $ptr i32) (param $len i32) (result i64)
+            (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 35))
+              (then (return (i64.or (i64.shl (i64.const 96) (i64.const 32)) (i64.const 4)))))
+            (memory.copy (i32.const 4096) (i32.const 0) (i32.const 14))
+            (memory.copy (i32.const 4110) (local.get $ptr) (i32.const 19))
+            (memory.copy (i32.const 4129) (i32.const 32) (i32.const 13))
+            (memory.copy
+              (i32.const 4142)
+              (i32.add (local.get $ptr) (i32.const 20))
+              (i32.sub (local.get $len) (i32.const 20)))
+            (memory.copy (i32.add (i32.const 4122) (local.get $len)) (i32.const 64) (i32.const 2))
+            (i64.or
+              (i64.shl (i64.const 4096) (i64.const 32))
+              (i64.extend_i32_u (i32.add (local.get $len) (i32.const 28))))))
+    "#;
+
+    /// Returns its whole input as `request`, and unsets `stale`.
+    #[cfg(feature = "plugins")]
+    const ECHO_EXTRACTOR: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"request\":")
+          (data (i32.const 32) ",\"stale\":null}")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "extract") (param $ptr i32) (param $len i32) (result i64)
+            (memory.copy (i32.const 32768) (i32.const 0) (i32.const 11))
+            (memory.copy (i32.const 32779) (local.get $ptr) (local.get $len))
+            (memory.copy (i32.add (i32.const 32779) (local.get $len)) (i32.const 32) (i32.const 14))
+            (i64.or
+              (i64.shl (i64.const 32768) (i64.const 32))
+              (i64.extend_i32_u (i32.add (local.get $len) (i32.const 25))))))
+    "#;
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_parser_plugin() {
+        let compiled = CompiledPlugin::new(&plugin(PluginKind::LineParser, PARSER)).unwrap();
+        let mut parser = compiled.instantiate().unwrap();
+
+        let parsed = parser
+            .parse_line("2024-01-01 10:00:00 user alice logged in")
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.timestamp.to_string(), "2024-01-01 10:00:00");
+        assert_eq!(parsed.content.as_deref(), Some("user alice logged in"));
+        assert!(parsed.fields.is_none());
+        assert!(parser.parse_line("# comment").unwrap().is_none());
+        assert!(
+            parser
+                .parse_line("not a timestamp at all")
+                .unwrap_err()
+                .contains("timestamp")
+        );
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_extractor_plugin() {
+        use chrono::NaiveDate;
+        use std::collections::HashMap;
+        use std::sync::Arc;
+
+        use crate::model::LogLine;
+
+        let compiled = CompiledPlugin::new(&plugin(PluginKind::Extractor, ECHO_EXTRACTOR)).unwrap();
+        let mut extractor = compiled.instantiate().unwrap();
+        let line = LogLine {
+            timestamp: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            source_id: 1,
+            raw: Arc::from("00:00:00 score=42"),
+            content: Arc::from("score=42"),
+            line_number: 1,
+            cached_json: None,
+        };
+        let fields = HashMap::from([("level".to_string(), StateValue::String("info".into()))]);
+        let bonus = StateValue::Integer(8);
+        let mut output = extractor
+            .extract(ScriptInput {
+                line: &line,
+                source_name: "server",
+                fields: Some(&fields),
+                state: vec![("bonus", &bonus)],
+            })
+            .unwrap();
+        output.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[1], ("stale".to_string(), None));
+        let Some(StateValue::String(request)) = &output[0].1 else {
+            panic!("request should be kept as JSON text");
+        };
+        let request: Value = serde_json::from_str(request).unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "line": "score=42",
+                "raw": "00:00:00 score=42",
+                "source": "server",
+                "fields": { "level": "info" },
+                "state": { "bonus": 8 },
+            })
+        );
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_plugin_sandbox() {
+        let spinning = plugin(
+            PluginKind::Extractor,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "alloc") (param i32) (result i32) (i32.const 0))
+              (func (export "extract") (param i32 i32) (result i64)
+                (loop (br 0))
+                (i64.const 0)))
+            "#,
+        );
+        let mut instance = CompiledPlugin::new(&spinning)
+            .unwrap()
+            .instantiate()
+            .unwrap();
+        assert!(instance.call(b"{}").is_err());
+
+        let importing = plugin(
+            PluginKind::Extractor,
+            r#"(module (import "env" "now" (func)))"#,
+        );
+        assert!(
+            validate_plugin(&importing)
+                .unwrap_err()
+                .contains("cannot import")
+        );
+
+        // A parser module does not export an extractor's entry point
+        let mut wrong_kind = plugin(PluginKind::LineParser, PARSER);
+        wrong_kind.kind = PluginKind::Extractor;
+        assert!(
+            validate_plugin(&wrong_kind)
+                .unwrap_err()
+                .contains("extract")
+        );
+        assert!(
+            validate_plugin(&Plugin {
+                wasm: b"not wasm".to_vec(),
+                ..spinning
+            })
+            .is_err()
+        );
+    }
+}
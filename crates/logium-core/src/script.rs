@@ -0,0 +1,343 @@
+//! Rhai extraction scripts, for state a regex cannot get at: decoding a
+//! base64 payload, checking a checksum, walking a nested key list.
+//!
+//! A rule's script runs on every line the rule applies to, after its
+//! extraction rules, with these variables in scope:
+//!
+//! - `line`: the line's content, and `raw`: the whole line;
+//! - `source`: the name of the line's source;
+//! - `fields`: the line's JSON fields, a map (empty for text logs);
+//! - `state`: the source's current state, a map.
+//!
+//! It evaluates to a map from state key to new value, where `()` unsets the
+//! key; a script evaluating to `()` changes nothing. Besides Rhai's standard
+//! library, `base64_decode(text)` and `crc32(text)` are available.
+//!
+//! ```text
+//! let payload = base64_decode(line.split("payload=")[1]);
+//! #{ player: payload.split(":")[0], checksum_ok: crc32(payload) == fields.crc }
+//! ```
+//!
+//! Scripts cannot reach the filesystem or the network, and each run is
+//! stopped after [`SCRIPT_TIME_LIMIT`]. A script that fails or runs out of
+//! time changes nothing on that line. Scripting needs logium-core's
+//! `scripting` feature; without it a rule with a script fails to compile.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::model::{LogLine, StateValue};
+
+/// Longest a script may run on one line.
+pub const SCRIPT_TIME_LIMIT: Duration = Duration::from_millis(50);
+
+/// What a script sees of the line it runs on.
+pub struct ScriptInput<'a> {
+    pub line: &'a LogLine,
+    pub source_name: &'a str,
+    pub fields: Option<&'a HashMap<String, StateValue>>,
+    pub state: Vec<(&'a str, &'a StateValue)>,
+}
+
+/// State changes a script asks for: a new value, or `None` to unset.
+pub type ScriptOutput = Vec<(String, Option<StateValue>)>;
+
+/// Compile a script on its own, to reject it before it is saved.
+pub fn validate_script(source: &str) -> Result<(), String> {
+    ScriptRunner::new().compile(source).map(drop)
+}
+
+#[cfg(feature = "scripting")]
+mod imp {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Instant;
+
+    use rhai::module_resolvers::DummyModuleResolver;
+    use rhai::{AST, Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+    use super::*;
+
+    /// A sandboxed engine, shared by every script of one analysis.
+    pub struct ScriptRunner {
+        engine: Engine,
+        started: Rc<Cell<Instant>>,
+    }
+
+    pub struct CompiledScript(AST);
+
+    impl Default for ScriptRunner {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl ScriptRunner {
+        pub fn new() -> Self {
+            let started = Rc::new(Cell::new(Instant::now()));
+            let mut engine = Engine::new();
+            engine
+                .set_max_string_size(1 << 20)
+                .set_max_array_size(10_000)
+                .set_max_map_size(10_000)
+                .set_max_call_levels(32)
+                .disable_symbol("eval")
+                .disable_symbol("import")
+                .set_module_resolver(DummyModuleResolver::new())
+                .on_print(|_| {})
+                .on_debug(|_, _, _| {});
+            let clock = started.clone();
+            engine.on_progress(move |_| {
+                (clock.get().elapsed() > SCRIPT_TIME_LIMIT).then_some(Dynamic::UNIT)
+            });
+            engine.register_fn("base64_decode", base64_decode);
+            engine.register_fn("crc32", |text: &str| {
+                crc32fast::hash(text.as_bytes()) as i64
+            });
+            Self { engine, started }
+        }
+
+        pub fn compile(&self, source: &str) -> Result<CompiledScript, String> {
+            self.engine
+                .compile(source)
+                .map(CompiledScript)
+                .map_err(|e| e.to_string())
+        }
+
+        pub fn run(
+            &self,
+            script: &CompiledScript,
+            input: ScriptInput,
+        ) -> Result<ScriptOutput, String> {
+            let mut scope = Scope::new();
+            scope.push("line", input.line.content.to_string());
+            scope.push("raw", input.line.raw.to_string());
+            scope.push("source", input.source_name.to_string());
+            let fields: Map = input
+                .fields
+                .into_iter()
+                .flatten()
+                .map(|(k, v)| (k.as_str().into(), to_dynamic(v)))
+                .collect();
+            scope.push("fields", fields);
+            let state: Map = input
+                .state
+                .into_iter()
+                .map(|(k, v)| (k.into(), to_dynamic(v)))
+                .collect();
+            scope.push("state", state);
+
+            self.started.set(Instant::now());
+            let result: Dynamic = self
+                .engine
+                .eval_ast_with_scope(&mut scope, &script.0)
+                .map_err(|e| match *e {
+                    EvalAltResult::ErrorTerminated(..) => {
+                        format!("time limit of {}ms exceeded", SCRIPT_TIME_LIMIT.as_millis())
+                    }
+                    e => e.to_string(),
+                })?;
+            if result.is_unit() {
+                return Ok(Vec::new());
+            }
+            let map = result
+                .try_cast::<Map>()
+                .ok_or("script must evaluate to a map of state keys")?;
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = from_dynamic(value)
+                        .map_err(|t| format!("state key '{key}': unsupported value of type {t}"))?;
+                    Ok((key.to_string(), value))
+                })
+                .collect()
+        }
+    }
+
+    fn to_dynamic(value: &StateValue) -> Dynamic {
+        match value {
+            StateValue::String(s) => s.clone().into(),
+            StateValue::Integer(i) => (*i).into(),
+            StateValue::Float(f) => (*f).into(),
+            StateValue::Bool(b) => (*b).into(),
+            StateValue::Timestamp(_) => value.to_string().into(),
+            StateValue::Duration(ms) => (*ms).into(),
+            StateValue::List(items) | StateValue::Set(items) => {
+                items.iter().map(to_dynamic).collect::<Array>().into()
+            }
+        }
+    }
+
+    /// `None` for `()`; the type name of anything that has no state form.
+    fn from_dynamic(value: Dynamic) -> Result<Option<StateValue>, &'static str> {
+        let type_name = value.type_name();
+        if value.is_unit() {
+            Ok(None)
+        } else if let Ok(i) = value.as_int() {
+            Ok(Some(StateValue::Integer(i)))
+        } else if let Ok(f) = value.as_float() {
+            Ok(Some(StateValue::Float(f)))
+        } else if let Ok(b) = value.as_bool() {
+            Ok(Some(StateValue::Bool(b)))
+        } else if let Ok(c) = value.as_char() {
+            Ok(Some(StateValue::String(c.to_string())))
+        } else if value.is_array() {
+            let items = value.into_array().map_err(|_| type_name)?;
+            items
+                .into_iter()
+                .map(|item| from_dynamic(item)?.ok_or("()"))
+                .collect::<Result<_, _>>()
+                .map(|items| Some(StateValue::List(items)))
+        } else {
+            value
+                .into_string()
+                .map(|s| Some(StateValue::String(s)))
+                .map_err(|_| type_name)
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod imp {
+    use super::*;
+
+    /// Stands in for the engine when scripting is compiled out.
+    #[derive(Default)]
+    pub struct ScriptRunner;
+
+    pub enum CompiledScript {}
+
+    impl ScriptRunner {
+        pub fn new() -> Self {
+            Self
+        }
+
+        pub fn compile(&self, _source: &str) -> Result<CompiledScript, String> {
+            Err("scripted rules need logium-core's `scripting` feature".into())
+        }
+
+        pub fn run(
+            &self,
+            script: &CompiledScript,
+            _input: ScriptInput,
+        ) -> Result<ScriptOutput, String> {
+            match *script {}
+        }
+    }
+}
+
+pub use imp::{CompiledScript, ScriptRunner};
+
+/// Decode standard or URL-safe base64, padded or not, as UTF-8 (lossily).
+/// Characters outside the alphabet, such as whitespace, are skipped, and
+/// anything from the first `=` on is ignored.
+#[cfg(feature = "scripting")]
+pub fn base64_decode(text: &str) -> String {
+    use base64::Engine;
+    use base64::alphabet;
+    use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+
+    /// Base64 as found in logs: padding optional, stray low bits ignored.
+    const LENIENT: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new()
+            .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+            .with_decode_allow_trailing_bits(true),
+    );
+
+    let standard: String = text
+        .chars()
+        .take_while(|&c| c != '=')
+        .filter_map(|c| match c {
+            '-' => Some('+'),
+            '_' => Some('/'),
+            c if c.is_ascii_alphanumeric() || c == '+' || c == '/' => Some(c),
+            _ => None,
+        })
+        .collect();
+    // A lone trailing character carries no whole byte
+    let whole = standard.len() - usize::from(standard.len() % 4 == 1);
+    let bytes = LENIENT.decode(&standard[..whole]).unwrap_or_default();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(base64_decode("aGVsbG8gd29ybGQ="), "hello world");
+        assert_eq!(base64_decode("aGVsbG8_"), "hello?");
+        assert_eq!(base64_decode("aGVs\nbG8 gd29y bGQ"), "hello world");
+        assert_eq!(base64_decode("aGVsbG8gd29ybGQ=trailing"), "hello world");
+        assert_eq!(base64_decode("aGVsbG8gd29ybGQhx"), "hello world!");
+        assert_eq!(base64_decode("*"), "");
+    }
+
+    #[test]
+    fn test_run_script() {
+        use chrono::NaiveDate;
+        use std::sync::Arc;
+
+        let line = LogLine {
+            timestamp: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            source_id: 1,
+            raw: Arc::from("00:00:00 payload=cGxheWVyOjQy"),
+            content: Arc::from("payload=cGxheWVyOjQy"),
+            line_number: 1,
+            cached_json: None,
+        };
+        let runner = ScriptRunner::new();
+        let script = runner
+            .compile(
+                r#"
+                let parts = base64_decode(line.split("=")[1]).split(":");
+                #{ player: parts[0], score: parse_int(parts[1]) + state.bonus, stale: () }
+                "#,
+            )
+            .unwrap();
+        let bonus = StateValue::Integer(8);
+        let mut out = runner
+            .run(
+                &script,
+                ScriptInput {
+                    line: &line,
+                    source_name: "server",
+                    fields: None,
+                    state: vec![("bonus", &bonus)],
+                },
+            )
+            .unwrap();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            out,
+            vec![
+                ("player".into(), Some(StateValue::String("player".into()))),
+                ("score".into(), Some(StateValue::Integer(50))),
+                ("stale".into(), None),
+            ]
+        );
+
+        let input = || ScriptInput {
+            line: &line,
+            source_name: "server",
+            fields: None,
+            state: Vec::new(),
+        };
+        let looping = runner.compile("loop { }").unwrap();
+        assert!(
+            runner
+                .run(&looping, input())
+                .unwrap_err()
+                .contains("time limit")
+        );
+        let not_a_map = runner.compile("42").unwrap();
+        assert!(runner.run(&not_a_map, input()).is_err());
+        assert!(validate_script("#{ a: ").is_err());
+        assert!(validate_script(r#"eval("1")"#).is_err());
+        assert!(validate_script(r#"import "/etc/passwd" as m; 1"#).is_err());
+    }
+}
@@ -0,0 +1,577 @@
+//! Extraction transforms and typed conversion.
+//!
+//! An extraction rule's text — a regex capture or its static value — runs
+//! through the rule's `Transform` pipeline and is then converted to the
+//! rule's `ValueType`:
+//!
+//! ```text
+//! "0x1F"      ParseInt { radix: 16 }           -> Integer(31)
+//! "1.5MB"     Unit { to: "KB" }                -> Float(1536.0)
+//! "1h2m3s"    Duration { to: "s" }             -> Integer(3723)
+//! " WARN "    Trim, Lowercase, Map { warn: 2 } -> Integer(2)
+//! "took 1.5s" Replace { "took ": "" } as Duration -> Duration(1500)
+//! ```
+//!
+//! Pipelines are compiled once per analysis by `compile_rules`, which rejects
+//! bad regexes, radixes and unit names up front. At run time a step that
+//! cannot handle its input drops the extraction, the same as a regex that
+//! does not capture.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+use crate::engine::AnalysisError;
+use crate::model::{ExtractionRule, ExtractionType, StateValue, Transform, ValueType};
+
+/// What a unit suffix measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Time,
+    Size,
+}
+
+/// A unit as its dimension and its size in that dimension's base unit
+/// (milliseconds or bytes).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Unit {
+    dimension: Dimension,
+    scale: f64,
+}
+
+/// Look up a unit suffix, case-insensitively. Sizes use binary multiples.
+fn unit(name: &str) -> Option<Unit> {
+    let (dimension, scale) = match name.to_ascii_lowercase().as_str() {
+        "ns" => (Dimension::Time, 1e-6),
+        "us" | "µs" => (Dimension::Time, 1e-3),
+        "ms" => (Dimension::Time, 1.0),
+        "s" | "sec" | "secs" => (Dimension::Time, 1_000.0),
+        "m" | "min" | "mins" => (Dimension::Time, 60_000.0),
+        "h" | "hr" | "hrs" => (Dimension::Time, 3_600_000.0),
+        "d" => (Dimension::Time, 86_400_000.0),
+        "b" | "byte" | "bytes" => (Dimension::Size, 1.0),
+        "kb" | "kib" => (Dimension::Size, 1024.0),
+        "mb" | "mib" => (Dimension::Size, 1024.0 * 1024.0),
+        "gb" | "gib" => (Dimension::Size, 1024.0 * 1024.0 * 1024.0),
+        "tb" | "tib" => (Dimension::Size, 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        _ => return None,
+    };
+    Some(Unit { dimension, scale })
+}
+
+fn parse_unit(name: &str) -> Result<Unit, AnalysisError> {
+    unit(name).ok_or_else(|| AnalysisError::ParseError(format!("unknown unit '{name}'")))
+}
+
+fn parse_time_unit(name: &str) -> Result<Unit, AnalysisError> {
+    let u = parse_unit(name)?;
+    if u.dimension != Dimension::Time {
+        return Err(AnalysisError::ParseError(format!(
+            "'{name}' is not a time unit"
+        )));
+    }
+    Ok(u)
+}
+
+/// A transform with its regex and units resolved.
+#[derive(Debug, Clone)]
+enum Step {
+    Lowercase,
+    Uppercase,
+    Trim,
+    Replace {
+        regex: Regex,
+        replacement: String,
+    },
+    ParseInt {
+        radix: u32,
+    },
+    Unit {
+        to: Unit,
+    },
+    Duration {
+        to: Unit,
+    },
+    Map {
+        table: BTreeMap<String, String>,
+        default: Option<String>,
+    },
+}
+
+impl Step {
+    fn compile(transform: &Transform) -> Result<Self, AnalysisError> {
+        Ok(match transform {
+            Transform::Lowercase => Step::Lowercase,
+            Transform::Uppercase => Step::Uppercase,
+            Transform::Trim => Step::Trim,
+            Transform::Replace {
+                pattern,
+                replacement,
+            } => Step::Replace {
+                regex: Regex::new(pattern)
+                    .map_err(|e| AnalysisError::InvalidRegex(e.to_string()))?,
+                replacement: replacement.clone(),
+            },
+            Transform::ParseInt { radix } => {
+                if !(2..=36).contains(radix) {
+                    return Err(AnalysisError::ParseError(format!(
+                        "radix {radix} is outside 2-36"
+                    )));
+                }
+                Step::ParseInt { radix: *radix }
+            }
+            Transform::Unit { to } => Step::Unit {
+                to: parse_unit(to)?,
+            },
+            Transform::Duration { to } => Step::Duration {
+                to: parse_time_unit(to)?,
+            },
+            Transform::Map { table, default } => Step::Map {
+                table: table.clone(),
+                default: default.clone(),
+            },
+        })
+    }
+
+    fn apply<'a>(&self, text: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        Some(match self {
+            Step::Lowercase => Cow::Owned(text.to_lowercase()),
+            Step::Uppercase => Cow::Owned(text.to_uppercase()),
+            Step::Trim => Cow::Owned(text.trim().to_string()),
+            Step::Replace { regex, replacement } => {
+                Cow::Owned(regex.replace_all(&text, replacement.as_str()).into_owned())
+            }
+            Step::ParseInt { radix } => Cow::Owned(parse_int(&text, *radix)?.to_string()),
+            Step::Unit { to } => Cow::Owned(format_number(convert_unit(&text, *to)?)),
+            Step::Duration { to } => {
+                Cow::Owned(format_number(parse_duration_ms(&text)? / to.scale))
+            }
+            Step::Map { table, default } => match (table.get(text.as_ref()), default) {
+                (Some(mapped), _) => Cow::Owned(mapped.clone()),
+                (None, Some(d)) => Cow::Owned(d.clone()),
+                (None, None) => text,
+            },
+        })
+    }
+}
+
+/// Parse a signed integer in `radix`, allowing the prefix conventional for
+/// that radix.
+fn parse_int(text: &str, radix: u32) -> Option<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let prefixes: &[&str] = match radix {
+        16 => &["0x", "0X"],
+        8 => &["0o", "0O"],
+        2 => &["0b", "0B"],
+        _ => &[],
+    };
+    let digits = prefixes
+        .iter()
+        .find_map(|p| digits.strip_prefix(p))
+        .unwrap_or(digits);
+    let value = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+/// Split text into a leading number and the rest, e.g. `"1.5 MB"` into
+/// `(1.5, "MB")`.
+fn split_number(text: &str) -> Option<(f64, &str)> {
+    let text = text.trim_start();
+    let end = text
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(text.len(), |(i, _)| i);
+    let value = text[..end].parse().ok()?;
+    Some((value, text[end..].trim_start()))
+}
+
+fn convert_unit(text: &str, to: Unit) -> Option<f64> {
+    let (value, suffix) = split_number(text)?;
+    let suffix = suffix.trim_end();
+    if suffix.is_empty() {
+        return Some(value);
+    }
+    let from = unit(suffix)?;
+    (from.dimension == to.dimension).then(|| value * from.scale / to.scale)
+}
+
+/// Parse `1h30m`, `1.5s`, `2m 3s`, `90` (seconds) or a clock form
+/// `[hh:]mm:ss[.frac]` into milliseconds.
+fn parse_duration_ms(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.contains(':') {
+        let mut total = 0.0;
+        let parts: Vec<&str> = text.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        for part in parts {
+            let v: f64 = part.parse().ok()?;
+            total = total * 60.0 + v;
+        }
+        return Some(total * 1_000.0);
+    }
+    let mut rest = text;
+    let mut total = 0.0;
+    let mut parsed_any = false;
+    while !rest.is_empty() {
+        let (value, after) = split_number(rest)?;
+        let end = after
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(after.len());
+        let suffix = &after[..end];
+        let scale = if suffix.is_empty() {
+            // A lone bare number means seconds.
+            if parsed_any || !after[end..].trim().is_empty() {
+                return None;
+            }
+            1_000.0
+        } else {
+            let u = unit(suffix)?;
+            if u.dimension != Dimension::Time {
+                return None;
+            }
+            u.scale
+        };
+        total += value * scale;
+        parsed_any = true;
+        rest = after[end..].trim_start();
+    }
+    parsed_any.then_some(total)
+}
+
+/// Parse the timestamp forms `ValueType::Timestamp` accepts. Offsets are
+/// applied, giving UTC.
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        let n: i64 = text.parse().ok()?;
+        let dt = if text.len() >= 13 {
+            DateTime::from_timestamp_millis(n)
+        } else {
+            DateTime::from_timestamp(n, 0)
+        };
+        return dt.map(|dt| dt.naive_utc());
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(text, fmt).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// Render a number without a fractional part when it has none, so it
+/// converts back to an integer.
+fn format_number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 9.0e15 {
+        format!("{}", v as i64)
+    } else {
+        v.to_string()
+    }
+}
+
+/// Guess a value's type: integer, then float, then bool, else string.
+fn guess(text: &str) -> StateValue {
+    if let Ok(i) = text.parse::<i64>() {
+        StateValue::Integer(i)
+    } else if let Ok(f) = text.parse::<f64>() {
+        StateValue::Float(f)
+    } else if text == "true" || text == "false" {
+        StateValue::Bool(text == "true")
+    } else {
+        StateValue::String(text.to_string())
+    }
+}
+
+/// Convert text to `ty`, or `None` when it does not parse as that type.
+pub fn convert(text: &str, ty: ValueType) -> Option<StateValue> {
+    match ty {
+        ValueType::Auto => Some(guess(text)),
+        ValueType::String => Some(StateValue::String(text.to_string())),
+        ValueType::Integer => {
+            let t = text.trim();
+            t.parse::<i64>().ok().or_else(|| {
+                let f = t.parse::<f64>().ok()?;
+                (f.fract() == 0.0 && f.abs() < 9.0e15).then_some(f as i64)
+            })
+        }
+        .map(StateValue::Integer),
+        ValueType::Float => text.trim().parse().ok().map(StateValue::Float),
+        ValueType::Bool => match text.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(StateValue::Bool(true)),
+            "false" | "no" | "off" | "0" => Some(StateValue::Bool(false)),
+            _ => None,
+        },
+        ValueType::Timestamp => parse_timestamp(text).map(StateValue::Timestamp),
+        ValueType::Duration => {
+            let ms = parse_duration_ms(text)?.round();
+            (ms.abs() < 9.0e15).then_some(StateValue::Duration(ms as i64))
+        }
+    }
+}
+
+/// An extraction rule's transforms and target type, compiled.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+    target: ValueType,
+}
+
+impl Pipeline {
+    /// Compile an extraction rule's pipeline. Static values with an `Auto`
+    /// target stay strings.
+    pub fn new(ext: &ExtractionRule) -> Result<Self, AnalysisError> {
+        let steps = ext
+            .transforms
+            .iter()
+            .map(Step::compile)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| match e {
+                AnalysisError::ParseError(msg) => {
+                    AnalysisError::ParseError(format!("extraction of '{}': {msg}", ext.state_key))
+                }
+                other => other,
+            })?;
+        let target = match (&ext.extraction_type, ext.target_type) {
+            (ExtractionType::Static, ValueType::Auto) => ValueType::String,
+            (_, ty) => ty,
+        };
+        let pipeline = Self { steps, target };
+        if let (ExtractionType::Static, Some(value)) = (&ext.extraction_type, &ext.static_value)
+            && pipeline.apply(value).is_none()
+        {
+            return Err(AnalysisError::ParseError(format!(
+                "extraction of '{}': static value '{value}' is not a valid {:?}",
+                ext.state_key, target
+            )));
+        }
+        Ok(pipeline)
+    }
+
+    /// Run the transforms over `text` and convert the result.
+    pub fn apply(&self, text: &str) -> Option<StateValue> {
+        let mut value = Cow::Borrowed(text);
+        for step in &self.steps {
+            value = step.apply(value)?;
+        }
+        convert(&value, self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ExtractionMode;
+
+    fn pipeline(target_type: ValueType, transforms: Vec<Transform>) -> Pipeline {
+        Pipeline::new(&ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Parsed,
+            state_key: "v".into(),
+            pattern: Some("(?P<v>.*)".into()),
+            static_value: None,
+            mode: ExtractionMode::Replace,
+            target_type,
+            transforms,
+            ttl_ms: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_explicit_string_keeps_leading_zeros() {
+        assert_eq!(
+            pipeline(ValueType::Auto, vec![]).apply("007"),
+            Some(StateValue::Integer(7))
+        );
+        assert_eq!(
+            pipeline(ValueType::String, vec![]).apply("007"),
+            Some(StateValue::String("007".into()))
+        );
+    }
+
+    #[test]
+    fn test_hex_parse() {
+        let p = pipeline(ValueType::Integer, vec![Transform::ParseInt { radix: 16 }]);
+        assert_eq!(p.apply("0x1F"), Some(StateValue::Integer(31)));
+        assert_eq!(p.apply("ff"), Some(StateValue::Integer(255)));
+        assert_eq!(p.apply("-0x10"), Some(StateValue::Integer(-16)));
+        assert_eq!(p.apply("zz"), None);
+    }
+
+    #[test]
+    fn test_lowercase_trim_and_replace() {
+        let p = pipeline(
+            ValueType::String,
+            vec![
+                Transform::Trim,
+                Transform::Lowercase,
+                Transform::Replace {
+                    pattern: r"^user-(\d+)$".into(),
+                    replacement: "u$1".into(),
+                },
+            ],
+        );
+        assert_eq!(
+            p.apply("  USER-42 "),
+            Some(StateValue::String("u42".into()))
+        );
+    }
+
+    #[test]
+    fn test_unit_normalization() {
+        let p = pipeline(ValueType::Auto, vec![Transform::Unit { to: "ms".into() }]);
+        assert_eq!(p.apply("1.5s"), Some(StateValue::Integer(1500)));
+        assert_eq!(p.apply("250 ms"), Some(StateValue::Integer(250)));
+        assert_eq!(p.apply("40"), Some(StateValue::Integer(40)));
+        assert_eq!(p.apply("3MB"), None);
+
+        let p = pipeline(ValueType::Float, vec![Transform::Unit { to: "KB".into() }]);
+        assert_eq!(p.apply("1.5MB"), Some(StateValue::Float(1536.0)));
+        assert_eq!(p.apply("512b"), Some(StateValue::Float(0.5)));
+    }
+
+    #[test]
+    fn test_duration_parsing() {
+        let p = pipeline(
+            ValueType::Auto,
+            vec![Transform::Duration { to: "s".into() }],
+        );
+        assert_eq!(p.apply("1h2m3s"), Some(StateValue::Integer(3723)));
+        assert_eq!(p.apply("2m 30s"), Some(StateValue::Integer(150)));
+        assert_eq!(p.apply("1.5s"), Some(StateValue::Float(1.5)));
+        assert_eq!(p.apply("01:02:03"), Some(StateValue::Integer(3723)));
+        assert_eq!(p.apply("90"), Some(StateValue::Integer(90)));
+        assert_eq!(p.apply("250ms"), Some(StateValue::Float(0.25)));
+        assert_eq!(p.apply("soon"), None);
+        assert_eq!(p.apply("5MB"), None);
+    }
+
+    #[test]
+    fn test_timestamp_and_duration_targets() {
+        let ts = |text| convert(text, ValueType::Timestamp);
+        let expected = NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_milli_opt(10, 30, 0, 500)
+            .unwrap();
+        assert_eq!(
+            ts("2024-01-15 10:30:00.5"),
+            Some(StateValue::Timestamp(expected))
+        );
+        assert_eq!(
+            ts("2024-01-15T12:30:00.500+02:00"),
+            Some(StateValue::Timestamp(expected))
+        );
+        assert_eq!(ts("1705314600500"), Some(StateValue::Timestamp(expected)));
+        assert!(matches!(ts("2024-01-15"), Some(StateValue::Timestamp(_))));
+        assert_eq!(ts("yesterday"), None);
+
+        let p = pipeline(
+            ValueType::Duration,
+            vec![Transform::Replace {
+                pattern: "^took ".into(),
+                replacement: String::new(),
+            }],
+        );
+        assert_eq!(p.apply("took 1.5s"), Some(StateValue::Duration(1500)));
+        assert_eq!(p.apply("took 01:00"), Some(StateValue::Duration(60_000)));
+        assert_eq!(p.apply("took ages"), None);
+    }
+
+    #[test]
+    fn test_value_mapping_table() {
+        let table = BTreeMap::from([
+            ("warn".to_string(), "2".to_string()),
+            ("error".to_string(), "3".to_string()),
+        ]);
+        let p = pipeline(
+            ValueType::Auto,
+            vec![Transform::Map {
+                table: table.clone(),
+                default: None,
+            }],
+        );
+        assert_eq!(p.apply("warn"), Some(StateValue::Integer(2)));
+        assert_eq!(p.apply("info"), Some(StateValue::String("info".into())));
+
+        let p = pipeline(
+            ValueType::Integer,
+            vec![Transform::Map {
+                table,
+                default: Some("0".into()),
+            }],
+        );
+        assert_eq!(p.apply("info"), Some(StateValue::Integer(0)));
+    }
+
+    #[test]
+    fn test_typed_conversion_failures_drop_value() {
+        assert_eq!(pipeline(ValueType::Integer, vec![]).apply("abc"), None);
+        assert_eq!(
+            pipeline(ValueType::Integer, vec![]).apply("4.0"),
+            Some(StateValue::Integer(4))
+        );
+        assert_eq!(
+            pipeline(ValueType::Bool, vec![]).apply("Yes"),
+            Some(StateValue::Bool(true))
+        );
+        assert_eq!(pipeline(ValueType::Bool, vec![]).apply("maybe"), None);
+        assert_eq!(
+            pipeline(ValueType::Float, vec![]).apply("3"),
+            Some(StateValue::Float(3.0))
+        );
+    }
+
+    #[test]
+    fn test_invalid_transforms_rejected() {
+        let bad = |t: Transform| {
+            Pipeline::new(&ExtractionRule {
+                id: 1,
+                extraction_type: ExtractionType::Parsed,
+                state_key: "v".into(),
+                pattern: None,
+                static_value: None,
+                mode: ExtractionMode::Replace,
+                target_type: ValueType::Auto,
+                transforms: vec![t],
+                ttl_ms: None,
+            })
+            .is_err()
+        };
+        assert!(bad(Transform::ParseInt { radix: 1 }));
+
+        let static_rule = |value: &str| ExtractionRule {
+            id: 1,
+            extraction_type: ExtractionType::Static,
+            state_key: "v".into(),
+            pattern: None,
+            static_value: Some(value.into()),
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Integer,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        };
+        assert!(Pipeline::new(&static_rule("3")).is_ok());
+        assert!(Pipeline::new(&static_rule("three")).is_err());
+        assert!(bad(Transform::Unit {
+            to: "parsecs".into()
+        }));
+        assert!(bad(Transform::Duration { to: "MB".into() }));
+        assert!(bad(Transform::Replace {
+            pattern: "(".into(),
+            replacement: String::new(),
+        }));
+    }
+}
@@ -52,6 +52,7 @@ fn make_source_template(
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     }
 }
 
@@ -155,6 +156,7 @@ fn test_zookeeper_cross_source() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r"WARN".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -163,7 +165,13 @@ fn test_zookeeper_cross_source() {
             pattern: None,
             static_value: Some("warn".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let connection_rule = LogRule {
@@ -173,6 +181,7 @@ fn test_zookeeper_cross_source() {
         match_rules: vec![MatchRule {
             id: 2,
             pattern: r"Connection broken|connection request".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 2,
@@ -181,14 +190,23 @@ fn test_zookeeper_cross_source() {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let ruleset = Ruleset {
         id: 1,
         name: "zk_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1, 2],
+        stop_rule_ids: vec![],
     };
 
     let pattern = Pattern {
@@ -200,14 +218,19 @@ fn test_zookeeper_cross_source() {
                 state_key: "level".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("warn".into())),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "connection_event".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -252,6 +275,7 @@ fn test_nginx_cross_source() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r#"HTTP/1\.\d"\s+\d+"#.into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -260,14 +284,23 @@ fn test_nginx_cross_source() {
             pattern: Some(r#"HTTP/1\.\d"\s+(?P<status>\d+)"#.into()),
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let ruleset = Ruleset {
         id: 1,
         name: "nginx_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1],
+        stop_rule_ids: vec![],
     };
 
     let pattern = Pattern {
@@ -279,14 +312,19 @@ fn test_nginx_cross_source() {
                 state_key: "status".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Integer(404)),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "status".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::Integer(404)),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -329,6 +367,7 @@ fn test_syslog_cross_source() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r"authentication failure".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -337,7 +376,13 @@ fn test_syslog_cross_source() {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let rhost_rule = LogRule {
@@ -347,6 +392,7 @@ fn test_syslog_cross_source() {
         match_rules: vec![MatchRule {
             id: 2,
             pattern: r"rhost=\S+".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 2,
@@ -355,14 +401,23 @@ fn test_syslog_cross_source() {
             pattern: Some(r"rhost=(?P<rhost>\S+)".into()),
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let ruleset = Ruleset {
         id: 1,
         name: "syslog_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1, 2],
+        stop_rule_ids: vec![],
     };
 
     let pattern = Pattern {
@@ -374,14 +429,19 @@ fn test_syslog_cross_source() {
                 state_key: "auth_failed".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "auth_failed".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -423,6 +483,7 @@ fn test_timestamp_template_reuse() {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let tmpl_b = SourceTemplate {
         id: 2,
@@ -434,6 +495,7 @@ fn test_timestamp_template_reuse() {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
 
     let src_a = make_source(1, "source_a", &fixture_path("zookeeper", "source_a.log"), 1);
@@ -446,6 +508,7 @@ fn test_timestamp_template_reuse() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r"INFO".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -454,20 +517,32 @@ fn test_timestamp_template_reuse() {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let rs_a = Ruleset {
         id: 1,
         name: "rs_a".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1],
+        stop_rule_ids: vec![],
     };
     let rs_b = Ruleset {
         id: 2,
         name: "rs_b".into(),
         template_id: 2,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1],
+        stop_rule_ids: vec![],
     };
 
     let pattern = Pattern {
@@ -479,14 +554,19 @@ fn test_timestamp_template_reuse() {
                 state_key: "seen".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "seen".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -530,6 +610,7 @@ fn test_cross_source_state_ref() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r"(INFO|WARN|ERROR)".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -538,14 +619,23 @@ fn test_cross_source_state_ref() {
             pattern: Some(r"(?P<level>INFO|WARN|ERROR)".into()),
             static_value: None,
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let ruleset = Ruleset {
         id: 1,
         name: "level_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1],
+        stop_rule_ids: vec![],
     };
 
     // Pattern: source_a.level == StateRef(source_b.level) — both at same level
@@ -560,7 +650,11 @@ fn test_cross_source_state_ref() {
                 source_name: "source_b".into(),
                 state_key: "level".into(),
             },
+            threshold: None,
         }],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -601,6 +695,7 @@ fn test_multiline_parsing() {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let src = make_source(
         1,
@@ -674,6 +769,7 @@ fn test_multiline_cross_source() {
         json_timestamp_field: None,
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let src_a = make_source(1, "source_a", &fixture_path("multiline", "source_a.log"), 1);
     let src_b = make_source(2, "source_b", &fixture_path("multiline", "source_b.log"), 1);
@@ -686,6 +782,7 @@ fn test_multiline_cross_source() {
         match_rules: vec![MatchRule {
             id: 1,
             pattern: r"OutOfMemoryError".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 1,
@@ -694,7 +791,13 @@ fn test_multiline_cross_source() {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     // Rule: detect WARN
@@ -705,6 +808,7 @@ fn test_multiline_cross_source() {
         match_rules: vec![MatchRule {
             id: 2,
             pattern: r"WARN".into(),
+            negate: false,
         }],
         extraction_rules: vec![ExtractionRule {
             id: 2,
@@ -713,14 +817,23 @@ fn test_multiline_cross_source() {
             pattern: None,
             static_value: Some("true".into()),
             mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
         }],
+        guard: None,
+        script: None,
+        plugin_id: None,
     };
 
     let ruleset = Ruleset {
         id: 1,
         name: "multiline_rules".into(),
         template_id: 1,
+        extra_template_ids: vec![],
+        source_names: vec![],
         rule_ids: vec![1, 2],
+        stop_rule_ids: vec![],
     };
 
     // Pattern: source_a has a warning AND source_b has OOM
@@ -733,14 +846,19 @@ fn test_multiline_cross_source() {
                 state_key: "warned".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "source_b".into(),
                 state_key: "oom".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("true".into())),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -793,6 +911,7 @@ fn test_json_lines_parsing() {
         json_timestamp_field: Some("timestamp".into()),
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let src = make_source(1, "json_app", &fixture_path("json", "app.log"), 1);
 
@@ -826,6 +945,7 @@ fn test_json_cross_source() {
         json_timestamp_field: Some("timestamp".into()),
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
     let tmpl_metrics = SourceTemplate {
         id: 2,
@@ -837,6 +957,7 @@ fn test_json_cross_source() {
         json_timestamp_field: Some("ts".into()),
         file_name_regex: None,
         log_content_regex: None,
+        parser_plugin_id: None,
     };
 
     let src_app = make_source(1, "app", &fixture_path("json", "app.log"), 1);
@@ -852,14 +973,19 @@ fn test_json_cross_source() {
                 state_key: "level".into(),
                 operator: Operator::Eq,
                 operand: Operand::Literal(StateValue::String("ERROR".into())),
+                threshold: None,
             },
             PatternPredicate {
                 source_name: "metrics".into(),
                 state_key: "value".into(),
                 operator: Operator::Gt,
                 operand: Operand::Literal(StateValue::Float(90.0)),
+                threshold: None,
             },
         ],
+        cooldown_ms: None,
+        outcome: PatternOutcome::default(),
+        parent: None,
     };
 
     let result = analyze(
@@ -4,6 +4,7 @@ use chrono::Datelike;
 use sqlx::Row;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 
+use logium_core::engine::MAX_SPAN_MS;
 use logium_core::model::*;
 
 use crate::routes::import_export::{ImportResult, ProjectExport};
@@ -156,6 +157,26 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS ruleset_templates (
+                ruleset_id INTEGER NOT NULL REFERENCES rulesets(id) ON DELETE CASCADE,
+                template_id INTEGER NOT NULL REFERENCES source_templates(id) ON DELETE CASCADE,
+                PRIMARY KEY (ruleset_id, template_id)
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS ruleset_sources (
+                ruleset_id INTEGER NOT NULL REFERENCES rulesets(id) ON DELETE CASCADE,
+                source_name TEXT NOT NULL,
+                PRIMARY KEY (ruleset_id, source_name)
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS patterns (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -181,6 +202,41 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS derived_keys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                project_id INTEGER NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+                source_name TEXT NOT NULL,
+                state_key TEXT NOT NULL,
+                expression TEXT NOT NULL
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS grok_patterns (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                project_id INTEGER NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                pattern TEXT NOT NULL
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS plugins (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                project_id INTEGER NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                wasm BLOB NOT NULL
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Enable foreign keys
         sqlx::query("PRAGMA foreign_keys = ON")
             .execute(&self.pool)
@@ -195,6 +251,53 @@ impl Database {
             .await?;
         self.migrate_add_column("source_templates", "log_content_regex", "TEXT")
             .await?;
+        self.migrate_add_column("pattern_predicates", "threshold", "TEXT")
+            .await?;
+        self.migrate_add_column("patterns", "cooldown_ms", "INTEGER")
+            .await?;
+        for column in ["severity", "category", "diagnosis", "remediation"] {
+            self.migrate_add_column("patterns", column, "TEXT").await?;
+        }
+        self.migrate_add_column(
+            "patterns",
+            "parent_id",
+            "INTEGER REFERENCES patterns(id) ON DELETE SET NULL",
+        )
+        .await?;
+        self.migrate_add_column("patterns", "parent_window_ms", "INTEGER")
+            .await?;
+        self.migrate_add_column("match_rules", "negate", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        self.migrate_add_column("rules", "guard", "TEXT").await?;
+        self.migrate_add_column("rules", "script", "TEXT").await?;
+        self.migrate_add_column(
+            "rules",
+            "plugin_id",
+            "INTEGER REFERENCES plugins(id) ON DELETE SET NULL",
+        )
+        .await?;
+        self.migrate_add_column(
+            "source_templates",
+            "parser_plugin_id",
+            "INTEGER REFERENCES plugins(id) ON DELETE SET NULL",
+        )
+        .await?;
+        self.migrate_add_column(
+            "extraction_rules",
+            "target_type",
+            "TEXT NOT NULL DEFAULT 'auto'",
+        )
+        .await?;
+        self.migrate_add_column("extraction_rules", "transforms", "TEXT")
+            .await?;
+        self.migrate_add_column("extraction_rules", "list_cap", "INTEGER")
+            .await?;
+        self.migrate_add_column("extraction_rules", "ttl_ms", "INTEGER")
+            .await?;
+        self.migrate_add_column("ruleset_rules", "position", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        self.migrate_add_column("ruleset_rules", "stop", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
 
         Ok(())
     }
@@ -432,7 +535,7 @@ impl Database {
 
     pub async fn list_templates(&self, project_id: i64) -> Result<Vec<SourceTemplate>, DbError> {
         let rows = sqlx::query(
-            "SELECT id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex
+            "SELECT id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex, parser_plugin_id
              FROM source_templates WHERE project_id = ? ORDER BY id",
         )
         .bind(project_id)
@@ -444,7 +547,7 @@ impl Database {
 
     pub async fn get_template(&self, project_id: i64, id: i64) -> Result<SourceTemplate, DbError> {
         let row = sqlx::query(
-            "SELECT id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex
+            "SELECT id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex, parser_plugin_id
              FROM source_templates WHERE id = ? AND project_id = ?",
         )
         .bind(id)
@@ -468,10 +571,13 @@ impl Database {
         json_timestamp_field: Option<&str>,
         file_name_regex: Option<&str>,
         log_content_regex: Option<&str>,
+        parser_plugin_id: Option<i64>,
     ) -> Result<SourceTemplate, DbError> {
+        self.check_plugin(project_id, parser_plugin_id, PluginKind::LineParser)
+            .await?;
         let id = sqlx::query_scalar::<_, i64>(
-            "INSERT INTO source_templates (project_id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            "INSERT INTO source_templates (project_id, name, timestamp_template_id, line_delimiter, content_regex, continuation_regex, json_timestamp_field, file_name_regex, log_content_regex, parser_plugin_id)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
         )
         .bind(project_id)
         .bind(name)
@@ -482,6 +588,7 @@ impl Database {
         .bind(json_timestamp_field)
         .bind(file_name_regex)
         .bind(log_content_regex)
+        .bind(parser_plugin_id)
         .fetch_one(&self.pool)
         .await?;
 
@@ -504,6 +611,7 @@ impl Database {
             json_timestamp_field: json_timestamp_field.map(|s| s.to_string()),
             file_name_regex: file_name_regex.map(|s| s.to_string()),
             log_content_regex: log_content_regex.map(|s| s.to_string()),
+            parser_plugin_id: parser_plugin_id.map(|id| id as u64),
         })
     }
 
@@ -520,9 +628,12 @@ impl Database {
         json_timestamp_field: Option<&str>,
         file_name_regex: Option<&str>,
         log_content_regex: Option<&str>,
+        parser_plugin_id: Option<i64>,
     ) -> Result<SourceTemplate, DbError> {
+        self.check_plugin(project_id, parser_plugin_id, PluginKind::LineParser)
+            .await?;
         let result = sqlx::query(
-            "UPDATE source_templates SET name = ?, timestamp_template_id = ?, line_delimiter = ?, content_regex = ?, continuation_regex = ?, json_timestamp_field = ?, file_name_regex = ?, log_content_regex = ?
+            "UPDATE source_templates SET name = ?, timestamp_template_id = ?, line_delimiter = ?, content_regex = ?, continuation_regex = ?, json_timestamp_field = ?, file_name_regex = ?, log_content_regex = ?, parser_plugin_id = ?
              WHERE id = ? AND project_id = ?",
         )
         .bind(name)
@@ -533,6 +644,7 @@ impl Database {
         .bind(json_timestamp_field)
         .bind(file_name_regex)
         .bind(log_content_regex)
+        .bind(parser_plugin_id)
         .bind(id)
         .bind(project_id)
         .execute(&self.pool)
@@ -653,11 +765,12 @@ impl Database {
     // -----------------------------------------------------------------------
 
     pub async fn list_rules(&self, project_id: i64) -> Result<Vec<LogRule>, DbError> {
-        let rule_rows =
-            sqlx::query("SELECT id, name, match_mode FROM rules WHERE project_id = ? ORDER BY id")
-                .bind(project_id)
-                .fetch_all(&self.pool)
-                .await?;
+        let rule_rows = sqlx::query(
+            "SELECT id, name, match_mode, guard, script, plugin_id FROM rules WHERE project_id = ? ORDER BY id",
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
 
         let mut rules = Vec::with_capacity(rule_rows.len());
         for row in &rule_rows {
@@ -668,13 +781,14 @@ impl Database {
     }
 
     pub async fn get_rule(&self, project_id: i64, id: i64) -> Result<LogRule, DbError> {
-        let row =
-            sqlx::query("SELECT id, name, match_mode FROM rules WHERE id = ? AND project_id = ?")
-                .bind(id)
-                .bind(project_id)
-                .fetch_optional(&self.pool)
-                .await?
-                .ok_or(DbError::NotFound)?;
+        let row = sqlx::query(
+            "SELECT id, name, match_mode, guard, script, plugin_id FROM rules WHERE id = ? AND project_id = ?",
+        )
+        .bind(id)
+        .bind(project_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(DbError::NotFound)?;
 
         self.build_log_rule(&row, id).await
     }
@@ -687,23 +801,34 @@ impl Database {
         let name: String = row.get("name");
         let match_mode_str: String = row.get("match_mode");
         let match_mode = parse_match_mode(&match_mode_str)?;
+        let guard_json: Option<String> = row.get("guard");
+        let guard = guard_json
+            .map(|j| {
+                serde_json::from_str(&j)
+                    .map_err(|e| DbError::InvalidData(format!("invalid guard JSON: {e}")))
+            })
+            .transpose()?;
+        let script: Option<String> = row.get("script");
+        let plugin_id = row.get::<Option<i64>, _>("plugin_id").map(|id| id as u64);
 
-        let match_rows =
-            sqlx::query("SELECT id, pattern FROM match_rules WHERE rule_id = ? ORDER BY id")
-                .bind(rule_id)
-                .fetch_all(&self.pool)
-                .await?;
+        let match_rows = sqlx::query(
+            "SELECT id, pattern, negate FROM match_rules WHERE rule_id = ? ORDER BY id",
+        )
+        .bind(rule_id)
+        .fetch_all(&self.pool)
+        .await?;
 
         let match_rules: Vec<MatchRule> = match_rows
             .iter()
             .map(|r| MatchRule {
                 id: r.get::<i64, _>("id") as u64,
                 pattern: r.get("pattern"),
+                negate: r.get("negate"),
             })
             .collect();
 
         let ext_rows = sqlx::query(
-            "SELECT id, extraction_type, state_key, pattern, static_value, mode
+            "SELECT id, extraction_type, state_key, pattern, static_value, mode, target_type, transforms, list_cap, ttl_ms
              FROM extraction_rules WHERE rule_id = ? ORDER BY id",
         )
         .bind(rule_id)
@@ -715,13 +840,26 @@ impl Database {
             .map(|r| {
                 let ext_type_str: String = r.get("extraction_type");
                 let mode_str: String = r.get("mode");
+                let target_type_str: String = r.get("target_type");
+                let transforms_json: Option<String> = r.get("transforms");
+                let transforms = transforms_json
+                    .map(|j| {
+                        serde_json::from_str(&j).map_err(|e| {
+                            DbError::InvalidData(format!("invalid transforms JSON: {e}"))
+                        })
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
                 Ok(ExtractionRule {
                     id: r.get::<i64, _>("id") as u64,
                     extraction_type: parse_extraction_type(&ext_type_str)?,
                     state_key: r.get("state_key"),
                     pattern: r.get("pattern"),
                     static_value: r.get("static_value"),
-                    mode: parse_extraction_mode(&mode_str)?,
+                    mode: parse_extraction_mode(&mode_str, r.get("list_cap"))?,
+                    target_type: parse_value_type(&target_type_str)?,
+                    transforms,
+                    ttl_ms: r.get::<Option<i64>, _>("ttl_ms").map(|ms| ms as u64),
                 })
             })
             .collect();
@@ -732,9 +870,13 @@ impl Database {
             match_mode,
             match_rules,
             extraction_rules: extraction_rules?,
+            guard,
+            script,
+            plugin_id,
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_rule(
         &self,
         project_id: i64,
@@ -742,29 +884,42 @@ impl Database {
         match_mode: &MatchMode,
         match_rules: &[CreateMatchRule],
         extraction_rules: &[CreateExtractionRule],
+        guard: Option<&RuleGuard>,
+        script: Option<&str>,
+        plugin_id: Option<i64>,
     ) -> Result<LogRule, DbError> {
+        let guard_json = guard_to_json(guard)?;
+        validate_script(script)?;
+        self.check_plugin(project_id, plugin_id, PluginKind::Extractor)
+            .await?;
+        validate_extraction_rules(extraction_rules)?;
         let mode_str = match_mode_to_str(match_mode);
         let rule_id = sqlx::query_scalar::<_, i64>(
-            "INSERT INTO rules (project_id, name, match_mode) VALUES (?, ?, ?) RETURNING id",
+            "INSERT INTO rules (project_id, name, match_mode, guard, script, plugin_id) VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
         )
         .bind(project_id)
         .bind(name)
         .bind(mode_str)
+        .bind(guard_json)
+        .bind(script)
+        .bind(plugin_id)
         .fetch_one(&self.pool)
         .await?;
 
         let mut built_match_rules = Vec::with_capacity(match_rules.len());
         for mr in match_rules {
             let id = sqlx::query_scalar::<_, i64>(
-                "INSERT INTO match_rules (rule_id, pattern) VALUES (?, ?) RETURNING id",
+                "INSERT INTO match_rules (rule_id, pattern, negate) VALUES (?, ?, ?) RETURNING id",
             )
             .bind(rule_id)
             .bind(&mr.pattern)
+            .bind(mr.negate)
             .fetch_one(&self.pool)
             .await?;
             built_match_rules.push(MatchRule {
                 id: id as u64,
                 pattern: mr.pattern.clone(),
+                negate: mr.negate,
             });
         }
 
@@ -772,9 +927,10 @@ impl Database {
         for er in extraction_rules {
             let ext_type_str = extraction_type_to_str(&er.extraction_type);
             let mode_str = extraction_mode_to_str(&er.mode);
+            let transforms_json = transforms_to_json(&er.transforms)?;
             let id = sqlx::query_scalar::<_, i64>(
-                "INSERT INTO extraction_rules (rule_id, extraction_type, state_key, pattern, static_value, mode)
-                 VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+                "INSERT INTO extraction_rules (rule_id, extraction_type, state_key, pattern, static_value, mode, target_type, transforms, list_cap, ttl_ms)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
             )
             .bind(rule_id)
             .bind(ext_type_str)
@@ -782,6 +938,10 @@ impl Database {
             .bind(er.pattern.as_deref())
             .bind(er.static_value.as_deref())
             .bind(mode_str)
+            .bind(value_type_to_str(er.target_type))
+            .bind(transforms_json)
+            .bind(extraction_list_cap(&er.mode))
+            .bind(er.ttl_ms.map(|ms| ms as i64))
             .fetch_one(&self.pool)
             .await?;
             built_ext_rules.push(ExtractionRule {
@@ -791,6 +951,9 @@ impl Database {
                 pattern: er.pattern.clone(),
                 static_value: er.static_value.clone(),
                 mode: er.mode.clone(),
+                target_type: er.target_type,
+                transforms: er.transforms.clone(),
+                ttl_ms: er.ttl_ms,
             });
         }
 
@@ -800,9 +963,13 @@ impl Database {
             match_mode: match_mode.clone(),
             match_rules: built_match_rules,
             extraction_rules: built_ext_rules,
+            guard: guard.cloned(),
+            script: script.map(str::to_string),
+            plugin_id: plugin_id.map(|id| id as u64),
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_rule(
         &self,
         project_id: i64,
@@ -811,13 +978,24 @@ impl Database {
         match_mode: &MatchMode,
         match_rules: &[CreateMatchRule],
         extraction_rules: &[CreateExtractionRule],
+        guard: Option<&RuleGuard>,
+        script: Option<&str>,
+        plugin_id: Option<i64>,
     ) -> Result<LogRule, DbError> {
+        let guard_json = guard_to_json(guard)?;
+        validate_script(script)?;
+        self.check_plugin(project_id, plugin_id, PluginKind::Extractor)
+            .await?;
+        validate_extraction_rules(extraction_rules)?;
         let mode_str = match_mode_to_str(match_mode);
         let result = sqlx::query(
-            "UPDATE rules SET name = ?, match_mode = ? WHERE id = ? AND project_id = ?",
+            "UPDATE rules SET name = ?, match_mode = ?, guard = ?, script = ?, plugin_id = ? WHERE id = ? AND project_id = ?",
         )
         .bind(name)
         .bind(mode_str)
+        .bind(guard_json)
+        .bind(script)
+        .bind(plugin_id)
         .bind(id)
         .bind(project_id)
         .execute(&self.pool)
@@ -840,15 +1018,17 @@ impl Database {
         let mut built_match_rules = Vec::with_capacity(match_rules.len());
         for mr in match_rules {
             let mr_id = sqlx::query_scalar::<_, i64>(
-                "INSERT INTO match_rules (rule_id, pattern) VALUES (?, ?) RETURNING id",
+                "INSERT INTO match_rules (rule_id, pattern, negate) VALUES (?, ?, ?) RETURNING id",
             )
             .bind(id)
             .bind(&mr.pattern)
+            .bind(mr.negate)
             .fetch_one(&self.pool)
             .await?;
             built_match_rules.push(MatchRule {
                 id: mr_id as u64,
                 pattern: mr.pattern.clone(),
+                negate: mr.negate,
             });
         }
 
@@ -856,9 +1036,10 @@ impl Database {
         for er in extraction_rules {
             let ext_type_str = extraction_type_to_str(&er.extraction_type);
             let mode_str = extraction_mode_to_str(&er.mode);
+            let transforms_json = transforms_to_json(&er.transforms)?;
             let er_id = sqlx::query_scalar::<_, i64>(
-                "INSERT INTO extraction_rules (rule_id, extraction_type, state_key, pattern, static_value, mode)
-                 VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+                "INSERT INTO extraction_rules (rule_id, extraction_type, state_key, pattern, static_value, mode, target_type, transforms, list_cap, ttl_ms)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
             )
             .bind(id)
             .bind(ext_type_str)
@@ -866,6 +1047,10 @@ impl Database {
             .bind(er.pattern.as_deref())
             .bind(er.static_value.as_deref())
             .bind(mode_str)
+            .bind(value_type_to_str(er.target_type))
+            .bind(transforms_json)
+            .bind(extraction_list_cap(&er.mode))
+            .bind(er.ttl_ms.map(|ms| ms as i64))
             .fetch_one(&self.pool)
             .await?;
             built_ext_rules.push(ExtractionRule {
@@ -875,6 +1060,9 @@ impl Database {
                 pattern: er.pattern.clone(),
                 static_value: er.static_value.clone(),
                 mode: er.mode.clone(),
+                target_type: er.target_type,
+                transforms: er.transforms.clone(),
+                ttl_ms: er.ttl_ms,
             });
         }
 
@@ -884,6 +1072,9 @@ impl Database {
             match_mode: match_mode.clone(),
             match_rules: built_match_rules,
             extraction_rules: built_ext_rules,
+            guard: guard.cloned(),
+            script: script.map(str::to_string),
+            plugin_id: plugin_id.map(|id| id as u64),
         })
     }
 
@@ -913,14 +1104,7 @@ impl Database {
 
         let mut rulesets = Vec::with_capacity(rows.len());
         for row in &rows {
-            let id: i64 = row.get("id");
-            let rule_ids = self.get_ruleset_rule_ids(id).await?;
-            rulesets.push(Ruleset {
-                id: id as u64,
-                name: row.get("name"),
-                template_id: row.get::<i64, _>("template_id") as u64,
-                rule_ids,
-            });
+            rulesets.push(self.row_to_ruleset(row).await?);
         }
         Ok(rulesets)
     }
@@ -935,34 +1119,129 @@ impl Database {
         .await?
         .ok_or(DbError::NotFound)?;
 
-        let rule_ids = self.get_ruleset_rule_ids(id).await?;
+        self.row_to_ruleset(&row).await
+    }
+
+    async fn row_to_ruleset(&self, row: &sqlx::sqlite::SqliteRow) -> Result<Ruleset, DbError> {
+        let id: i64 = row.get("id");
+        let (rule_ids, stop_rule_ids) = self.get_ruleset_rule_ids(id).await?;
+        let extra_template_ids = sqlx::query_scalar::<_, i64>(
+            "SELECT template_id FROM ruleset_templates WHERE ruleset_id = ? ORDER BY template_id",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|t| t as u64)
+        .collect();
+        let source_names = sqlx::query_scalar::<_, String>(
+            "SELECT source_name FROM ruleset_sources WHERE ruleset_id = ? ORDER BY source_name",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
         Ok(Ruleset {
             id: id as u64,
             name: row.get("name"),
             template_id: row.get::<i64, _>("template_id") as u64,
+            extra_template_ids,
+            source_names,
             rule_ids,
+            stop_rule_ids,
         })
     }
 
-    async fn get_ruleset_rule_ids(&self, ruleset_id: i64) -> Result<Vec<u64>, DbError> {
-        let rows =
-            sqlx::query("SELECT rule_id FROM ruleset_rules WHERE ruleset_id = ? ORDER BY rule_id")
-                .bind(ruleset_id)
-                .fetch_all(&self.pool)
-                .await?;
-        Ok(rows
+    /// A ruleset's rules in evaluation order, and those that stop later ones.
+    async fn get_ruleset_rule_ids(&self, ruleset_id: i64) -> Result<(Vec<u64>, Vec<u64>), DbError> {
+        let rows = sqlx::query(
+            "SELECT rule_id, stop FROM ruleset_rules WHERE ruleset_id = ? ORDER BY position, rule_id",
+        )
+        .bind(ruleset_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let rule_ids = rows
+            .iter()
+            .map(|r| r.get::<i64, _>("rule_id") as u64)
+            .collect();
+        let stop_rule_ids = rows
             .iter()
+            .filter(|r| r.get::<bool, _>("stop"))
             .map(|r| r.get::<i64, _>("rule_id") as u64)
-            .collect())
+            .collect();
+        Ok((rule_ids, stop_rule_ids))
+    }
+
+    /// Replace a ruleset's rules, storing their order and stop flags.
+    async fn set_ruleset_rules(
+        &self,
+        ruleset_id: i64,
+        rule_ids: &[i64],
+        stop_rule_ids: &[i64],
+    ) -> Result<(), DbError> {
+        sqlx::query("DELETE FROM ruleset_rules WHERE ruleset_id = ?")
+            .bind(ruleset_id)
+            .execute(&self.pool)
+            .await?;
+        for (position, rule_id) in rule_ids.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO ruleset_rules (ruleset_id, rule_id, position, stop) VALUES (?, ?, ?, ?)",
+            )
+            .bind(ruleset_id)
+            .bind(rule_id)
+            .bind(position as i64)
+            .bind(stop_rule_ids.contains(rule_id))
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the templates beyond its own and the sources a ruleset is
+    /// bound to.
+    async fn set_ruleset_bindings(
+        &self,
+        ruleset_id: i64,
+        extra_template_ids: &[i64],
+        source_names: &[String],
+    ) -> Result<(), DbError> {
+        sqlx::query("DELETE FROM ruleset_templates WHERE ruleset_id = ?")
+            .bind(ruleset_id)
+            .execute(&self.pool)
+            .await?;
+        for template_id in extra_template_ids {
+            sqlx::query("INSERT INTO ruleset_templates (ruleset_id, template_id) VALUES (?, ?)")
+                .bind(ruleset_id)
+                .bind(template_id)
+                .execute(&self.pool)
+                .await?;
+        }
+        sqlx::query("DELETE FROM ruleset_sources WHERE ruleset_id = ?")
+            .bind(ruleset_id)
+            .execute(&self.pool)
+            .await?;
+        for source_name in source_names {
+            sqlx::query("INSERT INTO ruleset_sources (ruleset_id, source_name) VALUES (?, ?)")
+                .bind(ruleset_id)
+                .bind(source_name)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_ruleset(
         &self,
         project_id: i64,
         name: &str,
         template_id: i64,
+        extra_template_ids: &[i64],
+        source_names: &[String],
         rule_ids: &[i64],
+        stop_rule_ids: &[i64],
     ) -> Result<Ruleset, DbError> {
+        validate_ruleset_bindings(template_id, extra_template_ids, source_names)?;
+        validate_ruleset_rules(rule_ids, stop_rule_ids)?;
         let id = sqlx::query_scalar::<_, i64>(
             "INSERT INTO rulesets (project_id, template_id, name) VALUES (?, ?, ?) RETURNING id",
         )
@@ -972,30 +1251,27 @@ impl Database {
         .fetch_one(&self.pool)
         .await?;
 
-        for rule_id in rule_ids {
-            sqlx::query("INSERT INTO ruleset_rules (ruleset_id, rule_id) VALUES (?, ?)")
-                .bind(id)
-                .bind(rule_id)
-                .execute(&self.pool)
-                .await?;
-        }
+        self.set_ruleset_bindings(id, extra_template_ids, source_names)
+            .await?;
+        self.set_ruleset_rules(id, rule_ids, stop_rule_ids).await?;
 
-        Ok(Ruleset {
-            id: id as u64,
-            name: name.to_string(),
-            template_id: template_id as u64,
-            rule_ids: rule_ids.iter().map(|&i| i as u64).collect(),
-        })
+        self.get_ruleset(project_id, id).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_ruleset(
         &self,
         project_id: i64,
         id: i64,
         name: &str,
         template_id: i64,
+        extra_template_ids: &[i64],
+        source_names: &[String],
         rule_ids: &[i64],
+        stop_rule_ids: &[i64],
     ) -> Result<Ruleset, DbError> {
+        validate_ruleset_bindings(template_id, extra_template_ids, source_names)?;
+        validate_ruleset_rules(rule_ids, stop_rule_ids)?;
         let result = sqlx::query(
             "UPDATE rulesets SET name = ?, template_id = ? WHERE id = ? AND project_id = ?",
         )
@@ -1010,25 +1286,11 @@ impl Database {
             return Err(DbError::NotFound);
         }
 
-        sqlx::query("DELETE FROM ruleset_rules WHERE ruleset_id = ?")
-            .bind(id)
-            .execute(&self.pool)
+        self.set_ruleset_bindings(id, extra_template_ids, source_names)
             .await?;
+        self.set_ruleset_rules(id, rule_ids, stop_rule_ids).await?;
 
-        for rule_id in rule_ids {
-            sqlx::query("INSERT INTO ruleset_rules (ruleset_id, rule_id) VALUES (?, ?)")
-                .bind(id)
-                .bind(rule_id)
-                .execute(&self.pool)
-                .await?;
-        }
-
-        Ok(Ruleset {
-            id: id as u64,
-            name: name.to_string(),
-            template_id: template_id as u64,
-            rule_ids: rule_ids.iter().map(|&i| i as u64).collect(),
-        })
+        self.get_ruleset(project_id, id).await
     }
 
     pub async fn delete_ruleset(&self, project_id: i64, id: i64) -> Result<(), DbError> {
@@ -1048,10 +1310,13 @@ impl Database {
     // -----------------------------------------------------------------------
 
     pub async fn list_patterns(&self, project_id: i64) -> Result<Vec<Pattern>, DbError> {
-        let rows = sqlx::query("SELECT id, name FROM patterns WHERE project_id = ? ORDER BY id")
-            .bind(project_id)
-            .fetch_all(&self.pool)
-            .await?;
+        let rows = sqlx::query(
+            "SELECT id, name, cooldown_ms, severity, category, diagnosis, remediation, parent_id, parent_window_ms
+             FROM patterns WHERE project_id = ? ORDER BY id",
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
 
         let mut patterns = Vec::with_capacity(rows.len());
         for row in &rows {
@@ -1061,30 +1326,39 @@ impl Database {
                 id: id as u64,
                 name: row.get("name"),
                 predicates,
+                cooldown_ms: row.get::<Option<i64>, _>("cooldown_ms").map(|v| v as u64),
+                outcome: row_to_pattern_outcome(row)?,
+                parent: row_to_pattern_parent(row),
             });
         }
         Ok(patterns)
     }
 
     pub async fn get_pattern(&self, project_id: i64, id: i64) -> Result<Pattern, DbError> {
-        let row = sqlx::query("SELECT id, name FROM patterns WHERE id = ? AND project_id = ?")
-            .bind(id)
-            .bind(project_id)
-            .fetch_optional(&self.pool)
-            .await?
-            .ok_or(DbError::NotFound)?;
+        let row = sqlx::query(
+            "SELECT id, name, cooldown_ms, severity, category, diagnosis, remediation, parent_id, parent_window_ms
+             FROM patterns WHERE id = ? AND project_id = ?",
+        )
+        .bind(id)
+        .bind(project_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(DbError::NotFound)?;
 
         let predicates = self.get_predicates(id).await?;
         Ok(Pattern {
             id: id as u64,
             name: row.get("name"),
             predicates,
+            cooldown_ms: row.get::<Option<i64>, _>("cooldown_ms").map(|v| v as u64),
+            outcome: row_to_pattern_outcome(&row)?,
+            parent: row_to_pattern_parent(&row),
         })
     }
 
     async fn get_predicates(&self, pattern_id: i64) -> Result<Vec<PatternPredicate>, DbError> {
         let rows = sqlx::query(
-            "SELECT source_name, state_key, operator, operand_type, operand_value
+            "SELECT source_name, state_key, operator, operand_type, operand_value, threshold
              FROM pattern_predicates WHERE pattern_id = ? ORDER BY order_index",
         )
         .bind(pattern_id)
@@ -1099,12 +1373,29 @@ impl Database {
         project_id: i64,
         name: &str,
         predicates: &[CreatePredicate],
+        cooldown_ms: Option<u64>,
+        outcome: &PatternOutcome,
+        parent: Option<&PatternParent>,
     ) -> Result<Pattern, DbError> {
+        validate_predicates(predicates)?;
+        validate_span("cooldown", cooldown_ms)?;
+        validate_outcome(outcome)?;
+        if let Some(parent) = parent {
+            self.validate_parent(project_id, None, parent).await?;
+        }
         let id = sqlx::query_scalar::<_, i64>(
-            "INSERT INTO patterns (project_id, name) VALUES (?, ?) RETURNING id",
+            "INSERT INTO patterns (project_id, name, cooldown_ms, severity, category, diagnosis, remediation, parent_id, parent_window_ms)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
         )
         .bind(project_id)
         .bind(name)
+        .bind(cooldown_ms.map(|v| v as i64))
+        .bind(outcome.severity.map(severity_to_str))
+        .bind(&outcome.category)
+        .bind(&outcome.diagnosis)
+        .bind(&outcome.remediation)
+        .bind(parent.map(|p| p.pattern_id as i64))
+        .bind(parent.and_then(|p| p.window_ms).map(|v| v as i64))
         .fetch_one(&self.pool)
         .await?;
 
@@ -1113,22 +1404,46 @@ impl Database {
             id: id as u64,
             name: name.to_string(),
             predicates: built,
+            cooldown_ms,
+            outcome: outcome.clone(),
+            parent: parent.cloned(),
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_pattern(
         &self,
         project_id: i64,
         id: i64,
         name: &str,
         predicates: &[CreatePredicate],
+        cooldown_ms: Option<u64>,
+        outcome: &PatternOutcome,
+        parent: Option<&PatternParent>,
     ) -> Result<Pattern, DbError> {
-        let result = sqlx::query("UPDATE patterns SET name = ? WHERE id = ? AND project_id = ?")
-            .bind(name)
-            .bind(id)
-            .bind(project_id)
-            .execute(&self.pool)
-            .await?;
+        validate_predicates(predicates)?;
+        validate_span("cooldown", cooldown_ms)?;
+        validate_outcome(outcome)?;
+        if let Some(parent) = parent {
+            self.validate_parent(project_id, Some(id), parent).await?;
+        }
+        let result = sqlx::query(
+            "UPDATE patterns SET name = ?, cooldown_ms = ?, severity = ?, category = ?, diagnosis = ?, remediation = ?,
+             parent_id = ?, parent_window_ms = ?
+             WHERE id = ? AND project_id = ?",
+        )
+        .bind(name)
+        .bind(cooldown_ms.map(|v| v as i64))
+        .bind(outcome.severity.map(severity_to_str))
+        .bind(&outcome.category)
+        .bind(&outcome.diagnosis)
+        .bind(&outcome.remediation)
+        .bind(parent.map(|p| p.pattern_id as i64))
+        .bind(parent.and_then(|p| p.window_ms).map(|v| v as i64))
+        .bind(id)
+        .bind(project_id)
+        .execute(&self.pool)
+        .await?;
 
         if result.rows_affected() == 0 {
             return Err(DbError::NotFound);
@@ -1144,9 +1459,41 @@ impl Database {
             id: id as u64,
             name: name.to_string(),
             predicates: built,
+            cooldown_ms,
+            outcome: outcome.clone(),
+            parent: parent.cloned(),
         })
     }
 
+    /// Check that a parent pattern exists in the project and that linking
+    /// pattern `id` under it would not create a cycle.
+    async fn validate_parent(
+        &self,
+        project_id: i64,
+        id: Option<i64>,
+        parent: &PatternParent,
+    ) -> Result<(), DbError> {
+        validate_span("parent window", parent.window_ms)?;
+        let mut current = Some(parent.pattern_id as i64);
+        while let Some(ancestor) = current {
+            if Some(ancestor) == id {
+                return Err(DbError::InvalidData(format!(
+                    "parent pattern {} would create a cycle",
+                    parent.pattern_id
+                )));
+            }
+            current = sqlx::query_scalar::<_, Option<i64>>(
+                "SELECT parent_id FROM patterns WHERE id = ? AND project_id = ?",
+            )
+            .bind(ancestor)
+            .bind(project_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| DbError::InvalidData(format!("unknown parent pattern {ancestor}")))?;
+        }
+        Ok(())
+    }
+
     async fn insert_predicates(
         &self,
         pattern_id: i64,
@@ -1155,9 +1502,13 @@ impl Database {
         let mut built = Vec::with_capacity(predicates.len());
         for (idx, p) in predicates.iter().enumerate() {
             let (operand_type, operand_value) = serialize_operand(&p.operand);
+            let threshold_json = p
+                .threshold
+                .as_ref()
+                .map(|t| serde_json::to_string(t).unwrap_or_default());
             sqlx::query(
-                "INSERT INTO pattern_predicates (pattern_id, order_index, source_name, state_key, operator, operand_type, operand_value)
-                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                "INSERT INTO pattern_predicates (pattern_id, order_index, source_name, state_key, operator, operand_type, operand_value, threshold)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
             )
             .bind(pattern_id)
             .bind(idx as i64)
@@ -1166,6 +1517,7 @@ impl Database {
             .bind(operator_to_str(&p.operator))
             .bind(operand_type)
             .bind(operand_value)
+            .bind(threshold_json)
             .execute(&self.pool)
             .await?;
 
@@ -1174,6 +1526,7 @@ impl Database {
                 state_key: p.state_key.clone(),
                 operator: p.operator.clone(),
                 operand: p.operand.clone(),
+                threshold: p.threshold.clone(),
             });
         }
         Ok(built)
@@ -1192,19 +1545,404 @@ impl Database {
     }
 
     // -----------------------------------------------------------------------
-    // Import project configuration
+    // Derived keys
     // -----------------------------------------------------------------------
 
-    pub async fn import_project_config(
+    pub async fn list_derived_keys(&self, project_id: i64) -> Result<Vec<DerivedKey>, DbError> {
+        let rows = sqlx::query(
+            "SELECT id, source_name, state_key, expression
+             FROM derived_keys WHERE project_id = ? ORDER BY id",
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(row_to_derived_key).collect())
+    }
+
+    pub async fn get_derived_key(&self, project_id: i64, id: i64) -> Result<DerivedKey, DbError> {
+        let row = sqlx::query(
+            "SELECT id, source_name, state_key, expression
+             FROM derived_keys WHERE id = ? AND project_id = ?",
+        )
+        .bind(id)
+        .bind(project_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(DbError::NotFound)?;
+
+        Ok(row_to_derived_key(&row))
+    }
+
+    pub async fn create_derived_key(
         &self,
         project_id: i64,
-        data: &ProjectExport,
-    ) -> Result<ImportResult, DbError> {
-        let mut tt_id_map: HashMap<u64, u64> = HashMap::new();
-        let mut st_id_map: HashMap<u64, u64> = HashMap::new();
-        let mut rule_id_map: HashMap<u64, u64> = HashMap::new();
+        source_name: &str,
+        state_key: &str,
+        expression: &str,
+    ) -> Result<DerivedKey, DbError> {
+        self.validate_derived_key(project_id, None, source_name, state_key, expression)
+            .await?;
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO derived_keys (project_id, source_name, state_key, expression)
+             VALUES (?, ?, ?, ?) RETURNING id",
+        )
+        .bind(project_id)
+        .bind(source_name)
+        .bind(state_key)
+        .bind(expression)
+        .fetch_one(&self.pool)
+        .await?;
 
-        // 1. TimestampTemplates (no FK deps)
+        Ok(DerivedKey {
+            id: id as u64,
+            source_name: source_name.to_string(),
+            state_key: state_key.to_string(),
+            expression: expression.to_string(),
+        })
+    }
+
+    pub async fn update_derived_key(
+        &self,
+        project_id: i64,
+        id: i64,
+        source_name: &str,
+        state_key: &str,
+        expression: &str,
+    ) -> Result<DerivedKey, DbError> {
+        self.get_derived_key(project_id, id).await?;
+        self.validate_derived_key(project_id, Some(id), source_name, state_key, expression)
+            .await?;
+        sqlx::query(
+            "UPDATE derived_keys SET source_name = ?, state_key = ?, expression = ?
+             WHERE id = ? AND project_id = ?",
+        )
+        .bind(source_name)
+        .bind(state_key)
+        .bind(expression)
+        .bind(id)
+        .bind(project_id)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_derived_key(project_id, id).await
+    }
+
+    pub async fn delete_derived_key(&self, project_id: i64, id: i64) -> Result<(), DbError> {
+        let result = sqlx::query("DELETE FROM derived_keys WHERE id = ? AND project_id = ?")
+            .bind(id)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(DbError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Check a derived key together with the rest of the project's keys, so
+    /// duplicates and cycles are caught when they are introduced. `id` is the
+    /// key being replaced, if any.
+    async fn validate_derived_key(
+        &self,
+        project_id: i64,
+        id: Option<i64>,
+        source_name: &str,
+        state_key: &str,
+        expression: &str,
+    ) -> Result<(), DbError> {
+        let mut keys = self.list_derived_keys(project_id).await?;
+        keys.retain(|k| Some(k.id as i64) != id);
+        keys.push(DerivedKey {
+            id: id.unwrap_or(0) as u64,
+            source_name: source_name.to_string(),
+            state_key: state_key.to_string(),
+            expression: expression.to_string(),
+        });
+        logium_core::engine::validate_derived_keys(&keys)
+            .map_err(|e| DbError::InvalidData(e.to_string()))
+    }
+
+    // -----------------------------------------------------------------------
+    // Grok patterns
+    // -----------------------------------------------------------------------
+
+    pub async fn list_grok_patterns(&self, project_id: i64) -> Result<Vec<GrokPattern>, DbError> {
+        let rows = sqlx::query(
+            "SELECT id, name, pattern FROM grok_patterns WHERE project_id = ? ORDER BY id",
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(row_to_grok_pattern).collect())
+    }
+
+    pub async fn get_grok_pattern(&self, project_id: i64, id: i64) -> Result<GrokPattern, DbError> {
+        let row = sqlx::query(
+            "SELECT id, name, pattern FROM grok_patterns WHERE id = ? AND project_id = ?",
+        )
+        .bind(id)
+        .bind(project_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(DbError::NotFound)?;
+
+        Ok(row_to_grok_pattern(&row))
+    }
+
+    pub async fn create_grok_pattern(
+        &self,
+        project_id: i64,
+        name: &str,
+        pattern: &str,
+    ) -> Result<GrokPattern, DbError> {
+        self.validate_grok_pattern(project_id, None, name, pattern)
+            .await?;
+        self.insert_grok_pattern(project_id, name, pattern).await
+    }
+
+    /// Insert a library entry already checked against the rest of the library.
+    async fn insert_grok_pattern(
+        &self,
+        project_id: i64,
+        name: &str,
+        pattern: &str,
+    ) -> Result<GrokPattern, DbError> {
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO grok_patterns (project_id, name, pattern) VALUES (?, ?, ?) RETURNING id",
+        )
+        .bind(project_id)
+        .bind(name)
+        .bind(pattern)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(GrokPattern {
+            id: id as u64,
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        })
+    }
+
+    pub async fn update_grok_pattern(
+        &self,
+        project_id: i64,
+        id: i64,
+        name: &str,
+        pattern: &str,
+    ) -> Result<GrokPattern, DbError> {
+        self.get_grok_pattern(project_id, id).await?;
+        self.validate_grok_pattern(project_id, Some(id), name, pattern)
+            .await?;
+        sqlx::query(
+            "UPDATE grok_patterns SET name = ?, pattern = ? WHERE id = ? AND project_id = ?",
+        )
+        .bind(name)
+        .bind(pattern)
+        .bind(id)
+        .bind(project_id)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_grok_pattern(project_id, id).await
+    }
+
+    /// Delete a library entry, unless other entries still refer to it.
+    pub async fn delete_grok_pattern(&self, project_id: i64, id: i64) -> Result<(), DbError> {
+        let deleted = self.get_grok_pattern(project_id, id).await?;
+        let mut library = self.list_grok_patterns(project_id).await?;
+        library.retain(|p| p.id != deleted.id);
+        logium_core::grok::validate_grok_patterns(&library).map_err(|e| {
+            DbError::InvalidData(format!(
+                "pattern %{{{}}} is still in use: {e}",
+                deleted.name
+            ))
+        })?;
+        let result = sqlx::query("DELETE FROM grok_patterns WHERE id = ? AND project_id = ?")
+            .bind(id)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(DbError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Check a pattern together with the rest of the project's library, so
+    /// duplicate names and self-reference are caught when they are introduced.
+    /// `id` is the pattern being replaced, if any.
+    async fn validate_grok_pattern(
+        &self,
+        project_id: i64,
+        id: Option<i64>,
+        name: &str,
+        pattern: &str,
+    ) -> Result<(), DbError> {
+        let mut library = self.list_grok_patterns(project_id).await?;
+        library.retain(|p| Some(p.id as i64) != id);
+        library.push(GrokPattern {
+            id: id.unwrap_or(0) as u64,
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        });
+        logium_core::grok::validate_grok_patterns(&library)
+            .map_err(|e| DbError::InvalidData(e.to_string()))
+    }
+
+    // -----------------------------------------------------------------------
+    // Plugins
+    // -----------------------------------------------------------------------
+
+    pub async fn list_plugins(&self, project_id: i64) -> Result<Vec<Plugin>, DbError> {
+        let rows = sqlx::query(
+            "SELECT id, name, kind, wasm FROM plugins WHERE project_id = ? ORDER BY id",
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(row_to_plugin).collect()
+    }
+
+    pub async fn get_plugin(&self, project_id: i64, id: i64) -> Result<Plugin, DbError> {
+        let row =
+            sqlx::query("SELECT id, name, kind, wasm FROM plugins WHERE id = ? AND project_id = ?")
+                .bind(id)
+                .bind(project_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or(DbError::NotFound)?;
+
+        row_to_plugin(&row)
+    }
+
+    pub async fn create_plugin(
+        &self,
+        project_id: i64,
+        name: &str,
+        kind: PluginKind,
+        wasm: &[u8],
+    ) -> Result<Plugin, DbError> {
+        let plugin = validate_plugin(name, kind, wasm)?;
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO plugins (project_id, name, kind, wasm) VALUES (?, ?, ?, ?) RETURNING id",
+        )
+        .bind(project_id)
+        .bind(name)
+        .bind(plugin_kind_to_str(kind))
+        .bind(wasm)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Plugin {
+            id: id as u64,
+            ..plugin
+        })
+    }
+
+    /// Replace a plugin's module. Its kind is fixed, since templates and
+    /// rules rely on it.
+    pub async fn update_plugin(
+        &self,
+        project_id: i64,
+        id: i64,
+        name: &str,
+        wasm: &[u8],
+    ) -> Result<Plugin, DbError> {
+        let existing = self.get_plugin(project_id, id).await?;
+        validate_plugin(name, existing.kind, wasm)?;
+        sqlx::query("UPDATE plugins SET name = ?, wasm = ? WHERE id = ? AND project_id = ?")
+            .bind(name)
+            .bind(wasm)
+            .bind(id)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+
+        self.get_plugin(project_id, id).await
+    }
+
+    pub async fn delete_plugin(&self, project_id: i64, id: i64) -> Result<(), DbError> {
+        let result = sqlx::query("DELETE FROM plugins WHERE id = ? AND project_id = ?")
+            .bind(id)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(DbError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Check that a template or rule names one of the project's plugins of
+    /// the kind it runs.
+    async fn check_plugin(
+        &self,
+        project_id: i64,
+        plugin_id: Option<i64>,
+        kind: PluginKind,
+    ) -> Result<(), DbError> {
+        let Some(plugin_id) = plugin_id else {
+            return Ok(());
+        };
+        let stored: Option<String> =
+            sqlx::query_scalar("SELECT kind FROM plugins WHERE id = ? AND project_id = ?")
+                .bind(plugin_id)
+                .bind(project_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        match stored {
+            None => Err(DbError::InvalidData(format!("unknown plugin {plugin_id}"))),
+            Some(stored) if parse_plugin_kind(&stored)? != kind => Err(DbError::InvalidData(
+                format!("plugin {plugin_id} is not a {stored} plugin"),
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Import project configuration
+    // -----------------------------------------------------------------------
+
+    pub async fn import_project_config(
+        &self,
+        project_id: i64,
+        data: &ProjectExport,
+    ) -> Result<ImportResult, DbError> {
+        let mut tt_id_map: HashMap<u64, u64> = HashMap::new();
+        let mut st_id_map: HashMap<u64, u64> = HashMap::new();
+        let mut rule_id_map: HashMap<u64, u64> = HashMap::new();
+        let mut plugin_id_map: HashMap<u64, u64> = HashMap::new();
+
+        // Grok patterns may refer to entries defined after them, so the
+        // library is checked as a whole, before anything is written
+        let mut library = self.list_grok_patterns(project_id).await?;
+        library.extend(data.grok_patterns.iter().cloned());
+        logium_core::grok::validate_grok_patterns(&library)
+            .map_err(|e| DbError::InvalidData(e.to_string()))?;
+
+        // 0. Plugins (no FK deps)
+        for plugin in &data.plugins {
+            let new_plugin = self
+                .create_plugin(project_id, &plugin.name, plugin.kind, &plugin.wasm)
+                .await?;
+            plugin_id_map.insert(plugin.id, new_plugin.id);
+        }
+        let remap_plugin = |owner: &str, id: Option<u64>| -> Result<Option<i64>, DbError> {
+            id.map(|id| {
+                plugin_id_map
+                    .get(&id)
+                    .map(|&new_id| new_id as i64)
+                    .ok_or_else(|| {
+                        DbError::InvalidData(format!("{owner} references unknown plugin {id}"))
+                    })
+            })
+            .transpose()
+        };
+
+        // 1. TimestampTemplates (no FK deps)
         for tt in &data.timestamp_templates {
             let new_tt = self
                 .create_timestamp_template(
@@ -1237,6 +1975,10 @@ impl Database {
                     st.json_timestamp_field.as_deref(),
                     st.file_name_regex.as_deref(),
                     st.log_content_regex.as_deref(),
+                    remap_plugin(
+                        &format!("source template '{}'", st.name),
+                        st.parser_plugin_id,
+                    )?,
                 )
                 .await?;
             st_id_map.insert(st.id, new_st.id);
@@ -1249,6 +1991,7 @@ impl Database {
                 .iter()
                 .map(|mr| CreateMatchRule {
                     pattern: mr.pattern.clone(),
+                    negate: mr.negate,
                 })
                 .collect();
             let create_ext_rules: Vec<CreateExtractionRule> = rule
@@ -1260,6 +2003,9 @@ impl Database {
                     pattern: er.pattern.clone(),
                     static_value: er.static_value.clone(),
                     mode: er.mode.clone(),
+                    target_type: er.target_type,
+                    transforms: er.transforms.clone(),
+                    ttl_ms: er.ttl_ms,
                 })
                 .collect();
             let new_rule = self
@@ -1269,6 +2015,9 @@ impl Database {
                     &rule.match_mode,
                     &create_match_rules,
                     &create_ext_rules,
+                    rule.guard.as_ref(),
+                    rule.script.as_deref(),
+                    remap_plugin(&format!("rule '{}'", rule.name), rule.plugin_id)?,
                 )
                 .await?;
             rule_id_map.insert(rule.id, new_rule.id);
@@ -1282,38 +2031,137 @@ impl Database {
                     rs.name, rs.template_id
                 ))
             })?;
-            let remapped_rule_ids: Vec<i64> = rs
-                .rule_ids
+            let remap = |ids: &[u64]| -> Result<Vec<i64>, DbError> {
+                ids.iter()
+                    .map(|old_id| {
+                        rule_id_map
+                            .get(old_id)
+                            .map(|&new_id| new_id as i64)
+                            .ok_or_else(|| {
+                                DbError::InvalidData(format!(
+                                    "ruleset '{}' references unknown rule_id {}",
+                                    rs.name, old_id
+                                ))
+                            })
+                    })
+                    .collect()
+            };
+            let extra_template_ids = rs
+                .extra_template_ids
                 .iter()
                 .map(|old_id| {
-                    rule_id_map
+                    st_id_map
                         .get(old_id)
                         .map(|&new_id| new_id as i64)
                         .ok_or_else(|| {
                             DbError::InvalidData(format!(
-                                "ruleset '{}' references unknown rule_id {}",
+                                "ruleset '{}' references unknown template_id {}",
                                 rs.name, old_id
                             ))
                         })
                 })
-                .collect::<Result<_, _>>()?;
-            self.create_ruleset(project_id, &rs.name, *new_st_id as i64, &remapped_rule_ids)
-                .await?;
+                .collect::<Result<Vec<_>, _>>()?;
+            // Sources are bound by name, so need no remapping
+            self.create_ruleset(
+                project_id,
+                &rs.name,
+                *new_st_id as i64,
+                &extra_template_ids,
+                &rs.source_names,
+                &remap(&rs.rule_ids)?,
+                &remap(&rs.stop_rule_ids)?,
+            )
+            .await?;
         }
 
-        // 5. Patterns (predicates use source_name strings, no ID FKs)
+        // 5. Patterns (predicates use source_name strings; threshold steps may
+        //    reference a rule_id). Parents are linked once every pattern exists.
+        let mut pattern_id_map: HashMap<u64, i64> = HashMap::new();
         for pattern in &data.patterns {
             let create_predicates: Vec<CreatePredicate> = pattern
                 .predicates
                 .iter()
-                .map(|p| CreatePredicate {
-                    source_name: p.source_name.clone(),
-                    state_key: p.state_key.clone(),
-                    operator: p.operator.clone(),
-                    operand: p.operand.clone(),
+                .map(|p| {
+                    let threshold = match &p.threshold {
+                        Some(t) => {
+                            let rule_id = match t.rule_id {
+                                Some(old_id) => {
+                                    Some(*rule_id_map.get(&old_id).ok_or_else(|| {
+                                        DbError::InvalidData(format!(
+                                            "pattern '{}' references unknown rule_id {}",
+                                            pattern.name, old_id
+                                        ))
+                                    })?)
+                                }
+                                None => None,
+                            };
+                            Some(Threshold {
+                                rule_id,
+                                ..t.clone()
+                            })
+                        }
+                        None => None,
+                    };
+                    Ok(CreatePredicate {
+                        source_name: p.source_name.clone(),
+                        state_key: p.state_key.clone(),
+                        operator: p.operator.clone(),
+                        operand: p.operand.clone(),
+                        threshold,
+                    })
                 })
-                .collect();
-            self.create_pattern(project_id, &pattern.name, &create_predicates)
+                .collect::<Result<_, DbError>>()?;
+            let created = self
+                .create_pattern(
+                    project_id,
+                    &pattern.name,
+                    &create_predicates,
+                    pattern.cooldown_ms,
+                    &pattern.outcome,
+                    None,
+                )
+                .await?;
+            pattern_id_map.insert(pattern.id, created.id as i64);
+        }
+        for pattern in &data.patterns {
+            let Some(parent) = &pattern.parent else {
+                continue;
+            };
+            let new_parent_id = pattern_id_map.get(&parent.pattern_id).ok_or_else(|| {
+                DbError::InvalidData(format!(
+                    "pattern '{}' references unknown parent pattern {}",
+                    pattern.name, parent.pattern_id
+                ))
+            })?;
+            let remapped = PatternParent {
+                pattern_id: *new_parent_id as u64,
+                window_ms: parent.window_ms,
+            };
+            let id = pattern_id_map[&pattern.id];
+            self.validate_parent(project_id, Some(id), &remapped)
+                .await?;
+            sqlx::query("UPDATE patterns SET parent_id = ?, parent_window_ms = ? WHERE id = ?")
+                .bind(*new_parent_id)
+                .bind(remapped.window_ms.map(|v| v as i64))
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // 6. Derived keys refer to sources by name, so they need no remapping
+        for key in &data.derived_keys {
+            self.create_derived_key(
+                project_id,
+                &key.source_name,
+                &key.state_key,
+                &key.expression,
+            )
+            .await?;
+        }
+
+        // 7. Grok patterns are referenced by name from rule patterns
+        for grok in &data.grok_patterns {
+            self.insert_grok_pattern(project_id, &grok.name, &grok.pattern)
                 .await?;
         }
 
@@ -1323,6 +2171,9 @@ impl Database {
             rules: data.rules.len(),
             rulesets: data.rulesets.len(),
             patterns: data.patterns.len(),
+            derived_keys: data.derived_keys.len(),
+            grok_patterns: data.grok_patterns.len(),
+            plugins: data.plugins.len(),
         })
     }
 
@@ -1337,6 +2188,9 @@ impl Database {
         let rules = self.list_rules(project_id).await?;
         let rulesets = self.list_rulesets(project_id).await?;
         let patterns = self.list_patterns(project_id).await?;
+        let derived_keys = self.list_derived_keys(project_id).await?;
+        let grok_patterns = self.list_grok_patterns(project_id).await?;
+        let plugins = self.list_plugins(project_id).await?;
         Ok(ProjectData {
             timestamp_templates,
             templates,
@@ -1344,6 +2198,9 @@ impl Database {
             rules,
             rulesets,
             patterns,
+            derived_keys,
+            grok_patterns,
+            plugins,
         })
     }
 }
@@ -1366,12 +2223,17 @@ pub struct ProjectData {
     pub rules: Vec<LogRule>,
     pub rulesets: Vec<Ruleset>,
     pub patterns: Vec<Pattern>,
+    pub derived_keys: Vec<DerivedKey>,
+    pub grok_patterns: Vec<GrokPattern>,
+    pub plugins: Vec<Plugin>,
 }
 
 /// Input type for creating match rules (no id yet).
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CreateMatchRule {
     pub pattern: String,
+    #[serde(default)]
+    pub negate: bool,
 }
 
 /// Input type for creating extraction rules (no id yet).
@@ -1382,6 +2244,12 @@ pub struct CreateExtractionRule {
     pub pattern: Option<String>,
     pub static_value: Option<String>,
     pub mode: ExtractionMode,
+    #[serde(default)]
+    pub target_type: ValueType,
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
 }
 
 /// Input type for creating pattern predicates (no id yet).
@@ -1391,6 +2259,149 @@ pub struct CreatePredicate {
     pub state_key: String,
     pub operator: Operator,
     pub operand: Operand,
+    #[serde(default)]
+    pub threshold: Option<Threshold>,
+}
+
+/// Reject predicates whose operand does not fit the operator before anything
+/// is written.
+fn validate_predicates(predicates: &[CreatePredicate]) -> Result<(), DbError> {
+    for p in predicates {
+        logium_core::engine::validate_predicate(&PatternPredicate {
+            source_name: p.source_name.clone(),
+            state_key: p.state_key.clone(),
+            operator: p.operator.clone(),
+            operand: p.operand.clone(),
+            threshold: p.threshold.clone(),
+        })
+        .map_err(|e| DbError::InvalidData(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Reject transform pipelines the engine would fail to compile.
+fn validate_extraction_rules(extraction_rules: &[CreateExtractionRule]) -> Result<(), DbError> {
+    for er in extraction_rules {
+        logium_core::engine::validate_extraction_rule(&ExtractionRule {
+            id: 0,
+            extraction_type: er.extraction_type.clone(),
+            state_key: er.state_key.clone(),
+            pattern: er.pattern.clone(),
+            static_value: er.static_value.clone(),
+            mode: er.mode.clone(),
+            target_type: er.target_type,
+            transforms: er.transforms.clone(),
+            ttl_ms: er.ttl_ms,
+        })
+        .map_err(|e| DbError::InvalidData(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// A ruleset names each further template and source once, and not its own
+/// template again.
+fn validate_ruleset_bindings(
+    template_id: i64,
+    extra_template_ids: &[i64],
+    source_names: &[String],
+) -> Result<(), DbError> {
+    for (i, id) in extra_template_ids.iter().enumerate() {
+        if *id == template_id || extra_template_ids[..i].contains(id) {
+            return Err(DbError::InvalidData(format!(
+                "template {id} is bound to the ruleset twice"
+            )));
+        }
+    }
+    for (i, name) in source_names.iter().enumerate() {
+        if name.trim().is_empty() || source_names[..i].contains(name) {
+            return Err(DbError::InvalidData(format!(
+                "source '{name}' is empty or listed twice in the ruleset"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A ruleset lists each rule once, and only stops after rules it lists.
+fn validate_ruleset_rules(rule_ids: &[i64], stop_rule_ids: &[i64]) -> Result<(), DbError> {
+    for (i, id) in rule_ids.iter().enumerate() {
+        if rule_ids[..i].contains(id) {
+            return Err(DbError::InvalidData(format!(
+                "rule {id} is listed twice in the ruleset"
+            )));
+        }
+    }
+    if let Some(id) = stop_rule_ids.iter().find(|id| !rule_ids.contains(id)) {
+        return Err(DbError::InvalidData(format!(
+            "stop rule {id} is not one of the ruleset's rules"
+        )));
+    }
+    Ok(())
+}
+
+/// Transforms for the `transforms` column; `NULL` when there are none.
+fn transforms_to_json(transforms: &[Transform]) -> Result<Option<String>, DbError> {
+    if transforms.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(transforms)
+        .map(Some)
+        .map_err(|e| DbError::InvalidData(e.to_string()))
+}
+
+/// Validate a rule guard and serialize it for the `guard` column.
+fn guard_to_json(guard: Option<&RuleGuard>) -> Result<Option<String>, DbError> {
+    let Some(guard) = guard else {
+        return Ok(None);
+    };
+    logium_core::engine::validate_guard(guard).map_err(|e| DbError::InvalidData(e.to_string()))?;
+    serde_json::to_string(guard)
+        .map(Some)
+        .map_err(|e| DbError::InvalidData(e.to_string()))
+}
+
+/// Reject a module that does not compile or lacks its kind's exports.
+fn validate_plugin(name: &str, kind: PluginKind, wasm: &[u8]) -> Result<Plugin, DbError> {
+    let plugin = Plugin {
+        id: 0,
+        name: name.to_string(),
+        kind,
+        wasm: wasm.to_vec(),
+    };
+    logium_core::plugin::validate_plugin(&plugin)
+        .map_err(|e| DbError::InvalidData(format!("invalid plugin: {e}")))?;
+    Ok(plugin)
+}
+
+/// Reject a rule script that does not compile.
+fn validate_script(script: Option<&str>) -> Result<(), DbError> {
+    match script {
+        Some(script) => logium_core::script::validate_script(script)
+            .map_err(|e| DbError::InvalidData(format!("invalid script: {e}"))),
+        None => Ok(()),
+    }
+}
+
+/// Reject cooldowns and windows longer than the engine accepts.
+pub(crate) fn validate_span(what: &str, ms: Option<u64>) -> Result<(), DbError> {
+    match ms {
+        Some(ms) if ms > MAX_SPAN_MS => Err(DbError::InvalidData(format!(
+            "{what} must be at most {MAX_SPAN_MS} ms"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Reject malformed diagnosis and remediation templates.
+pub(crate) fn validate_outcome(outcome: &PatternOutcome) -> Result<(), DbError> {
+    for template in [&outcome.diagnosis, &outcome.remediation]
+        .into_iter()
+        .flatten()
+    {
+        logium_core::engine::validate_template(template)
+            .map_err(|e| DbError::InvalidData(e.to_string()))?;
+    }
+    Ok(())
 }
 
 fn row_to_timestamp_template(row: &sqlx::sqlite::SqliteRow) -> TimestampTemplate {
@@ -1403,8 +2414,34 @@ fn row_to_timestamp_template(row: &sqlx::sqlite::SqliteRow) -> TimestampTemplate
     }
 }
 
-fn row_to_template(row: &sqlx::sqlite::SqliteRow) -> SourceTemplate {
-    SourceTemplate {
+fn row_to_derived_key(row: &sqlx::sqlite::SqliteRow) -> DerivedKey {
+    DerivedKey {
+        id: row.get::<i64, _>("id") as u64,
+        source_name: row.get("source_name"),
+        state_key: row.get("state_key"),
+        expression: row.get("expression"),
+    }
+}
+
+fn row_to_grok_pattern(row: &sqlx::sqlite::SqliteRow) -> GrokPattern {
+    GrokPattern {
+        id: row.get::<i64, _>("id") as u64,
+        name: row.get("name"),
+        pattern: row.get("pattern"),
+    }
+}
+
+fn row_to_plugin(row: &sqlx::sqlite::SqliteRow) -> Result<Plugin, DbError> {
+    Ok(Plugin {
+        id: row.get::<i64, _>("id") as u64,
+        name: row.get("name"),
+        kind: parse_plugin_kind(row.get("kind"))?,
+        wasm: row.get("wasm"),
+    })
+}
+
+fn row_to_template(row: &sqlx::sqlite::SqliteRow) -> SourceTemplate {
+    SourceTemplate {
         id: row.get::<i64, _>("id") as u64,
         name: row.get("name"),
         timestamp_template_id: row.get::<i64, _>("timestamp_template_id") as u64,
@@ -1414,6 +2451,9 @@ fn row_to_template(row: &sqlx::sqlite::SqliteRow) -> SourceTemplate {
         json_timestamp_field: row.get("json_timestamp_field"),
         file_name_regex: row.get("file_name_regex"),
         log_content_regex: row.get("log_content_regex"),
+        parser_plugin_id: row
+            .get::<Option<i64>, _>("parser_plugin_id")
+            .map(|id| id as u64),
     }
 }
 
@@ -1432,15 +2472,23 @@ fn row_to_predicate(row: &sqlx::sqlite::SqliteRow) -> Result<PatternPredicate, D
     let operator_str: String = row.get("operator");
     let operand_type: String = row.get("operand_type");
     let operand_value: String = row.get("operand_value");
+    let threshold_json: Option<String> = row.get("threshold");
 
     let operator = parse_operator(&operator_str)?;
     let operand = deserialize_operand(&operand_type, &operand_value)?;
+    let threshold = threshold_json
+        .map(|j| {
+            serde_json::from_str::<Threshold>(&j)
+                .map_err(|e| DbError::InvalidData(format!("invalid threshold JSON: {e}")))
+        })
+        .transpose()?;
 
     Ok(PatternPredicate {
         source_name,
         state_key,
         operator,
         operand,
+        threshold,
     })
 }
 
@@ -1448,6 +2496,21 @@ fn row_to_predicate(row: &sqlx::sqlite::SqliteRow) -> Result<PatternPredicate, D
 // Enum serialization helpers
 // ---------------------------------------------------------------------------
 
+fn parse_plugin_kind(s: &str) -> Result<PluginKind, DbError> {
+    match s {
+        "line_parser" => Ok(PluginKind::LineParser),
+        "extractor" => Ok(PluginKind::Extractor),
+        _ => Err(DbError::InvalidData(format!("unknown plugin kind: {s}"))),
+    }
+}
+
+fn plugin_kind_to_str(kind: PluginKind) -> &'static str {
+    match kind {
+        PluginKind::LineParser => "line_parser",
+        PluginKind::Extractor => "extractor",
+    }
+}
+
 fn parse_match_mode(s: &str) -> Result<MatchMode, DbError> {
     match s {
         "any" => Ok(MatchMode::Any),
@@ -1482,10 +2545,21 @@ fn extraction_type_to_str(t: &ExtractionType) -> &'static str {
     }
 }
 
-fn parse_extraction_mode(s: &str) -> Result<ExtractionMode, DbError> {
+fn parse_extraction_mode(s: &str, list_cap: Option<i64>) -> Result<ExtractionMode, DbError> {
     match s {
         "replace" => Ok(ExtractionMode::Replace),
         "accumulate" => Ok(ExtractionMode::Accumulate),
+        "count" => Ok(ExtractionMode::Count),
+        "min" => Ok(ExtractionMode::Min),
+        "max" => Ok(ExtractionMode::Max),
+        "average" => Ok(ExtractionMode::Average),
+        "distinct_set" => Ok(ExtractionMode::DistinctSet),
+        "list" => Ok(ExtractionMode::List {
+            cap: list_cap
+                .ok_or_else(|| DbError::InvalidData("list mode without list_cap".to_string()))?
+                as usize,
+        }),
+        "first_seen" => Ok(ExtractionMode::FirstSeen),
         _ => Err(DbError::InvalidData(format!(
             "unknown extraction_mode: {s}"
         ))),
@@ -1496,9 +2570,91 @@ fn extraction_mode_to_str(m: &ExtractionMode) -> &'static str {
     match m {
         ExtractionMode::Replace => "replace",
         ExtractionMode::Accumulate => "accumulate",
+        ExtractionMode::Count => "count",
+        ExtractionMode::Min => "min",
+        ExtractionMode::Max => "max",
+        ExtractionMode::Average => "average",
+        ExtractionMode::DistinctSet => "distinct_set",
+        ExtractionMode::List { .. } => "list",
+        ExtractionMode::FirstSeen => "first_seen",
     }
 }
 
+/// The `list_cap` column of a `List` mode extraction.
+fn extraction_list_cap(m: &ExtractionMode) -> Option<i64> {
+    match m {
+        ExtractionMode::List { cap } => Some(*cap as i64),
+        _ => None,
+    }
+}
+
+fn parse_value_type(s: &str) -> Result<ValueType, DbError> {
+    match s {
+        "auto" => Ok(ValueType::Auto),
+        "string" => Ok(ValueType::String),
+        "integer" => Ok(ValueType::Integer),
+        "float" => Ok(ValueType::Float),
+        "bool" => Ok(ValueType::Bool),
+        "timestamp" => Ok(ValueType::Timestamp),
+        "duration" => Ok(ValueType::Duration),
+        _ => Err(DbError::InvalidData(format!("unknown target_type: {s}"))),
+    }
+}
+
+fn value_type_to_str(t: ValueType) -> &'static str {
+    match t {
+        ValueType::Auto => "auto",
+        ValueType::String => "string",
+        ValueType::Integer => "integer",
+        ValueType::Float => "float",
+        ValueType::Bool => "bool",
+        ValueType::Timestamp => "timestamp",
+        ValueType::Duration => "duration",
+    }
+}
+
+fn parse_severity(s: &str) -> Result<Severity, DbError> {
+    match s {
+        "info" => Ok(Severity::Info),
+        "warning" => Ok(Severity::Warning),
+        "error" => Ok(Severity::Error),
+        "critical" => Ok(Severity::Critical),
+        _ => Err(DbError::InvalidData(format!("unknown severity: {s}"))),
+    }
+}
+
+fn severity_to_str(s: Severity) -> &'static str {
+    match s {
+        Severity::Info => "info",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+        Severity::Critical => "critical",
+    }
+}
+
+fn row_to_pattern_outcome(row: &sqlx::sqlite::SqliteRow) -> Result<PatternOutcome, DbError> {
+    Ok(PatternOutcome {
+        severity: row
+            .get::<Option<String>, _>("severity")
+            .as_deref()
+            .map(parse_severity)
+            .transpose()?,
+        category: row.get("category"),
+        diagnosis: row.get("diagnosis"),
+        remediation: row.get("remediation"),
+    })
+}
+
+fn row_to_pattern_parent(row: &sqlx::sqlite::SqliteRow) -> Option<PatternParent> {
+    row.get::<Option<i64>, _>("parent_id")
+        .map(|pattern_id| PatternParent {
+            pattern_id: pattern_id as u64,
+            window_ms: row
+                .get::<Option<i64>, _>("parent_window_ms")
+                .map(|v| v as u64),
+        })
+}
+
 fn parse_operator(s: &str) -> Result<Operator, DbError> {
     match s {
         "eq" => Ok(Operator::Eq),
@@ -1509,6 +2665,24 @@ fn parse_operator(s: &str) -> Result<Operator, DbError> {
         "lte" => Ok(Operator::Lte),
         "contains" => Ok(Operator::Contains),
         "exists" => Ok(Operator::Exists),
+        "not_exists" => Ok(Operator::NotExists),
+        "matches" => Ok(Operator::Matches),
+        "in" => Ok(Operator::In),
+        "not_in" => Ok(Operator::NotIn),
+        "starts_with" => Ok(Operator::StartsWith),
+        "ends_with" => Ok(Operator::EndsWith),
+        "between" => Ok(Operator::Between),
+        "older_than" => Ok(Operator::OlderThan),
+        "newer_than" => Ok(Operator::NewerThan),
+        "set_before" => Ok(Operator::SetBefore),
+        "set_after" => Ok(Operator::SetAfter),
+        "set_within" => Ok(Operator::SetWithin),
+        "size_eq" => Ok(Operator::SizeEq),
+        "size_gt" => Ok(Operator::SizeGt),
+        "size_lt" => Ok(Operator::SizeLt),
+        "changed" => Ok(Operator::Changed),
+        "became_set" => Ok(Operator::BecameSet),
+        "became_unset" => Ok(Operator::BecameUnset),
         _ => Err(DbError::InvalidData(format!("unknown operator: {s}"))),
     }
 }
@@ -1523,6 +2697,24 @@ fn operator_to_str(o: &Operator) -> &'static str {
         Operator::Lte => "lte",
         Operator::Contains => "contains",
         Operator::Exists => "exists",
+        Operator::NotExists => "not_exists",
+        Operator::Matches => "matches",
+        Operator::In => "in",
+        Operator::NotIn => "not_in",
+        Operator::StartsWith => "starts_with",
+        Operator::EndsWith => "ends_with",
+        Operator::Between => "between",
+        Operator::OlderThan => "older_than",
+        Operator::NewerThan => "newer_than",
+        Operator::SetBefore => "set_before",
+        Operator::SetAfter => "set_after",
+        Operator::SetWithin => "set_within",
+        Operator::SizeEq => "size_eq",
+        Operator::SizeGt => "size_gt",
+        Operator::SizeLt => "size_lt",
+        Operator::Changed => "changed",
+        Operator::BecameSet => "became_set",
+        Operator::BecameUnset => "became_unset",
     }
 }
 
@@ -1543,6 +2735,31 @@ fn serialize_operand(operand: &Operand) -> (&'static str, String) {
             .to_string();
             ("state_ref", json)
         }
+        Operand::Transition { from, to } => {
+            let json = serde_json::json!({ "from": from, "to": to }).to_string();
+            ("transition", json)
+        }
+        Operand::List(values) => ("list", serde_json::to_string(values).unwrap_or_default()),
+        Operand::Expr(src) => ("expr", src.clone()),
+        Operand::TimeRef {
+            source_name,
+            state_key,
+            within_ms,
+            skew_ms,
+        } => {
+            let json = serde_json::json!({
+                "source_name": source_name,
+                "state_key": state_key,
+                "within_ms": within_ms,
+                "skew_ms": skew_ms,
+            })
+            .to_string();
+            ("time_ref", json)
+        }
+        Operand::Range { min, max } => {
+            let json = serde_json::json!({ "min": min, "max": max }).to_string();
+            ("range", json)
+        }
     }
 }
 
@@ -1570,6 +2787,58 @@ fn deserialize_operand(operand_type: &str, operand_value: &str) -> Result<Operan
                 state_key,
             })
         }
+        "transition" => {
+            #[derive(serde::Deserialize)]
+            struct Transition {
+                from: Option<StateValue>,
+                to: Option<StateValue>,
+            }
+            let t: Transition = serde_json::from_str(operand_value).map_err(|e| {
+                DbError::InvalidData(format!("invalid transition operand JSON: {e}"))
+            })?;
+            Ok(Operand::Transition {
+                from: t.from,
+                to: t.to,
+            })
+        }
+        "expr" => Ok(Operand::Expr(operand_value.to_string())),
+        "time_ref" => {
+            #[derive(serde::Deserialize)]
+            struct TimeRef {
+                source_name: String,
+                state_key: String,
+                #[serde(default)]
+                within_ms: Option<u64>,
+                #[serde(default)]
+                skew_ms: i64,
+            }
+            let t: TimeRef = serde_json::from_str(operand_value)
+                .map_err(|e| DbError::InvalidData(format!("invalid time_ref operand JSON: {e}")))?;
+            Ok(Operand::TimeRef {
+                source_name: t.source_name,
+                state_key: t.state_key,
+                within_ms: t.within_ms,
+                skew_ms: t.skew_ms,
+            })
+        }
+        "list" => {
+            let values: Vec<StateValue> = serde_json::from_str(operand_value)
+                .map_err(|e| DbError::InvalidData(format!("invalid list operand JSON: {e}")))?;
+            Ok(Operand::List(values))
+        }
+        "range" => {
+            #[derive(serde::Deserialize)]
+            struct Range {
+                min: StateValue,
+                max: StateValue,
+            }
+            let r: Range = serde_json::from_str(operand_value)
+                .map_err(|e| DbError::InvalidData(format!("invalid range operand JSON: {e}")))?;
+            Ok(Operand::Range {
+                min: r.min,
+                max: r.max,
+            })
+        }
         _ => Err(DbError::InvalidData(format!(
             "unknown operand_type: {operand_type}"
         ))),
@@ -1672,6 +2941,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -1703,6 +2973,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -1732,6 +3003,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -1761,6 +3033,7 @@ mod tests {
                 &MatchMode::Any,
                 &[CreateMatchRule {
                     pattern: r"ERROR".to_string(),
+                    negate: false,
                 }],
                 &[CreateExtractionRule {
                     extraction_type: ExtractionType::Static,
@@ -1768,7 +3041,13 @@ mod tests {
                     pattern: None,
                     static_value: Some("error".to_string()),
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                None,
+                None,
+                None,
             )
             .await
             .unwrap();
@@ -1788,12 +3067,17 @@ mod tests {
                 &[
                     CreateMatchRule {
                         pattern: "WARN".to_string(),
+                        negate: false,
                     },
                     CreateMatchRule {
                         pattern: "CRITICAL".to_string(),
+                        negate: false,
                     },
                 ],
                 &[],
+                None,
+                None,
+                None,
             )
             .await
             .unwrap();
@@ -1805,6 +3089,341 @@ mod tests {
         assert!(db.get_rule(p.id, rule.id as i64).await.is_err());
     }
 
+    #[tokio::test]
+    async fn test_rule_negate_and_guard_round_trip() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let guard = RuleGuard {
+            source_name: None,
+            state_key: "phase".to_string(),
+            operator: Operator::Eq,
+            operand: Operand::Literal(StateValue::String("matchmaking".to_string())),
+        };
+
+        let rule = db
+            .create_rule(
+                p.id,
+                "guarded",
+                &MatchMode::Any,
+                &[
+                    CreateMatchRule {
+                        pattern: "ERROR".to_string(),
+                        negate: false,
+                    },
+                    CreateMatchRule {
+                        pattern: "expected".to_string(),
+                        negate: true,
+                    },
+                ],
+                &[],
+                Some(&guard),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let fetched = db.get_rule(p.id, rule.id as i64).await.unwrap();
+        assert!(!fetched.match_rules[0].negate);
+        assert!(fetched.match_rules[1].negate);
+        let fetched_guard = fetched.guard.unwrap();
+        assert_eq!(fetched_guard.state_key, "phase");
+        assert!(matches!(fetched_guard.operator, Operator::Eq));
+
+        let updated = db
+            .update_rule(
+                p.id,
+                rule.id as i64,
+                "guarded",
+                &MatchMode::Any,
+                &[CreateMatchRule {
+                    pattern: "ERROR".to_string(),
+                    negate: false,
+                }],
+                &[],
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(updated.guard.is_none());
+        assert!(
+            db.get_rule(p.id, rule.id as i64)
+                .await
+                .unwrap()
+                .guard
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extraction_transforms_round_trip() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let extraction = CreateExtractionRule {
+            extraction_type: ExtractionType::Parsed,
+            state_key: "code".to_string(),
+            pattern: Some(r"code=(?P<code>\S+)".to_string()),
+            static_value: None,
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Integer,
+            transforms: vec![Transform::Trim, Transform::ParseInt { radix: 16 }],
+            ttl_ms: Some(60_000),
+        };
+        let rule = db
+            .create_rule(
+                p.id,
+                "codes",
+                &MatchMode::Any,
+                &[CreateMatchRule {
+                    pattern: "code=".to_string(),
+                    negate: false,
+                }],
+                &[extraction],
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let fetched = db.get_rule(p.id, rule.id as i64).await.unwrap();
+        let ext = &fetched.extraction_rules[0];
+        assert_eq!(ext.target_type, ValueType::Integer);
+        assert_eq!(ext.transforms.len(), 2);
+        assert_eq!(ext.ttl_ms, Some(60_000));
+        assert!(matches!(
+            ext.transforms[1],
+            Transform::ParseInt { radix: 16 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_extraction_modes_round_trip() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let extraction = |key: &str, mode: ExtractionMode| CreateExtractionRule {
+            extraction_type: ExtractionType::Parsed,
+            state_key: key.to_string(),
+            pattern: Some(format!(r"{key}=(?P<{key}>\S+)")),
+            static_value: None,
+            mode,
+            target_type: ValueType::Auto,
+            transforms: Vec::new(),
+            ttl_ms: None,
+        };
+        let rule = db
+            .create_rule(
+                p.id,
+                "modes",
+                &MatchMode::Any,
+                &[CreateMatchRule {
+                    pattern: "code=".to_string(),
+                    negate: false,
+                }],
+                &[
+                    extraction("recent", ExtractionMode::List { cap: 3 }),
+                    extraction("codes", ExtractionMode::DistinctSet),
+                    extraction("peak", ExtractionMode::Max),
+                ],
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let fetched = db.get_rule(p.id, rule.id as i64).await.unwrap();
+        let modes: Vec<_> = fetched.extraction_rules.iter().map(|e| &e.mode).collect();
+        assert!(matches!(modes[0], ExtractionMode::List { cap: 3 }));
+        assert!(matches!(modes[1], ExtractionMode::DistinctSet));
+        assert!(matches!(modes[2], ExtractionMode::Max));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_extraction_transform_rejected() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let extraction = CreateExtractionRule {
+            extraction_type: ExtractionType::Parsed,
+            state_key: "size".to_string(),
+            pattern: Some(r"(?P<size>\S+)".to_string()),
+            static_value: None,
+            mode: ExtractionMode::Replace,
+            target_type: ValueType::Auto,
+            transforms: vec![Transform::Unit {
+                to: "furlongs".to_string(),
+            }],
+            ttl_ms: None,
+        };
+        let err = db
+            .create_rule(
+                p.id,
+                "bad",
+                &MatchMode::Any,
+                &[],
+                &[extraction],
+                None,
+                None,
+                None,
+            )
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+        assert!(db.list_rules(p.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_rule_guard_rejected() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let guard = RuleGuard {
+            source_name: None,
+            state_key: "phase".to_string(),
+            operator: Operator::Changed,
+            operand: Operand::Literal(StateValue::Bool(true)),
+        };
+        let err = db
+            .create_rule(
+                p.id,
+                "bad",
+                &MatchMode::Any,
+                &[],
+                &[],
+                Some(&guard),
+                None,
+                None,
+            )
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rule_script_round_trip() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let script = "#{ player: line }";
+        let rule = db
+            .create_rule(
+                p.id,
+                "scripted",
+                &MatchMode::Any,
+                &[],
+                &[],
+                None,
+                Some(script),
+                None,
+            )
+            .await
+            .unwrap();
+        let fetched = db.get_rule(p.id, rule.id as i64).await.unwrap();
+        assert_eq!(fetched.script.as_deref(), Some(script));
+
+        let err = db
+            .update_rule(
+                p.id,
+                rule.id as i64,
+                "scripted",
+                &MatchMode::Any,
+                &[],
+                &[],
+                None,
+                Some("#{ player: "),
+                None,
+            )
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+        let cleared = db
+            .update_rule(
+                p.id,
+                rule.id as i64,
+                "scripted",
+                &MatchMode::Any,
+                &[],
+                &[],
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(cleared.script.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_plugin_crud_and_references() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let wasm = wat::parse_str(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{}")
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "extract") (param i32 i32) (result i64) (i64.const 2)))
+            "#,
+        )
+        .unwrap();
+        let plugin = db
+            .create_plugin(p.id, "noop", PluginKind::Extractor, &wasm)
+            .await
+            .unwrap();
+        let fetched = db.get_plugin(p.id, plugin.id as i64).await.unwrap();
+        assert_eq!(fetched.kind, PluginKind::Extractor);
+        assert_eq!(fetched.wasm, wasm);
+
+        // An extractor module has no parse_line export
+        let err = db
+            .create_plugin(p.id, "parser", PluginKind::LineParser, &wasm)
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+        let err = db
+            .create_plugin(p.id, "junk", PluginKind::Extractor, b"junk")
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+
+        let rule = db
+            .create_rule(
+                p.id,
+                "plugged",
+                &MatchMode::Any,
+                &[],
+                &[],
+                None,
+                None,
+                Some(plugin.id as i64),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rule.plugin_id, Some(plugin.id));
+
+        // A template needs a line parser
+        let tt = db
+            .create_timestamp_template(p.id, "ts", "%Y-%m-%d %H:%M:%S", None, None)
+            .await
+            .unwrap();
+        let err = db
+            .create_template(
+                p.id,
+                "tmpl",
+                tt.id as i64,
+                "\n",
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(plugin.id as i64),
+            )
+            .await;
+        assert!(matches!(err, Err(DbError::InvalidData(_))));
+
+        db.delete_plugin(p.id, plugin.id as i64).await.unwrap();
+        let rule = db.get_rule(p.id, rule.id as i64).await.unwrap();
+        assert!(rule.plugin_id.is_none());
+        assert!(db.list_plugins(p.id).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_ruleset_crud() {
         let db = test_db().await;
@@ -1824,120 +3443,557 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
+            )
+            .await
+            .unwrap();
+        let r1 = db
+            .create_rule(p.id, "r1", &MatchMode::Any, &[], &[], None, None, None)
+            .await
+            .unwrap();
+        let r2 = db
+            .create_rule(p.id, "r2", &MatchMode::Any, &[], &[], None, None, None)
+            .await
+            .unwrap();
+
+        let t2 = db
+            .create_template(
+                p.id,
+                "tmpl2",
+                tt.id as i64,
+                "\n",
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let rs = db
+            .create_ruleset(
+                p.id,
+                "ruleset1",
+                t.id as i64,
+                &[t2.id as i64],
+                &["lobby-3".to_string()],
+                &[r1.id as i64, r2.id as i64],
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rs.rule_ids.len(), 2);
+
+        let fetched = db.get_ruleset(p.id, rs.id as i64).await.unwrap();
+        assert_eq!(fetched.name, "ruleset1");
+        assert_eq!(fetched.extra_template_ids, vec![t2.id]);
+        assert_eq!(fetched.source_names, vec!["lobby-3".to_string()]);
+
+        // Order and stop flags are kept as given
+        db.update_ruleset(
+            p.id,
+            rs.id as i64,
+            "ruleset1",
+            t.id as i64,
+            &[],
+            &[],
+            &[r2.id as i64, r1.id as i64],
+            &[r2.id as i64],
+        )
+        .await
+        .unwrap();
+        let fetched = db.get_ruleset(p.id, rs.id as i64).await.unwrap();
+        assert_eq!(fetched.rule_ids, vec![r2.id, r1.id]);
+        assert_eq!(fetched.stop_rule_ids, vec![r2.id]);
+        assert!(fetched.extra_template_ids.is_empty() && fetched.source_names.is_empty());
+
+        // Deleting a further template unbinds it
+        db.update_ruleset(
+            p.id,
+            rs.id as i64,
+            "ruleset1",
+            t.id as i64,
+            &[t2.id as i64],
+            &[],
+            &[r1.id as i64],
+            &[],
+        )
+        .await
+        .unwrap();
+        db.delete_template(p.id, t2.id as i64).await.unwrap();
+        let fetched = db.get_ruleset(p.id, rs.id as i64).await.unwrap();
+        assert!(fetched.extra_template_ids.is_empty());
+
+        for (extra_template_ids, source_names) in [
+            (vec![t.id as i64], vec![]),
+            (vec![], vec!["a".to_string(), "a".to_string()]),
+        ] {
+            let err = db
+                .update_ruleset(
+                    p.id,
+                    rs.id as i64,
+                    "ruleset1",
+                    t.id as i64,
+                    &extra_template_ids,
+                    &source_names,
+                    &[],
+                    &[],
+                )
+                .await;
+            assert!(matches!(err, Err(DbError::InvalidData(_))));
+        }
+
+        for (rule_ids, stop_rule_ids) in [
+            (vec![r1.id as i64, r1.id as i64], vec![]),
+            (vec![r1.id as i64], vec![r2.id as i64]),
+        ] {
+            let err = db
+                .update_ruleset(
+                    p.id,
+                    rs.id as i64,
+                    "ruleset1",
+                    t.id as i64,
+                    &[],
+                    &[],
+                    &rule_ids,
+                    &stop_rule_ids,
+                )
+                .await;
+            assert!(matches!(err, Err(DbError::InvalidData(_))));
+        }
+
+        db.delete_ruleset(p.id, rs.id as i64).await.unwrap();
+        assert!(db.get_ruleset(p.id, rs.id as i64).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pattern_crud() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let pattern = db
+            .create_pattern(
+                p.id,
+                "test_pattern",
+                &[
+                    CreatePredicate {
+                        source_name: "server".to_string(),
+                        state_key: "status".to_string(),
+                        operator: Operator::Eq,
+                        operand: Operand::Literal(StateValue::String("running".to_string())),
+                        threshold: None,
+                    },
+                    CreatePredicate {
+                        source_name: "server".to_string(),
+                        state_key: "count".to_string(),
+                        operator: Operator::Gt,
+                        operand: Operand::Literal(StateValue::Integer(10)),
+                        threshold: None,
+                    },
+                ],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(pattern.predicates.len(), 2);
+        assert_eq!(pattern.predicates[0].source_name, "server");
+
+        // Verify operand round-trip
+        if let Operand::Literal(StateValue::String(ref s)) = pattern.predicates[0].operand {
+            assert_eq!(s, "running");
+        } else {
+            panic!("expected literal string operand");
+        }
+
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert_eq!(fetched.predicates.len(), 2);
+
+        db.delete_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert!(db.get_pattern(p.id, pattern.id as i64).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_project_seeds_default_timestamp_templates() {
+        let db = test_db().await;
+        let p = db.create_project("Seeded").await.unwrap();
+        let tts = db.list_timestamp_templates(p.id).await.unwrap();
+        assert_eq!(tts.len(), 6);
+        let names: Vec<&str> = tts.iter().map(|t| t.name.as_str()).collect();
+        assert!(names.contains(&"ISO 8601"));
+        assert!(names.contains(&"Syslog (RFC 3164)"));
+        let syslog = tts.iter().find(|t| t.name == "Syslog (RFC 3164)").unwrap();
+        assert!(syslog.default_year.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_pattern_with_state_ref_operand() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let pattern = db
+            .create_pattern(
+                p.id,
+                "cross_source",
+                &[CreatePredicate {
+                    source_name: "server".to_string(),
+                    state_key: "region".to_string(),
+                    operator: Operator::Eq,
+                    operand: Operand::StateRef {
+                        source_name: "client".to_string(),
+                        state_key: "region".to_string(),
+                    },
+                    threshold: None,
+                }],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        if let Operand::StateRef {
+            ref source_name,
+            ref state_key,
+        } = fetched.predicates[0].operand
+        {
+            assert_eq!(source_name, "client");
+            assert_eq!(state_key, "region");
+        } else {
+            panic!("expected state_ref operand");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pattern_edge_operator_and_cooldown() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let pattern = db
+            .create_pattern(
+                p.id,
+                "leader_change",
+                &[CreatePredicate {
+                    source_name: "server".to_string(),
+                    state_key: "leader".to_string(),
+                    operator: Operator::Changed,
+                    operand: Operand::Transition {
+                        from: Some(StateValue::String("a".to_string())),
+                        to: None,
+                    },
+                    threshold: None,
+                }],
+                Some(30_000),
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(pattern.cooldown_ms, Some(30_000));
+
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert_eq!(fetched.cooldown_ms, Some(30_000));
+        assert!(matches!(fetched.predicates[0].operator, Operator::Changed));
+        if let Operand::Transition { ref from, ref to } = fetched.predicates[0].operand {
+            assert!(matches!(from, Some(StateValue::String(s)) if s == "a"));
+            assert!(to.is_none());
+        } else {
+            panic!("expected transition operand");
+        }
+
+        let updated = db
+            .update_pattern(
+                p.id,
+                pattern.id as i64,
+                "leader_change",
+                &[],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.cooldown_ms, None);
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert_eq!(fetched.cooldown_ms, None);
+
+        let err = db
+            .update_pattern(
+                p.id,
+                pattern.id as i64,
+                "leader_change",
+                &[],
+                Some(u64::MAX),
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(ref msg) if msg.contains("cooldown")));
+        let err = db
+            .create_pattern(
+                p.id,
+                "slow",
+                &[],
+                Some(MAX_SPAN_MS + 1),
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
+    }
+
+    #[tokio::test]
+    async fn test_pattern_list_and_range_operands() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let pred = |operator: Operator, operand: Operand| CreatePredicate {
+            source_name: "server".to_string(),
+            state_key: "code".to_string(),
+            operator,
+            operand,
+            threshold: None,
+        };
+
+        let pattern = db
+            .create_pattern(
+                p.id,
+                "server_errors",
+                &[
+                    pred(
+                        Operator::In,
+                        Operand::List(vec![StateValue::Integer(502), StateValue::Integer(503)]),
+                    ),
+                    pred(
+                        Operator::Between,
+                        Operand::Range {
+                            min: StateValue::Integer(500),
+                            max: StateValue::Integer(599),
+                        },
+                    ),
+                ],
+                None,
+                &PatternOutcome::default(),
+                None,
             )
             .await
             .unwrap();
-        let r1 = db
-            .create_rule(p.id, "r1", &MatchMode::Any, &[], &[])
-            .await
-            .unwrap();
-        let r2 = db
-            .create_rule(p.id, "r2", &MatchMode::Any, &[], &[])
-            .await
-            .unwrap();
 
-        let rs = db
-            .create_ruleset(p.id, "ruleset1", t.id as i64, &[r1.id as i64, r2.id as i64])
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert!(matches!(fetched.predicates[0].operator, Operator::In));
+        assert!(matches!(&fetched.predicates[0].operand, Operand::List(v) if v.len() == 2));
+        assert!(matches!(fetched.predicates[1].operator, Operator::Between));
+        assert!(matches!(
+            fetched.predicates[1].operand,
+            Operand::Range {
+                min: StateValue::Integer(500),
+                max: StateValue::Integer(599),
+            }
+        ));
+
+        // Invalid regexes are rejected before anything is written
+        let err = db
+            .create_pattern(
+                p.id,
+                "bad",
+                &[pred(
+                    Operator::Matches,
+                    Operand::Literal(StateValue::String("(".to_string())),
+                )],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
             .await
-            .unwrap();
-        assert_eq!(rs.rule_ids.len(), 2);
-
-        let fetched = db.get_ruleset(p.id, rs.id as i64).await.unwrap();
-        assert_eq!(fetched.name, "ruleset1");
-
-        db.delete_ruleset(p.id, rs.id as i64).await.unwrap();
-        assert!(db.get_ruleset(p.id, rs.id as i64).await.is_err());
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
+        assert_eq!(db.list_patterns(p.id).await.unwrap().len(), 1);
     }
 
     #[tokio::test]
-    async fn test_pattern_crud() {
+    async fn test_pattern_time_literal_operands() {
         let db = test_db().await;
         let p = db.create_project("P1").await.unwrap();
-
+        let deadline = chrono::NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_milli_opt(10, 30, 0, 250)
+            .unwrap();
+        let pred = |state_key: &str, operator: Operator, value: StateValue| CreatePredicate {
+            source_name: "server".to_string(),
+            state_key: state_key.to_string(),
+            operator,
+            operand: Operand::Literal(value),
+            threshold: None,
+        };
         let pattern = db
             .create_pattern(
                 p.id,
-                "test_pattern",
+                "slow_after_deadline",
                 &[
-                    CreatePredicate {
-                        source_name: "server".to_string(),
-                        state_key: "status".to_string(),
-                        operator: Operator::Eq,
-                        operand: Operand::Literal(StateValue::String("running".to_string())),
-                    },
-                    CreatePredicate {
-                        source_name: "server".to_string(),
-                        state_key: "count".to_string(),
-                        operator: Operator::Gt,
-                        operand: Operand::Literal(StateValue::Integer(10)),
-                    },
+                    pred("took", Operator::Gt, StateValue::Duration(5_000)),
+                    pred("finished_at", Operator::Gt, StateValue::Timestamp(deadline)),
                 ],
+                None,
+                &PatternOutcome::default(),
+                None,
             )
             .await
             .unwrap();
-        assert_eq!(pattern.predicates.len(), 2);
-        assert_eq!(pattern.predicates[0].source_name, "server");
 
-        // Verify operand round-trip
-        if let Operand::Literal(StateValue::String(ref s)) = pattern.predicates[0].operand {
-            assert_eq!(s, "running");
-        } else {
-            panic!("expected literal string operand");
-        }
+        let stored: Vec<String> =
+            sqlx::query_scalar("SELECT operand_value FROM pattern_predicates ORDER BY order_index")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                r#"{"Duration":5000}"#,
+                r#"{"Timestamp":"2024-01-15T10:30:00.250"}"#
+            ]
+        );
 
         let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
-        assert_eq!(fetched.predicates.len(), 2);
-
-        db.delete_pattern(p.id, pattern.id as i64).await.unwrap();
-        assert!(db.get_pattern(p.id, pattern.id as i64).await.is_err());
+        assert!(matches!(
+            fetched.predicates[0].operand,
+            Operand::Literal(StateValue::Duration(5_000))
+        ));
+        assert!(matches!(
+            fetched.predicates[1].operand,
+            Operand::Literal(StateValue::Timestamp(t)) if t == deadline
+        ));
     }
 
     #[tokio::test]
-    async fn test_project_seeds_default_timestamp_templates() {
+    async fn test_pattern_expression_operand() {
         let db = test_db().await;
-        let p = db.create_project("Seeded").await.unwrap();
-        let tts = db.list_timestamp_templates(p.id).await.unwrap();
-        assert_eq!(tts.len(), 6);
-        let names: Vec<&str> = tts.iter().map(|t| t.name.as_str()).collect();
-        assert!(names.contains(&"ISO 8601"));
-        assert!(names.contains(&"Syslog (RFC 3164)"));
-        let syslog = tts.iter().find(|t| t.name == "Syslog (RFC 3164)").unwrap();
-        assert!(syslog.default_year.is_some());
+        let p = db.create_project("P1").await.unwrap();
+        let pred = |expr: &str| CreatePredicate {
+            source_name: "client".to_string(),
+            state_key: "retry_count".to_string(),
+            operator: Operator::Gt,
+            operand: Operand::Expr(expr.to_string()),
+            threshold: None,
+        };
+
+        let pattern = db
+            .create_pattern(
+                p.id,
+                "retries",
+                &[pred("server.max_retries + 2")],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert!(
+            matches!(&fetched.predicates[0].operand, Operand::Expr(e) if e == "server.max_retries + 2")
+        );
+
+        let err = db
+            .update_pattern(
+                p.id,
+                pattern.id as i64,
+                "retries",
+                &[pred("server.max_retries * 'x'")],
+                None,
+                &PatternOutcome::default(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(ref msg) if msg.contains("type error")));
     }
 
     #[tokio::test]
-    async fn test_pattern_with_state_ref_operand() {
+    async fn test_pattern_time_ref_operand() {
         let db = test_db().await;
         let p = db.create_project("P1").await.unwrap();
 
         let pattern = db
             .create_pattern(
                 p.id,
-                "cross_source",
+                "server_saw_it",
                 &[CreatePredicate {
                     source_name: "server".to_string(),
-                    state_key: "region".to_string(),
-                    operator: Operator::Eq,
-                    operand: Operand::StateRef {
+                    state_key: "request".to_string(),
+                    operator: Operator::SetWithin,
+                    operand: Operand::TimeRef {
                         source_name: "client".to_string(),
-                        state_key: "region".to_string(),
+                        state_key: "request".to_string(),
+                        within_ms: Some(2_000),
+                        skew_ms: -250,
                     },
+                    threshold: None,
                 }],
+                None,
+                &PatternOutcome::default(),
+                None,
             )
             .await
             .unwrap();
 
         let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
-        if let Operand::StateRef {
-            ref source_name,
-            ref state_key,
-        } = fetched.predicates[0].operand
-        {
-            assert_eq!(source_name, "client");
-            assert_eq!(state_key, "region");
-        } else {
-            panic!("expected state_ref operand");
-        }
+        assert!(matches!(
+            fetched.predicates[0].operator,
+            Operator::SetWithin
+        ));
+        assert!(matches!(
+            &fetched.predicates[0].operand,
+            Operand::TimeRef {
+                source_name,
+                within_ms: Some(2_000),
+                skew_ms: -250,
+                ..
+            } if source_name == "client"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pattern_outcome_round_trip() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let outcome = PatternOutcome {
+            severity: Some(Severity::Warning),
+            category: Some("matchmaking".to_string()),
+            diagnosis: Some("Server {server.name} full: {server.players}".to_string()),
+            remediation: None,
+        };
+
+        let pattern = db
+            .create_pattern(p.id, "server_full", &[], None, &outcome, None)
+            .await
+            .unwrap();
+        let fetched = db.get_pattern(p.id, pattern.id as i64).await.unwrap();
+        assert_eq!(fetched.outcome.severity, Some(Severity::Warning));
+        assert_eq!(fetched.outcome.category.as_deref(), Some("matchmaking"));
+        assert_eq!(fetched.outcome.diagnosis, outcome.diagnosis);
+        assert_eq!(fetched.outcome.remediation, None);
+
+        let bad = PatternOutcome {
+            diagnosis: Some("Server {server.name full".to_string()),
+            ..outcome
+        };
+        let err = db
+            .update_pattern(
+                p.id,
+                pattern.id as i64,
+                "server_full",
+                &[],
+                None,
+                &bad,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
     }
 
     #[tokio::test]
@@ -1958,10 +4014,11 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .unwrap();
-        db.create_rule(p.id, "r1", &MatchMode::Any, &[], &[])
+        db.create_rule(p.id, "r1", &MatchMode::Any, &[], &[], None, None, None)
             .await
             .unwrap();
 
@@ -2004,6 +4061,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -2014,6 +4072,7 @@ mod tests {
                 &MatchMode::Any,
                 &[CreateMatchRule {
                     pattern: "ERROR".to_string(),
+                    negate: false,
                 }],
                 &[CreateExtractionRule {
                     extraction_type: ExtractionType::Static,
@@ -2021,25 +4080,63 @@ mod tests {
                     pattern: None,
                     static_value: Some("error".to_string()),
                     mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
                 }],
+                None,
+                None,
+                None,
             )
             .await
             .unwrap();
-        db.create_ruleset(src.id, "main_rules", st.id as i64, &[rule.id as i64])
-            .await
-            .unwrap();
+        db.create_ruleset(
+            src.id,
+            "main_rules",
+            st.id as i64,
+            &[],
+            &["server".to_string()],
+            &[rule.id as i64],
+            &[rule.id as i64],
+        )
+        .await
+        .unwrap();
         db.create_pattern(
             src.id,
             "failure_pattern",
-            &[CreatePredicate {
-                source_name: "server".to_string(),
-                state_key: "status".to_string(),
-                operator: Operator::Eq,
-                operand: Operand::Literal(StateValue::String("error".to_string())),
-            }],
+            &[
+                CreatePredicate {
+                    source_name: "server".to_string(),
+                    state_key: "status".to_string(),
+                    operator: Operator::Eq,
+                    operand: Operand::Literal(StateValue::String("error".to_string())),
+                    threshold: None,
+                },
+                CreatePredicate {
+                    source_name: "server".to_string(),
+                    state_key: "".to_string(),
+                    operator: Operator::Exists,
+                    operand: Operand::Literal(StateValue::Bool(true)),
+                    threshold: Some(Threshold {
+                        count: 5,
+                        window_ms: 60_000,
+                        rule_id: Some(rule.id),
+                        group_by: Some("client".to_string()),
+                    }),
+                },
+            ],
+            None,
+            &PatternOutcome::default(),
+            None,
         )
         .await
         .unwrap();
+        db.create_derived_key(src.id, "server", "wait", "done_ts - start_ts")
+            .await
+            .unwrap();
+        db.create_grok_pattern(src.id, "PORT", r"\d{1,5}")
+            .await
+            .unwrap();
 
         // Export source project data
         let data = db.load_project_data(src.id).await.unwrap();
@@ -2050,6 +4147,9 @@ mod tests {
             rules: data.rules,
             rulesets: data.rulesets,
             patterns: data.patterns,
+            derived_keys: data.derived_keys,
+            grok_patterns: data.grok_patterns,
+            plugins: data.plugins,
         };
 
         // Import into a fresh target project
@@ -2062,6 +4162,8 @@ mod tests {
         assert_eq!(result.rules, 1);
         assert_eq!(result.rulesets, 2);
         assert_eq!(result.patterns, 1);
+        assert_eq!(result.derived_keys, 1);
+        assert_eq!(result.grok_patterns, 1);
 
         // Verify entities exist in target project
         let target_data = db.load_project_data(target.id).await.unwrap();
@@ -2072,6 +4174,8 @@ mod tests {
         // 1 auto-created default (from create_template) + 2 imported
         assert_eq!(target_data.rulesets.len(), 3);
         assert_eq!(target_data.patterns.len(), 1);
+        assert_eq!(target_data.derived_keys[0].expression, "done_ts - start_ts");
+        assert_eq!(target_data.grok_patterns[0].name, "PORT");
 
         // Find the imported "main_rules" ruleset (not the auto-created defaults)
         let imported_rs = target_data
@@ -2085,6 +4189,18 @@ mod tests {
         // Verify ruleset's rule_ids point to the new rule
         let imported_rule = &target_data.rules[0];
         assert_eq!(imported_rs.rule_ids, vec![imported_rule.id]);
+        assert_eq!(imported_rs.stop_rule_ids, vec![imported_rule.id]);
+        assert_eq!(imported_rs.source_names, vec!["server".to_string()]);
+
+        // Threshold steps survive the round-trip with their rule_id remapped
+        let threshold = target_data.patterns[0].predicates[1]
+            .threshold
+            .as_ref()
+            .expect("threshold step should be imported");
+        assert_eq!(threshold.count, 5);
+        assert_eq!(threshold.window_ms, 60_000);
+        assert_eq!(threshold.rule_id, Some(imported_rule.id));
+        assert_eq!(threshold.group_by.as_deref(), Some("client"));
 
         // Verify imported template's timestamp_template_id points to a valid TT in target
         let imported_tt = target_data
@@ -2097,6 +4213,58 @@ mod tests {
         assert_eq!(imported_tt.default_year, Some(2025));
     }
 
+    #[tokio::test]
+    async fn test_import_grok_patterns_with_forward_reference() {
+        use crate::routes::import_export::ProjectExport;
+
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let grok = |id, name: &str, pattern: &str| GrokPattern {
+            id,
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        };
+        let export = |grok_patterns| ProjectExport {
+            version: 1,
+            timestamp_templates: vec![],
+            source_templates: vec![],
+            rules: vec![],
+            rulesets: vec![],
+            patterns: vec![],
+            derived_keys: vec![DerivedKey {
+                id: 1,
+                source_name: "server".to_string(),
+                state_key: "wait".to_string(),
+                expression: "done_ts - start_ts".to_string(),
+            }],
+            grok_patterns,
+            plugins: vec![],
+        };
+
+        // ENDPOINT was edited to use PORT after PORT was added
+        let result = db
+            .import_project_config(
+                p.id,
+                &export(vec![
+                    grok(1, "ENDPOINT", "%{IP}:%{PORT}"),
+                    grok(2, "PORT", r"\d{1,5}"),
+                ]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.grok_patterns, 2);
+        assert_eq!(db.list_grok_patterns(p.id).await.unwrap().len(), 2);
+
+        // A broken library is rejected before anything is imported
+        let q = db.create_project("P2").await.unwrap();
+        let err = db
+            .import_project_config(q.id, &export(vec![grok(1, "ENDPOINT", "%{NOPE}")]))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
+        assert!(db.list_derived_keys(q.id).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_import_empty_config() {
         use crate::routes::import_export::ProjectExport;
@@ -2111,6 +4279,9 @@ mod tests {
             rules: vec![],
             rulesets: vec![],
             patterns: vec![],
+            derived_keys: vec![],
+            grok_patterns: vec![],
+            plugins: vec![],
         };
 
         let result = db.import_project_config(p.id, &export).await.unwrap();
@@ -2121,6 +4292,223 @@ mod tests {
         assert_eq!(result.patterns, 0);
     }
 
+    #[tokio::test]
+    async fn test_pattern_parent_links_and_cycles() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let none = PatternOutcome::default();
+
+        let root = db
+            .create_pattern(p.id, "matchmaking_failed", &[], None, &none, None)
+            .await
+            .unwrap();
+        let link = |id: u64| PatternParent {
+            pattern_id: id,
+            window_ms: Some(30_000),
+        };
+        let child = db
+            .create_pattern(p.id, "server_full", &[], None, &none, Some(&link(root.id)))
+            .await
+            .unwrap();
+        let fetched = db.get_pattern(p.id, child.id as i64).await.unwrap();
+        let parent = fetched.parent.unwrap();
+        assert_eq!(parent.pattern_id, root.id);
+        assert_eq!(parent.window_ms, Some(30_000));
+
+        // root -> child -> root is a cycle, as is a self link
+        for parent_id in [child.id, root.id] {
+            let err = db
+                .update_pattern(
+                    p.id,
+                    root.id as i64,
+                    "matchmaking_failed",
+                    &[],
+                    None,
+                    &none,
+                    Some(&link(parent_id)),
+                )
+                .await
+                .unwrap_err();
+            assert!(matches!(err, DbError::InvalidData(ref msg) if msg.contains("cycle")));
+        }
+        let err = db
+            .create_pattern(p.id, "orphan", &[], None, &none, Some(&link(9_999)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
+        let too_long = PatternParent {
+            pattern_id: root.id,
+            window_ms: Some(u64::MAX),
+        };
+        let err = db
+            .create_pattern(p.id, "late", &[], None, &none, Some(&too_long))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(ref msg) if msg.contains("parent window")));
+
+        // Deleting the parent detaches the child
+        db.delete_pattern(p.id, root.id as i64).await.unwrap();
+        let fetched = db.get_pattern(p.id, child.id as i64).await.unwrap();
+        assert!(fetched.parent.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_remaps_pattern_parents() {
+        use crate::routes::import_export::ProjectExport;
+
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+        let pattern = |id: u64, name: &str, parent: Option<u64>| Pattern {
+            id,
+            name: name.to_string(),
+            predicates: vec![],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: parent.map(|pattern_id| PatternParent {
+                pattern_id,
+                window_ms: None,
+            }),
+        };
+        // The child comes before its parent in the export
+        let export = ProjectExport {
+            version: 1,
+            timestamp_templates: vec![],
+            source_templates: vec![],
+            rules: vec![],
+            rulesets: vec![],
+            patterns: vec![
+                pattern(51, "wrong_region", Some(50)),
+                pattern(50, "matchmaking_failed", None),
+            ],
+            derived_keys: vec![],
+            grok_patterns: vec![],
+            plugins: vec![],
+        };
+        db.import_project_config(p.id, &export).await.unwrap();
+
+        let patterns = db.list_patterns(p.id).await.unwrap();
+        let by_name = |name: &str| patterns.iter().find(|p| p.name == name).unwrap();
+        assert_eq!(
+            by_name("wrong_region").parent.as_ref().unwrap().pattern_id,
+            by_name("matchmaking_failed").id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_derived_key_crud() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let rate = db
+            .create_derived_key(p.id, "server", "error_rate", "errors / requests")
+            .await
+            .unwrap();
+        assert_eq!(rate.state_key, "error_rate");
+
+        let updated = db
+            .update_derived_key(
+                p.id,
+                rate.id as i64,
+                "server",
+                "error_rate",
+                "errors * 100 / requests",
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.expression, "errors * 100 / requests");
+        assert_eq!(db.list_derived_keys(p.id).await.unwrap().len(), 1);
+
+        db.delete_derived_key(p.id, rate.id as i64).await.unwrap();
+        assert!(db.get_derived_key(p.id, rate.id as i64).await.is_err());
+        assert!(matches!(
+            db.delete_derived_key(p.id, rate.id as i64).await,
+            Err(DbError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_derived_key_rejects_cycles_and_bad_expressions() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let a = db
+            .create_derived_key(p.id, "server", "a", "b + 1")
+            .await
+            .unwrap();
+        let err = db
+            .create_derived_key(p.id, "server", "b", "a * 2")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(ref m) if m.contains("depends on itself")));
+
+        let err = db
+            .create_derived_key(p.id, "server", "a", "c")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(ref m) if m.contains("defined twice")));
+
+        let err = db
+            .update_derived_key(p.id, a.id as i64, "server", "a", "b +")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidData(_)));
+
+        // Replacing a key's own expression is not a duplicate
+        db.update_derived_key(p.id, a.id as i64, "server", "a", "b + 2")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_grok_pattern_crud_and_validation() {
+        let db = test_db().await;
+        let p = db.create_project("P1").await.unwrap();
+
+        let port = db
+            .create_grok_pattern(p.id, "PORT", r"\d{1,5}")
+            .await
+            .unwrap();
+        let endpoint = db
+            .create_grok_pattern(p.id, "ENDPOINT", "%{IP}:%{PORT}")
+            .await
+            .unwrap();
+        assert_eq!(db.list_grok_patterns(p.id).await.unwrap().len(), 2);
+
+        for (name, pattern) in [
+            ("PORT", r"\d+"),
+            ("bad name", "x"),
+            ("LOOP", "%{LOOP}"),
+            ("MISSING", "%{NOPE}"),
+            ("BROKEN", "("),
+        ] {
+            let err = db
+                .create_grok_pattern(p.id, name, pattern)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, DbError::InvalidData(_)), "{name}: {err}");
+        }
+
+        let updated = db
+            .update_grok_pattern(p.id, port.id as i64, "PORT", r"\d{2,5}")
+            .await
+            .unwrap();
+        assert_eq!(updated.pattern, r"\d{2,5}");
+
+        // ENDPOINT still refers to PORT
+        assert!(matches!(
+            db.delete_grok_pattern(p.id, port.id as i64).await,
+            Err(DbError::InvalidData(_))
+        ));
+        db.update_grok_pattern(p.id, endpoint.id as i64, "ENDPOINT", r"%{IP}:\d+")
+            .await
+            .unwrap();
+        db.delete_grok_pattern(p.id, port.id as i64).await.unwrap();
+        assert!(matches!(
+            db.get_grok_pattern(p.id, port.id as i64).await,
+            Err(DbError::NotFound)
+        ));
+    }
+
     #[tokio::test]
     async fn test_template_auto_selection_fields() {
         let db = test_db().await;
@@ -2142,6 +4530,7 @@ mod tests {
                 None,
                 Some(r"nginx.*\.log$"),
                 Some(r"^\d+\.\d+\.\d+\.\d+ -"),
+                None,
             )
             .await
             .unwrap();
@@ -2177,6 +4566,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -49,6 +49,9 @@ async fn main() {
         .merge(routes::rules::router())
         .merge(routes::rulesets::router())
         .merge(routes::patterns::router())
+        .merge(routes::derived_keys::router())
+        .merge(routes::grok_patterns::router())
+        .merge(routes::plugins::router())
         .merge(routes::import_export::router())
         .merge(routes::analysis::router())
         .merge(routes::clustering::router())
@@ -4,11 +4,13 @@ use axum::response::Response;
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use chrono::NaiveDateTime;
+use logium_core::log_source::{CompressedFileSource, MemorySource};
+use logium_core::model::{LogRule, Pattern, Ruleset, Source};
 use serde::{Deserialize, Serialize};
 
 use super::{ApiError, ApiResult};
 use crate::AppState;
-use crate::db::DbError;
+use crate::db::{DbError, ProjectData, validate_outcome, validate_span};
 
 #[derive(Deserialize, Default)]
 pub struct TimeRangeQuery {
@@ -30,9 +32,38 @@ impl TimeRangeQuery {
     }
 }
 
+/// Optional analysis outputs requested via query string.
+#[derive(Deserialize, Default)]
+struct AnalyzeOptionsQuery {
+    #[serde(default)]
+    near_misses: bool,
+    #[serde(default)]
+    profile: bool,
+    #[serde(default)]
+    coverage: bool,
+}
+
+impl AnalyzeOptionsQuery {
+    fn to_options(&self) -> logium_core::engine::AnalysisOptions {
+        logium_core::engine::AnalysisOptions {
+            near_misses: self.near_misses,
+            profile: self.profile,
+            coverage: self.coverage,
+        }
+    }
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/api/projects/{project_id}/analyze", post(analyze))
+        .route(
+            "/api/projects/{project_id}/analyze/text",
+            post(analyze_text),
+        )
+        .route(
+            "/api/projects/{project_id}/patterns/{id}/explain",
+            get(explain_pattern),
+        )
         .route("/api/projects/{project_id}/analyze/ws", get(analyze_ws))
         .route(
             "/api/projects/{project_id}/analyze/export",
@@ -52,22 +83,126 @@ async fn analyze(
     State(state): State<AppState>,
     Path(project_id): Path<i64>,
     Query(time_query): Query<TimeRangeQuery>,
+    Query(options_query): Query<AnalyzeOptionsQuery>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let time_range = time_query
         .to_time_range()
         .map_err(|e| ApiError::from(DbError::InvalidData(e)))?;
+    let options = options_query.to_options();
 
     let data = state.db.load_project_data(project_id).await?;
 
     let result = tokio::task::spawn_blocking(move || {
-        logium_core::engine::analyze(
+        logium_core::engine::analyze_with_options(
+            &data.sources,
+            &CompressedFileSource,
+            &data.templates,
+            &data.timestamp_templates,
+            &data.rules,
+            &data.rulesets,
+            &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
+            &time_range,
+            &options,
+        )
+    })
+    .await
+    .map_err(|e| ApiError::from(DbError::InvalidData(format!("task join error: {e}"))))?
+    .map_err(|e| ApiError::from(DbError::InvalidData(format!("analysis error: {e}"))))?;
+
+    Ok(Json(serde_json::to_value(result).unwrap()))
+}
+
+/// Explain why a single pattern did or did not fire.
+async fn explain_pattern(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+    Query(time_query): Query<TimeRangeQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let time_range = time_query
+        .to_time_range()
+        .map_err(|e| ApiError::from(DbError::InvalidData(e)))?;
+
+    let pattern = state.db.get_pattern(project_id, id).await?;
+    let data = state.db.load_project_data(project_id).await?;
+
+    let explanation = tokio::task::spawn_blocking(move || {
+        logium_core::engine::explain_pattern(
+            &data.sources,
+            &CompressedFileSource,
+            &data.templates,
+            &data.timestamp_templates,
+            &data.rules,
+            &data.rulesets,
+            &pattern,
+            &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
+            &time_range,
+        )
+    })
+    .await
+    .map_err(|e| ApiError::from(DbError::InvalidData(format!("task join error: {e}"))))?
+    .map_err(|e| ApiError::from(DbError::InvalidData(format!("analysis error: {e}"))))?;
+
+    Ok(Json(serde_json::to_value(explanation).unwrap()))
+}
+
+/// A source given as pasted log text rather than a file.
+#[derive(Deserialize)]
+struct TextSource {
+    name: String,
+    template_id: u64,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AnalyzeTextRequest {
+    sources: Vec<TextSource>,
+    /// Draft rules. Each replaces the project rule with its id, or is added
+    /// and runs on every pasted source after the project's rules.
+    #[serde(default)]
+    rules: Vec<LogRule>,
+    /// Draft patterns, replacing or added to the project's by id.
+    #[serde(default)]
+    patterns: Vec<Pattern>,
+}
+
+/// Run the engine on pasted log text instead of the project's sources, with
+/// the project's configuration and any draft rules and patterns, so a dry run
+/// takes the same path as a real analysis.
+async fn analyze_text(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Query(time_query): Query<TimeRangeQuery>,
+    Query(options_query): Query<AnalyzeOptionsQuery>,
+    Json(body): Json<AnalyzeTextRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let time_range = time_query
+        .to_time_range()
+        .map_err(|e| ApiError::from(DbError::InvalidData(e)))?;
+    let options = options_query.to_options();
+
+    let mut data = state.db.load_project_data(project_id).await?;
+    let logs = apply_text_request(&mut data, body)?;
+
+    let result = tokio::task::spawn_blocking(move || {
+        logium_core::engine::analyze_with_options(
             &data.sources,
+            &logs,
             &data.templates,
             &data.timestamp_templates,
             &data.rules,
             &data.rulesets,
             &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
             &time_range,
+            &options,
         )
     })
     .await
@@ -77,6 +212,114 @@ async fn analyze(
     Ok(Json(serde_json::to_value(result).unwrap()))
 }
 
+/// Swap the project's sources for the pasted ones, numbered from 1, and merge
+/// in the draft rules and patterns once they pass the checks saved ones do.
+/// Returns the pasted logs by source id.
+fn apply_text_request(
+    data: &mut ProjectData,
+    body: AnalyzeTextRequest,
+) -> Result<MemorySource, DbError> {
+    if body.sources.is_empty() {
+        return Err(DbError::InvalidData(
+            "at least one source is required".to_string(),
+        ));
+    }
+    let mut logs = MemorySource::new();
+    let mut sources = Vec::new();
+    for (i, text_source) in body.sources.into_iter().enumerate() {
+        if !data
+            .templates
+            .iter()
+            .any(|t| t.id == text_source.template_id)
+        {
+            return Err(DbError::InvalidData(format!(
+                "unknown template {}",
+                text_source.template_id
+            )));
+        }
+        let id = i as u64 + 1;
+        logs.insert(id, text_source.text.into_bytes());
+        sources.push(Source {
+            id,
+            name: text_source.name,
+            template_id: text_source.template_id,
+            file_path: String::new(),
+        });
+    }
+
+    for rule in &body.rules {
+        validate_draft_rule(rule)?;
+    }
+    for pattern in &body.patterns {
+        validate_draft_pattern(pattern)?;
+    }
+
+    let mut added_rule_ids = Vec::new();
+    for rule in body.rules {
+        match data.rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => *existing = rule,
+            None => {
+                added_rule_ids.push(rule.id);
+                data.rules.push(rule);
+            }
+        }
+    }
+    if !added_rule_ids.is_empty() {
+        let mut template_ids: Vec<u64> = sources.iter().map(|s| s.template_id).collect();
+        template_ids.sort_unstable();
+        template_ids.dedup();
+        data.rulesets.push(Ruleset {
+            id: 0,
+            name: "drafts".to_string(),
+            template_id: template_ids[0],
+            extra_template_ids: template_ids[1..].to_vec(),
+            source_names: Vec::new(),
+            rule_ids: added_rule_ids,
+            stop_rule_ids: Vec::new(),
+        });
+    }
+    for pattern in body.patterns {
+        match data.patterns.iter_mut().find(|p| p.id == pattern.id) {
+            Some(existing) => *existing = pattern,
+            None => data.patterns.push(pattern),
+        }
+    }
+
+    data.sources = sources;
+    Ok(logs)
+}
+
+/// Hold a draft rule to the checks a saved rule passes.
+fn validate_draft_rule(rule: &LogRule) -> Result<(), DbError> {
+    let invalid = |e: logium_core::engine::AnalysisError| {
+        DbError::InvalidData(format!("rule {}: {e}", rule.id))
+    };
+    for er in &rule.extraction_rules {
+        logium_core::engine::validate_extraction_rule(er).map_err(invalid)?;
+    }
+    if let Some(guard) = &rule.guard {
+        logium_core::engine::validate_guard(guard).map_err(invalid)?;
+    }
+    if let Some(script) = &rule.script {
+        logium_core::script::validate_script(script)
+            .map_err(|e| DbError::InvalidData(format!("rule {}: invalid script: {e}", rule.id)))?;
+    }
+    Ok(())
+}
+
+/// Hold a draft pattern to the checks a saved pattern passes.
+fn validate_draft_pattern(pattern: &Pattern) -> Result<(), DbError> {
+    for pred in &pattern.predicates {
+        logium_core::engine::validate_predicate(pred)
+            .map_err(|e| DbError::InvalidData(format!("pattern '{}': {e}", pattern.name)))?;
+    }
+    validate_span("cooldown", pattern.cooldown_ms)?;
+    if let Some(parent) = &pattern.parent {
+        validate_span("parent window", parent.window_ms)?;
+    }
+    validate_outcome(&pattern.outcome)
+}
+
 #[derive(Deserialize, Default)]
 struct ExportQuery {
     format: Option<String>,
@@ -144,14 +387,19 @@ async fn export_analysis(
     let data = state.db.load_project_data(project_id).await?;
 
     let (body, content_type, filename) = tokio::task::spawn_blocking(move || {
-        let result = logium_core::engine::analyze(
+        let result = logium_core::engine::analyze_with_options(
             &data.sources,
+            &CompressedFileSource,
             &data.templates,
             &data.timestamp_templates,
             &data.rules,
             &data.rulesets,
             &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
             &time_range,
+            &logium_core::engine::AnalysisOptions::default(),
         )
         .map_err(|e| e.to_string())?;
 
@@ -200,12 +448,16 @@ async fn analyze_ws(
     State(state): State<AppState>,
     Path(project_id): Path<i64>,
     Query(time_query): Query<TimeRangeQuery>,
+    Query(options_query): Query<AnalyzeOptionsQuery>,
     ws: WebSocketUpgrade,
 ) -> Result<Response, ApiError> {
     let time_range = time_query
         .to_time_range()
         .map_err(|e| ApiError::from(DbError::InvalidData(e)))?;
-    Ok(ws.on_upgrade(move |socket| handle_analysis_ws(socket, state, project_id, time_range)))
+    let options = options_query.to_options();
+    Ok(ws.on_upgrade(move |socket| {
+        handle_analysis_ws(socket, state, project_id, time_range, options)
+    }))
 }
 
 async fn handle_analysis_ws(
@@ -213,6 +465,7 @@ async fn handle_analysis_ws(
     state: AppState,
     project_id: i64,
     time_range: logium_core::engine::TimeRange,
+    options: logium_core::engine::AnalysisOptions,
 ) {
     let data = match state.db.load_project_data(project_id).await {
         Ok(d) => d,
@@ -239,13 +492,18 @@ async fn handle_analysis_ws(
     tokio::task::spawn_blocking(move || {
         let _ = logium_core::engine::analyze_streaming(
             &data.sources,
+            &CompressedFileSource,
             &data.templates,
             &data.timestamp_templates,
             &data.rules,
             &data.rulesets,
             &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
             std_tx,
             &time_range,
+            &options,
         );
     });
 
@@ -484,6 +742,175 @@ fn is_regex_special(c: char) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::{CreateExtractionRule, CreateMatchRule, Database};
+    use logium_core::model::*;
+
+    #[tokio::test]
+    async fn test_analyze_text_with_draft_rule() {
+        let db = Database::new("sqlite::memory:").await.unwrap();
+        let project = db.create_project("DryRun").await.unwrap();
+        let tt = db
+            .create_timestamp_template(project.id, "ts", "%Y-%m-%d %H:%M:%S", None, None)
+            .await
+            .unwrap();
+        let tmpl = db
+            .create_template(
+                project.id,
+                "tmpl",
+                tt.id as i64,
+                "\n",
+                Some(r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2} (.+)$"),
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let rule = db
+            .create_rule(
+                project.id,
+                "errors",
+                &MatchMode::Any,
+                &[CreateMatchRule {
+                    pattern: "ERROR".to_string(),
+                    negate: false,
+                }],
+                &[CreateExtractionRule {
+                    extraction_type: ExtractionType::Static,
+                    state_key: "level".to_string(),
+                    pattern: None,
+                    static_value: Some("error".to_string()),
+                    mode: ExtractionMode::Replace,
+                    target_type: ValueType::Auto,
+                    transforms: Vec::new(),
+                    ttl_ms: None,
+                }],
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        db.create_ruleset(
+            project.id,
+            "rs",
+            tmpl.id as i64,
+            &[],
+            &[],
+            &[rule.id as i64],
+            &[],
+        )
+        .await
+        .unwrap();
+        let mut data = db.load_project_data(project.id).await.unwrap();
+
+        // Rust regex syntax a JavaScript regex would reject
+        let draft = LogRule {
+            id: 1000,
+            name: "slow".into(),
+            match_mode: MatchMode::Any,
+            match_rules: vec![MatchRule {
+                id: 0,
+                pattern: r"(?i)timeout \d+\z".into(),
+                negate: false,
+            }],
+            extraction_rules: Vec::new(),
+            guard: None,
+            script: None,
+            plugin_id: None,
+        };
+        let body = AnalyzeTextRequest {
+            sources: vec![TextSource {
+                name: "pasted".into(),
+                template_id: tmpl.id,
+                text: "2024-01-01 00:00:01 ERROR Timeout 100\n\
+                       2024-01-01 00:00:02 INFO started\n"
+                    .into(),
+            }],
+            rules: vec![draft],
+            patterns: Vec::new(),
+        };
+        let logs = apply_text_request(&mut data, body).unwrap();
+        let result = logium_core::engine::analyze_with_options(
+            &data.sources,
+            &logs,
+            &data.templates,
+            &data.timestamp_templates,
+            &data.rules,
+            &data.rulesets,
+            &data.patterns,
+            &data.derived_keys,
+            &data.grok_patterns,
+            &data.plugins,
+            &logium_core::engine::TimeRange::default(),
+            &logium_core::engine::AnalysisOptions::default(),
+        )
+        .unwrap();
+
+        let matched: Vec<(u64, u64)> = result
+            .rule_matches
+            .iter()
+            .map(|rm| (rm.rule_id, rm.log_line.line_number))
+            .collect();
+        assert_eq!(matched, vec![(rule.id, 1), (1000, 1)]);
+        assert_eq!(result.state_changes.len(), 1);
+        assert_eq!(result.state_changes[0].source_name, "pasted");
+
+        let unknown = AnalyzeTextRequest {
+            sources: vec![TextSource {
+                name: "pasted".into(),
+                template_id: 12345,
+                text: String::new(),
+            }],
+            rules: Vec::new(),
+            patterns: Vec::new(),
+        };
+        assert!(matches!(
+            apply_text_request(&mut data, unknown),
+            Err(DbError::InvalidData(_))
+        ));
+
+        // Draft patterns are checked like saved ones
+        let draft_pattern = |operator, operand| Pattern {
+            id: 2000,
+            name: "draft".into(),
+            predicates: vec![PatternPredicate {
+                source_name: "pasted".into(),
+                state_key: "level".into(),
+                operator,
+                operand,
+                threshold: None,
+            }],
+            cooldown_ms: None,
+            outcome: PatternOutcome::default(),
+            parent: None,
+        };
+        let with_pattern = |pattern| AnalyzeTextRequest {
+            sources: vec![TextSource {
+                name: "pasted".into(),
+                template_id: tmpl.id,
+                text: String::new(),
+            }],
+            rules: Vec::new(),
+            patterns: vec![pattern],
+        };
+        let older_than_min = draft_pattern(
+            Operator::OlderThan,
+            Operand::Literal(StateValue::Integer(i64::MIN)),
+        );
+        let in_literal = draft_pattern(Operator::In, Operand::Literal(StateValue::Integer(1)));
+        let mut long_cooldown =
+            draft_pattern(Operator::Exists, Operand::Literal(StateValue::Bool(true)));
+        long_cooldown.cooldown_ms = Some(u64::MAX);
+        for pattern in [older_than_min, in_literal, long_cooldown] {
+            assert!(matches!(
+                apply_text_request(&mut data, with_pattern(pattern)),
+                Err(DbError::InvalidData(_))
+            ));
+        }
+    }
 
     #[test]
     fn test_build_suggested_pattern_numbers() {
@@ -553,4 +980,23 @@ mod tests {
         assert!(tr.start.is_none());
         assert!(tr.end.is_none());
     }
+
+    #[test]
+    fn test_analyze_options_query() {
+        let uri: axum::http::Uri = "/analyze?start=2024-01-01T00:00:00&near_misses=true"
+            .parse()
+            .unwrap();
+        let Query(q) = Query::<AnalyzeOptionsQuery>::try_from_uri(&uri).unwrap();
+        assert!(q.to_options().near_misses);
+        assert!(!q.to_options().profile);
+
+        let uri: axum::http::Uri = "/analyze?profile=true&coverage=true".parse().unwrap();
+        let Query(q) = Query::<AnalyzeOptionsQuery>::try_from_uri(&uri).unwrap();
+        assert!(q.to_options().profile);
+        assert!(q.to_options().coverage);
+
+        let uri: axum::http::Uri = "/analyze".parse().unwrap();
+        let Query(q) = Query::<AnalyzeOptionsQuery>::try_from_uri(&uri).unwrap();
+        assert!(!q.to_options().near_misses);
+    }
 }
@@ -1,6 +1,7 @@
 use axum::extract::{Path, Query, State};
 use axum::routing::post;
 use axum::{Json, Router};
+use logium_core::log_source::CompressedFileSource;
 
 use super::analysis::TimeRangeQuery;
 use super::{ApiError, ApiResult};
@@ -25,8 +26,10 @@ async fn cluster(
     let result = tokio::task::spawn_blocking(move || {
         logium_core::engine::cluster_logs(
             &data.sources,
+            &CompressedFileSource,
             &data.templates,
             &data.timestamp_templates,
+            &data.plugins,
             &time_range,
         )
     })
@@ -62,6 +65,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -91,8 +95,10 @@ mod tests {
         let data = db.load_project_data(project.id).await.unwrap();
         let result = logium_core::engine::cluster_logs(
             &data.sources,
+            &logium_core::log_source::FileSource,
             &data.templates,
             &data.timestamp_templates,
+            &data.plugins,
             &logium_core::engine::TimeRange::default(),
         )
         .unwrap();
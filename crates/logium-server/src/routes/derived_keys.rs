@@ -0,0 +1,88 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+
+use logium_core::model::DerivedKey;
+
+use super::ApiResult;
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/projects/{project_id}/derived-keys",
+            get(list).post(create),
+        )
+        .route(
+            "/api/projects/{project_id}/derived-keys/{id}",
+            get(get_one).put(update).delete(remove),
+        )
+}
+
+#[derive(Deserialize)]
+struct CreateDerivedKey {
+    source_name: String,
+    state_key: String,
+    expression: String,
+}
+
+async fn list(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> ApiResult<Json<Vec<DerivedKey>>> {
+    let keys = state.db.list_derived_keys(project_id).await?;
+    Ok(Json(keys))
+}
+
+async fn create(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Json(body): Json<CreateDerivedKey>,
+) -> ApiResult<(StatusCode, Json<DerivedKey>)> {
+    let key = state
+        .db
+        .create_derived_key(
+            project_id,
+            &body.source_name,
+            &body.state_key,
+            &body.expression,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+async fn get_one(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<Json<DerivedKey>> {
+    let key = state.db.get_derived_key(project_id, id).await?;
+    Ok(Json(key))
+}
+
+async fn update(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+    Json(body): Json<CreateDerivedKey>,
+) -> ApiResult<Json<DerivedKey>> {
+    let key = state
+        .db
+        .update_derived_key(
+            project_id,
+            id,
+            &body.source_name,
+            &body.state_key,
+            &body.expression,
+        )
+        .await?;
+    Ok(Json(key))
+}
+
+async fn remove(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<StatusCode> {
+    state.db.delete_derived_key(project_id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,93 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use logium_core::model::GrokPattern;
+
+use super::ApiResult;
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/grok-patterns/builtin", get(builtin))
+        .route(
+            "/api/projects/{project_id}/grok-patterns",
+            get(list).post(create),
+        )
+        .route(
+            "/api/projects/{project_id}/grok-patterns/{id}",
+            get(get_one).put(update).delete(remove),
+        )
+}
+
+#[derive(Deserialize)]
+struct CreateGrokPattern {
+    name: String,
+    pattern: String,
+}
+
+#[derive(Serialize)]
+struct BuiltinPattern {
+    name: &'static str,
+    pattern: &'static str,
+}
+
+/// The built-in set every project can reference.
+async fn builtin() -> Json<Vec<BuiltinPattern>> {
+    Json(
+        logium_core::grok::BUILTIN_PATTERNS
+            .iter()
+            .map(|&(name, pattern)| BuiltinPattern { name, pattern })
+            .collect(),
+    )
+}
+
+async fn list(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> ApiResult<Json<Vec<GrokPattern>>> {
+    let patterns = state.db.list_grok_patterns(project_id).await?;
+    Ok(Json(patterns))
+}
+
+async fn create(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Json(body): Json<CreateGrokPattern>,
+) -> ApiResult<(StatusCode, Json<GrokPattern>)> {
+    let pattern = state
+        .db
+        .create_grok_pattern(project_id, &body.name, &body.pattern)
+        .await?;
+    Ok((StatusCode::CREATED, Json(pattern)))
+}
+
+async fn get_one(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<Json<GrokPattern>> {
+    let pattern = state.db.get_grok_pattern(project_id, id).await?;
+    Ok(Json(pattern))
+}
+
+async fn update(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+    Json(body): Json<CreateGrokPattern>,
+) -> ApiResult<Json<GrokPattern>> {
+    let pattern = state
+        .db
+        .update_grok_pattern(project_id, id, &body.name, &body.pattern)
+        .await?;
+    Ok(Json(pattern))
+}
+
+async fn remove(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<StatusCode> {
+    state.db.delete_grok_pattern(project_id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -20,6 +20,12 @@ pub struct ProjectExport {
     pub rules: Vec<LogRule>,
     pub rulesets: Vec<Ruleset>,
     pub patterns: Vec<Pattern>,
+    #[serde(default)]
+    pub derived_keys: Vec<DerivedKey>,
+    #[serde(default)]
+    pub grok_patterns: Vec<GrokPattern>,
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,6 +35,9 @@ pub struct ImportResult {
     pub rules: usize,
     pub rulesets: usize,
     pub patterns: usize,
+    pub derived_keys: usize,
+    pub grok_patterns: usize,
+    pub plugins: usize,
 }
 
 pub fn router() -> Router<AppState> {
@@ -55,6 +64,9 @@ async fn export(
         rules: data.rules,
         rulesets: data.rulesets,
         patterns: data.patterns,
+        derived_keys: data.derived_keys,
+        grok_patterns: data.grok_patterns,
+        plugins: data.plugins,
     };
 
     let json = serde_json::to_string_pretty(&export)
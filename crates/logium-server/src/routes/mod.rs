@@ -1,7 +1,10 @@
 pub mod analysis;
 pub mod clustering;
+pub mod derived_keys;
+pub mod grok_patterns;
 pub mod import_export;
 pub mod patterns;
+pub mod plugins;
 pub mod projects;
 pub mod rules;
 pub mod rulesets;
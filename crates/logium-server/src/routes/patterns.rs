@@ -4,6 +4,8 @@ use axum::routing::get;
 use axum::{Json, Router};
 use serde::Deserialize;
 
+use logium_core::model::{PatternOutcome, PatternParent};
+
 use super::ApiResult;
 use crate::AppState;
 use crate::db::CreatePredicate;
@@ -24,6 +26,12 @@ pub fn router() -> Router<AppState> {
 struct CreatePattern {
     name: String,
     predicates: Vec<CreatePredicate>,
+    #[serde(default)]
+    cooldown_ms: Option<u64>,
+    #[serde(flatten)]
+    outcome: PatternOutcome,
+    #[serde(default)]
+    parent: Option<PatternParent>,
 }
 
 async fn list(
@@ -41,7 +49,14 @@ async fn create(
 ) -> ApiResult<(StatusCode, Json<serde_json::Value>)> {
     let p = state
         .db
-        .create_pattern(project_id, &body.name, &body.predicates)
+        .create_pattern(
+            project_id,
+            &body.name,
+            &body.predicates,
+            body.cooldown_ms,
+            &body.outcome,
+            body.parent.as_ref(),
+        )
         .await?;
     Ok((StatusCode::CREATED, Json(serde_json::to_value(p).unwrap())))
 }
@@ -61,7 +76,15 @@ async fn update(
 ) -> ApiResult<Json<serde_json::Value>> {
     let p = state
         .db
-        .update_pattern(project_id, id, &body.name, &body.predicates)
+        .update_pattern(
+            project_id,
+            id,
+            &body.name,
+            &body.predicates,
+            body.cooldown_ms,
+            &body.outcome,
+            body.parent.as_ref(),
+        )
         .await?;
     Ok(Json(serde_json::to_value(p).unwrap()))
 }
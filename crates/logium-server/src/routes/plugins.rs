@@ -0,0 +1,83 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+
+use logium_core::model::{Plugin, PluginKind};
+
+use super::ApiResult;
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/projects/{project_id}/plugins", get(list).post(create))
+        .route(
+            "/api/projects/{project_id}/plugins/{id}",
+            get(get_one).put(update).delete(remove),
+        )
+}
+
+#[derive(Deserialize)]
+struct CreatePlugin {
+    name: String,
+    kind: PluginKind,
+    /// The module, base64-encoded.
+    #[serde(with = "logium_core::plugin::wasm_base64")]
+    wasm: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct UpdatePlugin {
+    name: String,
+    #[serde(with = "logium_core::plugin::wasm_base64")]
+    wasm: Vec<u8>,
+}
+
+async fn list(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+) -> ApiResult<Json<Vec<Plugin>>> {
+    let plugins = state.db.list_plugins(project_id).await?;
+    Ok(Json(plugins))
+}
+
+async fn create(
+    State(state): State<AppState>,
+    Path(project_id): Path<i64>,
+    Json(body): Json<CreatePlugin>,
+) -> ApiResult<(StatusCode, Json<Plugin>)> {
+    let plugin = state
+        .db
+        .create_plugin(project_id, &body.name, body.kind, &body.wasm)
+        .await?;
+    Ok((StatusCode::CREATED, Json(plugin)))
+}
+
+async fn get_one(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<Json<Plugin>> {
+    let plugin = state.db.get_plugin(project_id, id).await?;
+    Ok(Json(plugin))
+}
+
+async fn update(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+    Json(body): Json<UpdatePlugin>,
+) -> ApiResult<Json<Plugin>> {
+    let plugin = state
+        .db
+        .update_plugin(project_id, id, &body.name, &body.wasm)
+        .await?;
+    Ok(Json(plugin))
+}
+
+async fn remove(
+    State(state): State<AppState>,
+    Path((project_id, id)): Path<(i64, i64)>,
+) -> ApiResult<StatusCode> {
+    state.db.delete_plugin(project_id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
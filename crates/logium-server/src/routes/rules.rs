@@ -4,7 +4,7 @@ use axum::routing::get;
 use axum::{Json, Router};
 use serde::Deserialize;
 
-use logium_core::model::MatchMode;
+use logium_core::model::{MatchMode, RuleGuard};
 
 use super::ApiResult;
 use crate::AppState;
@@ -25,6 +25,12 @@ struct CreateRule {
     match_mode: MatchMode,
     match_rules: Vec<CreateMatchRule>,
     extraction_rules: Vec<CreateExtractionRule>,
+    #[serde(default)]
+    guard: Option<RuleGuard>,
+    #[serde(default)]
+    script: Option<String>,
+    #[serde(default)]
+    plugin_id: Option<u64>,
 }
 
 async fn list(
@@ -48,6 +54,9 @@ async fn create(
             &body.match_mode,
             &body.match_rules,
             &body.extraction_rules,
+            body.guard.as_ref(),
+            body.script.as_deref(),
+            body.plugin_id.map(|id| id as i64),
         )
         .await?;
     Ok((
@@ -78,6 +87,9 @@ async fn update(
             &body.match_mode,
             &body.match_rules,
             &body.extraction_rules,
+            body.guard.as_ref(),
+            body.script.as_deref(),
+            body.plugin_id.map(|id| id as i64),
         )
         .await?;
     Ok(Json(serde_json::to_value(rule).unwrap()))
@@ -23,7 +23,15 @@ pub fn router() -> Router<AppState> {
 struct CreateRuleset {
     name: String,
     template_id: i64,
+    #[serde(default)]
+    extra_template_ids: Vec<i64>,
+    #[serde(default)]
+    source_names: Vec<String>,
+    /// In evaluation order.
     rule_ids: Vec<i64>,
+    /// Omitted on update, the stop flags of rules still listed are kept.
+    #[serde(default)]
+    stop_rule_ids: Option<Vec<i64>>,
 }
 
 async fn list(
@@ -41,7 +49,15 @@ async fn create(
 ) -> ApiResult<(StatusCode, Json<serde_json::Value>)> {
     let rs = state
         .db
-        .create_ruleset(project_id, &body.name, body.template_id, &body.rule_ids)
+        .create_ruleset(
+            project_id,
+            &body.name,
+            body.template_id,
+            &body.extra_template_ids,
+            &body.source_names,
+            &body.rule_ids,
+            &body.stop_rule_ids.unwrap_or_default(),
+        )
         .await?;
     Ok((StatusCode::CREATED, Json(serde_json::to_value(rs).unwrap())))
 }
@@ -59,9 +75,30 @@ async fn update(
     Path((project_id, id)): Path<(i64, i64)>,
     Json(body): Json<CreateRuleset>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    let stop_rule_ids = match body.stop_rule_ids {
+        Some(ids) => ids,
+        None => {
+            let current = state.db.get_ruleset(project_id, id).await?;
+            current
+                .stop_rule_ids
+                .iter()
+                .map(|&id| id as i64)
+                .filter(|id| body.rule_ids.contains(id))
+                .collect()
+        }
+    };
     let rs = state
         .db
-        .update_ruleset(project_id, id, &body.name, body.template_id, &body.rule_ids)
+        .update_ruleset(
+            project_id,
+            id,
+            &body.name,
+            body.template_id,
+            &body.extra_template_ids,
+            &body.source_names,
+            &body.rule_ids,
+            &stop_rule_ids,
+        )
         .await?;
     Ok(Json(serde_json::to_value(rs).unwrap()))
 }
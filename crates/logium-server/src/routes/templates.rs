@@ -29,6 +29,8 @@ struct CreateTemplate {
     json_timestamp_field: Option<String>,
     file_name_regex: Option<String>,
     log_content_regex: Option<String>,
+    #[serde(default)]
+    parser_plugin_id: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -42,6 +44,7 @@ struct TemplateResponse {
     json_timestamp_field: Option<String>,
     file_name_regex: Option<String>,
     log_content_regex: Option<String>,
+    parser_plugin_id: Option<u64>,
 }
 
 impl From<logium_core::model::SourceTemplate> for TemplateResponse {
@@ -56,6 +59,7 @@ impl From<logium_core::model::SourceTemplate> for TemplateResponse {
             json_timestamp_field: t.json_timestamp_field,
             file_name_regex: t.file_name_regex,
             log_content_regex: t.log_content_regex,
+            parser_plugin_id: t.parser_plugin_id,
         }
     }
 }
@@ -85,6 +89,7 @@ async fn create(
             body.json_timestamp_field.as_deref(),
             body.file_name_regex.as_deref(),
             body.log_content_regex.as_deref(),
+            body.parser_plugin_id.map(|id| id as i64),
         )
         .await?;
     Ok((StatusCode::CREATED, Json(t.into())))
@@ -116,6 +121,7 @@ async fn update(
             body.json_timestamp_field.as_deref(),
             body.file_name_regex.as_deref(),
             body.log_content_regex.as_deref(),
+            body.parser_plugin_id.map(|id| id as i64),
         )
         .await?;
     Ok(Json(t.into()))
@@ -362,6 +362,40 @@ describe('AnalysisView', () => {
 
   // --- Facet Filtering ---
 
+  it('marks streamed pattern matches superseded by a child match', async () => {
+    runStreamingImpl = (_pid: number, callbacks: any) => {
+      callbacks.onPatternMatch(mockPatternMatch);
+      callbacks.onPatternMatch({ ...mockPatternMatch, timestamp: '2024-01-15T10:30:06.000' });
+      callbacks.onSuperseded(0);
+      setTimeout(() => {
+        callbacks.onComplete({
+          total_lines: 5,
+          total_rule_matches: 0,
+          total_pattern_matches: 2,
+          total_state_changes: 0,
+        });
+      }, 0);
+      return { close: vi.fn() };
+    };
+    vi.mocked(analysisApi.runStreaming).mockImplementation((...args: any[]) =>
+      runStreamingImpl(args[0], args[1]),
+    );
+
+    const { container } = renderAnalysis();
+    await tick();
+
+    await fireEvent.click(getRunButton());
+    vi.advanceTimersByTime(200);
+    await waitFor(() => {
+      expect(container.querySelectorAll('.pattern-match').length).toBe(2);
+    });
+
+    const cards = container.querySelectorAll('.pattern-match');
+    expect(cards[0].classList.contains('superseded')).toBe(true);
+    expect(cards[1].classList.contains('superseded')).toBe(false);
+    expect(screen.getAllByText('superseded')).toHaveLength(1);
+  });
+
   it('renders rule and source facet chips after analysis', async () => {
     renderAnalysis();
     await tick();
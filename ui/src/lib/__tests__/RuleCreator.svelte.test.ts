@@ -116,6 +116,23 @@ describe('RuleCreator', () => {
     expect(optionTexts).not.toContain('Client Rules');
   });
 
+  it('shows rulesets bound to the template as a further template', async () => {
+    vi.mocked(rulesetsApi.list).mockResolvedValue([
+      makeRuleset({ id: 7, name: 'Shared Rules', template_id: 2, extra_template_ids: [1] }),
+      makeRuleset({ id: 8, name: 'Client Rules', template_id: 2 }),
+    ]);
+
+    renderRuleCreator({ sourceTemplateId: 1 });
+    await tick();
+
+    await waitFor(() => {
+      expect(screen.getByText('Assign to Ruleset')).toBeInTheDocument();
+    });
+    const optionTexts = screen.getAllByRole('option').map((o) => o.textContent);
+    expect(optionTexts).toContain('Shared Rules');
+    expect(optionTexts).not.toContain('Client Rules');
+  });
+
   it('auto-selects ruleset when only one matches template', async () => {
     vi.mocked(rulesetsApi.list).mockResolvedValue([
       makeRuleset({ id: 5, name: 'Only One', template_id: 3, rule_ids: [] }),
@@ -336,6 +336,22 @@ describe('RuleEditor', () => {
     expect(screen.getByText(/All 2 patterns matched/)).toBeInTheDocument();
   });
 
+  it('fails the verdict when a negated pattern matches', async () => {
+    renderEditor({
+      match_mode: 'Any',
+      match_rules: [
+        { id: 1, pattern: 'ERROR' },
+        { id: 2, pattern: 'expected', negate: true },
+      ],
+    });
+
+    const testInput = screen.getByPlaceholderText('Paste a log line here to test...');
+    await fireEvent.input(testInput, { target: { value: 'ERROR expected shutdown' } });
+    await tick();
+
+    expect(screen.getByText('A negated pattern matched')).toBeInTheDocument();
+  });
+
   it('shows extraction preview for Parsed type', async () => {
     renderEditor({
       match_rules: [{ id: 1, pattern: 'ERROR (?P<message>.+)' }],
@@ -440,4 +456,93 @@ describe('RuleEditor', () => {
     expect(payload.match_rules![0].id).toBe(0);
     expect(payload.extraction_rules![0].id).toBe(0);
   });
+
+  it('sends negate flags and guard in save payload', async () => {
+    renderEditor({
+      match_rules: [
+        { id: 1, pattern: 'ERROR' },
+        { id: 2, pattern: 'expected', negate: true },
+      ],
+      guard: {
+        source_name: null,
+        state_key: 'phase',
+        operator: 'Eq',
+        operand: { Literal: { String: 'matchmaking' } },
+      },
+    });
+
+    expect(screen.getByDisplayValue('matchmaking')).toBeInTheDocument();
+    await fireEvent.click(screen.getByText('Save'));
+
+    await waitFor(() => {
+      expect(rulesApi.update).toHaveBeenCalledTimes(1);
+    });
+
+    const payload = vi.mocked(rulesApi.update).mock.calls[0][2];
+    expect(payload.match_rules!.map((mr) => mr.negate)).toEqual([false, true]);
+    expect(payload.guard?.state_key).toBe('phase');
+  });
+
+  it('sends the script in save payload', async () => {
+    renderEditor({ script: '#{ player: line }' });
+
+    expect(screen.getByDisplayValue('#{ player: line }')).toBeInTheDocument();
+    await fireEvent.click(screen.getByText('Save'));
+    await waitFor(() => {
+      expect(rulesApi.update).toHaveBeenCalledTimes(1);
+    });
+    expect(vi.mocked(rulesApi.update).mock.calls[0][2].script).toBe('#{ player: line }');
+  });
+
+  it('sends target type and transforms in save payload', async () => {
+    renderEditor({
+      extraction_rules: [
+        {
+          id: 1,
+          extraction_type: 'Parsed',
+          state_key: 'code',
+          pattern: 'code=(?P<code>\\S+)',
+          static_value: null,
+          mode: 'Replace',
+          target_type: 'Integer',
+          transforms: [{ ParseInt: { radix: 16 } }],
+        },
+      ],
+    });
+
+    await fireEvent.click(screen.getByText('Save'));
+
+    await waitFor(() => {
+      expect(rulesApi.update).toHaveBeenCalledTimes(1);
+    });
+
+    const payload = vi.mocked(rulesApi.update).mock.calls[0][2];
+    expect(payload.extraction_rules![0].target_type).toBe('Integer');
+    expect(payload.extraction_rules![0].transforms).toEqual([{ ParseInt: { radix: 16 } }]);
+  });
+
+  it('keeps a bounded list mode and its cap in save payload', async () => {
+    renderEditor({
+      extraction_rules: [
+        {
+          id: 1,
+          extraction_type: 'Parsed',
+          state_key: 'recent_errors',
+          pattern: 'code=(?P<recent_errors>\\S+)',
+          static_value: null,
+          mode: { List: { cap: 5 } },
+        },
+      ],
+    });
+
+    expect(screen.getByDisplayValue('5')).toBeInTheDocument();
+    await fireEvent.click(screen.getByText('Save'));
+
+    await waitFor(() => {
+      expect(rulesApi.update).toHaveBeenCalledTimes(1);
+    });
+
+    const payload = vi.mocked(rulesApi.update).mock.calls[0][2];
+    expect(payload.extraction_rules![0].mode).toEqual({ List: { cap: 5 } });
+  });
 });
@@ -125,6 +125,33 @@ describe('TimelineDetailPanel', () => {
     expect(screen.getByText('timeout')).toBeInTheDocument();
   });
 
+  it('renders the causal chain of pattern match steps', () => {
+    const { container } = renderPanel({
+      event: makePatternTimelineEvent({
+        patternMatch: makePatternMatch({
+          steps: [
+            {
+              step_index: 0,
+              source_id: 1,
+              source_name: 'nginx.log',
+              state_key: 'status',
+              timestamp: '2024-01-15T10:30:00',
+              rule_id: 1,
+              line_number: 42,
+              value: { Integer: 502 },
+            },
+          ],
+        }),
+      }),
+      ruleList: [makeRule({ id: 1, name: 'Upstream Error' })],
+    });
+    expect(container.querySelectorAll('.chain-step')).toHaveLength(1);
+    expect(screen.getByText('nginx.log.status')).toBeInTheDocument();
+    expect(screen.getByText('502')).toBeInTheDocument();
+    expect(container.querySelector('.chain-origin')?.textContent).toContain('Upstream Error');
+    expect(container.querySelector('.chain-origin')?.textContent).toContain('line 42');
+  });
+
   it('formats all StateValue variants', () => {
     renderPanel({
       event: makeRuleTimelineEvent({
@@ -28,6 +28,7 @@ export function makeLogLine(overrides: Partial<LogLine> = {}): LogLine {
   return {
     timestamp: '2024-01-15T10:30:00.000',
     source_id: 1,
+    line_number: 1,
     raw: 'ERROR something failed',
     content: 'something failed',
     ...overrides,
@@ -49,6 +50,8 @@ export function makePatternMatch(overrides: Partial<PatternMatch> = {}): Pattern
     pattern_id: 1,
     timestamp: '2024-01-15T10:30:05.000',
     state_snapshot: {},
+    counted_events: [],
+    steps: [],
     ...overrides,
   };
 }
@@ -142,7 +145,10 @@ export function makeRuleset(overrides: Partial<Ruleset> = {}): Ruleset {
     id: 1,
     name: 'Default Ruleset',
     template_id: 1,
+    extra_template_ids: [],
+    source_names: [],
     rule_ids: [],
+    stop_rule_ids: [],
     ...overrides,
   };
 }
@@ -175,6 +181,7 @@ export function makeAnalysisResult(overrides: Partial<AnalysisResult> = {}): Ana
     rule_matches: [makeRuleMatch()],
     pattern_matches: [makePatternMatch()],
     state_changes: [],
+    near_misses: [],
     ...overrides,
   };
 }
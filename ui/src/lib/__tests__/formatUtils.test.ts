@@ -0,0 +1,15 @@
+import { describe, it, expect } from 'vitest';
+import { formatDuration } from '../formatUtils';
+
+describe('formatDuration', () => {
+  it('renders sub-second and zero durations', () => {
+    expect(formatDuration(0)).toBe('0s');
+    expect(formatDuration(250)).toBe('250ms');
+  });
+
+  it('renders hours, minutes and fractional seconds', () => {
+    expect(formatDuration(3_602_500)).toBe('1h2.5s');
+    expect(formatDuration(90_000)).toBe('1m30s');
+    expect(formatDuration(-90_000)).toBe('-1m30s');
+  });
+});
@@ -24,6 +24,8 @@ export interface SourceTemplate {
   json_timestamp_field: string | null;
   file_name_regex: string | null;
   log_content_regex: string | null;
+  /** Line parser plugin replacing timestamp and content parsing. */
+  parser_plugin_id?: number | null;
 }
 
 export interface Source {
@@ -36,6 +38,8 @@ export interface Source {
 export interface MatchRule {
   id: number;
   pattern: string;
+  /** The rule only matches lines this pattern does not match. */
+  negate?: boolean;
 }
 
 export interface ExtractionRule {
@@ -44,51 +48,180 @@ export interface ExtractionRule {
   state_key: string;
   pattern: string | null;
   static_value: string | null;
-  mode: 'Replace' | 'Accumulate';
+  mode: ExtractionMode;
+  target_type?: ValueType;
+  transforms?: Transform[];
+  /** Unset the key this long (log time) after the rule last fired. */
+  ttl_ms?: number | null;
 }
 
+/** How an extracted value is combined with the key's current value. */
+export type ExtractionMode =
+  | 'Replace'
+  | 'Accumulate'
+  | 'Count'
+  | 'Min'
+  | 'Max'
+  | 'Average'
+  | 'DistinctSet'
+  | { List: { cap: number } }
+  | 'FirstSeen';
+
+/** Type an extracted value is converted to; `Auto` guesses. */
+export type ValueType =
+  | 'Auto'
+  | 'String'
+  | 'Integer'
+  | 'Float'
+  | 'Bool'
+  | 'Timestamp'
+  | 'Duration';
+
+/** One step of an extraction's transform pipeline. */
+export type Transform =
+  | 'Lowercase'
+  | 'Uppercase'
+  | 'Trim'
+  | { Replace: { pattern: string; replacement: string } }
+  | { ParseInt: { radix: number } }
+  | { Unit: { to: string } }
+  | { Duration: { to: string } }
+  | { Map: { table: Record<string, string>; default?: string | null } };
+
 export interface LogRule {
   id: number;
   name: string;
   match_mode: 'Any' | 'All';
   match_rules: MatchRule[];
   extraction_rules: ExtractionRule[];
+  guard?: RuleGuard | null;
+  /** Rhai extraction script, run after the extraction rules. */
+  script?: string | null;
+  /** Extractor plugin, run after the extraction rules and before the script. */
+  plugin_id?: number | null;
+}
+
+/** State condition checked before a rule's extractions are applied. */
+export interface RuleGuard {
+  /** Defaults to the matched line's own source. */
+  source_name?: string | null;
+  state_key: string;
+  operator: string;
+  operand: PatternPredicate['operand'];
 }
 
 export interface Ruleset {
   id: number;
   name: string;
   template_id: number;
+  /** Further templates whose sources the ruleset also applies to. */
+  extra_template_ids: number[];
+  /** When non-empty, the ruleset applies only to sources with these names. */
+  source_names: string[];
+  /** In evaluation order. */
   rule_ids: number[];
+  /** Rules that, once applied to a line, stop later rules from applying. */
+  stop_rule_ids: number[];
+}
+
+export interface DerivedKey {
+  id: number;
+  source_name: string;
+  state_key: string;
+  expression: string;
+}
+
+export interface GrokPattern {
+  id: number;
+  name: string;
+  pattern: string;
+}
+
+export type PluginKind = 'LineParser' | 'Extractor';
+
+export interface Plugin {
+  id: number;
+  name: string;
+  kind: PluginKind;
+  /** The WebAssembly module, base64-encoded. */
+  wasm: string;
 }
 
 export type StateValue =
   | { String: string }
   | { Integer: number }
   | { Float: number }
-  | { Bool: boolean };
+  | { Bool: boolean }
+  /** ISO date-time without an offset, e.g. `2024-01-15T10:30:00.250`. */
+  | { Timestamp: string }
+  /** Milliseconds. */
+  | { Duration: number }
+  | { List: StateValue[] }
+  | { Set: StateValue[] };
 
 export interface TrackedValue {
   value: StateValue;
   set_at: string;
+  rule_id?: number;
+  line_number?: number | null;
 }
 
 export interface PatternPredicate {
   source_name: string;
   state_key: string;
   operator: string;
-  operand: { Literal: StateValue } | { StateRef: { source_name: string; state_key: string } };
+  operand:
+    | { Literal: StateValue }
+    | { StateRef: { source_name: string; state_key: string } }
+    | { Transition: { from: StateValue | null; to: StateValue | null } }
+    | { List: StateValue[] }
+    | { Range: { min: StateValue; max: StateValue } }
+    | { Expr: string }
+    | {
+        TimeRef: {
+          source_name: string;
+          state_key: string;
+          within_ms?: number | null;
+          skew_ms?: number;
+        };
+      };
+  threshold?: Threshold | null;
+}
+
+export interface Threshold {
+  count: number;
+  window_ms: number;
+  rule_id: number | null;
+  group_by: string | null;
 }
 
-export interface Pattern {
+export type Severity = 'Info' | 'Warning' | 'Error' | 'Critical';
+
+/** Diagnosis and remediation are `{source.key}` templates on a Pattern and rendered text on a PatternMatch. */
+export interface PatternOutcome {
+  severity?: Severity | null;
+  category?: string | null;
+  diagnosis?: string | null;
+  remediation?: string | null;
+}
+
+export interface Pattern extends PatternOutcome {
   id: number;
   name: string;
   predicates: PatternPredicate[];
+  cooldown_ms?: number | null;
+  parent?: PatternParent | null;
+}
+
+export interface PatternParent {
+  pattern_id: number;
+  window_ms?: number | null;
 }
 
 export interface LogLine {
   timestamp: string;
   source_id: number;
+  line_number: number;
   raw: string;
   content: string;
 }
@@ -100,10 +233,36 @@ export interface RuleMatch {
   extracted_state: Record<string, StateValue>;
 }
 
-export interface PatternMatch {
+export interface PatternMatch extends PatternOutcome {
   pattern_id: number;
+  /** Parent pattern whose latest match this one refines. */
+  refines?: number | null;
+  /** A child pattern refined this match; streamed as a `superseded` event. */
+  superseded?: boolean;
   timestamp: string;
   state_snapshot: Record<string, Record<string, TrackedValue>>;
+  counted_events: CountedEvent[];
+  steps: StepProvenance[];
+}
+
+export interface StepProvenance {
+  step_index: number;
+  source_id: number;
+  source_name: string;
+  state_key: string;
+  timestamp: string;
+  rule_id: number;
+  line_number: number | null;
+  value: StateValue | null;
+}
+
+export interface CountedEvent {
+  timestamp: string;
+  source_id: number;
+  rule_id: number;
+  line_number: number;
+  value: StateValue | null;
+  group: StateValue | null;
 }
 
 export interface StateChange {
@@ -120,6 +279,69 @@ export interface AnalysisResult {
   rule_matches: RuleMatch[];
   pattern_matches: PatternMatch[];
   state_changes: StateChange[];
+  near_misses: NearMiss[];
+  profile?: AnalysisProfile | null;
+  coverage?: CoverageReport | null;
+}
+
+export interface UnmatchedTemplate {
+  template: string;
+  count: number;
+  sample_line: string;
+}
+
+export interface SourceCoverage {
+  source_id: number;
+  lines: number;
+  unmatched_lines: number;
+  unmatched_templates: UnmatchedTemplate[];
+}
+
+/** Blind spots of the rule set, present when analysis ran with `coverage`. */
+export interface CoverageReport {
+  sources: SourceCoverage[];
+  unmatched_rules: number[];
+  rules_without_changes: number[];
+  unfired_patterns: number[];
+}
+
+export interface RuleProfile {
+  rule_id: number;
+  evaluations: number;
+  matches: number;
+  match_ns: number;
+  extraction_ns: number;
+}
+
+export interface SourceProfile {
+  source_id: number;
+  lines: number;
+  parse_ns: number;
+}
+
+/** Per-rule and per-source costs, present when analysis ran with `profile`. */
+export interface AnalysisProfile {
+  rules: RuleProfile[];
+  sources: SourceProfile[];
+}
+
+export interface NearMiss {
+  pattern_id: number;
+  steps_reached: number;
+  total_steps: number;
+  reached_at: string | null;
+  state_snapshot: Record<string, Record<string, TrackedValue>>;
+  steps: StepProvenance[];
+  failed_step: number;
+  failed_predicate: PatternPredicate;
+  actual: StateValue | null;
+  expected: StateValue | null;
+}
+
+export interface PatternExplanation {
+  pattern_id: number;
+  match_count: number;
+  near_miss: NearMiss | null;
 }
 
 export interface DetectTemplateResponse {
@@ -299,6 +521,64 @@ export const patterns = {
     }),
   delete: (pid: number, id: number) =>
     request<void>(`/projects/${pid}/patterns/${id}`, { method: 'DELETE' }),
+  explain: (pid: number, id: number, timeRange?: TimeRange) =>
+    request<PatternExplanation>(
+      `/projects/${pid}/patterns/${id}/explain${buildTimeRangeParams(timeRange)}`,
+    ),
+};
+
+// Derived keys
+export const derivedKeys = {
+  list: (pid: number) => request<DerivedKey[]>(`/projects/${pid}/derived-keys`),
+  get: (pid: number, id: number) => request<DerivedKey>(`/projects/${pid}/derived-keys/${id}`),
+  create: (pid: number, data: Omit<DerivedKey, 'id'>) =>
+    request<DerivedKey>(`/projects/${pid}/derived-keys`, {
+      method: 'POST',
+      body: JSON.stringify(data),
+    }),
+  update: (pid: number, id: number, data: Omit<DerivedKey, 'id'>) =>
+    request<DerivedKey>(`/projects/${pid}/derived-keys/${id}`, {
+      method: 'PUT',
+      body: JSON.stringify(data),
+    }),
+  delete: (pid: number, id: number) =>
+    request<void>(`/projects/${pid}/derived-keys/${id}`, { method: 'DELETE' }),
+};
+
+// Grok patterns
+export const grokPatterns = {
+  builtin: () => request<Omit<GrokPattern, 'id'>[]>('/grok-patterns/builtin'),
+  list: (pid: number) => request<GrokPattern[]>(`/projects/${pid}/grok-patterns`),
+  create: (pid: number, data: Omit<GrokPattern, 'id'>) =>
+    request<GrokPattern>(`/projects/${pid}/grok-patterns`, {
+      method: 'POST',
+      body: JSON.stringify(data),
+    }),
+  update: (pid: number, id: number, data: Omit<GrokPattern, 'id'>) =>
+    request<GrokPattern>(`/projects/${pid}/grok-patterns/${id}`, {
+      method: 'PUT',
+      body: JSON.stringify(data),
+    }),
+  delete: (pid: number, id: number) =>
+    request<void>(`/projects/${pid}/grok-patterns/${id}`, { method: 'DELETE' }),
+};
+
+// WebAssembly plugins
+export const plugins = {
+  list: (pid: number) => request<Plugin[]>(`/projects/${pid}/plugins`),
+  create: (pid: number, data: Omit<Plugin, 'id'>) =>
+    request<Plugin>(`/projects/${pid}/plugins`, {
+      method: 'POST',
+      body: JSON.stringify(data),
+    }),
+  /** A plugin's kind cannot change. */
+  update: (pid: number, id: number, data: Omit<Plugin, 'id' | 'kind'>) =>
+    request<Plugin>(`/projects/${pid}/plugins/${id}`, {
+      method: 'PUT',
+      body: JSON.stringify(data),
+    }),
+  delete: (pid: number, id: number) =>
+    request<void>(`/projects/${pid}/plugins/${id}`, { method: 'DELETE' }),
 };
 
 // Analysis events (matches Rust AnalysisEvent serde output)
@@ -306,6 +586,9 @@ export type AnalysisEvent =
   | { type: 'rule_match'; data: RuleMatch }
   | { type: 'pattern_match'; data: PatternMatch }
   | { type: 'state_change'; data: StateChange }
+  | { type: 'near_miss'; data: NearMiss }
+  /** `match_index` counts the `pattern_match` events sent before it. */
+  | { type: 'superseded'; data: { match_index: number } }
   | { type: 'progress'; data: { lines_processed: number } }
   | {
       type: 'complete';
@@ -314,6 +597,8 @@ export type AnalysisEvent =
         total_rule_matches: number;
         total_pattern_matches: number;
         total_state_changes: number;
+        profile?: AnalysisProfile | null;
+        coverage?: CoverageReport | null;
       };
     }
   | { type: 'error'; data: { message: string } };
@@ -321,6 +606,7 @@ export type AnalysisEvent =
 export interface StreamingCallbacks {
   onRuleMatch: (rm: RuleMatch) => void;
   onPatternMatch: (pm: PatternMatch) => void;
+  onSuperseded: (matchIndex: number) => void;
   onStateChange: (sc: StateChange) => void;
   onProgress: (linesProcessed: number) => void;
   onComplete: (totals: {
@@ -328,6 +614,8 @@ export interface StreamingCallbacks {
     total_rule_matches: number;
     total_pattern_matches: number;
     total_state_changes: number;
+    profile?: AnalysisProfile | null;
+    coverage?: CoverageReport | null;
   }) => void;
   onError: (message: string) => void;
 }
@@ -342,10 +630,35 @@ function buildTimeRangeParams(timeRange?: TimeRange): string {
 }
 
 // Analysis
+/** Pasted log text to analyze in place of the project's sources. */
+export interface AnalyzeTextRequest {
+  sources: { name: string; template_id: number; text: string }[];
+  /** Replace the project rule with the same id, or are added for every pasted source. */
+  rules?: LogRule[];
+  /** Replace the project pattern with the same id, or are added. */
+  patterns?: Pattern[];
+}
+
 export const analysis = {
-  run: (pid: number, timeRange?: TimeRange) =>
-    request<AnalysisResult>(`/projects/${pid}/analyze${buildTimeRangeParams(timeRange)}`, {
+  run: (
+    pid: number,
+    timeRange?: TimeRange,
+    options?: { nearMisses?: boolean; profile?: boolean; coverage?: boolean },
+  ) => {
+    const params = new URLSearchParams(buildTimeRangeParams(timeRange));
+    if (options?.nearMisses) params.set('near_misses', 'true');
+    if (options?.profile) params.set('profile', 'true');
+    if (options?.coverage) params.set('coverage', 'true');
+    const qs = params.toString();
+    return request<AnalysisResult>(`/projects/${pid}/analyze${qs ? `?${qs}` : ''}`, {
+      method: 'POST',
+    });
+  },
+  /** Run the engine on pasted log text, with optional draft rules and patterns. */
+  runText: (pid: number, body: AnalyzeTextRequest, timeRange?: TimeRange) =>
+    request<AnalysisResult>(`/projects/${pid}/analyze/text${buildTimeRangeParams(timeRange)}`, {
       method: 'POST',
+      body: JSON.stringify(body),
     }),
   runStreaming: (
     pid: number,
@@ -367,6 +680,9 @@ export const analysis = {
         case 'pattern_match':
           callbacks.onPatternMatch(event.data);
           break;
+        case 'superseded':
+          callbacks.onSuperseded(event.data.match_index);
+          break;
         case 'state_change':
           callbacks.onStateChange(event.data);
           break;
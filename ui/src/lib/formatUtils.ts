@@ -0,0 +1,15 @@
+/**
+ * Shared helpers for displaying state values.
+ */
+
+/** Render milliseconds the way the server does, e.g. `1h2m3.5s` or `250ms`. */
+export function formatDuration(ms: number): string {
+  const sign = ms < 0 ? '-' : '';
+  ms = Math.abs(ms);
+  if (ms === 0) return '0s';
+  if (ms < 1000) return `${sign}${ms}ms`;
+  const h = Math.floor(ms / 3_600_000);
+  const m = Math.floor(ms / 60_000) % 60;
+  const s = (ms % 60_000) / 1000;
+  return sign + (h ? `${h}h` : '') + (m ? `${m}m` : '') + (s ? `${s}s` : '');
+}